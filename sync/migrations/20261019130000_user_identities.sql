@@ -0,0 +1,16 @@
+-- Allows several upstream identities to be linked to a single account.
+-- Existing accounts were authenticated by the single IdP, which is now the `default` provider.
+CREATE TABLE IF NOT EXISTS user_identities (
+  user_id TEXT NOT NULL,
+  provider TEXT NOT NULL,
+  upstream_userid TEXT NOT NULL,
+  linked_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  PRIMARY KEY (provider, upstream_userid),
+  UNIQUE (user_id, provider),
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+INSERT INTO user_identities (user_id, provider, upstream_userid)
+  SELECT id, 'default', upstream_userid FROM users;
+
+ALTER TABLE users DROP COLUMN upstream_userid;
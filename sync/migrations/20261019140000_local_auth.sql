@@ -0,0 +1,26 @@
+-- Built-in authentication, used when no external IdP is available.
+-- The username of a local account is stored as a `local` identity.
+CREATE TABLE IF NOT EXISTS local_credentials (
+  user_id TEXT PRIMARY KEY NOT NULL,
+  password_hash TEXT NOT NULL,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+CREATE TABLE IF NOT EXISTS passkeys (
+  id TEXT PRIMARY KEY NOT NULL,
+  user_id TEXT NOT NULL,
+  name TEXT NOT NULL,
+  passkey TEXT NOT NULL,
+  created_at DATETIME NOT NULL DEFAULT CURRENT_TIMESTAMP,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- Server-side state of ongoing WebAuthn registrations and authentications.
+-- Logins of unknown users get a challenge too, without a user, so they can not be told apart.
+CREATE TABLE IF NOT EXISTS webauthn_challenges (
+  id TEXT PRIMARY KEY NOT NULL,
+  user_id TEXT,
+  state TEXT NOT NULL,
+  expires_at DATETIME NOT NULL,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
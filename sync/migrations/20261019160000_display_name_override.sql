@@ -0,0 +1,2 @@
+-- Display name chosen by the user, taking precedence over the one refreshed from the IdP on login
+ALTER TABLE users ADD COLUMN display_name_override TEXT;
@@ -0,0 +1,11 @@
+-- Pending device authorization grants (RFC 8628), for clients without a browser.
+-- Only a SHA-256 hash of the device code is stored. `user_id` is set once the user approves.
+CREATE TABLE IF NOT EXISTS device_authorizations (
+  device_code_hash TEXT PRIMARY KEY NOT NULL,
+  user_code TEXT NOT NULL UNIQUE,
+  user_id TEXT,
+  created_at DATETIME NOT NULL,
+  expires_at DATETIME NOT NULL,
+  last_polled_at DATETIME,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
@@ -0,0 +1,11 @@
+-- Accounts are deleted after a grace period, in which the deletion can be cancelled.
+-- Set to when the account will be deleted by the background job.
+ALTER TABLE users ADD COLUMN deletion_scheduled_for DATETIME;
+
+-- Snapshot of all data of an account, taken when its deletion is scheduled.
+CREATE TABLE IF NOT EXISTS account_exports (
+  user_id TEXT PRIMARY KEY NOT NULL,
+  content TEXT NOT NULL,
+  created_at DATETIME NOT NULL,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
@@ -0,0 +1,50 @@
+-- Shared vaults, whose codes are available to every member according to their role.
+CREATE TABLE IF NOT EXISTS vaults (
+  id TEXT PRIMARY KEY NOT NULL,
+  name TEXT NOT NULL,
+  created_at DATETIME NOT NULL
+);
+
+-- Role is one of `owner`, `editor` and `viewer`
+CREATE TABLE IF NOT EXISTS vault_members (
+  vault_id TEXT NOT NULL,
+  user_id TEXT NOT NULL,
+  role TEXT NOT NULL,
+  joined_at DATETIME NOT NULL,
+  PRIMARY KEY (vault_id, user_id),
+  FOREIGN KEY (vault_id) REFERENCES vaults(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  FOREIGN KEY (user_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- Single-use invitations. Only a SHA-256 hash of the invitation code is stored.
+CREATE TABLE IF NOT EXISTS vault_invitations (
+  id TEXT PRIMARY KEY NOT NULL,
+  vault_id TEXT NOT NULL,
+  code_hash TEXT NOT NULL UNIQUE,
+  role TEXT NOT NULL,
+  created_by TEXT NOT NULL,
+  created_at DATETIME NOT NULL,
+  expires_at DATETIME NOT NULL,
+  FOREIGN KEY (vault_id) REFERENCES vaults(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- Codes are owned by either a user or a vault
+CREATE TABLE codes_new (
+  id TEXT PRIMARY KEY NOT NULL,
+  owner_id TEXT,
+  vault_id TEXT,
+  display_name TEXT NOT NULL,
+  content TEXT NOT NULL,
+  icon_url TEXT,
+  website_url TEXT,
+  FOREIGN KEY (owner_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  FOREIGN KEY (vault_id) REFERENCES vaults(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  CHECK ((owner_id IS NULL) <> (vault_id IS NULL))
+);
+
+INSERT INTO codes_new (id, owner_id, display_name, content, icon_url, website_url)
+  SELECT id, owner_id, display_name, content, icon_url, website_url FROM codes;
+
+DROP TABLE codes;
+ALTER TABLE codes_new RENAME TO codes;
@@ -0,0 +1,11 @@
+-- Grants on a single personal code, for sharing it without a vault.
+-- Permission is either `read` or `write`.
+CREATE TABLE IF NOT EXISTS code_shares (
+  code_id TEXT NOT NULL,
+  grantee_id TEXT NOT NULL,
+  permission TEXT NOT NULL,
+  created_at DATETIME NOT NULL,
+  PRIMARY KEY (code_id, grantee_id),
+  FOREIGN KEY (code_id) REFERENCES codes(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  FOREIGN KEY (grantee_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
@@ -0,0 +1,11 @@
+-- Single-use links showing the current one-time password of a code, without an account.
+-- Only a hash of the token is stored.
+CREATE TABLE IF NOT EXISTS share_links (
+  token_hash TEXT PRIMARY KEY NOT NULL,
+  code_id TEXT NOT NULL,
+  created_by TEXT NOT NULL,
+  created_at DATETIME NOT NULL,
+  expires_at DATETIME NOT NULL,
+  FOREIGN KEY (code_id) REFERENCES codes(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
@@ -0,0 +1,30 @@
+-- Trusted contacts, which may request read access to the codes of the owner.
+-- Status is one of `idle`, `requested` and `granted`. A request is granted at `grants_at`, unless
+-- the owner denies it before.
+CREATE TABLE IF NOT EXISTS emergency_contacts (
+  owner_id TEXT NOT NULL,
+  contact_id TEXT NOT NULL,
+  wait_days INTEGER NOT NULL,
+  status TEXT NOT NULL,
+  requested_at DATETIME,
+  grants_at DATETIME,
+  created_at DATETIME NOT NULL,
+  PRIMARY KEY (owner_id, contact_id),
+  FOREIGN KEY (owner_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE,
+  FOREIGN KEY (contact_id) REFERENCES users(id) ON DELETE CASCADE ON UPDATE CASCADE
+);
+
+-- Audit log of everything happening with emergency access. Kept after a contact is removed, and
+-- after either side deletes their account, in which case their id is cleared.
+CREATE TABLE IF NOT EXISTS emergency_events (
+  id TEXT PRIMARY KEY NOT NULL,
+  owner_id TEXT,
+  contact_id TEXT,
+  kind TEXT NOT NULL,
+  created_at DATETIME NOT NULL,
+  FOREIGN KEY (owner_id) REFERENCES users(id) ON DELETE SET NULL ON UPDATE CASCADE,
+  FOREIGN KEY (contact_id) REFERENCES users(id) ON DELETE SET NULL ON UPDATE CASCADE
+);
+
+CREATE INDEX IF NOT EXISTS emergency_events_owner ON emergency_events (owner_id, created_at);
+CREATE INDEX IF NOT EXISTS emergency_events_contact ON emergency_events (contact_id, created_at);
@@ -0,0 +1,5 @@
+-- Instance administrators, disabled accounts and forced logouts.
+-- JWTs issued before `sessions_valid_after` are rejected.
+ALTER TABLE users ADD COLUMN is_admin BOOLEAN NOT NULL DEFAULT FALSE;
+ALTER TABLE users ADD COLUMN disabled_at DATETIME;
+ALTER TABLE users ADD COLUMN sessions_valid_after DATETIME;
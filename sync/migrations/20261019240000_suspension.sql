@@ -0,0 +1,2 @@
+-- Suspended accounts are read-only, so that their codes can still be exported.
+ALTER TABLE users ADD COLUMN suspended BOOLEAN NOT NULL DEFAULT FALSE;
@@ -0,0 +1,12 @@
+-- Single-use invite codes for the `invite` registration mode. Only a SHA-256 hash of the code is stored.
+CREATE TABLE IF NOT EXISTS registration_invites (
+  id TEXT PRIMARY KEY NOT NULL,
+  code_hash TEXT NOT NULL UNIQUE,
+  created_by TEXT,
+  created_at DATETIME NOT NULL,
+  expires_at DATETIME,
+  used_by TEXT,
+  used_at DATETIME,
+  FOREIGN KEY (created_by) REFERENCES users(id) ON DELETE SET NULL ON UPDATE CASCADE,
+  FOREIGN KEY (used_by) REFERENCES users(id) ON DELETE SET NULL ON UPDATE CASCADE
+);
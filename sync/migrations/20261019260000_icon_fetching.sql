@@ -0,0 +1,2 @@
+-- Users may keep the server from contacting the websites of their codes to fetch icons.
+ALTER TABLE users ADD COLUMN icon_fetching BOOLEAN NOT NULL DEFAULT TRUE;
@@ -1,39 +1,122 @@
 use crate::{
-    models::{self, user::User},
+    models::{self, token::ApiToken, user::User},
     routes::v1::ApiError,
-    AppState,
+    utils, AppState, ProviderOptions, TokenRequest,
 };
 use axum::{
     extract::{Request, State},
-    http::header,
+    http::{header, HeaderMap, StatusCode},
     middleware::Next,
     response::IntoResponse,
 };
 use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use chrono::{DateTime, Timelike, Utc};
 use jsonwebtoken::{decode, encode, DecodingKey, EncodingKey, Header, Validation};
 use reqwest::header::USER_AGENT;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
 
+/// Prefix of personal access tokens, to tell them apart from JWTs.
+pub const API_TOKEN_PREFIX: &str = "ibp_";
+
+/// How long a JWT from logging in stays valid.
+pub const JWT_LIFETIME_DAYS: i64 = 90;
+
+/// How long after authenticating sensitive operations are allowed without authenticating again.
+pub const STEP_UP_MAX_AGE_MINUTES: i64 = 10;
+
+/// Permission granted to a personal access token. JWTs from logging in have every scope.
+#[derive(Serialize, Deserialize, utoipa::ToSchema, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scope {
+    /// List codes, fetch their icons and the checksum.
+    #[serde(rename = "codes:read")]
+    CodesRead,
+    /// Add, edit and delete codes. Does not imply `codes:read`.
+    #[serde(rename = "codes:write")]
+    CodesWrite,
+    /// Manage the account itself, including identities, passkeys and tokens.
+    #[serde(rename = "account:admin")]
+    AccountAdmin,
+    /// Use the `/v1/admin` routes. Only has an effect for administrators of the instance.
+    #[serde(rename = "instance:admin")]
+    InstanceAdmin,
+}
+
+/// Scopes of the current request, inserted as an extension by [`jwt_middleware`].
+#[derive(Clone, Debug)]
+pub struct Scopes(Vec<Scope>);
+
+impl Scopes {
+    pub fn all() -> Self {
+        Scopes(vec![
+            Scope::CodesRead,
+            Scope::CodesWrite,
+            Scope::AccountAdmin,
+            Scope::InstanceAdmin,
+        ])
+    }
+
+    pub fn require(&self, scope: Scope) -> Result<(), ApiError> {
+        if self.0.contains(&scope) {
+            Ok(())
+        } else {
+            Err(ApiError::MissingScope)
+        }
+    }
+}
+
+/// When the user last authenticated interactively, inserted as an extension by [`jwt_middleware`].
+/// Personal access tokens never count as a fresh authentication.
+#[derive(Clone, Debug)]
+pub struct AuthTime(pub Option<DateTime<Utc>>);
+
+impl AuthTime {
+    /// For sensitive operations, which should not be possible with a stolen long-lived token.
+    pub fn require_fresh(&self) -> Result<(), ApiError> {
+        match self.0 {
+            Some(auth_time)
+                if Utc::now() - auth_time <= chrono::Duration::minutes(STEP_UP_MAX_AGE_MINUTES) =>
+            {
+                Ok(())
+            }
+            _ => Err(ApiError::StepUpRequired),
+        }
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct TokenClaims {
     pub exp: usize,
     pub iat: usize,
+    /// Time of the login which issued this token. Missing in tokens from older versions.
+    #[serde(default)]
+    pub auth_time: usize,
     pub sub: String,
     pub username: String,
     pub display_name: String,
     pub avatar_url: String,
 }
 
-pub async fn create_jwt(user: &User, secret: String) -> (String, Cookie) {
-    let now = chrono::Utc::now();
+pub async fn create_jwt(user: &User, secret: String) -> (String, Cookie<'static>) {
+    create_jwt_with_auth_time(user, secret, Some(Utc::now())).await
+}
+
+/// Like [`create_jwt`], for tokens not issued right after the user authenticated, e.g. to devices.
+/// Without an authentication time, the token never counts as a fresh authentication.
+pub async fn create_jwt_with_auth_time(
+    user: &User,
+    secret: String,
+    auth_time: Option<DateTime<Utc>>,
+) -> (String, Cookie<'static>) {
+    let now = Utc::now();
 
     let claims = TokenClaims {
         iat: now.timestamp() as usize,
-        exp: (now + chrono::Duration::days(90)).timestamp() as usize,
+        auth_time: auth_time.map_or(0, |auth_time| auth_time.timestamp() as usize),
+        exp: (now + chrono::Duration::days(JWT_LIFETIME_DAYS)).timestamp() as usize,
         sub: user.id.clone(),
         username: user.username.clone(),
-        display_name: user.display_name.clone(),
+        display_name: user.effective_display_name(),
         avatar_url: user.avatar_url.clone(),
     };
 
@@ -53,6 +136,14 @@ pub async fn create_jwt(user: &User, secret: String) -> (String, Cookie) {
     (jwt, cookie)
 }
 
+/// Response for a successful login, setting the JWT as a cookie.
+pub async fn login_response(user: &User, secret: String) -> (StatusCode, HeaderMap) {
+    let mut headers = HeaderMap::default();
+    let (_, cookie) = create_jwt(user, secret).await;
+    headers.insert(header::SET_COOKIE, cookie.to_string().parse().unwrap());
+    (StatusCode::OK, headers)
+}
+
 pub async fn jwt_middleware(
     cookie_jar: CookieJar,
     State(data): State<Arc<AppState>>,
@@ -74,17 +165,79 @@ pub async fn jwt_middleware(
 
     let token = token.ok_or(ApiError::MissingAuthentication)?;
 
-    let claims = decode::<TokenClaims>(
-        &token,
-        &DecodingKey::from_secret(data.settings.jwt_secret.as_ref()),
-        &Validation::default(),
-    )?
-    .claims;
+    let (user_id, scopes, auth_time, issued_at) = if token.starts_with(API_TOKEN_PREFIX) {
+        let api_token = ApiToken::get_by_hash(&data.db, utils::hash_token(&token))
+            .await?
+            .ok_or(ApiError::InvalidAuthentication)?;
+
+        if api_token.expires_at <= Utc::now() {
+            return Err(ApiError::ApiTokenExpired);
+        }
+
+        api_token.mark_used(&data.db).await?;
+        (
+            api_token.user_id,
+            Scopes(api_token.scopes.0),
+            AuthTime(None),
+            None,
+        )
+    } else {
+        let claims = decode::<TokenClaims>(
+            &token,
+            &DecodingKey::from_secret(data.settings.jwt_secret.as_ref()),
+            &Validation::default(),
+        )?
+        .claims;
+
+        let auth_time =
+            DateTime::from_timestamp(claims.auth_time as i64, 0).filter(|_| claims.auth_time > 0);
 
-    let user = models::user::User::get_by_id(&data.db, claims.sub).await?;
+        let issued_at = DateTime::from_timestamp(claims.iat as i64, 0);
+
+        (claims.sub, Scopes::all(), AuthTime(auth_time), issued_at)
+    };
+
+    let user = models::user::User::get_by_id(&data.db, user_id).await?;
     let user = user.ok_or(ApiError::JwtUserGone)?;
 
+    if user.disabled_at.is_some() {
+        return Err(ApiError::AccountDisabled);
+    }
+
+    // Suspended accounts keep read access, e.g. to export their codes
+    if user.suspended && !req.method().is_safe() {
+        return Err(ApiError::AccountSuspended);
+    }
+
+    // JWTs only have second precision
+    if let (Some(issued_at), Some(valid_after)) = (issued_at, user.sessions_valid_after) {
+        if issued_at < valid_after.with_nanosecond(0).unwrap_or(valid_after) {
+            return Err(ApiError::InvalidAuthentication);
+        }
+    }
+
     req.extensions_mut().insert(user);
+    req.extensions_mut().insert(scopes);
+    req.extensions_mut().insert(auth_time);
+    Ok(next.run(req).await)
+}
+
+/// Guards the `/v1/admin` routes. Must run after [`jwt_middleware`].
+pub async fn admin_middleware(req: Request, next: Next) -> Result<impl IntoResponse, ApiError> {
+    let user = req
+        .extensions()
+        .get::<User>()
+        .ok_or(ApiError::MissingAuthentication)?;
+
+    if !user.is_admin {
+        return Err(ApiError::AdminRequired);
+    }
+
+    req.extensions()
+        .get::<Scopes>()
+        .ok_or(ApiError::MissingAuthentication)?
+        .require(Scope::InstanceAdmin)?;
+
     Ok(next.run(req).await)
 }
 
@@ -101,10 +254,25 @@ pub struct OpenIdUserInfo {
     pub id: String,
     #[serde(rename = "name")]
     pub display_name: Option<String>,
-    #[serde(rename = "preferred_username")]
-    pub username: String,
+    /// Not provided by every IdP, e.g. Google. Use [`OpenIdUserInfo::username`] instead.
+    pub preferred_username: Option<String>,
+    pub email: Option<String>,
+    pub email_verified: Option<bool>,
+    /// Not a standard claim, but sent by e.g. Keycloak and Authentik when configured.
+    #[serde(default)]
+    pub groups: Vec<String>,
     #[serde(rename = "picture")]
-    pub avatar: String,
+    pub avatar: Option<String>,
+}
+
+impl OpenIdUserInfo {
+    /// Falls back to the email, and lastly the subject, for IdPs without `preferred_username`.
+    pub fn username(&self) -> String {
+        self.preferred_username
+            .clone()
+            .or(self.email.clone())
+            .unwrap_or(self.id.clone())
+    }
 }
 
 #[derive(Deserialize, Debug)]
@@ -114,17 +282,23 @@ struct TokenExchangeResponse {
 
 #[derive(Clone)]
 pub struct OpenId {
+    pub id: String,
+    pub name: String,
     pub authorization: String,
     pub token: String,
     pub userinfo: String,
     pub client_id: String,
     pub client_secret: String,
+    pub redirect_uri: String,
+    pub token_request: TokenRequest,
 }
 
 #[derive(Serialize, Debug)]
 struct TokenExchangeRequest {
+    grant_type: &'static str,
     client_id: String,
     client_secret: String,
+    redirect_uri: String,
     code: String,
 }
 
@@ -132,9 +306,13 @@ struct TokenExchangeRequest {
 impl OpenId {
     #[builder]
     pub async fn discover(
+        id: String,
+        name: String,
         client_id: String,
         client_secret: String,
         server: String,
+        redirect_uri: String,
+        #[builder(default)] token_request: TokenRequest,
     ) -> Result<Self, reqwest::Error> {
         let config = reqwest::get(format!("{server}/.well-known/openid-configuration"))
             .await?
@@ -142,8 +320,12 @@ impl OpenId {
             .await?;
 
         Ok(OpenId {
+            id,
+            name,
             client_id,
             client_secret,
+            redirect_uri,
+            token_request,
             authorization: config.authorization_endpoint,
             token: config.token_endpoint,
             userinfo: config.userinfo_endpoint,
@@ -151,14 +333,21 @@ impl OpenId {
     }
 
     pub async fn exchange(self, code: String) -> Result<String, reqwest::Error> {
+        let body = TokenExchangeRequest {
+            grant_type: "authorization_code",
+            client_id: self.client_id,
+            client_secret: self.client_secret,
+            redirect_uri: self.redirect_uri,
+            code,
+        };
+
         let request = reqwest::Client::new()
             .post(self.token)
-            .header(USER_AGENT, "Iceblink")
-            .json(&TokenExchangeRequest {
-                client_id: self.client_id,
-                client_secret: self.client_secret,
-                code,
-            });
+            .header(USER_AGENT, "Iceblink");
+        let request = match self.token_request {
+            TokenRequest::Form => request.form(&body),
+            TokenRequest::Json => request.json(&body),
+        };
 
         let response = request
             .send()
@@ -178,3 +367,44 @@ impl OpenId {
         request.send().await?.json::<OpenIdUserInfo>().await
     }
 }
+
+/// All OpenId providers configured for this instance, looked up by their id.
+#[derive(Clone, Default)]
+pub struct OpenIdRegistry {
+    providers: Vec<OpenId>,
+}
+
+impl OpenIdRegistry {
+    pub fn new(providers: Vec<OpenId>) -> Self {
+        OpenIdRegistry { providers }
+    }
+
+    pub async fn discover(options: &[ProviderOptions]) -> Result<Self, reqwest::Error> {
+        let mut providers = Vec::with_capacity(options.len());
+
+        for provider in options {
+            providers.push(
+                OpenId::discover()
+                    .id(provider.id.clone())
+                    .name(provider.name.clone())
+                    .client_id(provider.client_id.clone())
+                    .client_secret(provider.client_secret.clone())
+                    .server(provider.server.clone())
+                    .redirect_uri(provider.redirect_uri.clone())
+                    .token_request(provider.token_request)
+                    .call()
+                    .await?,
+            );
+        }
+
+        Ok(OpenIdRegistry { providers })
+    }
+
+    pub fn get(&self, id: &str) -> Option<&OpenId> {
+        self.providers.iter().find(|provider| provider.id == id)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &OpenId> {
+        self.providers.iter()
+    }
+}
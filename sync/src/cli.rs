@@ -1,4 +1,7 @@
+use crate::{icons::provider::ServiceProvider, IconProviderOptions, ProviderOptions};
 use clap::{Parser, Subcommand};
+use serde::Serialize;
+use std::path::{Path, PathBuf};
 use tracing::level_filters::LevelFilter;
 
 #[derive(clap::ValueEnum, Clone, Debug)]
@@ -24,6 +27,43 @@ impl From<LoggingLevel> for LevelFilter {
     }
 }
 
+#[derive(clap::ValueEnum, Serialize, utoipa::ToSchema, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AuthBackend {
+    /// Authenticate using one or more external OpenID Connect providers.
+    #[default]
+    #[value(name = "openid")]
+    OpenId,
+    /// Accounts with passwords and passkeys stored on this instance, without any IdP.
+    Local,
+}
+
+/// Who may create a new account on this instance.
+#[derive(clap::ValueEnum, Serialize, utoipa::ToSchema, Clone, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum RegistrationMode {
+    /// Everyone who can authenticate gets an account.
+    #[default]
+    Open,
+    /// Only existing users can log in.
+    Closed,
+    /// Only users with an allowed email domain or IdP group. Not available with the local backend.
+    Allowlist,
+    /// Only users with an invite code from an administrator.
+    Invite,
+}
+
+/// Where icons of websites come from.
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum IconProviderKind {
+    /// Discover the icons the website itself offers.
+    Direct,
+    /// Ask the self-hosted icon service at `--icon-service-url`.
+    Service,
+    /// Icons of well known issuers compiled into the server, which work offline.
+    Bundled,
+}
+
 #[derive(Parser)]
 #[command(version, about, author)]
 pub struct Cli {
@@ -35,6 +75,8 @@ pub struct Cli {
     pub logging: Option<LoggingLevel>,
 }
 
+// Only constructed once, so the size of `Serve` does not matter
+#[allow(clippy::large_enum_variant)]
 #[derive(Subcommand)]
 pub enum Commands {
     Serve {
@@ -46,20 +88,30 @@ pub enum Commands {
         #[arg(long, env = "ICEBLINK_JWT_SECRET")]
         jwt_secret: String,
 
-        /// OAuth client id.
+        /// Authentication backend to use. Default is openid.
+        /// The OAuth options are ignored when using the local backend.
+        #[arg(long, env = "ICEBLINK_AUTH_BACKEND")]
+        auth_backend: Option<AuthBackend>,
+
+        /// JSON file listing the OpenId providers to allow.
+        /// When set, the single-provider OAuth options are ignored.
+        #[arg(long, env = "ICEBLINK_OAUTH_PROVIDERS")]
+        providers: Option<PathBuf>,
+
+        /// OAuth client id of the `default` provider.
         #[arg(long, env = "ICEBLINK_OAUTH_CLIENT_ID")]
-        client_id: String,
+        client_id: Option<String>,
 
-        /// OAuth client secret.
+        /// OAuth client secret of the `default` provider.
         #[arg(long, env = "ICEBLINK_OAUTH_CLIENT_SECRET")]
-        client_secret: String,
+        client_secret: Option<String>,
 
-        /// Redirect URI for OAuth.
-        /// Example: https://iceblink.snowflake.blue/v1/oauth.
+        /// Redirect URI for OAuth of the `default` provider.
+        /// Example: https://iceblink.snowflake.blue/v1/oauth/default.
         #[arg(long, env = "ICEBLINK_OAUTH_REDIRECT_URI")]
-        redirect_uri: String,
+        redirect_uri: Option<String>,
 
-        /// OAuth server with OIDC located at /.well-known/openid-configuration.
+        /// OAuth server of the `default` provider, with OIDC located at /.well-known/openid-configuration.
         /// Do not include a trailing slash.
         /// Defaults to https://pfapi.snowflake.blue.
         #[arg(long, env = "ICEBLINK_OAUTH_SERVER")]
@@ -67,13 +119,228 @@ pub enum Commands {
 
         /// URL of itself after passing through a possible reverse proxy.
         /// Should not have a trailing slash.
-        /// Used for CORS.
+        /// Used for CORS, and as WebAuthn origin with the local backend.
         /// Defaults to http://localhost:8085.
         #[arg(long, env = "ICEBLINK_URL")]
         frontfacing: Option<String>,
+
+        /// Maximum amount of codes per user, and per vault. Unlimited by default.
+        #[arg(long, env = "ICEBLINK_MAX_CODES")]
+        max_codes: Option<i64>,
+
+        /// Maximum length of the content of a code, in characters. Default is 2048.
+        #[arg(long, env = "ICEBLINK_MAX_CONTENT_LENGTH")]
+        max_content_length: Option<usize>,
+
+        /// Maximum length of the display name of a code, in characters. Default is 256.
+        #[arg(long, env = "ICEBLINK_MAX_DISPLAY_NAME_LENGTH")]
+        max_display_name_length: Option<usize>,
+
+        /// Who may create a new account. Default is open.
+        #[arg(long, env = "ICEBLINK_REGISTRATION")]
+        registration: Option<RegistrationMode>,
+
+        /// Comma separated email domains allowed to register with the allowlist mode.
+        #[arg(
+            long,
+            env = "ICEBLINK_REGISTRATION_EMAIL_DOMAINS",
+            value_delimiter = ','
+        )]
+        registration_email_domains: Vec<String>,
+
+        /// Comma separated IdP groups, from the `groups` claim, allowed to register with the allowlist mode.
+        #[arg(long, env = "ICEBLINK_REGISTRATION_GROUPS", value_delimiter = ',')]
+        registration_groups: Vec<String>,
+
+        /// Directory to cache icons of websites in. Defaults to iceblink-icons next to the database.
+        #[arg(long, env = "ICEBLINK_ICON_CACHE_DIR")]
+        icon_cache_dir: Option<PathBuf>,
+
+        /// Days until an icon is fetched from the website again. Default is 30.
+        #[arg(long, env = "ICEBLINK_ICON_CACHE_TTL_DAYS")]
+        icon_cache_ttl_days: Option<i64>,
+
+        /// Total size of the icon cache in megabytes, before the least recently used icons are evicted.
+        /// Default is 256.
+        #[arg(long, env = "ICEBLINK_ICON_CACHE_MAX_SIZE")]
+        icon_cache_max_size: Option<u64>,
+
+        /// Comma separated hosts, IP addresses and networks (e.g. 10.0.0.0/8) icons may be fetched from, on any
+        /// port. Private and loopback addresses are blocked otherwise.
+        #[arg(long, env = "ICEBLINK_ICON_FETCH_ALLOWLIST", value_delimiter = ',')]
+        icon_fetch_allowlist: Vec<String>,
+
+        /// Comma separated icon providers, asked in order until one finds an icon. Air-gapped installs may use
+        /// bundled, optionally after service.
+        #[arg(
+            long,
+            env = "ICEBLINK_ICON_PROVIDERS",
+            value_delimiter = ',',
+            default_value = "direct"
+        )]
+        icon_providers: Vec<IconProviderKind>,
+
+        /// URL of a self-hosted icon service, for the service provider. `{domain}` is replaced with the domain
+        /// of the website, e.g. `https://icons.example.com/{domain}.png`.
+        #[arg(long, env = "ICEBLINK_ICON_SERVICE_URL")]
+        icon_service_url: Option<String>,
+    },
+    /// Grants the administrator role to a user, e.g. to bootstrap the first administrator.
+    Admin {
+        /// Id or username of the user.
+        user: String,
+
+        /// Revoke the administrator role instead.
+        #[arg(long)]
+        revoke: bool,
+    },
+    /// Removes every cached icon. Running servers should be purged through the administration API instead.
+    PurgeIcons {
+        /// Directory of the icon cache. Defaults to iceblink-icons next to the database.
+        #[arg(long, env = "ICEBLINK_ICON_CACHE_DIR")]
+        icon_cache_dir: Option<PathBuf>,
+    },
+    /// Creates an invite code for the invite registration mode, and prints it.
+    Invite {
+        /// Days until the invite expires. Never expires by default.
+        #[arg(long)]
+        expires_in_days: Option<i64>,
     },
 }
 
 pub fn get_settings() -> Cli {
     Cli::parse()
 }
+
+#[derive(Debug)]
+pub enum ProvidersFileError {
+    UnableToRead(std::io::Error),
+    UnableToParse(serde_json::Error),
+    DuplicateId(String),
+}
+
+/// Reads the OpenId provider registry from a JSON file containing a list of providers.
+pub fn read_providers(path: &Path) -> Result<Vec<ProviderOptions>, ProvidersFileError> {
+    let content = std::fs::read_to_string(path).map_err(ProvidersFileError::UnableToRead)?;
+    parse_providers(&content)
+}
+
+fn parse_providers(content: &str) -> Result<Vec<ProviderOptions>, ProvidersFileError> {
+    let providers: Vec<ProviderOptions> =
+        serde_json::from_str(content).map_err(ProvidersFileError::UnableToParse)?;
+
+    for (i, provider) in providers.iter().enumerate() {
+        if providers[..i].iter().any(|p| p.id == provider.id) {
+            return Err(ProvidersFileError::DuplicateId(provider.id.clone()));
+        }
+    }
+
+    Ok(providers)
+}
+
+#[derive(Debug)]
+pub enum IconProvidersError {
+    /// The service provider is used without `--icon-service-url`.
+    MissingServiceUrl,
+    InvalidServiceUrl(url::ParseError),
+}
+
+impl std::fmt::Display for IconProvidersError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IconProvidersError::MissingServiceUrl => {
+                write!(f, "The service icon provider requires --icon-service-url")
+            }
+            IconProvidersError::InvalidServiceUrl(err) => {
+                write!(f, "Invalid icon service URL: {err}")
+            }
+        }
+    }
+}
+
+impl std::error::Error for IconProvidersError {}
+
+/// Checks the configured icon providers, e.g. that the icon service URL is valid.
+pub fn parse_icon_providers(
+    kinds: &[IconProviderKind],
+    service_url: Option<&str>,
+) -> Result<Vec<IconProviderOptions>, IconProvidersError> {
+    kinds
+        .iter()
+        .map(|kind| match kind {
+            IconProviderKind::Direct => Ok(IconProviderOptions::Direct),
+            IconProviderKind::Service => {
+                let template = service_url.ok_or(IconProvidersError::MissingServiceUrl)?;
+                ServiceProvider::new(template.to_string())
+                    .map(IconProviderOptions::Service)
+                    .map_err(IconProvidersError::InvalidServiceUrl)
+            }
+            IconProviderKind::Bundled => Ok(IconProviderOptions::Bundled),
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googletest::prelude::*;
+
+    #[gtest]
+    fn parse_multiple_providers() {
+        let providers = parse_providers(
+            r#"[
+                {"id": "google", "name": "Google", "client_id": "a", "client_secret": "b", "server": "https://accounts.google.com", "redirect_uri": "http://localhost:8085/v1/oauth/google"},
+                {"id": "keycloak", "name": "Keycloak", "client_id": "c", "client_secret": "d", "server": "https://sso.example.com/realms/main", "redirect_uri": "http://localhost:8085/v1/oauth/keycloak"}
+            ]"#,
+        )
+        .unwrap();
+
+        assert_that!(providers.len(), eq(2));
+        assert_that!(providers[0].id, eq("google"));
+        assert_that!(
+            providers[1].server,
+            eq("https://sso.example.com/realms/main")
+        );
+    }
+
+    #[gtest]
+    fn parse_providers_rejects_duplicate_ids() {
+        let result = parse_providers(
+            r#"[
+                {"id": "google", "name": "Google", "client_id": "a", "client_secret": "b", "server": "x", "redirect_uri": "y"},
+                {"id": "google", "name": "Google 2", "client_id": "c", "client_secret": "d", "server": "x", "redirect_uri": "y"}
+            ]"#,
+        );
+
+        assert_that!(
+            result,
+            err(matches_pattern!(ProvidersFileError::DuplicateId(eq(
+                "google"
+            ))))
+        );
+    }
+
+    #[gtest]
+    fn parse_icon_providers_checks_service_url() {
+        let kinds = [IconProviderKind::Bundled, IconProviderKind::Service];
+
+        expect_that!(
+            parse_icon_providers(&kinds, None),
+            err(matches_pattern!(IconProvidersError::MissingServiceUrl))
+        );
+        expect_that!(
+            parse_icon_providers(&kinds, Some("icons/{domain}.png")),
+            err(matches_pattern!(IconProvidersError::InvalidServiceUrl(_)))
+        );
+        expect_that!(
+            parse_icon_providers(&kinds, Some("https://icons.example.com/{domain}.png")),
+            ok(len(eq(2)))
+        );
+
+        // The URL is only needed for the service provider
+        expect_that!(
+            parse_icon_providers(&[IconProviderKind::Direct], None),
+            ok(len(eq(1)))
+        );
+    }
+}
@@ -0,0 +1,156 @@
+use super::IconMetadata;
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// Websites without a usable icon are asked again after this long, doubling after every failure up to the
+/// maximum.
+pub const NEGATIVE_TTL: Duration = Duration::hours(1);
+pub const MAX_NEGATIVE_TTL: Duration = Duration::days(7);
+
+/// What the store knows about a domain, keyed by the hash of the domain.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CacheEntry {
+    /// `None` when the website had no usable icon.
+    pub icon: Option<IconMetadata>,
+    pub checked_at: DateTime<Utc>,
+    /// Bytes on disk, of every size and format together.
+    pub size: u64,
+    pub last_accessed: DateTime<Utc>,
+    /// Failed attempts in a row, reset once an icon is found.
+    #[serde(default)]
+    pub failures: u32,
+}
+
+impl CacheEntry {
+    pub fn is_fresh(&self, now: DateTime<Utc>, ttl: Duration) -> bool {
+        let ttl = match self.icon {
+            Some(_) => ttl,
+            None => {
+                let doublings = self.failures.saturating_sub(1).min(16);
+                (NEGATIVE_TTL * 2_i32.pow(doublings)).min(MAX_NEGATIVE_TTL)
+            }
+        };
+
+        self.checked_at + ttl > now
+    }
+}
+
+/// Index of the cached icons, persisted as JSON in the cache directory.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct CacheIndex {
+    entries: HashMap<String, CacheEntry>,
+}
+
+impl CacheIndex {
+    /// Looks up an entry, marking it as used.
+    pub fn get(&mut self, key: &str, now: DateTime<Utc>) -> Option<CacheEntry> {
+        let entry = self.entries.get_mut(key)?;
+        entry.last_accessed = now;
+        Some(entry.clone())
+    }
+
+    pub fn peek(&self, key: &str) -> Option<&CacheEntry> {
+        self.entries.get(key)
+    }
+
+    pub fn insert(&mut self, key: String, entry: CacheEntry) {
+        self.entries.insert(key, entry);
+    }
+
+    pub fn remove(&mut self, key: &str) -> Option<CacheEntry> {
+        self.entries.remove(key)
+    }
+
+    pub fn contains(&self, key: &str) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.entries.keys().cloned().collect()
+    }
+
+    pub fn total_size(&self) -> u64 {
+        self.entries.values().map(|entry| entry.size).sum()
+    }
+
+    /// Drops the least recently used icons until the total size fits, except `keep`. Returns the dropped keys.
+    pub fn evict(&mut self, max_size: u64, keep: &str) -> Vec<String> {
+        let mut candidates: Vec<(String, DateTime<Utc>)> = self
+            .entries
+            .iter()
+            .filter(|(key, entry)| *key != keep && entry.size > 0)
+            .map(|(key, entry)| (key.clone(), entry.last_accessed))
+            .collect();
+        candidates.sort_by_key(|(_, last_accessed)| *last_accessed);
+
+        let mut total = self.total_size();
+        let mut evicted = vec![];
+        for (key, _) in candidates {
+            if total <= max_size {
+                break;
+            }
+
+            if let Some(entry) = self.entries.remove(&key) {
+                total -= entry.size;
+                evicted.push(key);
+            }
+        }
+
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googletest::prelude::*;
+
+    fn entry(size: u64, last_accessed: DateTime<Utc>) -> CacheEntry {
+        CacheEntry {
+            icon: None,
+            checked_at: last_accessed,
+            size,
+            last_accessed,
+            failures: 1,
+        }
+    }
+
+    #[gtest]
+    fn evicts_least_recently_used() {
+        let now = Utc::now();
+        let mut index = CacheIndex::default();
+        index.insert("old".into(), entry(40, now - Duration::hours(3)));
+        index.insert("older".into(), entry(40, now - Duration::hours(4)));
+        index.insert("recent".into(), entry(40, now - Duration::hours(1)));
+        index.insert("negative".into(), entry(0, now - Duration::days(2)));
+        index.insert("new".into(), entry(40, now - Duration::days(5)));
+
+        // Using an icon makes it recent again
+        index.get("older", now);
+
+        expect_that!(
+            index.evict(100, "new"),
+            elements_are![eq("old"), eq("recent")]
+        );
+        expect_that!(index.total_size(), eq(80));
+        expect_that!(index.contains("negative"), eq(true));
+        expect_that!(index.evict(100, "new"), empty());
+    }
+
+    #[gtest]
+    fn negative_entries_back_off() {
+        let now = Utc::now();
+        let ttl = Duration::days(30);
+        let mut negative = entry(0, now - Duration::minutes(90));
+        expect_that!(negative.is_fresh(now, ttl), eq(false));
+
+        negative.failures = 2;
+        expect_that!(negative.is_fresh(now, ttl), eq(true));
+
+        negative.checked_at = now - Duration::days(8);
+        negative.failures = 100;
+        expect_that!(negative.is_fresh(now, ttl), eq(false));
+    }
+}
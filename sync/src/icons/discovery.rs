@@ -0,0 +1,312 @@
+use scraper::{Html, Selector};
+use serde::Deserialize;
+use std::collections::HashSet;
+use url::Url;
+
+/// Icons around this size look sharp in the apps, without wasting bandwidth on huge ones.
+pub const PREFERRED_SIZE: u32 = 128;
+
+/// Size Apple assumes for touch icons without a `sizes` attribute.
+const APPLE_TOUCH_ICON_SIZE: u32 = 180;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IconSize {
+    /// Vector images, e.g. SVGs or `sizes="any"`.
+    Scalable,
+    /// The largest dimension in pixels.
+    Pixels(u32),
+    Unknown,
+}
+
+/// An icon a website offers, which may still turn out to be missing or not an image.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Candidate {
+    pub url: Url,
+    pub size: IconSize,
+}
+
+impl Candidate {
+    /// Lower is better. Prefers vectors, then the smallest icon of at least the preferred size, then the largest
+    /// smaller one.
+    fn rank(&self) -> (u8, u32) {
+        match self.size {
+            IconSize::Scalable => (0, 0),
+            IconSize::Pixels(px) if px >= PREFERRED_SIZE => (1, px - PREFERRED_SIZE),
+            IconSize::Pixels(px) => (2, PREFERRED_SIZE - px),
+            IconSize::Unknown => (3, 0),
+        }
+    }
+}
+
+/// Icons and the web app manifest linked from a page.
+#[derive(Debug, Default)]
+pub struct PageLinks {
+    pub icons: Vec<Candidate>,
+    pub manifest: Option<Url>,
+}
+
+fn parse_size(sizes: Option<&str>, mime: Option<&str>, url: &Url) -> IconSize {
+    let sizes = sizes.unwrap_or_default().to_ascii_lowercase();
+    if sizes.split_ascii_whitespace().any(|size| size == "any") {
+        return IconSize::Scalable;
+    }
+
+    let largest = sizes
+        .split_ascii_whitespace()
+        .filter_map(|size| {
+            let (width, height) = size.split_once('x')?;
+            Some(width.parse::<u32>().ok()?.max(height.parse().ok()?))
+        })
+        .max();
+
+    match largest {
+        Some(px) => IconSize::Pixels(px),
+        None if mime.is_some_and(|mime| mime.eq_ignore_ascii_case("image/svg+xml"))
+            || url.path().to_ascii_lowercase().ends_with(".svg") =>
+        {
+            IconSize::Scalable
+        }
+        None => IconSize::Unknown,
+    }
+}
+
+/// Only web URLs can be fetched, data URIs and the like are skipped.
+fn resolve(base: &Url, href: &str) -> Option<Url> {
+    base.join(href.trim())
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https"))
+}
+
+/// Finds `<link rel="icon">`, `apple-touch-icon` and `manifest` links, resolved against the page or its `<base>`.
+pub fn parse_html(html: &str, page_url: &Url) -> PageLinks {
+    let document = Html::parse_document(html);
+
+    let base = document
+        .select(&Selector::parse("base[href]").unwrap())
+        .next()
+        .and_then(|base| page_url.join(base.value().attr("href")?).ok())
+        .unwrap_or_else(|| page_url.clone());
+
+    let mut links = PageLinks::default();
+    for link in document.select(&Selector::parse("link[rel][href]").unwrap()) {
+        let element = link.value();
+        let Some(url) = element.attr("href").and_then(|href| resolve(&base, href)) else {
+            continue;
+        };
+
+        let rel = element.attr("rel").unwrap_or_default().to_ascii_lowercase();
+        let rels: Vec<&str> = rel.split_ascii_whitespace().collect();
+
+        if rels.contains(&"manifest") {
+            links.manifest.get_or_insert(url);
+        } else if rels.contains(&"icon") {
+            let size = parse_size(element.attr("sizes"), element.attr("type"), &url);
+            links.icons.push(Candidate { url, size });
+        } else if rels
+            .iter()
+            .any(|rel| matches!(*rel, "apple-touch-icon" | "apple-touch-icon-precomposed"))
+        {
+            let size = match parse_size(element.attr("sizes"), element.attr("type"), &url) {
+                IconSize::Unknown => IconSize::Pixels(APPLE_TOUCH_ICON_SIZE),
+                size => size,
+            };
+            links.icons.push(Candidate { url, size });
+        }
+    }
+
+    links
+}
+
+#[derive(Deserialize)]
+struct Manifest {
+    #[serde(default)]
+    icons: Vec<ManifestIcon>,
+}
+
+#[derive(Deserialize)]
+struct ManifestIcon {
+    src: String,
+    sizes: Option<String>,
+    #[serde(rename = "type")]
+    mime: Option<String>,
+    purpose: Option<String>,
+}
+
+/// Finds the icons of a web app manifest. Maskable and monochrome only icons are skipped, as they are padded or
+/// lack colour.
+pub fn parse_manifest(content: &[u8], manifest_url: &Url) -> Vec<Candidate> {
+    let Ok(manifest) = serde_json::from_slice::<Manifest>(content) else {
+        return vec![];
+    };
+
+    manifest
+        .icons
+        .into_iter()
+        .filter(|icon| {
+            icon.purpose.as_ref().is_none_or(|purpose| {
+                purpose
+                    .split_ascii_whitespace()
+                    .any(|purpose| purpose.eq_ignore_ascii_case("any"))
+            })
+        })
+        .filter_map(|icon| {
+            let url = resolve(manifest_url, &icon.src)?;
+            let size = parse_size(icon.sizes.as_deref(), icon.mime.as_deref(), &url);
+            Some(Candidate { url, size })
+        })
+        .collect()
+}
+
+/// Orders the candidates best first, with the fallback last, and drops duplicates.
+pub fn rank(mut candidates: Vec<Candidate>, fallback: Url) -> Vec<Candidate> {
+    candidates.sort_by_key(Candidate::rank);
+    candidates.push(Candidate {
+        url: fallback,
+        size: IconSize::Unknown,
+    });
+
+    let mut seen = HashSet::new();
+    candidates.retain(|candidate| seen.insert(candidate.url.clone()));
+    candidates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googletest::prelude::*;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[gtest]
+    fn html_links() {
+        let links = parse_html(
+            r#"<!DOCTYPE html>
+            <html>
+                <head>
+                    <link rel="shortcut icon" href="/favicon.ico">
+                    <link rel="icon" type="image/png" sizes="16x16 32x32" href="icons/small.png">
+                    <link rel="icon" type="image/svg+xml" href="//cdn.example.com/icon.svg">
+                    <link rel="apple-touch-icon" href="/apple.png">
+                    <link rel="mask-icon" href="/mask.svg">
+                    <link rel="icon" href="data:image/png;base64,iVBORw0KGgo=">
+                    <link rel="manifest" href="/site.webmanifest">
+                </head>
+            </html>"#,
+            &url("https://example.com/login/"),
+        );
+
+        expect_that!(
+            links.icons,
+            elements_are![
+                eq(&Candidate {
+                    url: url("https://example.com/favicon.ico"),
+                    size: IconSize::Unknown
+                }),
+                eq(&Candidate {
+                    url: url("https://example.com/login/icons/small.png"),
+                    size: IconSize::Pixels(32)
+                }),
+                eq(&Candidate {
+                    url: url("https://cdn.example.com/icon.svg"),
+                    size: IconSize::Scalable
+                }),
+                eq(&Candidate {
+                    url: url("https://example.com/apple.png"),
+                    size: IconSize::Pixels(APPLE_TOUCH_ICON_SIZE)
+                }),
+            ]
+        );
+        expect_that!(
+            links.manifest,
+            some(eq(&url("https://example.com/site.webmanifest")))
+        );
+    }
+
+    #[gtest]
+    fn html_base_element() {
+        let links = parse_html(
+            r#"<head><base href="https://static.example.com/assets/"><link rel="icon" href="icon.png"></head>"#,
+            &url("https://example.com/"),
+        );
+
+        expect_that!(
+            links.icons,
+            elements_are![eq(&Candidate {
+                url: url("https://static.example.com/assets/icon.png"),
+                size: IconSize::Unknown
+            })]
+        );
+    }
+
+    #[gtest]
+    fn manifest_icons() {
+        let icons = parse_manifest(
+            br#"{
+                "name": "Example",
+                "icons": [
+                    { "src": "/android-192.png", "sizes": "192x192", "type": "image/png" },
+                    { "src": "/maskable-512.png", "sizes": "512x512", "purpose": "maskable" },
+                    { "src": "512.png", "sizes": "512x512", "purpose": "any maskable" }
+                ]
+            }"#,
+            &url("https://example.com/static/manifest.json"),
+        );
+
+        expect_that!(
+            icons,
+            elements_are![
+                eq(&Candidate {
+                    url: url("https://example.com/android-192.png"),
+                    size: IconSize::Pixels(192)
+                }),
+                eq(&Candidate {
+                    url: url("https://example.com/static/512.png"),
+                    size: IconSize::Pixels(512)
+                }),
+            ]
+        );
+        expect_that!(
+            parse_manifest(b"<html></html>", &url("https://example.com/")),
+            empty()
+        );
+    }
+
+    #[gtest]
+    fn rank_prefers_close_sizes() {
+        let candidate = |path: &str, size| Candidate {
+            url: url("https://example.com/").join(path).unwrap(),
+            size,
+        };
+
+        let ranked = rank(
+            vec![
+                candidate("/unknown.png", IconSize::Unknown),
+                candidate("/16.png", IconSize::Pixels(16)),
+                candidate("/512.png", IconSize::Pixels(512)),
+                candidate("/64.png", IconSize::Pixels(64)),
+                candidate("/180.png", IconSize::Pixels(180)),
+                candidate("/icon.svg", IconSize::Scalable),
+                candidate("/favicon.ico", IconSize::Unknown),
+            ],
+            url("https://example.com/favicon.ico"),
+        );
+
+        expect_that!(
+            ranked
+                .iter()
+                .map(|candidate| candidate.url.path())
+                .collect::<Vec<_>>(),
+            elements_are![
+                eq(&"/icon.svg"),
+                eq(&"/180.png"),
+                eq(&"/512.png"),
+                eq(&"/64.png"),
+                eq(&"/16.png"),
+                eq(&"/unknown.png"),
+                eq(&"/favicon.ico"),
+            ]
+        );
+    }
+}
@@ -0,0 +1,98 @@
+use super::{
+    format::{self, IconFormat},
+    guard::{Allowlist, Guard},
+    IconStoreError, MAX_ICON_SIZE,
+};
+use crate::utils;
+use reqwest::header;
+use tracing::debug;
+use url::Url;
+
+/// Contacts websites on behalf of the providers, refusing private destinations.
+#[derive(Debug, Clone)]
+pub struct Fetcher {
+    scheme: &'static str,
+    client: reqwest::Client,
+    guard: Guard,
+}
+
+impl Default for Fetcher {
+    fn default() -> Self {
+        Self::new(Allowlist::default())
+    }
+}
+
+impl Fetcher {
+    pub fn new(allowlist: Allowlist) -> Self {
+        let guard = Guard::new(allowlist);
+        Fetcher {
+            scheme: "https",
+            client: guard.client(utils::USER_AGENT),
+            guard,
+        }
+    }
+
+    /// Contacts websites over plain HTTP instead of HTTPS, e.g. to test against a local server.
+    pub fn with_http(mut self) -> Self {
+        self.scheme = "http";
+        self
+    }
+
+    pub fn with_allowlist(self, allowlist: Allowlist) -> Self {
+        Fetcher {
+            scheme: self.scheme,
+            ..Self::new(allowlist)
+        }
+    }
+
+    /// The front page of a domain.
+    pub fn site(&self, domain: &str) -> Result<Url, IconStoreError> {
+        Url::parse(&format!("{}://{domain}/", self.scheme))
+            .map_err(|_| IconStoreError::InvalidDomain)
+    }
+
+    /// Requests a URL, unless it points somewhere private.
+    pub async fn get(&self, url: &Url, accept: &str) -> Result<reqwest::Response, IconStoreError> {
+        if !self.guard.allows_url(url) {
+            debug!("Blocked request to {url}");
+            return Err(IconStoreError::BlockedDestination);
+        }
+
+        self.client
+            .get(url.clone())
+            .header(header::ACCEPT, accept)
+            .send()
+            .await
+            .map_err(|_| IconStoreError::UnableToSendRequest)
+    }
+
+    /// Fetches an icon, returning `None` for anything which is not an image, e.g. error pages served with 200 OK.
+    pub async fn fetch_icon(&self, url: &Url) -> Option<(Vec<u8>, IconFormat)> {
+        let response = self.get(url, "image/*").await.ok()?;
+        if !response.status().is_success() {
+            return None;
+        }
+
+        let content = read_up_to(response, MAX_ICON_SIZE + 1).await?;
+        if content.len() > MAX_ICON_SIZE {
+            return None;
+        }
+
+        let format = format::sniff(&content)?;
+        Some((content, format))
+    }
+}
+
+/// Reads at most `limit` bytes of the body, cutting off the rest instead of buffering huge responses.
+pub async fn read_up_to(mut response: reqwest::Response, limit: usize) -> Option<Vec<u8>> {
+    let mut content = Vec::new();
+    while content.len() < limit {
+        match response.chunk().await.ok()? {
+            Some(chunk) => content.extend_from_slice(&chunk),
+            None => return Some(content),
+        }
+    }
+
+    content.truncate(limit);
+    Some(content)
+}
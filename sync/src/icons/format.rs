@@ -0,0 +1,80 @@
+/// Image formats accepted as icons, recognised by their content rather than what the server claims.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IconFormat {
+    Ico,
+    Png,
+    Jpeg,
+    Gif,
+    Webp,
+    Bmp,
+    Svg,
+}
+
+impl IconFormat {
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            IconFormat::Ico => "image/x-icon",
+            IconFormat::Png => "image/png",
+            IconFormat::Jpeg => "image/jpeg",
+            IconFormat::Gif => "image/gif",
+            IconFormat::Webp => "image/webp",
+            IconFormat::Bmp => "image/bmp",
+            IconFormat::Svg => "image/svg+xml",
+        }
+    }
+}
+
+/// Sniffs the format of an image from its first bytes. HTML error pages and other non-images return `None`.
+pub fn sniff(content: &[u8]) -> Option<IconFormat> {
+    match content {
+        [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, ..] => Some(IconFormat::Png),
+        [0x00, 0x00, 0x01, 0x00, ..] => Some(IconFormat::Ico),
+        [0xFF, 0xD8, 0xFF, ..] => Some(IconFormat::Jpeg),
+        [b'G', b'I', b'F', b'8', b'7' | b'9', b'a', ..] => Some(IconFormat::Gif),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some(IconFormat::Webp),
+        [b'B', b'M', ..] => Some(IconFormat::Bmp),
+        _ if is_svg(content) => Some(IconFormat::Svg),
+        _ => None,
+    }
+}
+
+/// SVGs are text, so look for the root element near the start while rejecting HTML documents.
+fn is_svg(content: &[u8]) -> bool {
+    let head = String::from_utf8_lossy(&content[..content.len().min(1024)]).to_ascii_lowercase();
+    let head = head.trim_start_matches('\u{feff}').trim_start();
+
+    head.starts_with('<') && head.contains("<svg") && !head.contains("<html")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googletest::prelude::*;
+
+    #[gtest]
+    fn sniff_images() {
+        expect_that!(
+            sniff(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"),
+            some(eq(IconFormat::Png))
+        );
+        expect_that!(sniff(b"\0\0\x01\0\x01\0"), some(eq(IconFormat::Ico)));
+        expect_that!(sniff(b"\xff\xd8\xff\xe0"), some(eq(IconFormat::Jpeg)));
+        expect_that!(sniff(b"GIF89a\x01\0"), some(eq(IconFormat::Gif)));
+        expect_that!(sniff(b"RIFF\x24\0\0\0WEBPVP8 "), some(eq(IconFormat::Webp)));
+        expect_that!(
+            sniff(b"<?xml version=\"1.0\"?>\n<svg xmlns=\"http://www.w3.org/2000/svg\"/>"),
+            some(eq(IconFormat::Svg))
+        );
+    }
+
+    #[gtest]
+    fn sniff_rejects_documents() {
+        expect_that!(sniff(b""), none());
+        expect_that!(
+            sniff(b"<!DOCTYPE html><html><body><svg></svg></body></html>"),
+            none()
+        );
+        expect_that!(sniff(b"{\"error\": \"Not found\"}"), none());
+        expect_that!(sniff(b"Not found"), none());
+    }
+}
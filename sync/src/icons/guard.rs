@@ -0,0 +1,255 @@
+use ipnet::IpNet;
+use reqwest::{
+    dns::{Addrs, Name, Resolve, Resolving},
+    redirect,
+};
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    sync::Arc,
+    time::Duration,
+};
+use url::{Host, Url};
+
+/// Websites are only contacted on the default ports, unless allowlisted.
+const ALLOWED_PORTS: [u16; 2] = [80, 443];
+const MAX_REDIRECTS: usize = 5;
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Hosts and networks which may be contacted despite being private, on any port. E.g. an intranet.
+#[derive(Debug, Clone, Default)]
+pub struct Allowlist {
+    hosts: Vec<String>,
+    networks: Vec<IpNet>,
+}
+
+impl Allowlist {
+    /// Entries are host names, IP addresses or networks in CIDR notation, e.g. `10.0.0.0/8`.
+    pub fn new(entries: impl IntoIterator<Item = impl AsRef<str>>) -> Self {
+        let mut allowlist = Allowlist::default();
+        for entry in entries {
+            let entry = entry.as_ref().trim();
+            if let Ok(network) = entry.parse::<IpNet>() {
+                allowlist.networks.push(network);
+            } else if let Ok(ip) = entry.parse::<IpAddr>() {
+                allowlist.networks.push(ip.into());
+            } else if !entry.is_empty() {
+                allowlist
+                    .hosts
+                    .push(entry.trim_end_matches('.').to_ascii_lowercase());
+            }
+        }
+
+        allowlist
+    }
+
+    fn allows_host(&self, host: &str) -> bool {
+        let host = host.trim_end_matches('.');
+        self.hosts
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(host))
+    }
+
+    fn allows_ip(&self, ip: IpAddr) -> bool {
+        self.networks.iter().any(|network| network.contains(&ip))
+    }
+}
+
+/// Whether an address is reachable over the internet, rather than loopback, private, link-local (e.g. cloud
+/// metadata services) or otherwise reserved.
+pub fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, c, _] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_documentation()
+                || ip.is_multicast()
+                // This network
+                || a == 0
+                // Shared address space of carrier-grade NAT
+                || (a == 100 && (64..128).contains(&b))
+                // IETF protocol assignments
+                || (a == 192 && b == 0 && c == 0)
+                // Benchmarking
+                || (a == 198 && (18..20).contains(&b))
+                // Reserved
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => {
+            if let Some(ip) = ip.to_ipv4_mapped() {
+                return is_public(ip.into());
+            }
+
+            let segments = ip.segments();
+            // 6to4 embeds an IPv4 address
+            if segments[0] == 0x2002 {
+                let [a, b] = segments[1].to_be_bytes();
+                let [c, d] = segments[2].to_be_bytes();
+                return is_public(Ipv4Addr::new(a, b, c, d).into());
+            }
+
+            !(ip.is_multicast()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local()
+                // Unspecified, loopback and IPv4-compatible
+                || segments[..6] == [0; 6]
+                // NAT64, which may reach private IPv4 addresses
+                || (segments[0] == 0x64 && segments[1] == 0xff9b)
+                // Documentation
+                || (segments[0] == 0x2001 && segments[1] == 0xdb8))
+        }
+    }
+}
+
+/// Keeps icon fetching from reaching the server itself or its private network, as the websites are chosen by
+/// users. URLs are checked before every request and redirect, and domains once resolved, so they can not
+/// sneak past by pointing at a private address.
+#[derive(Debug, Clone, Default)]
+pub struct Guard {
+    allowlist: Arc<Allowlist>,
+}
+
+impl Guard {
+    pub fn new(allowlist: Allowlist) -> Self {
+        Guard {
+            allowlist: Arc::new(allowlist),
+        }
+    }
+
+    pub fn allows_url(&self, url: &Url) -> bool {
+        if !matches!(url.scheme(), "http" | "https") {
+            return false;
+        }
+
+        let ip = match url.host() {
+            Some(Host::Domain(domain)) if self.allowlist.allows_host(domain) => return true,
+            Some(Host::Domain(_)) => None,
+            Some(Host::Ipv4(ip)) => Some(IpAddr::V4(ip)),
+            Some(Host::Ipv6(ip)) => Some(IpAddr::V6(ip)),
+            None => return false,
+        };
+        if ip.is_some_and(|ip| self.allowlist.allows_ip(ip)) {
+            return true;
+        }
+
+        // Domains are checked by the resolver, as they may point anywhere
+        ip.is_none_or(is_public)
+            && url
+                .port_or_known_default()
+                .is_some_and(|port| ALLOWED_PORTS.contains(&port))
+    }
+
+    /// An HTTP client which only connects to allowed addresses.
+    pub fn client(&self, user_agent: &str) -> reqwest::Client {
+        let guard = self.clone();
+        let redirects = redirect::Policy::custom(move |attempt| {
+            if attempt.previous().len() >= MAX_REDIRECTS {
+                attempt.error("too many redirects")
+            } else if !guard.allows_url(attempt.url()) {
+                attempt.error("redirected to a blocked destination")
+            } else {
+                attempt.follow()
+            }
+        });
+
+        reqwest::Client::builder()
+            .user_agent(user_agent)
+            .connect_timeout(CONNECT_TIMEOUT)
+            .timeout(TIMEOUT)
+            .redirect(redirects)
+            // A proxy would resolve the domains itself, bypassing the resolver
+            .no_proxy()
+            .dns_resolver(Arc::new(self.clone()))
+            .build()
+            .unwrap()
+    }
+}
+
+impl Resolve for Guard {
+    fn resolve(&self, name: Name) -> Resolving {
+        let allowlist = self.allowlist.clone();
+        Box::pin(async move {
+            let host = name.as_str();
+            let addrs: Vec<_> = tokio::net::lookup_host((host, 0))
+                .await?
+                .filter(|addr| {
+                    allowlist.allows_host(host)
+                        || allowlist.allows_ip(addr.ip())
+                        || is_public(addr.ip())
+                })
+                .collect();
+
+            if addrs.is_empty() {
+                return Err(format!("{host} only resolves to blocked addresses").into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googletest::prelude::*;
+
+    #[gtest]
+    fn blocks_private_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.100.100.200",
+            "0.0.0.0",
+            "::1",
+            "::",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+            "2002:7f00:1::",
+            "64:ff9b::a00:1",
+        ] {
+            expect_that!(is_public(ip.parse().unwrap()), eq(false), "{ip}");
+        }
+
+        for ip in ["1.1.1.1", "93.184.215.14", "2606:4700:4700::1111"] {
+            expect_that!(is_public(ip.parse().unwrap()), eq(true), "{ip}");
+        }
+    }
+
+    #[gtest]
+    fn checks_urls() {
+        let guard = Guard::default();
+        let allows = |url: &str| guard.allows_url(&Url::parse(url).unwrap());
+
+        expect_that!(allows("https://example.com/favicon.ico"), eq(true));
+        expect_that!(allows("http://1.1.1.1/"), eq(true));
+        expect_that!(allows("https://example.com:8443/"), eq(false));
+        expect_that!(allows("ftp://example.com/"), eq(false));
+        expect_that!(allows("file:///etc/passwd"), eq(false));
+        expect_that!(allows("http://127.0.0.1/"), eq(false));
+        expect_that!(allows("http://[::1]/"), eq(false));
+        expect_that!(
+            allows("http://169.254.169.254/latest/meta-data/"),
+            eq(false)
+        );
+    }
+
+    #[gtest]
+    fn allowlist() {
+        let guard = Guard::new(Allowlist::new(["10.0.0.0/8", "::1", "Intranet.Local."]));
+        let allows = |url: &str| guard.allows_url(&Url::parse(url).unwrap());
+
+        expect_that!(allows("http://10.1.2.3:8080/"), eq(true));
+        expect_that!(allows("http://[::1]/"), eq(true));
+        expect_that!(allows("http://intranet.local:3000/"), eq(true));
+        expect_that!(allows("http://192.168.1.1/"), eq(false));
+        expect_that!(allows("gopher://intranet.local/"), eq(false));
+    }
+}
@@ -0,0 +1,694 @@
+pub mod cache;
+pub mod discovery;
+pub mod fetch;
+pub mod format;
+pub mod guard;
+pub mod normalize;
+pub mod pack;
+pub mod provider;
+
+use crate::utils;
+use cache::{CacheEntry, CacheIndex};
+use chrono::{DateTime, Utc};
+use fetch::Fetcher;
+use guard::Allowlist;
+use normalize::OutputFormat;
+use provider::{DirectProvider, IconProvider};
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{HashMap, HashSet},
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+use tracing::{debug, warn};
+
+const INDEX_FILE: &str = "index.json";
+/// Uploaded icons, in a directory per code.
+const CUSTOM_DIR: &str = "custom";
+
+/// Icons larger than this are refused, whether fetched or uploaded.
+pub const MAX_ICON_SIZE: usize = 1024 * 1024;
+
+pub const DEFAULT_TTL_DAYS: i64 = 30;
+pub const DEFAULT_MAX_SIZE: u64 = 256 * 1024 * 1024;
+/// Icons younger than this are never collected, as their code may have been created after the references
+/// were queried.
+pub const DEFAULT_GARBAGE_GRACE_PERIOD: chrono::Duration = chrono::Duration::hours(1);
+
+#[derive(Debug, Clone)]
+pub struct IconStore {
+    base: PathBuf,
+    fetcher: Fetcher,
+    /// Asked in order until one finds an icon.
+    providers: Arc<Vec<Box<dyn IconProvider>>>,
+    /// How long icons are used before asking the website again.
+    ttl: chrono::Duration,
+    /// Total bytes on disk before the least recently used icons are evicted.
+    max_size: u64,
+    garbage_grace_period: chrono::Duration,
+    index: Arc<Mutex<CacheIndex>>,
+    /// Serializes writes of the index file, so an older snapshot never overwrites a newer one.
+    save_lock: Arc<tokio::sync::Mutex<()>>,
+    /// Domains currently being gathered, so concurrent requests contact the website only once.
+    in_flight: Arc<Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>>,
+    stats: Arc<IconStats>,
+}
+
+/// Counters of how the store is used, exported as Prometheus metrics.
+#[derive(Debug, Default)]
+pub struct IconStats {
+    /// Icons served from the cache.
+    pub hits: AtomicU64,
+    /// Websites remembered to have no icon, which were not asked again.
+    pub negative_hits: AtomicU64,
+    /// Requests which had to gather the icon, as it was missing or expired.
+    pub misses: AtomicU64,
+    pub fetch_successes: AtomicU64,
+    pub fetch_failures: AtomicU64,
+    /// Gathers which waited for another one of the same domain, instead of contacting the website again.
+    pub coalesced: AtomicU64,
+}
+
+#[derive(Debug)]
+pub enum IconStoreError {
+    FileSystemFailToRead,
+    FileSystemFailToWrite,
+    InvalidDomain,
+    UnableToSendRequest,
+    UnableToParseResponse,
+    NoIconFound,
+    /// An uploaded icon is larger than `MAX_ICON_SIZE`.
+    IconTooLarge,
+    /// An uploaded icon is not an image, or can not be decoded.
+    InvalidIcon,
+    /// The website is private, e.g. the server itself or its network, and not allowlisted.
+    BlockedDestination,
+}
+
+/// Where a stored icon came from.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct IconMetadata {
+    pub source_url: String,
+    /// Content type of the original icon, before it was normalized.
+    pub source_content_type: String,
+    /// Dimensions of the original icon. Vectors are rasterized at the largest standard size first.
+    pub source_width: u32,
+    pub source_height: u32,
+    pub fetched_at: DateTime<Utc>,
+    /// Name of the provider which found the icon.
+    #[serde(default)]
+    pub provider: String,
+}
+
+impl Default for IconStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Whether a file in the cache directory was created by the store, so nothing else is ever deleted.
+fn is_cache_file(name: &str) -> bool {
+    if name == INDEX_FILE || name.starts_with(&format!(".{INDEX_FILE}-")) {
+        return true;
+    }
+
+    let hash = name.trim_start_matches('.');
+    let hash = hash.split_once(['-', '.']).map_or(hash, |(hash, _)| hash);
+    hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+impl IconStore {
+    pub fn new() -> Self {
+        Self::new_with_custom_base(
+            std::env::temp_dir().join("iceblink-".to_string() + &utils::generate_id(5)),
+        )
+    }
+
+    pub fn new_with_custom_base(base: PathBuf) -> Self {
+        IconStore {
+            base,
+            fetcher: Fetcher::default(),
+            providers: Arc::new(vec![Box::new(DirectProvider)]),
+            ttl: chrono::Duration::days(DEFAULT_TTL_DAYS),
+            max_size: DEFAULT_MAX_SIZE,
+            garbage_grace_period: DEFAULT_GARBAGE_GRACE_PERIOD,
+            index: Arc::default(),
+            save_lock: Arc::default(),
+            in_flight: Arc::default(),
+            stats: Arc::default(),
+        }
+    }
+
+    /// Contacts websites over plain HTTP instead of HTTPS, e.g. to test against a local server.
+    pub fn with_http(mut self) -> Self {
+        self.fetcher = self.fetcher.with_http();
+        self
+    }
+
+    /// Allows contacting private hosts and networks, which are blocked by default.
+    pub fn with_allowlist(mut self, allowlist: Allowlist) -> Self {
+        self.fetcher = self.fetcher.with_allowlist(allowlist);
+        self
+    }
+
+    /// Replaces the providers, which default to only asking the website directly.
+    pub fn with_providers(mut self, providers: Vec<Box<dyn IconProvider>>) -> Self {
+        self.providers = Arc::new(providers);
+        self
+    }
+
+    pub fn with_ttl(mut self, ttl: chrono::Duration) -> Self {
+        self.ttl = ttl;
+        self
+    }
+
+    pub fn with_max_size(mut self, max_size: u64) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    pub fn with_garbage_grace_period(mut self, grace_period: chrono::Duration) -> Self {
+        self.garbage_grace_period = grace_period;
+        self
+    }
+
+    /// Every domain gets a directory, with a file per size and format.
+    fn get_dir(&self, key: &str) -> PathBuf {
+        self.base.join(key)
+    }
+
+    fn get_path(&self, key: &str, size: u32, format: OutputFormat) -> PathBuf {
+        self.get_dir(key)
+            .join(format!("{size}.{}", format.extension()))
+    }
+
+    /// Creates the cache directory and loads its index. Icons missing from the index, e.g. after a crash, and
+    /// index entries without their icon are dropped.
+    pub async fn init(&self) -> Result<&Self, IconStoreError> {
+        tokio::fs::create_dir_all(&self.base)
+            .await
+            .map_err(|_| IconStoreError::FileSystemFailToWrite)?;
+
+        let mut index: CacheIndex = match tokio::fs::read(self.base.join(INDEX_FILE)).await {
+            Ok(content) => serde_json::from_slice(&content).unwrap_or_else(|_| {
+                warn!("Icon cache index is corrupt, starting over");
+                CacheIndex::default()
+            }),
+            Err(_) => CacheIndex::default(),
+        };
+
+        for key in index.keys() {
+            let missing = index.peek(&key).is_some_and(|entry| entry.icon.is_some())
+                && !tokio::fs::try_exists(self.get_dir(&key))
+                    .await
+                    .unwrap_or(false);
+            if missing {
+                index.remove(&key);
+            }
+        }
+
+        let mut files = tokio::fs::read_dir(&self.base)
+            .await
+            .map_err(|_| IconStoreError::FileSystemFailToRead)?;
+        while let Ok(Some(file)) = files.next_entry().await {
+            let name = file.file_name().to_string_lossy().to_string();
+            if name != INDEX_FILE && is_cache_file(&name) && !index.contains(&name) {
+                remove_path(&file.path()).await?;
+            }
+        }
+
+        *self.index.lock().unwrap() = index;
+        self.save().await?;
+
+        Ok(self)
+    }
+
+    /// Writes the index to disk, including when icons were last used.
+    pub async fn save(&self) -> Result<(), IconStoreError> {
+        let _guard = self.save_lock.lock().await;
+        let content = serde_json::to_vec(&*self.index.lock().unwrap()).unwrap();
+
+        let staging = self
+            .base
+            .join(format!(".{INDEX_FILE}-{}", utils::generate_id(5)));
+        let write = async {
+            tokio::fs::write(&staging, content).await?;
+            tokio::fs::rename(&staging, self.base.join(INDEX_FILE)).await
+        };
+
+        if write.await.is_err() {
+            let _ = tokio::fs::remove_file(&staging).await;
+            return Err(IconStoreError::FileSystemFailToWrite);
+        }
+
+        Ok(())
+    }
+
+    /// Finds the icon of a domain at the standard size closest to the requested one. Icons are gathered when
+    /// missing or expired. Should refreshing fail, the previous icon keeps being used.
+    pub async fn find_or_gather(
+        &self,
+        domain: &str,
+        size: u32,
+        format: OutputFormat,
+    ) -> Result<Vec<u8>, IconStoreError> {
+        let key = utils::hash_domain(domain);
+        let path = self.get_path(&key, normalize::standard_size(size), format);
+        let entry = self.index.lock().unwrap().get(&key, Utc::now());
+
+        if let Some(entry) = &entry {
+            if entry.is_fresh(Utc::now(), self.ttl) {
+                if entry.icon.is_none() {
+                    self.stats.negative_hits.fetch_add(1, Ordering::Relaxed);
+                    return Err(IconStoreError::NoIconFound);
+                }
+                if let Ok(content) = tokio::fs::read(&path).await {
+                    self.stats.hits.fetch_add(1, Ordering::Relaxed);
+                    return Ok(content);
+                }
+            }
+        }
+
+        self.stats.misses.fetch_add(1, Ordering::Relaxed);
+        if let Err(err) = self.gather(domain).await {
+            return match tokio::fs::read(&path).await {
+                Ok(content) if entry.is_some_and(|entry| entry.icon.is_some()) => Ok(content),
+                _ => Err(err),
+            };
+        }
+
+        tokio::fs::read(&path)
+            .await
+            .map_err(|_| IconStoreError::FileSystemFailToRead)
+    }
+
+    /// Finds a cached icon without ever contacting the website, e.g. for users who opted out of fetching. Expired
+    /// icons are used too.
+    pub async fn find_cached(
+        &self,
+        domain: &str,
+        size: u32,
+        format: OutputFormat,
+    ) -> Option<Vec<u8>> {
+        let key = utils::hash_domain(domain);
+        self.index.lock().unwrap().get(&key, Utc::now())?.icon?;
+
+        let content = tokio::fs::read(self.get_path(&key, normalize::standard_size(size), format))
+            .await
+            .ok()?;
+        self.stats.hits.fetch_add(1, Ordering::Relaxed);
+        Some(content)
+    }
+
+    /// Finds the icon of a well known issuer, e.g. the display name of a code, from the providers which know
+    /// issuers. Nobody is contacted, so this is fine for users who opted out of fetching too.
+    pub async fn find_issuer(
+        &self,
+        issuer: &str,
+        size: u32,
+        format: OutputFormat,
+    ) -> Option<Vec<u8>> {
+        for provider in self.providers.iter() {
+            if let Some(icon) = provider.find_issuer(issuer, size, format).await {
+                self.stats.hits.fetch_add(1, Ordering::Relaxed);
+                return Some(icon);
+            }
+        }
+
+        None
+    }
+
+    /// Whether the domain was checked recently enough that gathering it again is not needed.
+    pub fn is_fresh(&self, domain: &str) -> bool {
+        self.index
+            .lock()
+            .unwrap()
+            .peek(&utils::hash_domain(domain))
+            .is_some_and(|entry| entry.is_fresh(Utc::now(), self.ttl))
+    }
+
+    /// Whether the icon of the domain is being gathered right now.
+    pub fn is_gathering(&self, domain: &str) -> bool {
+        self.in_flight
+            .lock()
+            .unwrap()
+            .contains_key(&utils::hash_domain(domain))
+    }
+
+    pub fn stats(&self) -> &IconStats {
+        &self.stats
+    }
+
+    /// Total bytes of every cached icon.
+    pub fn size(&self) -> u64 {
+        self.index.lock().unwrap().total_size()
+    }
+
+    pub async fn metadata(&self, domain: &str) -> Option<IconMetadata> {
+        self.index
+            .lock()
+            .unwrap()
+            .peek(&utils::hash_domain(domain))?
+            .icon
+            .clone()
+    }
+
+    /// Removes the cached icon of a domain, if any.
+    pub async fn remove(&self, domain: &str) -> Result<(), IconStoreError> {
+        let key = utils::hash_domain(domain);
+        remove_path(&self.get_dir(&key)).await?;
+
+        if self.index.lock().unwrap().remove(&key).is_some() {
+            self.save().await?;
+        }
+
+        Ok(())
+    }
+
+    /// Uploaded icons live apart from the cache, as they are never refreshed, evicted or purged.
+    fn get_custom_dir(&self, code_id: &str) -> PathBuf {
+        self.base.join(CUSTOM_DIR).join(code_id)
+    }
+
+    /// Validates, normalizes and stores an icon uploaded for a code, replacing any previous one.
+    pub async fn store_custom(
+        &self,
+        code_id: &str,
+        content: Vec<u8>,
+    ) -> Result<IconMetadata, IconStoreError> {
+        if content.len() > MAX_ICON_SIZE {
+            return Err(IconStoreError::IconTooLarge);
+        }
+        let format = format::sniff(&content).ok_or(IconStoreError::InvalidIcon)?;
+        let icon = provider::prepare(content, format, "upload".to_string(), "custom")
+            .await
+            .ok_or(IconStoreError::InvalidIcon)?;
+
+        tokio::fs::create_dir_all(self.base.join(CUSTOM_DIR))
+            .await
+            .map_err(|_| IconStoreError::FileSystemFailToWrite)?;
+        self.store(&self.get_custom_dir(code_id), icon.variants)
+            .await?;
+
+        Ok(icon.metadata)
+    }
+
+    /// Finds the uploaded icon of a code at the standard size closest to the requested one.
+    pub async fn find_custom(
+        &self,
+        code_id: &str,
+        size: u32,
+        format: OutputFormat,
+    ) -> Option<Vec<u8>> {
+        let name = format!("{}.{}", normalize::standard_size(size), format.extension());
+        tokio::fs::read(self.get_custom_dir(code_id).join(name))
+            .await
+            .ok()
+    }
+
+    pub async fn remove_custom(&self, code_id: &str) -> Result<(), IconStoreError> {
+        remove_path(&self.get_custom_dir(code_id)).await
+    }
+
+    /// Removes every cached icon, and forgets which websites had none. Returns how many domains were removed.
+    pub async fn purge(&self) -> Result<usize, IconStoreError> {
+        let keys = std::mem::take(&mut *self.index.lock().unwrap()).keys();
+        for key in &keys {
+            remove_path(&self.get_dir(key)).await?;
+        }
+
+        self.save().await?;
+        Ok(keys.len())
+    }
+
+    /// Removes the cached icons of domains not in `domains`, and the uploaded icons of codes not in `code_ids`.
+    /// Icons stored within the grace period and uploads still being written are kept. Returns how many were
+    /// removed.
+    pub async fn collect_garbage(
+        &self,
+        domains: &[String],
+        code_ids: &[String],
+    ) -> Result<usize, IconStoreError> {
+        let cutoff = Utc::now() - self.garbage_grace_period;
+        let used: HashSet<String> = domains
+            .iter()
+            .map(|domain| utils::hash_domain(domain))
+            .collect();
+        let unused: Vec<String> = {
+            let mut index = self.index.lock().unwrap();
+            let unused: Vec<String> = index
+                .keys()
+                .into_iter()
+                .filter(|key| !used.contains(key))
+                .filter(|key| {
+                    index
+                        .peek(key)
+                        .is_some_and(|entry| entry.checked_at < cutoff)
+                })
+                .collect();
+            for key in &unused {
+                index.remove(key);
+            }
+            unused
+        };
+
+        for key in &unused {
+            remove_path(&self.get_dir(key)).await?;
+        }
+        if !unused.is_empty() {
+            self.save().await?;
+        }
+
+        let mut removed = unused.len();
+        let code_ids: HashSet<&str> = code_ids.iter().map(String::as_str).collect();
+        if let Ok(mut dirs) = tokio::fs::read_dir(self.base.join(CUSTOM_DIR)).await {
+            while let Ok(Some(dir)) = dirs.next_entry().await {
+                let name = dir.file_name().to_string_lossy().to_string();
+                // Staging directories of uploads in progress start with a dot
+                if name.starts_with('.') || code_ids.contains(name.as_str()) {
+                    continue;
+                }
+
+                let modified = dir
+                    .metadata()
+                    .await
+                    .and_then(|metadata| metadata.modified());
+                if modified.is_ok_and(|modified| DateTime::<Utc>::from(modified) >= cutoff) {
+                    continue;
+                }
+
+                remove_path(&dir.path()).await?;
+                removed += 1;
+            }
+        }
+
+        Ok(removed)
+    }
+
+    /// Discovers the icons a website offers, and normalizes and stores the best one which actually is an image.
+    /// Failures are remembered, backing off for longer after every one, so the website is not asked on every
+    /// request. Concurrent calls for the same domain wait for the first one and share its result.
+    ///
+    /// Runs as a task of its own, so a request timing out does not abandon the gathering halfway, before the
+    /// result is recorded and the in-flight entry is cleared.
+    pub async fn gather(&self, domain: &str) -> Result<IconMetadata, IconStoreError> {
+        let store = self.clone();
+        let domain = domain.to_string();
+
+        tokio::spawn(async move { store.gather_coalesced(&domain).await })
+            .await
+            .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()))
+    }
+
+    async fn gather_coalesced(&self, domain: &str) -> Result<IconMetadata, IconStoreError> {
+        let key = utils::hash_domain(domain);
+        let started = Utc::now();
+
+        let flight = self
+            .in_flight
+            .lock()
+            .unwrap()
+            .entry(key.clone())
+            .or_default()
+            .clone();
+        let guard = flight.lock().await;
+
+        // Another call gathered the icon while this one was waiting
+        let finished = self
+            .index
+            .lock()
+            .unwrap()
+            .peek(&key)
+            .filter(|entry| entry.checked_at >= started)
+            .map(|entry| entry.icon.clone());
+
+        let result = match finished {
+            Some(icon) => {
+                self.stats.coalesced.fetch_add(1, Ordering::Relaxed);
+                icon.ok_or(IconStoreError::NoIconFound)
+            }
+            None => self.gather_uncoalesced(domain, key.clone()).await,
+        };
+
+        drop(guard);
+        let mut in_flight = self.in_flight.lock().unwrap();
+        // Only the map and this call still hold it, so nobody else is waiting
+        if Arc::strong_count(&flight) == 2 {
+            in_flight.remove(&key);
+        }
+
+        result
+    }
+
+    async fn gather_uncoalesced(
+        &self,
+        domain: &str,
+        key: String,
+    ) -> Result<IconMetadata, IconStoreError> {
+        debug!("Gathering icon for {}", domain);
+
+        match self.discover_and_store(domain, &key).await {
+            Ok((metadata, size)) => {
+                self.stats.fetch_successes.fetch_add(1, Ordering::Relaxed);
+
+                let evicted = {
+                    let mut index = self.index.lock().unwrap();
+                    index.insert(
+                        key.clone(),
+                        CacheEntry {
+                            icon: Some(metadata.clone()),
+                            checked_at: metadata.fetched_at,
+                            size,
+                            last_accessed: metadata.fetched_at,
+                            failures: 0,
+                        },
+                    );
+                    index.evict(self.max_size, &key)
+                };
+
+                for evicted in evicted {
+                    debug!("Evicting icon {evicted} from the cache");
+                    remove_path(&self.get_dir(&evicted)).await?;
+                }
+                self.save().await?;
+
+                Ok(metadata)
+            }
+            Err(err) => {
+                self.stats.fetch_failures.fetch_add(1, Ordering::Relaxed);
+
+                let now = Utc::now();
+                {
+                    let mut index = self.index.lock().unwrap();
+                    let previous = index.remove(&key);
+                    let failures = previous.as_ref().map_or(0, |entry| entry.failures) + 1;
+
+                    match previous {
+                        // Keep the previous icon until the next refresh
+                        Some(entry) if entry.icon.is_some() => index.insert(
+                            key,
+                            CacheEntry {
+                                checked_at: now,
+                                failures,
+                                ..entry
+                            },
+                        ),
+                        _ => index.insert(
+                            key,
+                            CacheEntry {
+                                icon: None,
+                                checked_at: now,
+                                size: 0,
+                                last_accessed: now,
+                                failures,
+                            },
+                        ),
+                    }
+                }
+                self.save().await?;
+
+                Err(err)
+            }
+        }
+    }
+
+    /// Asks the providers in order, storing the first icon found. Returns the most specific error otherwise.
+    async fn discover_and_store(
+        &self,
+        domain: &str,
+        key: &str,
+    ) -> Result<(IconMetadata, u64), IconStoreError> {
+        let mut error = IconStoreError::NoIconFound;
+        for provider in self.providers.iter() {
+            match provider.find(domain, &self.fetcher).await {
+                Ok(icon) => {
+                    let size = self.store(&self.get_dir(key), icon.variants).await?;
+                    return Ok((icon.metadata, size));
+                }
+                Err(IconStoreError::NoIconFound) => {
+                    debug!("Provider {} has no icon for {domain}", provider.name())
+                }
+                Err(err) => {
+                    debug!("Provider {} failed for {domain}: {err:?}", provider.name());
+                    error = err;
+                }
+            }
+        }
+
+        Err(error)
+    }
+
+    /// Writes the variants to a fresh directory before swapping it in, so readers never see a partial icon.
+    /// Returns the total size.
+    async fn store(
+        &self,
+        dir: &Path,
+        variants: Vec<normalize::Variant>,
+    ) -> Result<u64, IconStoreError> {
+        let size = variants
+            .iter()
+            .map(|variant| variant.content.len() as u64)
+            .sum();
+
+        let name = dir.file_name().unwrap_or_default().to_string_lossy();
+        let staging = dir.with_file_name(format!(".{name}-{}", utils::generate_id(5)));
+        let write = async {
+            tokio::fs::create_dir(&staging).await?;
+            for variant in variants {
+                let name = format!("{}.{}", variant.size, variant.format.extension());
+                tokio::fs::write(staging.join(name), variant.content).await?;
+            }
+
+            match tokio::fs::remove_dir_all(dir).await {
+                Err(e) if e.kind() != ErrorKind::NotFound => Err(e),
+                _ => tokio::fs::rename(&staging, dir).await,
+            }
+        };
+
+        if write.await.is_err() {
+            let _ = tokio::fs::remove_dir_all(&staging).await;
+            return Err(IconStoreError::FileSystemFailToWrite);
+        }
+
+        Ok(size)
+    }
+}
+
+/// Removes a file or directory, which may already be gone.
+async fn remove_path(path: &std::path::Path) -> Result<(), IconStoreError> {
+    let removed = match tokio::fs::metadata(path).await {
+        Ok(metadata) if metadata.is_dir() => tokio::fs::remove_dir_all(path).await,
+        Ok(_) => tokio::fs::remove_file(path).await,
+        Err(e) => Err(e),
+    };
+
+    match removed {
+        Ok(_) => Ok(()),
+        Err(e) if e.kind() == ErrorKind::NotFound => Ok(()),
+        Err(_) => Err(IconStoreError::FileSystemFailToWrite),
+    }
+}
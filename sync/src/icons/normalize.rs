@@ -0,0 +1,292 @@
+use super::format::IconFormat;
+use image::{imageops::FilterType, DynamicImage, ImageReader, Limits, RgbaImage};
+use serde::Deserialize;
+use std::io::Cursor;
+use utoipa::ToSchema;
+
+/// Sizes every icon is rendered at, in pixels. Other sizes are rounded up to the next one.
+pub const SIZES: [u32; 4] = [32, 64, 128, 256];
+pub const DEFAULT_SIZE: u32 = 128;
+
+/// Sources larger than this are most likely not icons, and decoding them is expensive.
+const MAX_SOURCE_DIMENSIONS: u32 = 4096;
+
+/// SVGs are limited further, as rendering is slow for large or complex ones.
+const MAX_SVG_SIZE: usize = 256 * 1024;
+const MAX_SVG_NODES: u32 = 4096;
+/// Every `<use>` copies the element it refers to, so nesting them multiplies the nodes to render.
+const MAX_SVG_USES: usize = 16;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Deserialize, ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    #[default]
+    Png,
+    Webp,
+}
+
+impl OutputFormat {
+    pub const ALL: [OutputFormat; 2] = [OutputFormat::Png, OutputFormat::Webp];
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Webp => "image/webp",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Webp => "webp",
+        }
+    }
+
+    fn image_format(&self) -> image::ImageFormat {
+        match self {
+            OutputFormat::Png => image::ImageFormat::Png,
+            OutputFormat::Webp => image::ImageFormat::WebP,
+        }
+    }
+}
+
+/// The smallest standard size at least as large as requested, or the largest one.
+pub fn standard_size(requested: u32) -> u32 {
+    SIZES
+        .into_iter()
+        .find(|size| *size >= requested)
+        .unwrap_or(SIZES[SIZES.len() - 1])
+}
+
+/// Decodes an icon of a sniffed format. Vectors are rasterized at the largest standard size.
+pub fn decode(content: &[u8], format: IconFormat) -> Option<RgbaImage> {
+    let format = match format {
+        IconFormat::Svg => return rasterize_svg(content, SIZES[SIZES.len() - 1]),
+        IconFormat::Ico => image::ImageFormat::Ico,
+        IconFormat::Png => image::ImageFormat::Png,
+        IconFormat::Jpeg => image::ImageFormat::Jpeg,
+        IconFormat::Gif => image::ImageFormat::Gif,
+        IconFormat::Webp => image::ImageFormat::WebP,
+        IconFormat::Bmp => image::ImageFormat::Bmp,
+    };
+
+    let mut limits = Limits::default();
+    limits.max_image_width = Some(MAX_SOURCE_DIMENSIONS);
+    limits.max_image_height = Some(MAX_SOURCE_DIMENSIONS);
+
+    let mut reader = ImageReader::with_format(Cursor::new(content), format);
+    reader.limits(limits);
+
+    let image = reader.decode().ok()?.into_rgba8();
+    (image.width() > 0 && image.height() > 0).then_some(image)
+}
+
+fn rasterize_svg(content: &[u8], size: u32) -> Option<RgbaImage> {
+    use resvg::{tiny_skia, usvg, usvg::roxmltree};
+
+    if content.len() > MAX_SVG_SIZE {
+        return None;
+    }
+    let document = roxmltree::Document::parse_with_options(
+        std::str::from_utf8(content).ok()?,
+        roxmltree::ParsingOptions {
+            allow_dtd: true,
+            nodes_limit: MAX_SVG_NODES,
+            ..Default::default()
+        },
+    )
+    .ok()?;
+    let uses = document
+        .descendants()
+        .filter(|node| node.has_tag_name("use"))
+        .count();
+    if uses > MAX_SVG_USES {
+        return None;
+    }
+
+    // Never let an SVG reference files on the server. Embedded images are skipped too, as nested SVGs
+    // would escape the limits above, and rasters the ones on source dimensions.
+    let mut options = usvg::Options::default();
+    options.image_href_resolver.resolve_string = Box::new(|_, _| None);
+    options.image_href_resolver.resolve_data = Box::new(|_, _, _| None);
+
+    let tree = usvg::Tree::from_xmltree(&document, &options).ok()?;
+    let scale = size as f32 / tree.size().width().max(tree.size().height());
+    let width = ((tree.size().width() * scale).round() as u32).max(1);
+    let height = ((tree.size().height() * scale).round() as u32).max(1);
+
+    let mut pixmap = tiny_skia::Pixmap::new(width, height)?;
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    let pixels = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let pixel = pixel.demultiply();
+            [pixel.red(), pixel.green(), pixel.blue(), pixel.alpha()]
+        })
+        .collect();
+
+    RgbaImage::from_raw(width, height, pixels)
+}
+
+/// An icon rendered at one of the standard sizes.
+pub struct Variant {
+    pub size: u32,
+    pub format: OutputFormat,
+    pub content: Vec<u8>,
+}
+
+/// Decodes an icon and renders it at every standard size and output format, returning the decoded dimensions too.
+/// Slow, so best run on a blocking thread.
+pub fn normalize(content: &[u8], format: IconFormat) -> Option<((u32, u32), Vec<Variant>)> {
+    let image = decode(content, format)?;
+
+    let mut variants = vec![];
+    for size in SIZES {
+        for format in OutputFormat::ALL {
+            variants.push(Variant {
+                size,
+                format,
+                content: render(&image, size, format)?,
+            });
+        }
+    }
+
+    Some((image.dimensions(), variants))
+}
+
+/// Scales the icon to fit a transparent square of the given size, keeping its aspect ratio.
+pub fn render(image: &RgbaImage, size: u32, format: OutputFormat) -> Option<Vec<u8>> {
+    // Small favicons are pixel art, smoothing them while enlarging only blurs them
+    let filter = if image.width().max(image.height()) < size {
+        FilterType::Nearest
+    } else {
+        FilterType::Lanczos3
+    };
+
+    let scaled = DynamicImage::ImageRgba8(image.clone())
+        .resize(size, size, filter)
+        .into_rgba8();
+
+    let mut canvas = RgbaImage::new(size, size);
+    image::imageops::overlay(
+        &mut canvas,
+        &scaled,
+        ((size - scaled.width()) / 2).into(),
+        ((size - scaled.height()) / 2).into(),
+    );
+
+    let mut content = Cursor::new(Vec::new());
+    DynamicImage::ImageRgba8(canvas)
+        .write_to(&mut content, format.image_format())
+        .ok()?;
+
+    Some(content.into_inner())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{engine::general_purpose::STANDARD, Engine};
+    use googletest::prelude::*;
+    use image::Rgba;
+
+    #[gtest]
+    fn standard_sizes() {
+        expect_that!(standard_size(0), eq(32));
+        expect_that!(standard_size(64), eq(64));
+        expect_that!(standard_size(65), eq(128));
+        expect_that!(standard_size(4096), eq(256));
+    }
+
+    #[gtest]
+    fn render_fits_square() {
+        let wide = RgbaImage::from_pixel(40, 20, Rgba([255, 0, 0, 255]));
+
+        for format in OutputFormat::ALL {
+            let content = render(&wide, 64, format).unwrap();
+            let image = image::load_from_memory(&content).unwrap().into_rgba8();
+
+            expect_that!(image.dimensions(), eq((64, 64)));
+            expect_that!(image.get_pixel(32, 32), eq(&Rgba([255, 0, 0, 255])));
+            expect_that!(image.get_pixel(32, 2), eq(&Rgba([0, 0, 0, 0])));
+        }
+    }
+
+    #[gtest]
+    fn decode_svg() {
+        let image = decode(
+            br##"<svg xmlns="http://www.w3.org/2000/svg" width="16" height="16">
+                <rect width="16" height="16" fill="#00ff00"/>
+                <image href="/etc/hostname" width="16" height="16"/>
+            </svg>"##,
+            IconFormat::Svg,
+        )
+        .unwrap();
+
+        expect_that!(image.dimensions(), eq((256, 256)));
+        expect_that!(image.get_pixel(128, 128), eq(&Rgba([0, 255, 0, 255])));
+    }
+
+    #[gtest]
+    fn decode_invalid() {
+        expect_that!(decode(b"\x89PNG\r\n\x1a\ngarbage", IconFormat::Png), none());
+        expect_that!(decode(b"<svg", IconFormat::Svg), none());
+    }
+
+    #[gtest]
+    fn decode_svg_limits() {
+        let svg = |body: String| {
+            format!(
+                r#"<svg xmlns="http://www.w3.org/2000/svg" xmlns:xlink="http://www.w3.org/1999/xlink" width="16" height="16">{body}</svg>"#
+            )
+        };
+
+        let rects = |count| r#"<rect width="1" height="1"/>"#.repeat(count);
+        expect_that!(
+            decode(svg(rects(100)).as_bytes(), IconFormat::Svg),
+            some(anything())
+        );
+        expect_that!(decode(svg(rects(5000)).as_bytes(), IconFormat::Svg), none());
+
+        // Each level doubles the previous one, which would be millions of rectangles
+        let mut nested = r#"<g id="l0"><rect width="1" height="1"/></g>"#.to_string();
+        for level in 1..24 {
+            let previous = level - 1;
+            nested += &format!(
+                r##"<g id="l{level}"><use xlink:href="#l{previous}"/><use xlink:href="#l{previous}"/></g>"##
+            );
+        }
+        expect_that!(
+            decode(svg(nested.clone()).as_bytes(), IconFormat::Svg),
+            none()
+        );
+
+        // Embedded images are left out, so the same SVG nested in a data URI is not rendered either
+        let embedded = |content: String| {
+            svg(format!(
+                r#"<image width="16" height="16" xlink:href="data:image/svg+xml;base64,{}"/>"#,
+                STANDARD.encode(content)
+            ))
+        };
+        expect_that!(
+            decode(embedded(svg(nested)).as_bytes(), IconFormat::Svg)
+                .map(|image| image.pixels().all(|pixel| pixel[3] == 0)),
+            some(eq(true))
+        );
+        let red = svg(r#"<rect width="16" height="16" fill="red"/>"#.to_string());
+        expect_that!(
+            decode(embedded(red).as_bytes(), IconFormat::Svg)
+                .map(|image| image.pixels().all(|pixel| pixel[3] == 0)),
+            some(eq(true))
+        );
+
+        let padding = format!("<!--{}-->", " ".repeat(MAX_SVG_SIZE));
+        expect_that!(decode(svg(padding).as_bytes(), IconFormat::Svg), none());
+    }
+}
@@ -0,0 +1,260 @@
+use super::{
+    fetch::Fetcher,
+    format::IconFormat,
+    normalize::{self, OutputFormat},
+    provider::{prepare, FoundIcon, IconProvider},
+    IconStoreError,
+};
+use async_trait::async_trait;
+use std::{collections::HashMap, sync::Mutex};
+
+/// An icon compiled into the binary, as an SVG in `src/icons/pack`.
+struct PackIcon {
+    /// Names of the issuer as in `otpauth` URIs, reduced to lowercase letters and digits.
+    issuers: &'static [&'static str],
+    /// Domains of the issuer. Their subdomains match too.
+    domains: &'static [&'static str],
+    svg: &'static [u8],
+}
+
+const PACK: &[PackIcon] = &[
+    PackIcon {
+        issuers: &["1password"],
+        domains: &["1password.com", "1password.eu", "1password.ca"],
+        svg: include_bytes!("pack/1password.svg"),
+    },
+    PackIcon {
+        issuers: &["binance"],
+        domains: &["binance.com", "binance.us"],
+        svg: include_bytes!("pack/binance.svg"),
+    },
+    PackIcon {
+        issuers: &["bitwarden"],
+        domains: &["bitwarden.com", "bitwarden.eu"],
+        svg: include_bytes!("pack/bitwarden.svg"),
+    },
+    PackIcon {
+        issuers: &["cloudflare"],
+        domains: &["cloudflare.com"],
+        svg: include_bytes!("pack/cloudflare.svg"),
+    },
+    PackIcon {
+        issuers: &["coinbase"],
+        domains: &["coinbase.com"],
+        svg: include_bytes!("pack/coinbase.svg"),
+    },
+    PackIcon {
+        issuers: &["digitalocean"],
+        domains: &["digitalocean.com"],
+        svg: include_bytes!("pack/digitalocean.svg"),
+    },
+    PackIcon {
+        issuers: &["dropbox"],
+        domains: &["dropbox.com"],
+        svg: include_bytes!("pack/dropbox.svg"),
+    },
+    PackIcon {
+        issuers: &["gitlab"],
+        domains: &["gitlab.com"],
+        svg: include_bytes!("pack/gitlab.svg"),
+    },
+    PackIcon {
+        issuers: &["google", "gmail", "googleworkspace"],
+        domains: &["google.com", "gmail.com"],
+        svg: include_bytes!("pack/google.svg"),
+    },
+    PackIcon {
+        issuers: &["linkedin"],
+        domains: &["linkedin.com"],
+        svg: include_bytes!("pack/linkedin.svg"),
+    },
+    PackIcon {
+        issuers: &[
+            "microsoft",
+            "outlook",
+            "office365",
+            "azure",
+            "azuread",
+            "entraid",
+        ],
+        domains: &[
+            "microsoft.com",
+            "live.com",
+            "outlook.com",
+            "office.com",
+            "azure.com",
+        ],
+        svg: include_bytes!("pack/microsoft.svg"),
+    },
+    PackIcon {
+        issuers: &["nextcloud"],
+        domains: &["nextcloud.com"],
+        svg: include_bytes!("pack/nextcloud.svg"),
+    },
+    PackIcon {
+        issuers: &["npm", "npmjs"],
+        domains: &["npmjs.com"],
+        svg: include_bytes!("pack/npm.svg"),
+    },
+    PackIcon {
+        issuers: &["okta"],
+        domains: &["okta.com", "oktapreview.com"],
+        svg: include_bytes!("pack/okta.svg"),
+    },
+    PackIcon {
+        issuers: &["tailscale"],
+        domains: &["tailscale.com"],
+        svg: include_bytes!("pack/tailscale.svg"),
+    },
+    PackIcon {
+        issuers: &["twitch"],
+        domains: &["twitch.tv"],
+        svg: include_bytes!("pack/twitch.svg"),
+    },
+    PackIcon {
+        issuers: &["vercel"],
+        domains: &["vercel.com"],
+        svg: include_bytes!("pack/vercel.svg"),
+    },
+    PackIcon {
+        issuers: &["x", "twitter"],
+        domains: &["x.com", "twitter.com"],
+        svg: include_bytes!("pack/x.svg"),
+    },
+];
+
+/// Reduces a name to lowercase letters and digits, e.g. `Microsoft 365` to `microsoft365`.
+fn slug(name: &str) -> String {
+    name.chars()
+        .filter(|c| c.is_alphanumeric())
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// Icons of well known issuers bundled with the server, so installs without internet access still show them.
+#[derive(Debug, Default)]
+pub struct BundledProvider {
+    /// Icons already rendered for issuers, by position in the pack, size and format.
+    rendered: Mutex<HashMap<(usize, u32, OutputFormat), Vec<u8>>>,
+}
+
+impl BundledProvider {
+    /// Finds the icon of a domain or of any domain it is a subdomain of, e.g. `login.live.com`.
+    fn lookup(domain: &str) -> Option<(&'static str, &'static [u8])> {
+        let host = domain
+            .rsplit_once(':')
+            .map_or(domain, |(host, _)| host)
+            .trim_end_matches('.')
+            .to_ascii_lowercase();
+
+        let mut suffix = host.as_str();
+        loop {
+            let found = PACK.iter().find_map(|icon| {
+                let domain = icon.domains.iter().find(|domain| **domain == suffix)?;
+                Some((*domain, icon.svg))
+            });
+            if found.is_some() {
+                return found;
+            }
+
+            suffix = suffix.split_once('.')?.1;
+        }
+    }
+
+    /// Finds the icon of an issuer, which may be followed by the account as in `GitHub:erb3` or `Google (work)`.
+    /// Only the first word is tried too, e.g. `Microsoft` of `Microsoft Azure`.
+    fn lookup_issuer(issuer: &str) -> Option<usize> {
+        let name = issuer.split([':', '(']).next().unwrap_or_default();
+        let first_word = name.split_whitespace().next().unwrap_or_default();
+
+        [slug(name), slug(first_word)]
+            .iter()
+            .filter(|slug| !slug.is_empty())
+            .find_map(|slug| {
+                PACK.iter()
+                    .position(|icon| icon.issuers.contains(&slug.as_str()))
+            })
+    }
+}
+
+#[async_trait]
+impl IconProvider for BundledProvider {
+    fn name(&self) -> &'static str {
+        "bundled"
+    }
+
+    async fn find(&self, domain: &str, _: &Fetcher) -> Result<FoundIcon, IconStoreError> {
+        let (domain, svg) = Self::lookup(domain).ok_or(IconStoreError::NoIconFound)?;
+
+        prepare(
+            svg.to_vec(),
+            IconFormat::Svg,
+            format!("bundled:{domain}"),
+            self.name(),
+        )
+        .await
+        .ok_or(IconStoreError::NoIconFound)
+    }
+
+    async fn find_issuer(&self, issuer: &str, size: u32, format: OutputFormat) -> Option<Vec<u8>> {
+        let position = Self::lookup_issuer(issuer)?;
+        let key = (position, normalize::standard_size(size), format);
+
+        if let Some(icon) = self.rendered.lock().unwrap().get(&key) {
+            return Some(icon.clone());
+        }
+
+        let icon = tokio::task::spawn_blocking(move || {
+            let image = normalize::decode(PACK[key.0].svg, IconFormat::Svg)?;
+            normalize::render(&image, key.1, key.2)
+        })
+        .await
+        .ok()
+        .flatten()?;
+
+        self.rendered.lock().unwrap().insert(key, icon.clone());
+        Some(icon)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::icons::{format::sniff, normalize::decode};
+    use googletest::prelude::*;
+
+    #[gtest]
+    fn lookup_subdomains() {
+        expect_that!(
+            BundledProvider::lookup("login.LIVE.com:443").map(|(domain, _)| domain),
+            some(eq("live.com"))
+        );
+        expect_that!(BundledProvider::lookup("microsoft.com.evil.com"), none());
+        expect_that!(BundledProvider::lookup("com"), none());
+    }
+
+    #[gtest]
+    fn lookup_issuers() {
+        let issuer =
+            |name| BundledProvider::lookup_issuer(name).map(|position| PACK[position].issuers[0]);
+
+        expect_that!(issuer("GitLab"), some(eq("gitlab")));
+        expect_that!(issuer("Google:erb3@snowflake.blue"), some(eq("google")));
+        expect_that!(issuer("Microsoft Azure (work)"), some(eq("microsoft")));
+        expect_that!(issuer("Office 365"), some(eq("microsoft")));
+        expect_that!(issuer("1Password"), some(eq("1password")));
+        expect_that!(issuer("Snowflake"), none());
+        expect_that!(issuer(""), none());
+    }
+
+    #[gtest]
+    fn pack_is_valid() {
+        for icon in PACK {
+            expect_that!(sniff(icon.svg), some(eq(IconFormat::Svg)));
+            expect_that!(decode(icon.svg, IconFormat::Svg), some(anything()));
+            for issuer in icon.issuers {
+                expect_that!(slug(issuer), eq(*issuer));
+            }
+        }
+    }
+}
@@ -0,0 +1,194 @@
+use super::{
+    discovery::{self, Candidate},
+    fetch::{read_up_to, Fetcher},
+    format::IconFormat,
+    guard::Allowlist,
+    normalize::{self, OutputFormat, Variant},
+    IconMetadata, IconStoreError,
+};
+use async_trait::async_trait;
+use chrono::Utc;
+use tracing::debug;
+use url::Url;
+
+/// Only the head of a page is needed to find its icons.
+const MAX_PAGE_SIZE: usize = 512 * 1024;
+const MAX_MANIFEST_SIZE: usize = 64 * 1024;
+
+/// An icon found by a provider, normalized but not stored yet.
+pub struct FoundIcon {
+    pub metadata: IconMetadata,
+    pub variants: Vec<Variant>,
+}
+
+/// A source of icons. The store asks each configured provider in order, until one finds an icon.
+#[async_trait]
+pub trait IconProvider: std::fmt::Debug + Send + Sync {
+    /// Identifies the provider in configuration, logs and the icon metadata.
+    fn name(&self) -> &'static str;
+
+    /// Finds the icon of a domain. `NoIconFound` lets the next provider try.
+    async fn find(&self, domain: &str, fetcher: &Fetcher) -> Result<FoundIcon, IconStoreError>;
+
+    /// Finds the icon of a well known issuer by its name, rendered at a standard size. Asked before any domain,
+    /// and only offline providers know issuers.
+    async fn find_issuer(
+        &self,
+        _issuer: &str,
+        _size: u32,
+        _format: OutputFormat,
+    ) -> Option<Vec<u8>> {
+        None
+    }
+}
+
+/// Decodes and normalizes an icon on a blocking thread, as it is slow. `None` when it is not a usable image.
+pub async fn prepare(
+    content: Vec<u8>,
+    format: IconFormat,
+    source_url: String,
+    provider: &str,
+) -> Option<FoundIcon> {
+    let normalized = tokio::task::spawn_blocking(move || normalize::normalize(&content, format))
+        .await
+        .ok()
+        .flatten();
+    let ((width, height), variants) = normalized?;
+
+    Some(FoundIcon {
+        metadata: IconMetadata {
+            source_url,
+            source_content_type: format.content_type().to_string(),
+            source_width: width,
+            source_height: height,
+            fetched_at: Utc::now(),
+            provider: provider.to_string(),
+        },
+        variants,
+    })
+}
+
+/// Discovers the icons the website itself offers, and picks the best one which actually is an image.
+#[derive(Debug, Default)]
+pub struct DirectProvider;
+
+impl DirectProvider {
+    /// Lists the icons linked from the front page and its web app manifest, best first. Browsers fall back to
+    /// `/favicon.ico`, and so do we.
+    async fn discover(
+        &self,
+        site: &Url,
+        fetcher: &Fetcher,
+    ) -> Result<Vec<Candidate>, IconStoreError> {
+        let response = fetcher.get(site, "text/html").await?;
+
+        let mut candidates = vec![];
+        let page_url = response.url().clone();
+        if response.status().is_success() {
+            let html = read_up_to(response, MAX_PAGE_SIZE)
+                .await
+                .ok_or(IconStoreError::UnableToParseResponse)?;
+            let links = discovery::parse_html(&String::from_utf8_lossy(&html), &page_url);
+
+            candidates = links.icons;
+            if let Some(manifest) = links.manifest {
+                candidates.extend(self.fetch_manifest(&manifest, fetcher).await);
+            }
+        }
+
+        Ok(discovery::rank(
+            candidates,
+            site.join("/favicon.ico").unwrap(),
+        ))
+    }
+
+    async fn fetch_manifest(&self, url: &Url, fetcher: &Fetcher) -> Vec<Candidate> {
+        let Ok(response) = fetcher
+            .get(url, "application/manifest+json, application/json")
+            .await
+        else {
+            return vec![];
+        };
+        if !response.status().is_success() {
+            return vec![];
+        }
+
+        match read_up_to(response, MAX_MANIFEST_SIZE).await {
+            Some(content) => discovery::parse_manifest(&content, url),
+            None => vec![],
+        }
+    }
+}
+
+#[async_trait]
+impl IconProvider for DirectProvider {
+    fn name(&self) -> &'static str {
+        "direct"
+    }
+
+    async fn find(&self, domain: &str, fetcher: &Fetcher) -> Result<FoundIcon, IconStoreError> {
+        let site = fetcher.site(domain)?;
+
+        for candidate in self.discover(&site, fetcher).await? {
+            let Some((content, format)) = fetcher.fetch_icon(&candidate.url).await else {
+                debug!("Icon candidate {} for {domain} is unusable", candidate.url);
+                continue;
+            };
+
+            match prepare(content, format, candidate.url.to_string(), self.name()).await {
+                Some(icon) => return Ok(icon),
+                None => debug!(
+                    "Icon candidate {} for {domain} can not be decoded",
+                    candidate.url
+                ),
+            }
+        }
+
+        Err(IconStoreError::NoIconFound)
+    }
+}
+
+/// Asks a self-hosted icon service, e.g. one proxying favicons for a whole organization. The URL contains
+/// `{domain}`, which is replaced with the domain, e.g. `https://icons.example.com/{domain}.png`.
+#[derive(Debug, Clone)]
+pub struct ServiceProvider {
+    template: String,
+    /// The service is chosen by the administrator, so it may be private, unlike where it redirects to.
+    fetcher: Fetcher,
+}
+
+impl ServiceProvider {
+    pub fn new(template: String) -> Result<Self, url::ParseError> {
+        let url = Url::parse(&template.replace("{domain}", "example.com"))?;
+        let host = url.host_str().ok_or(url::ParseError::EmptyHost)?;
+
+        Ok(ServiceProvider {
+            fetcher: Fetcher::new(Allowlist::new([host])),
+            template,
+        })
+    }
+}
+
+#[async_trait]
+impl IconProvider for ServiceProvider {
+    fn name(&self) -> &'static str {
+        "service"
+    }
+
+    async fn find(&self, domain: &str, fetcher: &Fetcher) -> Result<FoundIcon, IconStoreError> {
+        // Only the host, so the domain can not alter the rest of the URL
+        let site = fetcher.site(domain)?;
+        let host = site.host_str().ok_or(IconStoreError::InvalidDomain)?;
+        let url = Url::parse(&self.template.replace("{domain}", host))
+            .map_err(|_| IconStoreError::InvalidDomain)?;
+
+        let (content, format) = self
+            .fetcher
+            .fetch_icon(&url)
+            .await
+            .ok_or(IconStoreError::NoIconFound)?;
+        prepare(content, format, url.to_string(), self.name())
+            .await
+            .ok_or(IconStoreError::NoIconFound)
+    }
+}
@@ -0,0 +1,175 @@
+use crate::{
+    icons::IconStore,
+    models::{
+        codes::Code,
+        emergency::EmergencyContact,
+        user::User,
+        vault::{Vault, VaultMember},
+    },
+};
+use sqlx::SqlitePool;
+use std::{
+    collections::HashSet,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::sync::mpsc::{self, error::TrySendError};
+use tracing::{info, warn};
+
+/// How often the background jobs run.
+const JOB_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// How many websites may wait for their icons to be gathered in the background.
+const WARM_QUEUE_SIZE: usize = 256;
+
+/// Hard deletes an account immediately, together with icons no other code uses and vaults without any
+/// remaining members. Vaults the user is the only owner of are handed over to their longest-standing member.
+pub async fn delete_account(
+    pool: &SqlitePool,
+    icon_store: &IconStore,
+    user: &User,
+) -> Result<(), sqlx::Error> {
+    let codes = Code::get_many(pool, user.id.clone()).await?;
+    VaultMember::promote_successors(pool, user.id.clone()).await?;
+    user.delete(pool).await?;
+
+    // Shared codes are still there, and so are their uploaded icons
+    for code in codes.iter().filter(|code| code.shared_permission.is_none()) {
+        if icon_store.remove_custom(&code.id).await.is_err() {
+            warn!("Unable to remove uploaded icon of code {}", code.id);
+        }
+    }
+
+    for website_url in codes.into_iter().filter_map(|code| code.website_url) {
+        if !Code::website_in_use(pool, website_url.clone()).await?
+            && icon_store.remove(&website_url).await.is_err()
+        {
+            warn!("Unable to remove cached icon of {website_url}");
+        }
+    }
+
+    Vault::delete_orphaned(pool).await
+}
+
+/// Hard deletes accounts whose deletion grace period is over.
+/// Returns the amount of deleted accounts.
+pub async fn purge_deleted_accounts(
+    pool: &SqlitePool,
+    icon_store: &IconStore,
+) -> Result<usize, sqlx::Error> {
+    let users = User::get_due_for_deletion(pool, chrono::Utc::now()).await?;
+
+    for user in &users {
+        delete_account(pool, icon_store, user).await?;
+    }
+
+    Ok(users.len())
+}
+
+/// Removes cached icons no code uses anymore, and uploaded icons of deleted codes, e.g. of deleted vaults.
+/// Returns the amount of removed icons.
+pub async fn collect_icon_garbage(
+    pool: &SqlitePool,
+    icon_store: &IconStore,
+) -> Result<usize, sqlx::Error> {
+    let (websites, code_ids) = Code::icon_references(pool).await?;
+
+    match icon_store.collect_garbage(&websites, &code_ids).await {
+        Ok(count) => Ok(count),
+        Err(err) => {
+            warn!("Unable to remove unused icons: {err:?}");
+            Ok(0)
+        }
+    }
+}
+
+/// Gathers the icons of websites in the background, one at a time, so they are ready once the codes are shown.
+///
+/// Websites wait in a bounded queue for a single worker, so a burst of requests cannot start an unbounded amount
+/// of gatherings. Websites that are queued already, being gathered, or were checked recently, including failed
+/// ones still backing off, are skipped. When the queue is full, websites are dropped and warmed on a later request.
+#[derive(Clone, Debug)]
+pub struct IconWarmer {
+    queue: mpsc::Sender<String>,
+    pending: Arc<Mutex<HashSet<String>>>,
+    icon_store: IconStore,
+}
+
+impl IconWarmer {
+    /// Starts the worker, which stops once every copy of the warmer is dropped.
+    pub fn spawn(icon_store: IconStore) -> Self {
+        let (queue, mut receiver) = mpsc::channel::<String>(WARM_QUEUE_SIZE);
+        let pending: Arc<Mutex<HashSet<String>>> = Arc::default();
+
+        let warmer = Self {
+            queue,
+            pending: pending.clone(),
+            icon_store: icon_store.clone(),
+        };
+
+        tokio::spawn(async move {
+            while let Some(website_url) = receiver.recv().await {
+                if !icon_store.is_fresh(&website_url) {
+                    let _ = icon_store.gather(&website_url).await;
+                }
+                pending.lock().unwrap().remove(&website_url);
+            }
+        });
+
+        warmer
+    }
+
+    pub fn warm(&self, websites: impl IntoIterator<Item = String>) {
+        for website_url in websites {
+            if self.icon_store.is_fresh(&website_url) || self.icon_store.is_gathering(&website_url)
+            {
+                continue;
+            }
+            if !self.pending.lock().unwrap().insert(website_url.clone()) {
+                continue;
+            }
+
+            if let Err(err) = self.queue.try_send(website_url) {
+                let website_url = match err {
+                    TrySendError::Full(website_url) | TrySendError::Closed(website_url) => {
+                        website_url
+                    }
+                };
+                self.pending.lock().unwrap().remove(&website_url);
+            }
+        }
+    }
+}
+
+pub fn spawn(pool: SqlitePool, icon_store: IconStore) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(JOB_INTERVAL);
+
+        loop {
+            interval.tick().await;
+
+            match purge_deleted_accounts(&pool, &icon_store).await {
+                Ok(0) => {}
+                Ok(count) => info!("Deleted {count} accounts after their grace period"),
+                Err(err) => warn!("Unable to delete accounts after their grace period: {err}"),
+            }
+
+            match EmergencyContact::grant_due(&pool, chrono::Utc::now()).await {
+                Ok(0) => {}
+                Ok(count) => info!("Granted {count} emergency access requests"),
+                Err(err) => warn!("Unable to grant emergency access requests: {err}"),
+            }
+
+            match collect_icon_garbage(&pool, &icon_store).await {
+                Ok(0) => {}
+                Ok(count) => info!("Removed {count} unused icons"),
+                Err(err) => warn!("Unable to find unused icons: {err}"),
+            }
+
+            // Persists when icons were last used, for evicting the least recently used ones
+            if icon_store.save().await.is_err() {
+                warn!("Unable to save the icon cache index");
+            }
+        }
+    });
+}
@@ -1,21 +1,29 @@
 pub mod auth;
 pub mod cli;
 pub mod icons;
+pub mod jobs;
+pub mod local_auth;
 pub mod models;
+pub mod otp;
+pub mod registration;
 pub mod routes;
 pub mod utils;
 
-use axum::extract::{MatchedPath, Request};
+use axum::extract::{MatchedPath, Request, State};
 use axum::http::{header, HeaderValue, Method};
 use axum::middleware::Next;
 use axum::response::IntoResponse;
+use axum::routing::get;
 use axum::{middleware, Router};
 use icons::IconStore;
 use memory_serve::{load_assets, MemoryServe};
-use metrics_exporter_prometheus::{Matcher, PrometheusBuilder, PrometheusHandle};
-use serde::Serialize;
+use metrics_exporter_prometheus::{
+    Matcher, PrometheusBuilder, PrometheusHandle, PrometheusRecorder,
+};
+use serde::{Deserialize, Serialize};
 use sqlx::sqlite::SqliteConnectOptions;
 use sqlx::SqlitePool;
+use std::path::PathBuf;
 use std::sync::Arc;
 use std::time::Duration;
 use tokio::signal;
@@ -30,25 +38,126 @@ use utoipa::{Modify, OpenApi};
 use utoipa_axum::router::OpenApiRouter;
 use utoipa_axum::routes;
 use utoipa_swagger_ui::SwaggerUi;
+use webauthn_rs::prelude::Webauthn;
 
 #[derive(Clone)]
 pub struct ServerOptions {
     pub port: u32,
     pub jwt_secret: String,
+    pub auth_backend: cli::AuthBackend,
+    pub providers: Vec<ProviderOptions>,
+    pub frontfacing: String,
+    pub quotas: Quotas,
+    pub registration: RegistrationOptions,
+    pub icon_cache: IconCacheOptions,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct RegistrationOptions {
+    pub mode: cli::RegistrationMode,
+    /// Email domains allowed to register with the allowlist mode, e.g. `snowflake.blue`.
+    pub allowed_email_domains: Vec<String>,
+    /// IdP groups allowed to register with the allowlist mode, from the `groups` claim.
+    pub allowed_groups: Vec<String>,
+}
+
+/// Limits on what users may store, e.g. for public instances.
+#[derive(Clone, Debug)]
+pub struct Quotas {
+    /// Maximum amount of codes per user, and per vault. Unlimited when `None`.
+    pub max_codes: Option<i64>,
+    /// Maximum length of the content of a code, in characters.
+    pub max_content_length: usize,
+    /// Maximum length of the display name of a code, in characters.
+    pub max_display_name_length: usize,
+}
+
+impl Default for Quotas {
+    fn default() -> Self {
+        Quotas {
+            max_codes: None,
+            max_content_length: 2048,
+            max_display_name_length: 256,
+        }
+    }
+}
+
+/// Where icons of websites are cached, and for how long.
+#[derive(Clone, Debug)]
+pub struct IconCacheOptions {
+    pub dir: PathBuf,
+    /// Days until an icon is fetched from the website again.
+    pub ttl_days: i64,
+    /// Total size in bytes, before the least recently used icons are evicted.
+    pub max_size: u64,
+    /// Hosts, IP addresses and networks icons may be fetched from despite being private.
+    pub fetch_allowlist: Vec<String>,
+    /// Asked in order until one finds an icon.
+    pub providers: Vec<IconProviderOptions>,
+}
+
+/// An icon provider as configured, checked while parsing the options so a bad one fails at startup.
+#[derive(Clone, Debug)]
+pub enum IconProviderOptions {
+    Direct,
+    Service(icons::provider::ServiceProvider),
+    Bundled,
+}
+
+impl Default for IconCacheOptions {
+    fn default() -> Self {
+        IconCacheOptions {
+            // Next to iceblink.db, as uploaded icons must survive a reboot just like the database
+            dir: PathBuf::from("iceblink-icons"),
+            ttl_days: icons::DEFAULT_TTL_DAYS,
+            max_size: icons::DEFAULT_MAX_SIZE,
+            fetch_allowlist: vec![],
+            providers: vec![IconProviderOptions::Direct],
+        }
+    }
+}
+
+/// A single OpenID Connect identity provider, as configured in the providers file.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ProviderOptions {
+    /// Short identifier used in routes, e.g. `/v1/oauth/{id}`.
+    pub id: String,
+    /// Human readable name shown to users.
+    pub name: String,
     pub client_id: String,
     pub client_secret: String,
-    pub oauth_server: String,
+    /// Server with OIDC located at /.well-known/openid-configuration.
+    /// Do not include a trailing slash.
+    pub server: String,
     pub redirect_uri: String,
-    pub frontfacing: String,
+    /// How the authorization code is sent to the token endpoint.
+    #[serde(default)]
+    pub token_request: TokenRequest,
+}
+
+/// Encoding of the token request of an OpenID Connect provider.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenRequest {
+    /// A form-encoded body, as required by RFC 6749.
+    #[default]
+    Form,
+    /// A JSON body, for providers which do not accept forms, like pfapi.
+    Json,
 }
 
 #[derive(Clone)]
 pub struct AppState {
     pub db: SqlitePool,
     pub settings: ServerOptions,
-    pub openid: auth::OpenId,
+    pub openid: auth::OpenIdRegistry,
+    /// Only configured when using the local authentication backend.
+    pub webauthn: Option<Webauthn>,
     pub icon_store: IconStore,
+    /// Gathers icons of codes in the background.
+    pub icon_warmer: jobs::IconWarmer,
     pub metrics: PrometheusHandle,
+    pub recorder: Arc<PrometheusRecorder>,
 }
 
 #[derive(Debug, Serialize)]
@@ -62,7 +171,7 @@ impl Modify for ApiDocumentationBearer {
                     HttpBuilder::new()
                         .scheme(HttpAuthScheme::Bearer)
                         .bearer_format("JWT")
-                        .description(Some("Requires a Json Web Token (JWT) for authentication. Generated by the /v1/oauth endpoint"))
+                        .description(Some("Requires a Json Web Token (JWT) for authentication. Generated by the /v1/oauth endpoint. Personal access tokens from /v1/user/tokens are also accepted"))
                         .build(),
                 ),
             );
@@ -83,6 +192,9 @@ impl Modify for ApiDocumentationBearer {
 	tags(
 		(name = "codes", description = "Code management endpoints"),
 		(name = "user", description = "User endpoints"),
+		(name = "vaults", description = "Shared vault endpoints"),
+		(name = "emergency", description = "Emergency access for trusted contacts"),
+		(name = "admin", description = "Instance administration, only for administrators"),
 		(name = "misc", description = "Other endpoints")
 	),
 	servers(
@@ -112,19 +224,41 @@ struct ApiDocumentation;
 pub fn configure_router(
     pool: &SqlitePool,
     opts: ServerOptions,
-    openid: auth::OpenId,
+    openid: auth::OpenIdRegistry,
+    webauthn: Option<Webauthn>,
     icon_store: IconStore,
 ) -> Router {
+    let recorder = setup_metrics_recorder();
     let state = Arc::new(AppState {
         db: pool.clone(),
         settings: opts.clone(),
         openid,
+        webauthn,
+        icon_warmer: jobs::IconWarmer::spawn(icon_store.clone()),
         icon_store,
-        metrics: setup_metrics_recorder(),
+        metrics: recorder.handle(),
+        recorder: Arc::new(recorder),
     });
 
     // Note: Read bottom to top
     let (router, api) = OpenApiRouter::with_openapi(ApiDocumentation::openapi())
+        .routes(routes!(routes::v1::admin::list_users))
+        .routes(routes!(
+            routes::v1::admin::get_user,
+            routes::v1::admin::delete_user
+        ))
+        .routes(routes!(routes::v1::admin::disable_user))
+        .routes(routes!(routes::v1::admin::enable_user))
+        .routes(routes!(routes::v1::admin::suspend_user))
+        .routes(routes!(routes::v1::admin::unsuspend_user))
+        .routes(routes!(routes::v1::admin::logout_user))
+        .routes(routes!(
+            routes::v1::admin::list_invites,
+            routes::v1::admin::create_invite
+        ))
+        .routes(routes!(routes::v1::admin::delete_invite))
+        .routes(routes!(routes::v1::admin::purge_icons))
+        .layer(middleware::from_fn(auth::admin_middleware))
         .routes(routes!(
             routes::v1::codes::list_all_codes,
             routes::v1::codes::add_code
@@ -133,9 +267,66 @@ pub fn configure_router(
             routes::v1::codes::delete_code,
             routes::v1::codes::edit_code
         ))
-        .routes(routes!(routes::v1::codes::get_code_icon))
-        .routes(routes!(routes::v1::users::delete_account))
+        .routes(routes!(
+            routes::v1::codes::get_code_icon,
+            routes::v1::codes::set_code_icon,
+            routes::v1::codes::delete_code_icon
+        ))
+        .routes(routes!(routes::v1::codes::list_code_icons))
+        .routes(routes!(routes::v1::shares::list_shares))
+        .routes(routes!(
+            routes::v1::shares::share_code,
+            routes::v1::shares::revoke_share
+        ))
+        .routes(routes!(routes::v1::shares::create_share_link))
+        .routes(routes!(
+            routes::v1::vaults::list_vaults,
+            routes::v1::vaults::create_vault
+        ))
+        .routes(routes!(routes::v1::vaults::delete_vault))
+        .routes(routes!(routes::v1::vaults::join_vault))
+        .routes(routes!(routes::v1::vaults::list_vault_codes))
+        .routes(routes!(routes::v1::vaults::list_members))
+        .routes(routes!(
+            routes::v1::vaults::edit_member,
+            routes::v1::vaults::remove_member
+        ))
+        .routes(routes!(routes::v1::vaults::create_invitation))
+        .routes(routes!(routes::v1::emergency::list_contacts))
+        .routes(routes!(
+            routes::v1::emergency::set_contact,
+            routes::v1::emergency::remove_contact
+        ))
+        .routes(routes!(routes::v1::emergency::deny_request))
+        .routes(routes!(routes::v1::emergency::list_grantors))
+        .routes(routes!(routes::v1::emergency::request_access))
+        .routes(routes!(routes::v1::emergency::grantor_codes))
+        .routes(routes!(routes::v1::emergency::list_events))
+        .routes(routes!(
+            routes::v1::users::get_profile,
+            routes::v1::users::edit_profile,
+            routes::v1::users::delete_account
+        ))
+        .routes(routes!(routes::v1::users::cancel_deletion))
+        .routes(routes!(routes::v1::users::final_export))
+        .routes(routes!(routes::v1::users::delete_icons))
+        .routes(routes!(routes::v1::users::export_account))
         .routes(routes!(routes::v1::users::checksum))
+        .routes(routes!(routes::v1::local::passkey_register_start))
+        .routes(routes!(routes::v1::local::passkey_register_finish))
+        .routes(routes!(routes::v1::local::passkey_step_up_start))
+        .routes(routes!(routes::v1::local::passkey_step_up_finish))
+        .routes(routes!(
+            routes::v1::tokens::list_tokens,
+            routes::v1::tokens::create_token
+        ))
+        .routes(routes!(routes::v1::tokens::delete_token))
+        .routes(routes!(routes::v1::users::list_identities))
+        .routes(routes!(routes::v1::device::device_approve))
+        .routes(routes!(
+            routes::v1::users::link_identity,
+            routes::v1::users::unlink_identity
+        ))
         .layer(middleware::from_fn_with_state(
             state.clone(),
             auth::jwt_middleware,
@@ -143,7 +334,17 @@ pub fn configure_router(
         .routes(routes!(routes::v1::misc::instance_metadata))
         .routes(routes!(routes::v1::misc::metrics))
         .routes(routes!(routes::v1::users::oauth))
-        .with_state(state)
+        .routes(routes!(routes::v1::device::device_code))
+        .routes(routes!(routes::v1::device::device_token))
+        .routes(routes!(routes::v1::local::register))
+        .routes(routes!(routes::v1::local::login))
+        .routes(routes!(routes::v1::local::passkey_login_start))
+        .routes(routes!(routes::v1::local::passkey_login_finish))
+        .route(
+            "/s/{token}",
+            get(routes::share_link::view_share_link).post(routes::share_link::reveal_share_link),
+        )
+        .with_state(state.clone())
         .fallback_service(
             MemoryServe::new(load_assets!("./src/static"))
                 .index_file(Some("/landing.html"))
@@ -181,12 +382,13 @@ pub fn configure_router(
                 .zstd(true)
                 .quality(tower_http::CompressionLevel::Fastest),
         )
-        .route_layer(middleware::from_fn(track_metrics))
+        .route_layer(middleware::from_fn_with_state(state, track_metrics))
         .layer(TraceLayer::new_for_http())
         .layer(TimeoutLayer::new(Duration::from_secs(2)))
 }
 
-pub async fn serve(opts: ServerOptions) {
+/// Connects to `iceblink.db`, creating and migrating it as needed.
+pub async fn connect_database() -> SqlitePool {
     info!("Connecting to SQLite: iceblink.db");
     let pool = SqlitePool::connect_with(
         SqliteConnectOptions::new()
@@ -202,21 +404,93 @@ pub async fn serve(opts: ServerOptions) {
         .await
         .expect("Unable to run database migrations");
 
-    info!("Discovering OpenId configuration");
-    let openid = auth::OpenId::discover()
-        .client_id(opts.clone().client_id)
-        .client_secret(opts.clone().client_secret)
-        .server(opts.clone().oauth_server)
+    pool
+}
+
+#[derive(Debug)]
+pub enum SetAdminError {
+    Database(sqlx::Error),
+    UnknownUser,
+    /// Several users share the username. Use the id instead.
+    AmbiguousUsername,
+}
+
+/// Grants or revokes the administrator role of the user with the given id or username.
+pub async fn set_admin(
+    pool: &SqlitePool,
+    user: &str,
+    is_admin: bool,
+) -> Result<models::user::User, SetAdminError> {
+    let mut user = match models::user::User::get_by_id(pool, user.to_string())
+        .await
+        .map_err(SetAdminError::Database)?
+    {
+        Some(user) => user,
+        None => {
+            let mut users = models::user::User::get_by_username(pool, user.to_string())
+                .await
+                .map_err(SetAdminError::Database)?;
+
+            match users.len() {
+                0 => return Err(SetAdminError::UnknownUser),
+                1 => users.remove(0),
+                _ => return Err(SetAdminError::AmbiguousUsername),
+            }
+        }
+    };
+
+    user.edit()
+        .pool(pool)
+        .is_admin(is_admin)
         .call()
         .await
-        .expect("Unable to setup OpenId authentication");
+        .map_err(SetAdminError::Database)?;
+
+    Ok(user)
+}
+
+pub async fn serve(opts: ServerOptions) {
+    let pool = connect_database().await;
+
+    let (openid, webauthn) = match opts.auth_backend {
+        cli::AuthBackend::OpenId => {
+            info!("Discovering OpenId configuration");
+            let openid = auth::OpenIdRegistry::discover(&opts.providers)
+                .await
+                .expect("Unable to setup OpenId authentication");
+            (openid, None)
+        }
+        cli::AuthBackend::Local => {
+            info!("Using local authentication");
+            let webauthn =
+                local_auth::webauthn(&opts.frontfacing).expect("Unable to setup WebAuthn");
+            (auth::OpenIdRegistry::default(), Some(webauthn))
+        }
+    };
+
+    info!("Loading icon cache from {}", opts.icon_cache.dir.display());
+    let icon_store = IconStore::new_with_custom_base(opts.icon_cache.dir.clone())
+        .with_ttl(chrono::Duration::days(opts.icon_cache.ttl_days))
+        .with_max_size(opts.icon_cache.max_size)
+        .with_allowlist(icons::guard::Allowlist::new(
+            &opts.icon_cache.fetch_allowlist,
+        ))
+        .with_providers(icon_providers(&opts.icon_cache))
+        .init()
+        .await
+        .expect("Unable to load icon cache")
+        .clone();
+
+    info!("Starting background jobs");
+    jobs::spawn(pool.clone(), icon_store.clone());
 
     info!("Configuring HTTP router");
     let routes = configure_router()
         .pool(&pool)
         .opts(opts.clone())
         .openid(openid)
-        .icon_store(IconStore::new().init().await.unwrap().clone())
+        .maybe_webauthn(webauthn)
+        .icon_store(icon_store)
         .call();
 
     info!("Starting HTTP server");
@@ -257,20 +531,37 @@ async fn shutdown_signal() {
     info!("Exit imminent")
 }
 
-fn setup_metrics_recorder() -> PrometheusHandle {
+fn icon_providers(opts: &IconCacheOptions) -> Vec<Box<dyn icons::provider::IconProvider>> {
+    opts.providers
+        .iter()
+        .map(|provider| -> Box<dyn icons::provider::IconProvider> {
+            match provider {
+                IconProviderOptions::Direct => Box::new(icons::provider::DirectProvider),
+                IconProviderOptions::Service(service) => Box::new(service.clone()),
+                IconProviderOptions::Bundled => Box::new(icons::pack::BundledProvider::default()),
+            }
+        })
+        .collect()
+}
+
+fn setup_metrics_recorder() -> PrometheusRecorder {
     const EXPONENTIAL_SECONDS: &[f64] = &[0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0, 10.0];
 
+    // Not installed globally, so that every router (e.g. one per test) has its own metrics
     PrometheusBuilder::new()
         .set_buckets_for_metric(
             Matcher::Full("http_requests_duration_seconds".to_string()),
             EXPONENTIAL_SECONDS,
         )
         .unwrap()
-        .install_recorder()
-        .unwrap()
+        .build_recorder()
 }
 
-async fn track_metrics(req: Request, next: Next) -> impl IntoResponse {
+async fn track_metrics(
+    State(state): State<Arc<AppState>>,
+    req: Request,
+    next: Next,
+) -> impl IntoResponse {
     let start = Instant::now();
     let path = if let Some(matched_path) = req.extensions().get::<MatchedPath>() {
         matched_path.as_str().to_owned()
@@ -290,8 +581,10 @@ async fn track_metrics(req: Request, next: Next) -> impl IntoResponse {
         ("status", status),
     ];
 
-    metrics::counter!("http_requests_total", &labels).increment(1);
-    metrics::histogram!("http_requests_duration_seconds", &labels).record(latency);
+    metrics::with_local_recorder(state.recorder.as_ref(), || {
+        metrics::counter!("http_requests_total", &labels).increment(1);
+        metrics::histogram!("http_requests_duration_seconds", &labels).record(latency);
+    });
 
     response
 }
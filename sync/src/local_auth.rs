@@ -0,0 +1,107 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use sha2::{Digest, Sha256};
+use std::sync::LazyLock;
+use webauthn_rs::prelude::{Url, Uuid, Webauthn, WebauthnBuilder, WebauthnResult};
+
+pub const MINIMUM_PASSWORD_LENGTH: usize = 8;
+
+/// Hash of a random password, which nobody knows.
+static DUMMY_HASH: LazyLock<String> = LazyLock::new(|| {
+    Argon2::default()
+        .hash_password(
+            SaltString::generate(&mut OsRng).as_str().as_bytes(),
+            &SaltString::generate(&mut OsRng),
+        )
+        .expect("Unable to hash password")
+        .to_string()
+});
+
+/// Hashes a password with Argon2id. Runs on the blocking thread pool, as hashing is intentionally slow.
+pub async fn hash_password(password: String) -> String {
+    tokio::task::spawn_blocking(move || {
+        Argon2::default()
+            .hash_password(password.as_bytes(), &SaltString::generate(&mut OsRng))
+            .expect("Unable to hash password")
+            .to_string()
+    })
+    .await
+    .expect("Password hashing task panicked")
+}
+
+/// Verifies a password against its hash. Without a hash, e.g. for unknown users, a dummy hash is verified against
+/// instead, so the response time does not reveal which users exist.
+pub async fn verify_password(password: String, hash: Option<String>) -> bool {
+    tokio::task::spawn_blocking(move || {
+        let valid = PasswordHash::new(hash.as_deref().unwrap_or(&DUMMY_HASH))
+            .map(|parsed| {
+                Argon2::default()
+                    .verify_password(password.as_bytes(), &parsed)
+                    .is_ok()
+            })
+            .unwrap_or(false);
+
+        hash.is_some() && valid
+    })
+    .await
+    .expect("Password verification task panicked")
+}
+
+/// Configures WebAuthn with the frontfacing URL as both relying party id and origin.
+pub fn webauthn(frontfacing: &str) -> WebauthnResult<Webauthn> {
+    let origin =
+        Url::parse(frontfacing).map_err(|_| webauthn_rs::prelude::WebauthnError::Configuration)?;
+    let rp_id = origin
+        .host_str()
+        .ok_or(webauthn_rs::prelude::WebauthnError::Configuration)?
+        .to_string();
+
+    WebauthnBuilder::new(&rp_id, &origin)?
+        .rp_name("Iceblink")
+        .build()
+}
+
+/// WebAuthn requires a UUID user handle, while our user ids are random strings.
+pub fn user_handle(user_id: &str) -> Uuid {
+    Uuid::from_slice(&Sha256::digest(user_id)[..16]).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googletest::prelude::*;
+
+    #[gtest]
+    #[tokio::test]
+    async fn verify_hashed_password() {
+        let hash = hash_password("correct horse".to_string()).await;
+
+        assert_that!(hash, starts_with("$argon2id$"));
+        assert_that!(
+            verify_password("correct horse".to_string(), Some(hash.clone())).await,
+            is_true()
+        );
+        assert_that!(
+            verify_password("wrong horse".to_string(), Some(hash)).await,
+            is_false()
+        );
+        assert_that!(
+            verify_password("correct horse".to_string(), None).await,
+            is_false()
+        );
+    }
+
+    #[gtest]
+    fn user_handle_is_stable() {
+        assert_that!(
+            user_handle("k0d8WrkRjK6gkc3C"),
+            eq(user_handle("k0d8WrkRjK6gkc3C"))
+        );
+        assert_that!(
+            user_handle("k0d8WrkRjK6gkc3C"),
+            not(eq(user_handle("3Ck0d8WrkRjK6gkc")))
+        );
+    }
+}
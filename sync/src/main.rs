@@ -1,50 +1,163 @@
 use iceblink_sync::cli;
-use iceblink_sync::ServerOptions;
+use iceblink_sync::{
+    IconCacheOptions, ProviderOptions, Quotas, RegistrationOptions, ServerOptions, TokenRequest,
+};
 use std::error::Error;
 use tracing::info;
 
+/// OpenId server used when neither a server nor a providers file is given.
+const PFAPI_SERVER: &str = "https://pfapi.snowflake.blue";
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn Error>> {
     let _ = dotenvy::dotenv();
     let settings = cli::get_settings();
 
     tracing_subscriber::fmt()
-        .with_max_level(settings.logging.unwrap_or_else(|| {
-            if cfg!(debug_assertions) {
-                cli::LoggingLevel::Debug
-            } else {
-                cli::LoggingLevel::Info
-            }
+        .with_max_level(settings.logging.unwrap_or(if cfg!(debug_assertions) {
+            cli::LoggingLevel::Debug
+        } else {
+            cli::LoggingLevel::Info
         }))
         .init();
 
     match &settings.command {
         cli::Commands::Serve {
             port,
+            auth_backend,
+            providers,
             client_id,
             client_secret,
             oauth_server,
             jwt_secret,
             redirect_uri,
             frontfacing,
+            max_codes,
+            max_content_length,
+            max_display_name_length,
+            registration,
+            registration_email_domains,
+            registration_groups,
+            icon_cache_dir,
+            icon_cache_ttl_days,
+            icon_cache_max_size,
+            icon_fetch_allowlist,
+            icon_providers,
+            icon_service_url,
         } => {
             info!("Iceblink Sync Server");
 
+            let auth_backend = auth_backend.clone().unwrap_or_default();
+            let providers = match (&auth_backend, providers) {
+                (cli::AuthBackend::Local, _) => vec![],
+                (cli::AuthBackend::OpenId, Some(path)) => {
+                    cli::read_providers(path).expect("Unable to read OpenId providers file")
+                }
+                (cli::AuthBackend::OpenId, None) => {
+                    let server = oauth_server.clone().unwrap_or(PFAPI_SERVER.to_string());
+
+                    vec![ProviderOptions {
+                        id: "default".to_string(),
+                        name: "Default".to_string(),
+                        client_id: client_id
+                            .clone()
+                            .expect("An OAuth client id or providers file is required"),
+                        client_secret: client_secret
+                            .clone()
+                            .expect("An OAuth client secret or providers file is required"),
+                        redirect_uri: redirect_uri
+                            .clone()
+                            .expect("An OAuth redirect URI or providers file is required"),
+                        // pfapi only accepts JSON token requests
+                        token_request: if server == PFAPI_SERVER {
+                            TokenRequest::Json
+                        } else {
+                            TokenRequest::Form
+                        },
+                        server,
+                    }]
+                }
+            };
+
             iceblink_sync::serve(ServerOptions {
                 port: port.unwrap_or(8085),
-                client_id: client_id.to_string(),
-                client_secret: client_secret.to_string(),
-                oauth_server: oauth_server
-                    .clone()
-                    .unwrap_or("https://pfapi.snowflake.blue".to_string()),
-                redirect_uri: redirect_uri.to_string(),
+                auth_backend,
+                providers,
                 jwt_secret: jwt_secret.to_string(),
                 frontfacing: frontfacing
                     .clone()
                     .unwrap_or("http://localhost:8085".to_string()),
+                quotas: {
+                    let defaults = Quotas::default();
+                    Quotas {
+                        max_codes: *max_codes,
+                        max_content_length: max_content_length
+                            .unwrap_or(defaults.max_content_length),
+                        max_display_name_length: max_display_name_length
+                            .unwrap_or(defaults.max_display_name_length),
+                    }
+                },
+                registration: RegistrationOptions {
+                    mode: registration.clone().unwrap_or_default(),
+                    allowed_email_domains: registration_email_domains.clone(),
+                    allowed_groups: registration_groups.clone(),
+                },
+                icon_cache: {
+                    let defaults = IconCacheOptions::default();
+                    IconCacheOptions {
+                        dir: icon_cache_dir.clone().unwrap_or(defaults.dir),
+                        ttl_days: icon_cache_ttl_days.unwrap_or(defaults.ttl_days),
+                        max_size: icon_cache_max_size
+                            .map(|megabytes| megabytes.saturating_mul(1024 * 1024))
+                            .unwrap_or(defaults.max_size),
+                        fetch_allowlist: icon_fetch_allowlist.clone(),
+                        providers: cli::parse_icon_providers(
+                            icon_providers,
+                            icon_service_url.as_deref(),
+                        )?,
+                    }
+                },
             })
             .await;
         }
+        cli::Commands::Admin { user, revoke } => {
+            let pool = iceblink_sync::connect_database().await;
+            let user = iceblink_sync::set_admin(&pool, user, !revoke)
+                .await
+                .expect("Unable to change the administrator role");
+
+            if user.is_admin {
+                info!("{} ({}) is now an administrator", user.username, user.id);
+            } else {
+                info!(
+                    "{} ({}) is no longer an administrator",
+                    user.username, user.id
+                );
+            }
+        }
+        cli::Commands::PurgeIcons { icon_cache_dir } => {
+            let dir = icon_cache_dir
+                .clone()
+                .unwrap_or(IconCacheOptions::default().dir);
+            let count = iceblink_sync::icons::IconStore::new_with_custom_base(dir)
+                .init()
+                .await
+                .expect("Unable to load icon cache")
+                .purge()
+                .await
+                .expect("Unable to purge icon cache");
+
+            info!("Purged the icons of {count} websites");
+        }
+        cli::Commands::Invite { expires_in_days } => {
+            let pool = iceblink_sync::connect_database().await;
+            let (code, _) =
+                iceblink_sync::registration::create_invite(&pool, None, *expires_in_days)
+                    .await
+                    .expect("Unable to create invite");
+
+            println!("{code}");
+        }
     }
 
     Ok(())
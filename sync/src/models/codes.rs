@@ -1,46 +1,166 @@
+use super::share::SharePermission;
 use serde::{Deserialize, Serialize};
 use sqlx::SqlitePool;
 
 #[derive(Serialize, Deserialize, Clone, Debug, sqlx::FromRow, utoipa::ToSchema, PartialEq)]
 pub struct Code {
     pub id: String,
-    pub owner_id: String,
+    /// Set for personal codes. Exactly one of `owner_id` and `vault_id` is set.
+    pub owner_id: Option<String>,
+    /// Set for codes shared through a vault.
+    pub vault_id: Option<String>,
     pub content: String,
     pub display_name: String,
     pub icon_url: Option<String>,
     pub website_url: Option<String>,
+    /// Set when another user shared this code with you.
+    pub shared_permission: Option<SharePermission>,
 }
 
 #[bon::bon]
 impl Code {
+    /// Finds a code owned by, or shared with, the user.
     pub async fn get(
         pool: &SqlitePool,
         id: String,
-        owner_id: String,
+        user_id: String,
     ) -> Result<Option<Code>, sqlx::error::Error> {
         sqlx::query_as!(
             Code,
-            "SELECT * FROM codes WHERE id = ? AND owner_id = ?",
+            r#"SELECT codes.id, codes.owner_id, codes.vault_id, codes.content, codes.display_name, codes.icon_url, codes.website_url, code_shares.permission as "shared_permission?: SharePermission" FROM codes LEFT JOIN code_shares ON code_shares.code_id = codes.id AND code_shares.grantee_id = $2 WHERE codes.id = $1 AND (codes.owner_id = $2 OR code_shares.grantee_id = $2)"#,
             id,
-            owner_id
+            user_id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Does not check whether the user has access to the code.
+    pub async fn get_by_id(
+        pool: &SqlitePool,
+        id: String,
+    ) -> Result<Option<Code>, sqlx::error::Error> {
+        sqlx::query_as!(
+            Code,
+            r#"SELECT id, owner_id, vault_id, content, display_name, icon_url, website_url, NULL as "shared_permission?: SharePermission" FROM codes WHERE id = ?"#,
+            id
         )
         .fetch_optional(pool)
         .await
     }
 
+    pub async fn get_many_in_vault(
+        pool: &SqlitePool,
+        vault_id: String,
+    ) -> Result<Vec<Code>, sqlx::error::Error> {
+        sqlx::query_as!(
+            Code,
+            r#"SELECT id, owner_id, vault_id, content, display_name, icon_url, website_url, NULL as "shared_permission?: SharePermission" FROM codes WHERE vault_id = ?"#,
+            vault_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Codes owned by, or shared with, the user.
     pub async fn get_many(
         pool: &SqlitePool,
-        owner_id: String,
+        user_id: String,
     ) -> Result<Vec<Code>, sqlx::error::Error> {
-        sqlx::query_as!(Code, "SELECT * FROM codes WHERE owner_id = ?", owner_id)
+        sqlx::query_as!(
+            Code,
+            r#"SELECT codes.id, codes.owner_id, codes.vault_id, codes.content, codes.display_name, codes.icon_url, codes.website_url, code_shares.permission as "shared_permission?: SharePermission" FROM codes LEFT JOIN code_shares ON code_shares.code_id = codes.id AND code_shares.grantee_id = $1 WHERE codes.owner_id = $1 OR code_shares.grantee_id = $1"#,
+            user_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Codes owned by, or shared with, the user, together with the codes of every vault they are a member of.
+    pub async fn get_many_visible(
+        pool: &SqlitePool,
+        user_id: String,
+    ) -> Result<Vec<Code>, sqlx::error::Error> {
+        sqlx::query_as!(
+            Code,
+            r#"SELECT codes.id, codes.owner_id, codes.vault_id, codes.content, codes.display_name, codes.icon_url, codes.website_url, code_shares.permission as "shared_permission?: SharePermission" FROM codes LEFT JOIN code_shares ON code_shares.code_id = codes.id AND code_shares.grantee_id = $1 WHERE codes.owner_id = $1 OR code_shares.grantee_id = $1 OR codes.vault_id IN (SELECT vault_id FROM vault_members WHERE user_id = $1)"#,
+            user_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Whether any code still uses the website, e.g. to know if its icon can be removed.
+    pub async fn website_in_use(
+        pool: &SqlitePool,
+        website_url: String,
+    ) -> Result<bool, sqlx::error::Error> {
+        let count = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM codes WHERE website_url = ?",
+            website_url
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(count > 0)
+    }
+
+    /// Whether a code not owned by the user uses the website, e.g. one shared with them or in a vault.
+    pub async fn website_used_by_others(
+        pool: &SqlitePool,
+        website_url: String,
+        user_id: String,
+    ) -> Result<bool, sqlx::error::Error> {
+        let count = sqlx::query_scalar!(
+            "SELECT COUNT(*) FROM codes WHERE website_url = $1 AND (owner_id IS NULL OR owner_id != $2)",
+            website_url,
+            user_id
+        )
+        .fetch_one(pool)
+        .await?;
+
+        Ok(count > 0)
+    }
+
+    /// Every website and code id, so icons of neither can be garbage collected.
+    pub async fn icon_references(
+        pool: &SqlitePool,
+    ) -> Result<(Vec<String>, Vec<String>), sqlx::error::Error> {
+        let websites = sqlx::query_scalar!(
+            r#"SELECT DISTINCT website_url as "website_url!" FROM codes WHERE website_url IS NOT NULL"#
+        )
+        .fetch_all(pool)
+        .await?;
+        let ids = sqlx::query_scalar!("SELECT id FROM codes")
             .fetch_all(pool)
+            .await?;
+
+        Ok((websites, ids))
+    }
+
+    /// Amount of personal codes of the user, excluding codes shared with them.
+    pub async fn count_owned(
+        pool: &SqlitePool,
+        owner_id: String,
+    ) -> Result<i64, sqlx::error::Error> {
+        sqlx::query_scalar!("SELECT COUNT(*) FROM codes WHERE owner_id = ?", owner_id)
+            .fetch_one(pool)
+            .await
+    }
+
+    pub async fn count_in_vault(
+        pool: &SqlitePool,
+        vault_id: String,
+    ) -> Result<i64, sqlx::error::Error> {
+        sqlx::query_scalar!("SELECT COUNT(*) FROM codes WHERE vault_id = ?", vault_id)
+            .fetch_one(pool)
             .await
     }
 
     pub async fn insert(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
         sqlx::query!(
-			"INSERT INTO codes (id, owner_id, content, display_name, icon_url, website_url) VALUES ($1, $2, $3, $4, $5, $6)",
-			self.id, self.owner_id, self.content, self.display_name, self.icon_url, self.website_url).execute(pool).await?;
+			"INSERT INTO codes (id, owner_id, vault_id, content, display_name, icon_url, website_url) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+			self.id, self.owner_id, self.vault_id, self.content, self.display_name, self.icon_url, self.website_url).execute(pool).await?;
 
         Ok(())
     }
@@ -101,29 +221,43 @@ impl Code {
         }
 
         if let Some(website_url_inner) = website_url {
+            // The icon of the previous website no longer applies, unlike an uploaded one
+            let custom_icon_url = Self::custom_icon_url(&self.id);
+            let icon_url_inner = self
+                .icon_url
+                .take()
+                .filter(|icon_url| *icon_url == custom_icon_url);
+
             sqlx::query!(
-                "UPDATE codes SET website_url = $2, icon_url = NULL WHERE id = $1",
+                "UPDATE codes SET website_url = $2, icon_url = $3 WHERE id = $1",
                 self.id,
-                website_url_inner
+                website_url_inner,
+                icon_url_inner
             )
             .execute(&mut *tx)
             .await?;
 
             self.website_url = website_url_inner;
-            self.icon_url = None;
+            self.icon_url = icon_url_inner;
         };
 
         tx.commit().await?;
         Ok(self)
     }
 
+    /// Where the icon uploaded for a code is served, which is set as its `icon_url`.
+    pub fn custom_icon_url(id: &str) -> String {
+        format!("/v1/code/{id}/icon")
+    }
+
     pub fn fmt_for_hasher(&self) -> String {
         format!(
-            "{}{}{}{}",
+            "{}{}{}{}{}",
             self.content,
             self.display_name,
             self.icon_url.clone().unwrap_or("".to_string()),
-            self.website_url.clone().unwrap_or("".to_string())
+            self.website_url.clone().unwrap_or("".to_string()),
+            self.shared_permission.map_or("", SharePermission::as_str)
         )
     }
 }
@@ -0,0 +1,140 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json, SqliteExecutor, SqlitePool};
+use webauthn_rs::prelude::{Passkey, PasskeyAuthentication, PasskeyRegistration};
+
+#[derive(Clone, Debug, sqlx::FromRow)]
+pub struct LocalCredential {
+    pub user_id: String,
+    /// Argon2id hash in PHC string format.
+    pub password_hash: String,
+}
+
+impl LocalCredential {
+    pub async fn get(
+        pool: &SqlitePool,
+        user_id: String,
+    ) -> Result<Option<LocalCredential>, sqlx::error::Error> {
+        sqlx::query_as!(
+            LocalCredential,
+            "SELECT * FROM local_credentials WHERE user_id = ?",
+            user_id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    pub async fn insert(
+        &self,
+        executor: impl SqliteExecutor<'_>,
+    ) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "INSERT INTO local_credentials (user_id, password_hash) VALUES ($1, $2)",
+            self.user_id,
+            self.password_hash
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, sqlx::FromRow)]
+pub struct StoredPasskey {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    pub passkey: Json<Passkey>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl StoredPasskey {
+    pub async fn get_many(
+        pool: &SqlitePool,
+        user_id: String,
+    ) -> Result<Vec<StoredPasskey>, sqlx::error::Error> {
+        sqlx::query_as!(
+            StoredPasskey,
+            r#"SELECT id, user_id, name, passkey as "passkey: Json<Passkey>", created_at as "created_at: DateTime<Utc>" FROM passkeys WHERE user_id = ?"#,
+            user_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn insert(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "INSERT INTO passkeys (id, user_id, name, passkey, created_at) VALUES ($1, $2, $3, $4, $5)",
+            self.id,
+            self.user_id,
+            self.name,
+            self.passkey,
+            self.created_at
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Persists the passkey, e.g. after its signature counter was updated by an authentication.
+    pub async fn update(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "UPDATE passkeys SET passkey = $2 WHERE id = $1",
+            self.id,
+            self.passkey
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub enum CeremonyState {
+    Registration(PasskeyRegistration),
+    Authentication(PasskeyAuthentication),
+}
+
+#[derive(Clone, Debug, sqlx::FromRow)]
+pub struct WebauthnChallenge {
+    pub id: String,
+    /// Missing for decoy logins of unknown users, and of users without passkeys.
+    pub user_id: Option<String>,
+    pub state: Json<CeremonyState>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl WebauthnChallenge {
+    /// Fetches and removes a challenge, so that it can only be used once. Expired challenges are never returned.
+    pub async fn take(
+        pool: &SqlitePool,
+        id: String,
+    ) -> Result<Option<WebauthnChallenge>, sqlx::error::Error> {
+        let now = Utc::now();
+        sqlx::query_as!(
+            WebauthnChallenge,
+            r#"DELETE FROM webauthn_challenges WHERE id = ? RETURNING id, user_id as "user_id?", state as "state: Json<CeremonyState>", expires_at as "expires_at: DateTime<Utc>""#,
+            id
+        )
+        .fetch_optional(pool)
+        .await
+        .map(|challenge| challenge.filter(|c| c.expires_at > now))
+    }
+
+    pub async fn insert(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "INSERT INTO webauthn_challenges (id, user_id, state, expires_at) VALUES ($1, $2, $3, $4)",
+            self.id,
+            self.user_id,
+            self.state,
+            self.expires_at
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
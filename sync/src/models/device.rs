@@ -0,0 +1,114 @@
+use chrono::{DateTime, Utc};
+use sqlx::SqlitePool;
+
+/// A device authorization grant, waiting for the user to approve it and the device to poll for its token.
+#[derive(Clone, Debug, sqlx::FromRow)]
+pub struct DeviceAuthorization {
+    pub device_code_hash: String,
+    /// Stored normalized, without the separator shown to the user.
+    pub user_code: String,
+    /// Set once the user has approved the device.
+    pub user_id: Option<String>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub last_polled_at: Option<DateTime<Utc>>,
+}
+
+impl DeviceAuthorization {
+    pub async fn get_by_device_code_hash(
+        pool: &SqlitePool,
+        device_code_hash: String,
+    ) -> Result<Option<DeviceAuthorization>, sqlx::error::Error> {
+        sqlx::query_as!(
+            DeviceAuthorization,
+            r#"SELECT device_code_hash, user_code, user_id, created_at as "created_at: DateTime<Utc>", expires_at as "expires_at: DateTime<Utc>", last_polled_at as "last_polled_at: DateTime<Utc>" FROM device_authorizations WHERE device_code_hash = ?"#,
+            device_code_hash
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    pub async fn get_by_user_code(
+        pool: &SqlitePool,
+        user_code: String,
+    ) -> Result<Option<DeviceAuthorization>, sqlx::error::Error> {
+        sqlx::query_as!(
+            DeviceAuthorization,
+            r#"SELECT device_code_hash, user_code, user_id, created_at as "created_at: DateTime<Utc>", expires_at as "expires_at: DateTime<Utc>", last_polled_at as "last_polled_at: DateTime<Utc>" FROM device_authorizations WHERE user_code = ?"#,
+            user_code
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    pub async fn insert(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "INSERT INTO device_authorizations (device_code_hash, user_code, user_id, created_at, expires_at, last_polled_at) VALUES ($1, $2, $3, $4, $5, $6)",
+            self.device_code_hash,
+            self.user_code,
+            self.user_id,
+            self.created_at,
+            self.expires_at,
+            self.last_polled_at
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn approve(
+        &mut self,
+        pool: &SqlitePool,
+        user_id: String,
+    ) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "UPDATE device_authorizations SET user_id = $2 WHERE device_code_hash = $1",
+            self.device_code_hash,
+            user_id
+        )
+        .execute(pool)
+        .await?;
+
+        self.user_id = Some(user_id);
+        Ok(())
+    }
+
+    pub async fn mark_polled(&mut self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        let now = Utc::now();
+        sqlx::query!(
+            "UPDATE device_authorizations SET last_polled_at = $2 WHERE device_code_hash = $1",
+            self.device_code_hash,
+            now
+        )
+        .execute(pool)
+        .await?;
+
+        self.last_polled_at = Some(now);
+        Ok(())
+    }
+
+    pub async fn delete(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "DELETE FROM device_authorizations WHERE device_code_hash = $1",
+            self.device_code_hash
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Removes grants which were never completed in time.
+    pub async fn delete_expired(pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        let now = Utc::now();
+        sqlx::query!(
+            "DELETE FROM device_authorizations WHERE expires_at <= $1",
+            now
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+use crate::utils;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+#[derive(
+    Serialize, Deserialize, sqlx::Type, utoipa::ToSchema, Clone, Copy, Debug, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum EmergencyStatus {
+    /// The contact has not requested access.
+    Idle,
+    /// The contact requested access, which is granted after the waiting period.
+    Requested,
+    /// The contact can read the codes of the owner.
+    Granted,
+}
+
+/// A trusted contact, which may gain read access to the personal codes of the owner.
+#[derive(Serialize, Deserialize, Clone, Debug, sqlx::FromRow, utoipa::ToSchema, PartialEq)]
+pub struct EmergencyContact {
+    pub owner_id: String,
+    pub contact_id: String,
+    /// Days between a request and access being granted, during which the owner can deny it.
+    pub wait_days: i64,
+    pub status: EmergencyStatus,
+    pub requested_at: Option<DateTime<Utc>>,
+    /// When the pending request will be granted, unless the owner denies it.
+    pub grants_at: Option<DateTime<Utc>>,
+    pub created_at: DateTime<Utc>,
+}
+
+impl EmergencyContact {
+    pub async fn get(
+        pool: &SqlitePool,
+        owner_id: String,
+        contact_id: String,
+    ) -> Result<Option<EmergencyContact>, sqlx::error::Error> {
+        sqlx::query_as!(
+            EmergencyContact,
+            r#"SELECT owner_id, contact_id, wait_days, status as "status: EmergencyStatus", requested_at as "requested_at?: DateTime<Utc>", grants_at as "grants_at?: DateTime<Utc>", created_at as "created_at: DateTime<Utc>" FROM emergency_contacts WHERE owner_id = ? AND contact_id = ?"#,
+            owner_id,
+            contact_id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Contacts the owner has designated.
+    pub async fn get_by_owner(
+        pool: &SqlitePool,
+        owner_id: String,
+    ) -> Result<Vec<EmergencyContact>, sqlx::error::Error> {
+        sqlx::query_as!(
+            EmergencyContact,
+            r#"SELECT owner_id, contact_id, wait_days, status as "status: EmergencyStatus", requested_at as "requested_at?: DateTime<Utc>", grants_at as "grants_at?: DateTime<Utc>", created_at as "created_at: DateTime<Utc>" FROM emergency_contacts WHERE owner_id = ? ORDER BY created_at"#,
+            owner_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Owners who have designated the user as their contact.
+    pub async fn get_by_contact(
+        pool: &SqlitePool,
+        contact_id: String,
+    ) -> Result<Vec<EmergencyContact>, sqlx::error::Error> {
+        sqlx::query_as!(
+            EmergencyContact,
+            r#"SELECT owner_id, contact_id, wait_days, status as "status: EmergencyStatus", requested_at as "requested_at?: DateTime<Utc>", grants_at as "grants_at?: DateTime<Utc>", created_at as "created_at: DateTime<Utc>" FROM emergency_contacts WHERE contact_id = ? ORDER BY created_at"#,
+            contact_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn insert(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "INSERT INTO emergency_contacts (owner_id, contact_id, wait_days, status, requested_at, grants_at, created_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            self.owner_id,
+            self.contact_id,
+            self.wait_days,
+            self.status,
+            self.requested_at,
+            self.grants_at,
+            self.created_at
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Only affects future requests, pending requests keep their original waiting period.
+    pub async fn set_wait_days(
+        &mut self,
+        pool: &SqlitePool,
+        wait_days: i64,
+    ) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "UPDATE emergency_contacts SET wait_days = $3 WHERE owner_id = $1 AND contact_id = $2",
+            self.owner_id,
+            self.contact_id,
+            wait_days
+        )
+        .execute(pool)
+        .await?;
+
+        self.wait_days = wait_days;
+        Ok(())
+    }
+
+    /// Starts the waiting period.
+    pub async fn request(
+        &mut self,
+        pool: &SqlitePool,
+        now: DateTime<Utc>,
+    ) -> Result<(), sqlx::error::Error> {
+        let grants_at = now + chrono::Duration::days(self.wait_days);
+        sqlx::query!(
+            "UPDATE emergency_contacts SET status = 'requested', requested_at = $3, grants_at = $4 WHERE owner_id = $1 AND contact_id = $2",
+            self.owner_id,
+            self.contact_id,
+            now,
+            grants_at
+        )
+        .execute(pool)
+        .await?;
+
+        self.status = EmergencyStatus::Requested;
+        self.requested_at = Some(now);
+        self.grants_at = Some(grants_at);
+        Ok(())
+    }
+
+    /// Denies a pending request, or revokes granted access.
+    pub async fn reset(&mut self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "UPDATE emergency_contacts SET status = 'idle', requested_at = NULL, grants_at = NULL WHERE owner_id = $1 AND contact_id = $2",
+            self.owner_id,
+            self.contact_id
+        )
+        .execute(pool)
+        .await?;
+
+        self.status = EmergencyStatus::Idle;
+        self.requested_at = None;
+        self.grants_at = None;
+        Ok(())
+    }
+
+    /// Grants every request whose waiting period is over, recording it in the audit log.
+    /// Returns the amount of granted requests.
+    pub async fn grant_due(
+        pool: &SqlitePool,
+        now: DateTime<Utc>,
+    ) -> Result<usize, sqlx::error::Error> {
+        let mut transaction = pool.begin().await?;
+
+        let granted = sqlx::query!(
+            "UPDATE emergency_contacts SET status = 'granted' WHERE status = 'requested' AND grants_at <= $1 RETURNING owner_id, contact_id",
+            now
+        )
+        .fetch_all(&mut *transaction)
+        .await?;
+
+        for contact in &granted {
+            let id = utils::generate_id(16);
+            sqlx::query!(
+                "INSERT INTO emergency_events (id, owner_id, contact_id, kind, created_at) VALUES ($1, $2, $3, 'granted', $4)",
+                id,
+                contact.owner_id,
+                contact.contact_id,
+                now
+            )
+            .execute(&mut *transaction)
+            .await?;
+        }
+
+        transaction.commit().await?;
+        Ok(granted.len())
+    }
+
+    pub async fn delete(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "DELETE FROM emergency_contacts WHERE owner_id = $1 AND contact_id = $2",
+            self.owner_id,
+            self.contact_id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(
+    Serialize, Deserialize, sqlx::Type, utoipa::ToSchema, Clone, Copy, Debug, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum EmergencyEventKind {
+    /// The owner designated the contact, or changed the waiting period.
+    Designated,
+    /// The owner removed the contact.
+    Removed,
+    Requested,
+    /// The owner denied a pending request, or revoked granted access.
+    Denied,
+    Granted,
+    /// The contact read the codes of the owner.
+    Accessed,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, sqlx::FromRow, utoipa::ToSchema, PartialEq)]
+pub struct EmergencyEvent {
+    pub id: String,
+    /// Cleared if the owner deleted their account.
+    pub owner_id: Option<String>,
+    /// Cleared if the contact deleted their account.
+    pub contact_id: Option<String>,
+    pub kind: EmergencyEventKind,
+    pub created_at: DateTime<Utc>,
+}
+
+impl EmergencyEvent {
+    /// Events where the user is either the owner or the contact, newest first.
+    pub async fn get_many(
+        pool: &SqlitePool,
+        user_id: String,
+    ) -> Result<Vec<EmergencyEvent>, sqlx::error::Error> {
+        sqlx::query_as!(
+            EmergencyEvent,
+            r#"SELECT id, owner_id, contact_id, kind as "kind: EmergencyEventKind", created_at as "created_at: DateTime<Utc>" FROM emergency_events WHERE owner_id = $1 OR contact_id = $1 ORDER BY created_at DESC"#,
+            user_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn record(
+        pool: &SqlitePool,
+        contact: &EmergencyContact,
+        kind: EmergencyEventKind,
+    ) -> Result<(), sqlx::error::Error> {
+        let id = utils::generate_id(16);
+        let now = Utc::now();
+        sqlx::query!(
+            "INSERT INTO emergency_events (id, owner_id, contact_id, kind, created_at) VALUES ($1, $2, $3, $4, $5)",
+            id,
+            contact.owner_id,
+            contact.contact_id,
+            kind,
+            now
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use chrono::{DateTime, Utc};
+use sqlx::SqlitePool;
+
+/// The final export of an account, taken when its deletion is scheduled. Kept until the account is deleted, or
+/// the deletion is cancelled.
+#[derive(Debug, Clone)]
+pub struct FinalExport {
+    pub user_id: String,
+    /// The export as JSON.
+    pub content: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl FinalExport {
+    pub async fn get(
+        pool: &SqlitePool,
+        user_id: String,
+    ) -> Result<Option<FinalExport>, sqlx::error::Error> {
+        sqlx::query_as!(
+            FinalExport,
+            r#"SELECT user_id, content, created_at as "created_at: DateTime<Utc>" FROM account_exports WHERE user_id = ?"#,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Stores the export, replacing any previous one of the user.
+    pub async fn insert(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "INSERT OR REPLACE INTO account_exports (user_id, content, created_at) VALUES ($1, $2, $3)",
+            self.user_id,
+            self.content,
+            self.created_at
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(pool: &SqlitePool, user_id: String) -> Result<(), sqlx::error::Error> {
+        sqlx::query!("DELETE FROM account_exports WHERE user_id = ?", user_id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,65 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{SqliteExecutor, SqlitePool};
+
+/// An upstream IdP account linked to an Iceblink user.
+#[derive(Serialize, Deserialize, Clone, Debug, sqlx::FromRow, utoipa::ToSchema, PartialEq)]
+pub struct Identity {
+    pub user_id: String,
+    pub provider: String,
+    pub upstream_userid: String,
+    pub linked_at: DateTime<Utc>,
+}
+
+impl Identity {
+    pub async fn get(
+        pool: &SqlitePool,
+        provider: String,
+        upstream_userid: String,
+    ) -> Result<Option<Identity>, sqlx::error::Error> {
+        sqlx::query_as!(
+            Identity,
+            r#"SELECT user_id, provider, upstream_userid, linked_at as "linked_at: DateTime<Utc>" FROM user_identities WHERE provider = ? AND upstream_userid = ?"#,
+            provider,
+            upstream_userid
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    pub async fn get_many(
+        pool: &SqlitePool,
+        user_id: String,
+    ) -> Result<Vec<Identity>, sqlx::error::Error> {
+        sqlx::query_as!(
+            Identity,
+            r#"SELECT user_id, provider, upstream_userid, linked_at as "linked_at: DateTime<Utc>" FROM user_identities WHERE user_id = ? ORDER BY linked_at"#,
+            user_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn insert(
+        &self,
+        executor: impl SqliteExecutor<'_>,
+    ) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+			"INSERT INTO user_identities (user_id, provider, upstream_userid, linked_at) VALUES ($1, $2, $3, $4)",
+			self.user_id, self.provider, self.upstream_userid, self.linked_at).execute(executor).await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "DELETE FROM user_identities WHERE provider = $1 AND upstream_userid = $2",
+            self.provider,
+            self.upstream_userid
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
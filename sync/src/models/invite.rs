@@ -0,0 +1,101 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{SqliteExecutor, SqlitePool};
+
+/// Invite code allowing a new user to register, when registration is limited to invites.
+#[derive(Serialize, Deserialize, Clone, Debug, sqlx::FromRow, utoipa::ToSchema, PartialEq)]
+pub struct RegistrationInvite {
+    pub id: String,
+    #[serde(skip)]
+    pub code_hash: String,
+    /// Administrator who created the invite. Missing for invites created using the CLI.
+    pub created_by: Option<String>,
+    pub created_at: DateTime<Utc>,
+    /// Never expires when missing.
+    pub expires_at: Option<DateTime<Utc>>,
+    pub used_by: Option<String>,
+    pub used_at: Option<DateTime<Utc>>,
+}
+
+impl RegistrationInvite {
+    pub async fn get(
+        pool: &SqlitePool,
+        id: String,
+    ) -> Result<Option<RegistrationInvite>, sqlx::error::Error> {
+        sqlx::query_as!(
+            RegistrationInvite,
+            r#"SELECT id, code_hash, created_by, created_at as "created_at: DateTime<Utc>", expires_at as "expires_at?: DateTime<Utc>", used_by, used_at as "used_at?: DateTime<Utc>" FROM registration_invites WHERE id = ?"#,
+            id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    pub async fn get_many(
+        pool: &SqlitePool,
+    ) -> Result<Vec<RegistrationInvite>, sqlx::error::Error> {
+        sqlx::query_as!(
+            RegistrationInvite,
+            r#"SELECT id, code_hash, created_by, created_at as "created_at: DateTime<Utc>", expires_at as "expires_at?: DateTime<Utc>", used_by, used_at as "used_at?: DateTime<Utc>" FROM registration_invites ORDER BY created_at DESC"#
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Marks an unused and unexpired invite as used, so that it can only be used once.
+    pub async fn take(
+        executor: impl SqliteExecutor<'_>,
+        code_hash: String,
+    ) -> Result<Option<RegistrationInvite>, sqlx::error::Error> {
+        let now = Utc::now();
+        sqlx::query_as!(
+            RegistrationInvite,
+            r#"UPDATE registration_invites SET used_at = $2 WHERE code_hash = $1 AND used_at IS NULL AND (expires_at IS NULL OR expires_at > $2) RETURNING id, code_hash, created_by, created_at as "created_at: DateTime<Utc>", expires_at as "expires_at?: DateTime<Utc>", used_by, used_at as "used_at?: DateTime<Utc>""#,
+            code_hash,
+            now
+        )
+        .fetch_optional(executor)
+        .await
+    }
+
+    /// Records who registered using the invite, after their account is created.
+    pub async fn set_used_by(
+        &mut self,
+        executor: impl SqliteExecutor<'_>,
+        user_id: String,
+    ) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "UPDATE registration_invites SET used_by = $2 WHERE id = $1",
+            self.id,
+            user_id
+        )
+        .execute(executor)
+        .await?;
+
+        self.used_by = Some(user_id);
+        Ok(())
+    }
+
+    pub async fn insert(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "INSERT INTO registration_invites (id, code_hash, created_by, created_at, expires_at) VALUES ($1, $2, $3, $4, $5)",
+            self.id,
+            self.code_hash,
+            self.created_by,
+            self.created_at,
+            self.expires_at
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!("DELETE FROM registration_invites WHERE id = $1", self.id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -1,2 +1,11 @@
 pub mod codes;
+pub mod credentials;
+pub mod device;
+pub mod emergency;
+pub mod export;
+pub mod identity;
+pub mod invite;
+pub mod share;
+pub mod token;
 pub mod user;
+pub mod vault;
@@ -0,0 +1,160 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::SqlitePool;
+
+#[derive(
+    Serialize, Deserialize, sqlx::Type, utoipa::ToSchema, Clone, Copy, Debug, PartialEq, Eq,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum SharePermission {
+    /// See the code and fetch its icon.
+    Read,
+    /// Also edit the code. Only the owner can delete it.
+    Write,
+}
+
+impl SharePermission {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            SharePermission::Read => "read",
+            SharePermission::Write => "write",
+        }
+    }
+}
+
+/// Grant on a single personal code for another user.
+#[derive(Serialize, Deserialize, Clone, Debug, sqlx::FromRow, utoipa::ToSchema, PartialEq)]
+pub struct CodeShare {
+    pub code_id: String,
+    pub grantee_id: String,
+    pub permission: SharePermission,
+    pub created_at: DateTime<Utc>,
+}
+
+impl CodeShare {
+    pub async fn get(
+        pool: &SqlitePool,
+        code_id: String,
+        grantee_id: String,
+    ) -> Result<Option<CodeShare>, sqlx::error::Error> {
+        sqlx::query_as!(
+            CodeShare,
+            r#"SELECT code_id, grantee_id, permission as "permission: SharePermission", created_at as "created_at: DateTime<Utc>" FROM code_shares WHERE code_id = ? AND grantee_id = ?"#,
+            code_id,
+            grantee_id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    pub async fn get_many(
+        pool: &SqlitePool,
+        code_id: String,
+    ) -> Result<Vec<CodeShare>, sqlx::error::Error> {
+        sqlx::query_as!(
+            CodeShare,
+            r#"SELECT code_id, grantee_id, permission as "permission: SharePermission", created_at as "created_at: DateTime<Utc>" FROM code_shares WHERE code_id = ? ORDER BY created_at"#,
+            code_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Inserts the share, or updates the permission if the code was already shared with the grantee.
+    pub async fn upsert(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "INSERT INTO code_shares (code_id, grantee_id, permission, created_at) VALUES ($1, $2, $3, $4) ON CONFLICT (code_id, grantee_id) DO UPDATE SET permission = excluded.permission",
+            self.code_id,
+            self.grantee_id,
+            self.permission,
+            self.created_at
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "DELETE FROM code_shares WHERE code_id = $1 AND grantee_id = $2",
+            self.code_id,
+            self.grantee_id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// Single-use link for viewing the current one-time password of a code without an account.
+#[derive(Serialize, Deserialize, Clone, Debug, sqlx::FromRow, utoipa::ToSchema, PartialEq)]
+pub struct ShareLink {
+    #[serde(skip)]
+    pub token_hash: String,
+    pub code_id: String,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl ShareLink {
+    /// Looks up the link without consuming it, returning it only if it has not expired yet.
+    pub async fn get(
+        pool: &SqlitePool,
+        token_hash: String,
+    ) -> Result<Option<ShareLink>, sqlx::error::Error> {
+        let now = Utc::now();
+        sqlx::query_as!(
+            ShareLink,
+            r#"SELECT token_hash, code_id, created_by, created_at as "created_at: DateTime<Utc>", expires_at as "expires_at: DateTime<Utc>" FROM share_links WHERE token_hash = ? AND expires_at > ?"#,
+            token_hash,
+            now
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Consumes the link, returning it only if it has not expired yet.
+    pub async fn take(
+        pool: &SqlitePool,
+        token_hash: String,
+    ) -> Result<Option<ShareLink>, sqlx::error::Error> {
+        let now = Utc::now();
+        sqlx::query_as!(
+            ShareLink,
+            r#"DELETE FROM share_links WHERE token_hash = ? RETURNING token_hash, code_id, created_by, created_at as "created_at: DateTime<Utc>", expires_at as "expires_at: DateTime<Utc>""#,
+            token_hash
+        )
+        .fetch_optional(pool)
+        .await
+        .map(|link| link.filter(|l| l.expires_at > now))
+    }
+
+    pub async fn insert(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "INSERT INTO share_links (token_hash, code_id, created_by, created_at, expires_at) VALUES ($1, $2, $3, $4, $5)",
+            self.token_hash,
+            self.code_id,
+            self.created_by,
+            self.created_at,
+            self.expires_at
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Removes links which were never opened in time.
+    pub async fn delete_expired(pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        let now = Utc::now();
+        sqlx::query!("DELETE FROM share_links WHERE expires_at <= $1", now)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use crate::auth::Scope;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{types::Json, SqlitePool};
+
+/// A personal access token. The token itself is only known to the user, we store its hash.
+#[derive(Serialize, Deserialize, Clone, Debug, sqlx::FromRow, utoipa::ToSchema, PartialEq)]
+pub struct ApiToken {
+    pub id: String,
+    #[serde(skip)]
+    pub user_id: String,
+    pub name: String,
+    #[serde(skip)]
+    pub token_hash: String,
+    #[schema(value_type = Vec<Scope>)]
+    pub scopes: Json<Vec<Scope>>,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+    pub last_used_at: Option<DateTime<Utc>>,
+}
+
+impl ApiToken {
+    pub async fn get(
+        pool: &SqlitePool,
+        id: String,
+        user_id: String,
+    ) -> Result<Option<ApiToken>, sqlx::error::Error> {
+        sqlx::query_as!(
+            ApiToken,
+            r#"SELECT id, user_id, name, token_hash, scopes as "scopes: Json<Vec<Scope>>", created_at as "created_at: DateTime<Utc>", expires_at as "expires_at: DateTime<Utc>", last_used_at as "last_used_at: DateTime<Utc>" FROM api_tokens WHERE id = ? AND user_id = ?"#,
+            id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    pub async fn get_by_hash(
+        pool: &SqlitePool,
+        token_hash: String,
+    ) -> Result<Option<ApiToken>, sqlx::error::Error> {
+        sqlx::query_as!(
+            ApiToken,
+            r#"SELECT id, user_id, name, token_hash, scopes as "scopes: Json<Vec<Scope>>", created_at as "created_at: DateTime<Utc>", expires_at as "expires_at: DateTime<Utc>", last_used_at as "last_used_at: DateTime<Utc>" FROM api_tokens WHERE token_hash = ?"#,
+            token_hash
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    pub async fn get_many(
+        pool: &SqlitePool,
+        user_id: String,
+    ) -> Result<Vec<ApiToken>, sqlx::error::Error> {
+        sqlx::query_as!(
+            ApiToken,
+            r#"SELECT id, user_id, name, token_hash, scopes as "scopes: Json<Vec<Scope>>", created_at as "created_at: DateTime<Utc>", expires_at as "expires_at: DateTime<Utc>", last_used_at as "last_used_at: DateTime<Utc>" FROM api_tokens WHERE user_id = ? ORDER BY created_at"#,
+            user_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn insert(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+			"INSERT INTO api_tokens (id, user_id, name, token_hash, scopes, created_at, expires_at, last_used_at) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)",
+			self.id, self.user_id, self.name, self.token_hash, self.scopes, self.created_at, self.expires_at, self.last_used_at).execute(pool).await?;
+
+        Ok(())
+    }
+
+    pub async fn mark_used(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        let now = Utc::now();
+        sqlx::query!(
+            "UPDATE api_tokens SET last_used_at = $2 WHERE id = $1",
+            self.id,
+            now
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn delete(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!("DELETE FROM api_tokens WHERE id = $1", self.id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+}
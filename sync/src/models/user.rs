@@ -1,5 +1,6 @@
+use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
-use sqlx::SqlitePool;
+use sqlx::{SqliteExecutor, SqlitePool};
 
 #[derive(Serialize, Deserialize, Clone, Debug, sqlx::FromRow)]
 pub struct User {
@@ -7,36 +8,264 @@ pub struct User {
     pub username: String,
     pub display_name: String,
     pub avatar_url: String,
-    pub upstream_userid: String,
+    pub display_name_override: Option<String>,
+    /// Set while the account is waiting to be deleted.
+    pub deletion_scheduled_for: Option<DateTime<Utc>>,
+    /// Administrators of the instance may use the `/v1/admin` routes.
+    pub is_admin: bool,
+    /// Set while an administrator has disabled the account.
+    pub disabled_at: Option<DateTime<Utc>>,
+    /// JWTs issued before this are rejected, e.g. after an administrator forced a logout.
+    pub sessions_valid_after: Option<DateTime<Utc>>,
+    /// Suspended accounts can only read, e.g. to export their codes.
+    pub suspended: bool,
+    /// Whether the server may contact the websites of the codes to fetch their icons.
+    pub icon_fetching: bool,
 }
 
+#[bon::bon]
 impl User {
     pub async fn get_by_id(
         pool: &SqlitePool,
         id: String,
     ) -> Result<Option<User>, sqlx::error::Error> {
-        sqlx::query_as!(User, "SELECT * FROM users WHERE id = ?", id)
-            .fetch_optional(pool)
-            .await
+        sqlx::query_as!(
+            User,
+            r#"SELECT id, username, display_name, avatar_url, display_name_override, deletion_scheduled_for as "deletion_scheduled_for: DateTime<Utc>", is_admin, disabled_at as "disabled_at: DateTime<Utc>", sessions_valid_after as "sessions_valid_after: DateTime<Utc>", suspended, icon_fetching FROM users WHERE id = ?"#,
+            id
+        )
+        .fetch_optional(pool)
+        .await
     }
 
     pub async fn get_by_upstream_id(
         pool: &SqlitePool,
+        provider: String,
         id: String,
     ) -> Result<Option<User>, sqlx::error::Error> {
-        sqlx::query_as!(User, "SELECT * FROM users WHERE upstream_userid = ?", id)
-            .fetch_optional(pool)
-            .await
+        sqlx::query_as!(
+            User,
+            r#"SELECT users.id, users.username, users.display_name, users.avatar_url, users.display_name_override, users.deletion_scheduled_for as "deletion_scheduled_for: DateTime<Utc>", is_admin, disabled_at as "disabled_at: DateTime<Utc>", sessions_valid_after as "sessions_valid_after: DateTime<Utc>", suspended, icon_fetching FROM users INNER JOIN user_identities ON users.id = user_identities.user_id WHERE user_identities.provider = ? AND user_identities.upstream_userid = ?"#,
+            provider,
+            id
+        )
+        .fetch_optional(pool)
+        .await
     }
 
-    pub async fn insert(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+    /// Usernames are only unique per identity provider, so there may be several.
+    pub async fn get_by_username(
+        pool: &SqlitePool,
+        username: String,
+    ) -> Result<Vec<User>, sqlx::error::Error> {
+        sqlx::query_as!(
+            User,
+            r#"SELECT id, username, display_name, avatar_url, display_name_override, deletion_scheduled_for as "deletion_scheduled_for: DateTime<Utc>", is_admin, disabled_at as "disabled_at: DateTime<Utc>", sessions_valid_after as "sessions_valid_after: DateTime<Utc>", suspended, icon_fetching FROM users WHERE username = ?"#,
+            username
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Matches the id exactly, or part of the username or display name.
+    pub async fn search(
+        pool: &SqlitePool,
+        query: String,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<User>, sqlx::error::Error> {
+        let pattern = format!("%{query}%");
+        sqlx::query_as!(
+            User,
+            r#"SELECT id, username, display_name, avatar_url, display_name_override, deletion_scheduled_for as "deletion_scheduled_for: DateTime<Utc>", is_admin, disabled_at as "disabled_at: DateTime<Utc>", sessions_valid_after as "sessions_valid_after: DateTime<Utc>", suspended, icon_fetching FROM users WHERE id = $1 OR username LIKE $2 OR display_name LIKE $2 OR display_name_override LIKE $2 ORDER BY username, id LIMIT $3 OFFSET $4"#,
+            query,
+            pattern,
+            limit,
+            offset
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Accounts whose grace period is over.
+    pub async fn get_due_for_deletion(
+        pool: &SqlitePool,
+        now: DateTime<Utc>,
+    ) -> Result<Vec<User>, sqlx::error::Error> {
+        sqlx::query_as!(
+            User,
+            r#"SELECT id, username, display_name, avatar_url, display_name_override, deletion_scheduled_for as "deletion_scheduled_for: DateTime<Utc>", is_admin, disabled_at as "disabled_at: DateTime<Utc>", sessions_valid_after as "sessions_valid_after: DateTime<Utc>", suspended, icon_fetching FROM users WHERE deletion_scheduled_for <= ?"#,
+            now
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn insert(
+        &self,
+        executor: impl SqliteExecutor<'_>,
+    ) -> Result<(), sqlx::error::Error> {
         sqlx::query!(
-			"INSERT INTO users (id, username, display_name, avatar_url, upstream_userid) VALUES ($1, $2, $3, $4, $5)",
-			self.id, self.username, self.display_name, self.avatar_url, self.upstream_userid).execute(pool).await?;
+            "INSERT INTO users (id, username, display_name, avatar_url) VALUES ($1, $2, $3, $4)",
+            self.id,
+            self.username,
+            self.display_name,
+            self.avatar_url
+        )
+        .execute(executor)
+        .await?;
 
         Ok(())
     }
 
+    /// Display name to show, preferring the user's own override over the one from the IdP.
+    pub fn effective_display_name(&self) -> String {
+        self.display_name_override
+            .clone()
+            .unwrap_or(self.display_name.clone())
+    }
+
+    #[builder]
+    pub async fn edit(
+        &mut self,
+        pool: &SqlitePool,
+        username: Option<String>,
+        display_name: Option<String>,
+        avatar_url: Option<String>,
+        display_name_override: Option<Option<String>>,
+        deletion_scheduled_for: Option<Option<DateTime<Utc>>>,
+        is_admin: Option<bool>,
+        disabled_at: Option<Option<DateTime<Utc>>>,
+        sessions_valid_after: Option<DateTime<Utc>>,
+        suspended: Option<bool>,
+        icon_fetching: Option<bool>,
+    ) -> Result<&User, sqlx::error::Error> {
+        let mut tx = pool.begin().await?;
+
+        if let Some(username_inner) = username {
+            sqlx::query!(
+                "UPDATE users SET username = $2 WHERE id = $1",
+                self.id,
+                username_inner
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            self.username = username_inner;
+        }
+
+        if let Some(display_name_inner) = display_name {
+            sqlx::query!(
+                "UPDATE users SET display_name = $2 WHERE id = $1",
+                self.id,
+                display_name_inner
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            self.display_name = display_name_inner;
+        }
+
+        if let Some(avatar_url_inner) = avatar_url {
+            sqlx::query!(
+                "UPDATE users SET avatar_url = $2 WHERE id = $1",
+                self.id,
+                avatar_url_inner
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            self.avatar_url = avatar_url_inner;
+        }
+
+        if let Some(display_name_override_inner) = display_name_override {
+            sqlx::query!(
+                "UPDATE users SET display_name_override = $2 WHERE id = $1",
+                self.id,
+                display_name_override_inner
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            self.display_name_override = display_name_override_inner;
+        }
+
+        if let Some(deletion_scheduled_for_inner) = deletion_scheduled_for {
+            sqlx::query!(
+                "UPDATE users SET deletion_scheduled_for = $2 WHERE id = $1",
+                self.id,
+                deletion_scheduled_for_inner
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            self.deletion_scheduled_for = deletion_scheduled_for_inner;
+        }
+
+        if let Some(is_admin_inner) = is_admin {
+            sqlx::query!(
+                "UPDATE users SET is_admin = $2 WHERE id = $1",
+                self.id,
+                is_admin_inner
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            self.is_admin = is_admin_inner;
+        }
+
+        if let Some(disabled_at_inner) = disabled_at {
+            sqlx::query!(
+                "UPDATE users SET disabled_at = $2 WHERE id = $1",
+                self.id,
+                disabled_at_inner
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            self.disabled_at = disabled_at_inner;
+        }
+
+        if let Some(sessions_valid_after_inner) = sessions_valid_after {
+            sqlx::query!(
+                "UPDATE users SET sessions_valid_after = $2 WHERE id = $1",
+                self.id,
+                sessions_valid_after_inner
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            self.sessions_valid_after = Some(sessions_valid_after_inner);
+        }
+
+        if let Some(suspended_inner) = suspended {
+            sqlx::query!(
+                "UPDATE users SET suspended = $2 WHERE id = $1",
+                self.id,
+                suspended_inner
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            self.suspended = suspended_inner;
+        }
+
+        if let Some(icon_fetching_inner) = icon_fetching {
+            sqlx::query!(
+                "UPDATE users SET icon_fetching = $2 WHERE id = $1",
+                self.id,
+                icon_fetching_inner
+            )
+            .execute(&mut *tx)
+            .await?;
+
+            self.icon_fetching = icon_fetching_inner;
+        }
+
+        tx.commit().await?;
+        Ok(self)
+    }
+
     pub async fn delete(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
         sqlx::query!("DELETE from users WHERE id = $1", self.id)
             .execute(pool)
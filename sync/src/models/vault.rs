@@ -0,0 +1,280 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use sqlx::{SqliteExecutor, SqlitePool};
+
+/// Role of a vault member. Every role includes the permissions of the roles before it.
+#[derive(
+    Serialize,
+    Deserialize,
+    sqlx::Type,
+    utoipa::ToSchema,
+    Clone,
+    Copy,
+    Debug,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+)]
+#[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "lowercase")]
+pub enum VaultRole {
+    /// List codes and fetch their icons.
+    Viewer,
+    /// Add, edit and delete codes.
+    Editor,
+    /// Manage members, invitations and the vault itself.
+    Owner,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, sqlx::FromRow, utoipa::ToSchema, PartialEq)]
+pub struct Vault {
+    pub id: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Vault {
+    pub async fn get(pool: &SqlitePool, id: String) -> Result<Option<Vault>, sqlx::error::Error> {
+        sqlx::query_as!(
+            Vault,
+            r#"SELECT id, name, created_at as "created_at: DateTime<Utc>" FROM vaults WHERE id = ?"#,
+            id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    pub async fn insert(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "INSERT INTO vaults (id, name, created_at) VALUES ($1, $2, $3)",
+            self.id,
+            self.name,
+            self.created_at
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    /// Also deletes every code in the vault.
+    pub async fn delete(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!("DELETE FROM vaults WHERE id = $1", self.id)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    /// Deletes vaults without members, e.g. after every member deleted their account.
+    pub async fn delete_orphaned(pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "DELETE FROM vaults WHERE id NOT IN (SELECT DISTINCT vault_id FROM vault_members)"
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, sqlx::FromRow, utoipa::ToSchema, PartialEq)]
+pub struct VaultMember {
+    pub vault_id: String,
+    pub user_id: String,
+    pub role: VaultRole,
+    pub joined_at: DateTime<Utc>,
+}
+
+/// A vault, together with the role of the current user in it.
+#[derive(Serialize, Deserialize, Clone, Debug, sqlx::FromRow, utoipa::ToSchema, PartialEq)]
+pub struct VaultMembership {
+    pub id: String,
+    pub name: String,
+    pub created_at: DateTime<Utc>,
+    pub role: VaultRole,
+}
+
+impl VaultMember {
+    pub async fn get(
+        pool: &SqlitePool,
+        vault_id: String,
+        user_id: String,
+    ) -> Result<Option<VaultMember>, sqlx::error::Error> {
+        sqlx::query_as!(
+            VaultMember,
+            r#"SELECT vault_id, user_id, role as "role: VaultRole", joined_at as "joined_at: DateTime<Utc>" FROM vault_members WHERE vault_id = ? AND user_id = ?"#,
+            vault_id,
+            user_id
+        )
+        .fetch_optional(pool)
+        .await
+    }
+
+    pub async fn get_many(
+        pool: &SqlitePool,
+        vault_id: String,
+    ) -> Result<Vec<VaultMember>, sqlx::error::Error> {
+        sqlx::query_as!(
+            VaultMember,
+            r#"SELECT vault_id, user_id, role as "role: VaultRole", joined_at as "joined_at: DateTime<Utc>" FROM vault_members WHERE vault_id = ? ORDER BY joined_at"#,
+            vault_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn get_memberships(
+        pool: &SqlitePool,
+        user_id: String,
+    ) -> Result<Vec<VaultMembership>, sqlx::error::Error> {
+        sqlx::query_as!(
+            VaultMembership,
+            r#"SELECT vaults.id, vaults.name, vaults.created_at as "created_at: DateTime<Utc>", vault_members.role as "role: VaultRole" FROM vaults INNER JOIN vault_members ON vaults.id = vault_members.vault_id WHERE vault_members.user_id = ? ORDER BY vaults.created_at"#,
+            user_id
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    pub async fn insert(
+        &self,
+        executor: impl SqliteExecutor<'_>,
+    ) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "INSERT INTO vault_members (vault_id, user_id, role, joined_at) VALUES ($1, $2, $3, $4)",
+            self.vault_id,
+            self.user_id,
+            self.role,
+            self.joined_at
+        )
+        .execute(executor)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn set_role(
+        &mut self,
+        pool: &SqlitePool,
+        role: VaultRole,
+    ) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "UPDATE vault_members SET role = $3 WHERE vault_id = $1 AND user_id = $2",
+            self.vault_id,
+            self.user_id,
+            role
+        )
+        .execute(pool)
+        .await?;
+
+        self.role = role;
+        Ok(())
+    }
+
+    /// Promotes the longest-standing other member of every vault the user is the only owner of, so that deleting
+    /// the user leaves no vault without an owner. Vaults without other members are deleted with the user instead.
+    pub async fn promote_successors(
+        pool: &SqlitePool,
+        user_id: String,
+    ) -> Result<(), sqlx::error::Error> {
+        let owned = VaultMember::get_memberships(pool, user_id.clone())
+            .await?
+            .into_iter()
+            .filter(|membership| membership.role == VaultRole::Owner);
+
+        for vault in owned {
+            let others: Vec<_> = VaultMember::get_many(pool, vault.id)
+                .await?
+                .into_iter()
+                .filter(|member| member.user_id != user_id)
+                .collect();
+
+            if others.iter().any(|member| member.role == VaultRole::Owner) {
+                continue;
+            }
+            if let Some(mut successor) = others.into_iter().next() {
+                successor.set_role(pool, VaultRole::Owner).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn delete(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "DELETE FROM vault_members WHERE vault_id = $1 AND user_id = $2",
+            self.vault_id,
+            self.user_id
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, sqlx::FromRow, utoipa::ToSchema, PartialEq)]
+pub struct VaultInvitation {
+    pub id: String,
+    pub vault_id: String,
+    #[serde(skip)]
+    pub code_hash: String,
+    /// Role the invitee gets when joining.
+    pub role: VaultRole,
+    pub created_by: String,
+    pub created_at: DateTime<Utc>,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl VaultInvitation {
+    /// Fetches an invitation without using it up. Expired invitations are never returned.
+    pub async fn get(
+        pool: &SqlitePool,
+        code_hash: String,
+    ) -> Result<Option<VaultInvitation>, sqlx::error::Error> {
+        let now = Utc::now();
+        sqlx::query_as!(
+            VaultInvitation,
+            r#"SELECT id, vault_id, code_hash, role as "role: VaultRole", created_by, created_at as "created_at: DateTime<Utc>", expires_at as "expires_at: DateTime<Utc>" FROM vault_invitations WHERE code_hash = ?"#,
+            code_hash
+        )
+        .fetch_optional(pool)
+        .await
+        .map(|invitation| invitation.filter(|i| i.expires_at > now))
+    }
+
+    /// Fetches and removes an invitation, so that it can only be used once. Expired invitations are never returned.
+    pub async fn take(
+        executor: impl SqliteExecutor<'_>,
+        code_hash: String,
+    ) -> Result<Option<VaultInvitation>, sqlx::error::Error> {
+        let now = Utc::now();
+        sqlx::query_as!(
+            VaultInvitation,
+            r#"DELETE FROM vault_invitations WHERE code_hash = ? RETURNING id, vault_id, code_hash, role as "role: VaultRole", created_by, created_at as "created_at: DateTime<Utc>", expires_at as "expires_at: DateTime<Utc>""#,
+            code_hash
+        )
+        .fetch_optional(executor)
+        .await
+        .map(|invitation| invitation.filter(|i| i.expires_at > now))
+    }
+
+    pub async fn insert(&self, pool: &SqlitePool) -> Result<(), sqlx::error::Error> {
+        sqlx::query!(
+            "INSERT INTO vault_invitations (id, vault_id, code_hash, role, created_by, created_at, expires_at) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+            self.id,
+            self.vault_id,
+            self.code_hash,
+            self.role,
+            self.created_by,
+            self.created_at,
+            self.expires_at
+        )
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,141 @@
+//! Decides whether new accounts may be created, according to the registration mode of the instance.
+
+use crate::{
+    cli::RegistrationMode, models::invite::RegistrationInvite, routes::v1::ApiError, utils,
+    AppState, RegistrationOptions,
+};
+use chrono::Utc;
+use sqlx::{SqliteExecutor, SqlitePool};
+
+/// Cookie holding the invite code during the OAuth flow, set by the landing page.
+pub const INVITE_COOKIE: &str = "iceblink_invite";
+
+/// Someone about to get a new account.
+#[derive(Default)]
+pub struct Applicant<'a> {
+    pub email: Option<&'a str>,
+    /// Only emails the IdP marks as verified match the allowlist.
+    pub email_verified: Option<bool>,
+    pub groups: &'a [String],
+    pub invite_code: Option<&'a str>,
+}
+
+fn is_allowlisted(options: &RegistrationOptions, applicant: &Applicant) -> bool {
+    let domain_allowed = applicant.email_verified == Some(true)
+        && applicant
+            .email
+            .and_then(|email| email.rsplit_once('@'))
+            .is_some_and(|(_, domain)| {
+                options
+                    .allowed_email_domains
+                    .iter()
+                    .any(|allowed| allowed.eq_ignore_ascii_case(domain))
+            });
+
+    let group_allowed = applicant
+        .groups
+        .iter()
+        .any(|group| options.allowed_groups.contains(group));
+
+    domain_allowed || group_allowed
+}
+
+/// Checks whether the applicant may register. In the invite mode the invite is used up, and returned
+/// so that the new user can be recorded on it. Pass the transaction creating the account, so that
+/// the invite is not lost if that fails.
+pub async fn authorize(
+    state: &AppState,
+    executor: impl SqliteExecutor<'_>,
+    applicant: Applicant<'_>,
+) -> Result<Option<RegistrationInvite>, ApiError> {
+    let options = &state.settings.registration;
+
+    match options.mode {
+        RegistrationMode::Open => Ok(None),
+        RegistrationMode::Closed => Err(ApiError::RegistrationClosed),
+        RegistrationMode::Allowlist if is_allowlisted(options, &applicant) => Ok(None),
+        RegistrationMode::Allowlist => Err(ApiError::RegistrationNotAllowed),
+        RegistrationMode::Invite => {
+            let code = applicant.invite_code.ok_or(ApiError::InvalidInviteCode)?;
+
+            RegistrationInvite::take(executor, utils::hash_token(code))
+                .await?
+                .ok_or(ApiError::InvalidInviteCode)
+                .map(Some)
+        }
+    }
+}
+
+/// Creates a new invite, returning the code which is only available now.
+pub async fn create_invite(
+    pool: &SqlitePool,
+    created_by: Option<String>,
+    expires_in_days: Option<i64>,
+) -> Result<(String, RegistrationInvite), sqlx::Error> {
+    let code = utils::generate_id(24);
+    let now = Utc::now();
+
+    let invite = RegistrationInvite {
+        id: utils::generate_id(16),
+        code_hash: utils::hash_token(&code),
+        created_by,
+        created_at: now,
+        expires_at: expires_in_days.map(|days| now + chrono::Duration::days(days)),
+        used_by: None,
+        used_at: None,
+    };
+    invite.insert(pool).await?;
+
+    Ok((code, invite))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googletest::prelude::*;
+
+    fn options() -> RegistrationOptions {
+        RegistrationOptions {
+            mode: RegistrationMode::Allowlist,
+            allowed_email_domains: vec!["snowflake.blue".into()],
+            allowed_groups: vec!["iceblink-users".into()],
+        }
+    }
+
+    #[gtest]
+    fn allowlist_email_domain() {
+        let allowed = |email, email_verified| {
+            is_allowlisted(
+                &options(),
+                &Applicant {
+                    email: Some(email),
+                    email_verified,
+                    ..Default::default()
+                },
+            )
+        };
+
+        expect_that!(allowed("erb3@snowflake.blue", None), eq(false));
+        expect_that!(allowed("erb3@SNOWFLAKE.blue", Some(true)), eq(true));
+        expect_that!(allowed("erb3@snowflake.blue", Some(false)), eq(false));
+        expect_that!(allowed("erb3@evil-snowflake.blue", Some(true)), eq(false));
+        expect_that!(allowed("snowflake.blue", Some(true)), eq(false));
+    }
+
+    #[gtest]
+    fn allowlist_groups() {
+        let groups = vec!["staff".to_string(), "iceblink-users".to_string()];
+        expect_that!(
+            is_allowlisted(
+                &options(),
+                &Applicant {
+                    groups: &groups,
+                    ..Default::default()
+                }
+            ),
+            eq(true)
+        );
+
+        expect_that!(is_allowlisted(&options(), &Applicant::default()), eq(false));
+    }
+}
@@ -1 +1,2 @@
+pub mod share_link;
 pub mod v1;
@@ -0,0 +1,115 @@
+use crate::{
+    models::{codes::Code, share::ShareLink},
+    otp, utils, AppState,
+};
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap, StatusCode},
+};
+use std::sync::Arc;
+
+const TEMPLATE: &str = include_str!("../templates/share_link.html");
+const REVEAL_FORM: &str = r#"<form method="post"><button type="submit">Show code</button></form>"#;
+
+fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn render(
+    status: StatusCode,
+    title: &str,
+    message: &str,
+    code: &str,
+    form: &str,
+) -> (StatusCode, HeaderMap, String) {
+    let mut headers = HeaderMap::new();
+    headers.insert(
+        header::CONTENT_TYPE,
+        "text/html; charset=utf-8".parse().unwrap(),
+    );
+    // The page is only valid once, and must not leak the token
+    headers.insert(header::CACHE_CONTROL, "no-store".parse().unwrap());
+    headers.insert(header::REFERRER_POLICY, "no-referrer".parse().unwrap());
+
+    let page = TEMPLATE
+        .replace("{{title}}", &escape_html(title))
+        .replace("{{message}}", &escape_html(message))
+        .replace("{{code}}", &escape_html(code))
+        .replace("{{form}}", form);
+
+    (status, headers, page)
+}
+
+fn expired() -> (StatusCode, HeaderMap, String) {
+    render(
+        StatusCode::NOT_FOUND,
+        "Link expired",
+        "This link has expired or was already used. Ask for a new one.",
+        "",
+        "",
+    )
+}
+
+fn failed() -> (StatusCode, HeaderMap, String) {
+    render(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        "Something went wrong",
+        "Unable to open the link. Try again later.",
+        "",
+        "",
+    )
+}
+
+/// Asks for confirmation before revealing the code, so link previews do not consume the link.
+pub async fn view_share_link(
+    State(state): State<Arc<AppState>>,
+    Path(token): Path<String>,
+) -> (StatusCode, HeaderMap, String) {
+    match ShareLink::get(&state.db, utils::hash_token(&token)).await {
+        Ok(Some(_)) => render(
+            StatusCode::OK,
+            "Shared code",
+            "Someone shared a one-time password with you. It can only be shown once.",
+            "",
+            REVEAL_FORM,
+        ),
+        Ok(None) => expired(),
+        Err(_) => failed(),
+    }
+}
+
+/// Shows the current one-time password of a shared code, consuming the link.
+pub async fn reveal_share_link(
+    State(state): State<Arc<AppState>>,
+    Path(token): Path<String>,
+) -> (StatusCode, HeaderMap, String) {
+    let code = match ShareLink::take(&state.db, utils::hash_token(&token)).await {
+        Ok(Some(link)) => Code::get_by_id(&state.db, link.code_id).await,
+        Ok(None) => return expired(),
+        Err(err) => Err(err),
+    };
+
+    let code = match code {
+        Ok(Some(code)) => code,
+        Ok(None) => return expired(),
+        Err(_) => return failed(),
+    };
+
+    match otp::generate(&code.content, chrono::Utc::now()) {
+        Some(otp) => render(
+            StatusCode::OK,
+            &code.display_name,
+            &format!(
+                "This code is valid for another {} seconds. The link can not be opened again.",
+                otp.valid_for
+            ),
+            &otp.code,
+            "",
+        ),
+        None => expired(),
+    }
+}
@@ -0,0 +1,372 @@
+use super::{ApiError, JSON};
+use crate::{
+    jobs,
+    models::{codes::Code, invite::RegistrationInvite, user::User},
+    registration, AppState,
+};
+use axum::{
+    extract::{Path, Query, State},
+    http::StatusCode,
+    Extension,
+};
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::{IntoParams, ToSchema};
+
+const DEFAULT_PAGE_SIZE: i64 = 50;
+const MAX_PAGE_SIZE: i64 = 200;
+
+/// A user as seen by administrators of the instance.
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct UserOverview {
+    pub id: String,
+    pub username: String,
+    pub display_name: String,
+    pub is_admin: bool,
+    pub disabled_at: Option<DateTime<Utc>>,
+    /// Suspended users can only read their codes.
+    pub suspended: bool,
+    pub deletion_scheduled_for: Option<DateTime<Utc>>,
+    /// Amount of personal codes, excluding codes in vaults or shared with the user.
+    pub code_count: i64,
+}
+
+impl UserOverview {
+    async fn new(state: &AppState, user: User) -> Result<Self, ApiError> {
+        Ok(UserOverview {
+            code_count: Code::count_owned(&state.db, user.id.clone()).await?,
+            display_name: user.effective_display_name(),
+            id: user.id,
+            username: user.username,
+            is_admin: user.is_admin,
+            disabled_at: user.disabled_at,
+            suspended: user.suspended,
+            deletion_scheduled_for: user.deletion_scheduled_for,
+        })
+    }
+}
+
+async fn find_user(state: &AppState, id: String) -> Result<User, ApiError> {
+    User::get_by_id(&state.db, id)
+        .await?
+        .ok_or(ApiError::NotFound)
+}
+
+#[derive(Deserialize, IntoParams)]
+pub struct UserSearchParams {
+    /// Exact id, or part of the username or display name. Lists every user when left out.
+    query: Option<String>,
+    /// Defaults to 50, at most 200.
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[utoipa::path(
+	get,
+	path = "/v1/admin/users",
+	tag = "admin",
+	params(UserSearchParams),
+	responses(
+		(status = OK, description = "Successfully fetched users, ordered by username", body = Vec<UserOverview>),
+		(status = FORBIDDEN, description = "Only administrators may use this")
+	),
+)]
+pub async fn list_users(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<UserSearchParams>,
+) -> Result<JSON<Vec<UserOverview>>, ApiError> {
+    let limit = params.limit.unwrap_or(DEFAULT_PAGE_SIZE);
+    let offset = params.offset.unwrap_or(0);
+    if !(1..=MAX_PAGE_SIZE).contains(&limit) || offset < 0 {
+        return Err(ApiError::JsonDataError);
+    }
+
+    let users = User::search(&state.db, params.query.unwrap_or_default(), limit, offset).await?;
+
+    let mut overviews = Vec::with_capacity(users.len());
+    for user in users {
+        overviews.push(UserOverview::new(&state, user).await?);
+    }
+
+    Ok(JSON(overviews))
+}
+
+#[utoipa::path(
+	get,
+	path = "/v1/admin/users/{id}",
+	tag = "admin",
+	params(
+		("id", description = "Id of the user")
+	),
+	responses(
+		(status = OK, description = "Successfully fetched user", body = UserOverview)
+	),
+)]
+pub async fn get_user(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<JSON<UserOverview>, ApiError> {
+    let user = find_user(&state, id).await?;
+    Ok(JSON(UserOverview::new(&state, user).await?))
+}
+
+#[utoipa::path(
+	method(delete),
+	path = "/v1/admin/users/{id}",
+	tag = "admin",
+	params(
+		("id", description = "Id of the user to delete immediately, without a grace period. Vaults they own are handed over to the longest-standing member")
+	),
+	responses(
+		(status = NO_CONTENT, description = "Successfully deleted user"),
+		(status = CONFLICT, description = "Refusing to delete your own account")
+	),
+)]
+pub async fn delete_user(
+    State(state): State<Arc<AppState>>,
+    Extension(admin): Extension<User>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    if id == admin.id {
+        return Err(ApiError::AdminSelfAction);
+    }
+
+    let user = find_user(&state, id).await?;
+    jobs::delete_account(&state.db, &state.icon_store, &user).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/admin/users/{id}/disable",
+	tag = "admin",
+	params(
+		("id", description = "Id of the user to disable. Every request of theirs is rejected until enabled again")
+	),
+	responses(
+		(status = OK, description = "Successfully disabled user", body = UserOverview),
+		(status = CONFLICT, description = "Refusing to disable your own account")
+	),
+)]
+pub async fn disable_user(
+    State(state): State<Arc<AppState>>,
+    Extension(admin): Extension<User>,
+    Path(id): Path<String>,
+) -> Result<JSON<UserOverview>, ApiError> {
+    if id == admin.id {
+        return Err(ApiError::AdminSelfAction);
+    }
+
+    let mut user = find_user(&state, id).await?;
+    if user.disabled_at.is_none() {
+        user.edit()
+            .pool(&state.db)
+            .disabled_at(Some(Utc::now()))
+            .call()
+            .await?;
+    }
+
+    Ok(JSON(UserOverview::new(&state, user).await?))
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/admin/users/{id}/enable",
+	tag = "admin",
+	params(
+		("id", description = "Id of the user to enable again")
+	),
+	responses(
+		(status = OK, description = "Successfully enabled user", body = UserOverview)
+	),
+)]
+pub async fn enable_user(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<JSON<UserOverview>, ApiError> {
+    let mut user = find_user(&state, id).await?;
+    user.edit().pool(&state.db).disabled_at(None).call().await?;
+
+    Ok(JSON(UserOverview::new(&state, user).await?))
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/admin/users/{id}/suspend",
+	tag = "admin",
+	params(
+		("id", description = "Id of the user to suspend. They can still read and export their codes, but not change anything")
+	),
+	responses(
+		(status = OK, description = "Successfully suspended user", body = UserOverview),
+		(status = CONFLICT, description = "Refusing to suspend your own account")
+	),
+)]
+pub async fn suspend_user(
+    State(state): State<Arc<AppState>>,
+    Extension(admin): Extension<User>,
+    Path(id): Path<String>,
+) -> Result<JSON<UserOverview>, ApiError> {
+    if id == admin.id {
+        return Err(ApiError::AdminSelfAction);
+    }
+
+    let mut user = find_user(&state, id).await?;
+    user.edit().pool(&state.db).suspended(true).call().await?;
+
+    Ok(JSON(UserOverview::new(&state, user).await?))
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/admin/users/{id}/unsuspend",
+	tag = "admin",
+	params(
+		("id", description = "Id of the user to lift the suspension of")
+	),
+	responses(
+		(status = OK, description = "Successfully lifted the suspension", body = UserOverview)
+	),
+)]
+pub async fn unsuspend_user(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<JSON<UserOverview>, ApiError> {
+    let mut user = find_user(&state, id).await?;
+    user.edit().pool(&state.db).suspended(false).call().await?;
+
+    Ok(JSON(UserOverview::new(&state, user).await?))
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/admin/users/{id}/logout",
+	tag = "admin",
+	params(
+		("id", description = "Id of the user to log out everywhere")
+	),
+	responses(
+		(status = NO_CONTENT, description = "Successfully revoked every login. Personal access tokens are kept, disable the user to block those too")
+	),
+)]
+pub async fn logout_user(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    find_user(&state, id)
+        .await?
+        .edit()
+        .pool(&state.db)
+        .sessions_valid_after(Utc::now())
+        .call()
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+	get,
+	path = "/v1/admin/invites",
+	tag = "admin",
+	responses(
+		(status = OK, description = "Successfully fetched invites, newest first", body = Vec<RegistrationInvite>)
+	),
+)]
+pub async fn list_invites(
+    State(state): State<Arc<AppState>>,
+) -> Result<JSON<Vec<RegistrationInvite>>, ApiError> {
+    Ok(JSON(RegistrationInvite::get_many(&state.db).await?))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct InviteCreatePayload {
+    /// Days until the invite expires. Never expires when left out.
+    pub expires_in_days: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct InviteCreateResponse {
+    /// Code to give to the new user. Only shown once, it can not be retrieved later.
+    pub code: String,
+    #[serde(flatten)]
+    pub details: RegistrationInvite,
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/admin/invites",
+	tag = "admin",
+	request_body = InviteCreatePayload,
+	responses(
+		(status = OK, description = "Successfully created a single-use invite", body = InviteCreateResponse)
+	),
+)]
+pub async fn create_invite(
+    State(state): State<Arc<AppState>>,
+    Extension(admin): Extension<User>,
+    JSON(payload): JSON<InviteCreatePayload>,
+) -> Result<JSON<InviteCreateResponse>, ApiError> {
+    if payload.expires_in_days.is_some_and(|days| days < 1) {
+        return Err(ApiError::JsonDataError);
+    }
+
+    let (code, invite) =
+        registration::create_invite(&state.db, Some(admin.id), payload.expires_in_days).await?;
+
+    Ok(JSON(InviteCreateResponse {
+        code,
+        details: invite,
+    }))
+}
+
+#[utoipa::path(
+	method(delete),
+	path = "/v1/admin/invites/{id}",
+	tag = "admin",
+	params(
+		("id", description = "Id of the invite to revoke")
+	),
+	responses(
+		(status = NO_CONTENT, description = "Successfully revoked invite")
+	),
+)]
+pub async fn delete_invite(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    RegistrationInvite::get(&state.db, id)
+        .await?
+        .ok_or(ApiError::NotFound)?
+        .delete(&state.db)
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct IconPurgeResponse {
+    /// Amount of websites whose icons, or lack thereof, were forgotten.
+    pub purged: usize,
+}
+
+#[utoipa::path(
+	method(delete),
+	path = "/v1/admin/icons",
+	tag = "admin",
+	responses(
+		(status = OK, description = "Successfully purged the icon cache. Icons are fetched again when next requested", body = IconPurgeResponse)
+	),
+)]
+pub async fn purge_icons(
+    State(state): State<Arc<AppState>>,
+) -> Result<JSON<IconPurgeResponse>, ApiError> {
+    let purged = state
+        .icon_store
+        .purge()
+        .await
+        .map_err(ApiError::IconStoreFail)?;
+
+    Ok(JSON(IconPurgeResponse { purged }))
+}
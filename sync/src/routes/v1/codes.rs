@@ -1,35 +1,100 @@
 use super::{ApiError, JSON};
 use crate::{
-    models::{codes::Code, user::User},
+    auth::{Scope, Scopes},
+    icons::{
+        normalize::{self, OutputFormat},
+        IconStoreError,
+    },
+    models::{codes::Code, share::SharePermission, user::User, vault::VaultRole},
     utils, AppState,
 };
 use axum::{
-    extract::{Path, State},
+    body::Bytes,
+    extract::{Path, Query, State},
     http::{HeaderMap, StatusCode},
+    response::{IntoResponse, Response},
     Extension,
 };
+use base64::{engine::general_purpose::STANDARD, Engine};
 use reqwest::header;
-use serde::Deserialize;
-use std::sync::Arc;
-use utoipa::ToSchema;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{collections::HashMap, sync::Arc};
+use tracing::warn;
+use utoipa::{IntoParams, ToSchema};
+
+/// Level of access an operation needs on a code.
+#[derive(Clone, Copy, PartialEq)]
+pub(crate) enum Access {
+    Read,
+    Write,
+}
+
+/// Finds a code the user owns, or has the given access to through a share or vault.
+pub(crate) async fn find_code(
+    state: &AppState,
+    id: String,
+    user: &User,
+    access: Access,
+) -> Result<Code, ApiError> {
+    if let Some(code) = Code::get(&state.db, id.clone(), user.id.clone()).await? {
+        return match code.shared_permission {
+            Some(SharePermission::Read) if access == Access::Write => {
+                Err(ApiError::InsufficientSharePermission)
+            }
+            _ => Ok(code),
+        };
+    }
+
+    let code = Code::get_by_id(&state.db, id)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+    let vault_id = code.vault_id.clone().ok_or(ApiError::NotFound)?;
+
+    let role = match access {
+        Access::Read => VaultRole::Viewer,
+        Access::Write => VaultRole::Editor,
+    };
+    super::vaults::require_role(state, vault_id, user, role).await?;
+
+    Ok(code)
+}
+
+/// Enforces the size limits of the instance on new or edited codes.
+fn check_sizes(
+    state: &AppState,
+    content: Option<&str>,
+    display_name: Option<&str>,
+) -> Result<(), ApiError> {
+    let quotas = &state.settings.quotas;
+
+    if content.is_some_and(|content| content.chars().count() > quotas.max_content_length) {
+        return Err(ApiError::CodeContentTooLong);
+    }
+
+    if display_name.is_some_and(|name| name.chars().count() > quotas.max_display_name_length) {
+        return Err(ApiError::DisplayNameTooLong);
+    }
+
+    Ok(())
+}
 
 #[utoipa::path(
 	get,
 	path = "/v1/code",
 	responses(
-		(status = OK, description = "Successfully fetches codes", body = Vec<Code>)
+		(status = OK, description = "Successfully fetches your codes, including codes shared with you", body = Vec<Code>)
 	),
 	tag = "codes",
 )]
 pub async fn list_all_codes(
     State(state): State<Arc<AppState>>,
     Extension(user): Extension<User>,
-) -> JSON<Vec<Code>> {
-    JSON(
-        Code::get_many(&state.db, user.id)
-            .await
-            .expect("Unable to find codes owned by user"),
-    )
+    Extension(scopes): Extension<Scopes>,
+) -> Result<JSON<Vec<Code>>, ApiError> {
+    scopes.require(Scope::CodesRead)?;
+
+    Ok(JSON(Code::get_many(&state.db, user.id).await?))
 }
 
 #[derive(Deserialize, ToSchema)]
@@ -37,13 +102,17 @@ pub struct CodeAddPayload {
     pub content: String,
     pub display_name: String,
     pub website_url: Option<String>,
+    /// Adds the code to a vault instead of your personal codes. Requires the editor role.
+    pub vault_id: Option<String>,
 }
 
 #[utoipa::path(
 	method(put),
 	path = "/v1/code",
 	responses(
-		(status = OK, description = "Succesfully created code. Response contains contents of the new code", body = Code)
+		(status = OK, description = "Succesfully created code. Response contains contents of the new code", body = Code),
+		(status = FORBIDDEN, description = "Not allowed to add codes to the vault, or the maximum amount of codes is reached"),
+		(status = BAD_REQUEST, description = "The content or display name is too long")
 	),
 	request_body = CodeAddPayload,
 	tag = "codes"
@@ -51,18 +120,53 @@ pub struct CodeAddPayload {
 pub async fn add_code(
     State(state): State<Arc<AppState>>,
     Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
     JSON(payload): JSON<CodeAddPayload>,
 ) -> Result<JSON<Code>, ApiError> {
+    scopes.require(Scope::CodesWrite)?;
+
+    check_sizes(&state, Some(&payload.content), Some(&payload.display_name))?;
+
+    let (owner_id, count) = match &payload.vault_id {
+        Some(vault_id) => {
+            super::vaults::require_role(&state, vault_id.clone(), &user, VaultRole::Editor).await?;
+            (
+                None,
+                Code::count_in_vault(&state.db, vault_id.clone()).await?,
+            )
+        }
+        None => {
+            let count = Code::count_owned(&state.db, user.id.clone()).await?;
+            (Some(user.id), count)
+        }
+    };
+
+    if state
+        .settings
+        .quotas
+        .max_codes
+        .is_some_and(|max| count >= max)
+    {
+        return Err(ApiError::CodeLimitReached);
+    }
+
     let code = Code {
         id: utils::generate_id(16),
-        owner_id: user.id,
+        owner_id,
+        vault_id: payload.vault_id,
         content: payload.content,
         display_name: payload.display_name,
         website_url: payload.website_url,
         icon_url: None,
+        shared_permission: None,
     };
 
     code.insert(&state.db).await?;
+
+    if user.icon_fetching {
+        state.icon_warmer.warm(code.website_url.clone());
+    }
+
     Ok(JSON(code))
 }
 
@@ -87,28 +191,43 @@ pub struct CodeEditPayload {
 	),
 	request_body = CodeEditPayload,
 	responses(
-		(status = OK, description = "Success", body = Vec<Code>)
+		(status = OK, description = "Success", body = Vec<Code>),
+		(status = FORBIDDEN, description = "Not allowed to edit codes in the vault"),
+		(status = BAD_REQUEST, description = "The content or display name is too long")
 	),
 )]
 pub async fn edit_code(
     State(state): State<Arc<AppState>>,
     Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
     Path(id): Path<String>,
     JSON(payload): JSON<CodeEditPayload>,
 ) -> Result<JSON<Code>, ApiError> {
-    Ok(JSON(
-        Code::get(&state.db, id, user.id)
-            .await?
-            .ok_or(ApiError::NotFound)?
-            .edit()
-            .pool(&state.db)
-            .maybe_content(payload.content)
-            .maybe_display_name(payload.display_name)
-            .maybe_website_url(payload.website_url)
-            .call()
-            .await?
-            .clone(),
-    ))
+    scopes.require(Scope::CodesWrite)?;
+
+    check_sizes(
+        &state,
+        payload.content.as_deref(),
+        payload.display_name.as_deref(),
+    )?;
+
+    let website_changed = payload.website_url.is_some();
+    let code = find_code(&state, id, &user, Access::Write)
+        .await?
+        .edit()
+        .pool(&state.db)
+        .maybe_content(payload.content)
+        .maybe_display_name(payload.display_name)
+        .maybe_website_url(payload.website_url)
+        .call()
+        .await?
+        .clone();
+
+    if website_changed && user.icon_fetching {
+        state.icon_warmer.warm(code.website_url.clone());
+    }
+
+    Ok(JSON(code))
 }
 
 #[utoipa::path(
@@ -116,7 +235,8 @@ pub async fn edit_code(
 	path = "/v1/code/{id}",
 	tag = "codes",
 	responses(
-		(status = NO_CONTENT, description = "Deleted")
+		(status = NO_CONTENT, description = "Deleted"),
+		(status = FORBIDDEN, description = "Not allowed to delete codes in the vault")
 	),
 	params(
 		("id", description = "Id of code to delete")
@@ -125,49 +245,286 @@ pub async fn edit_code(
 pub async fn delete_code(
     State(state): State<Arc<AppState>>,
     Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
     Path(id): Path<String>,
 ) -> Result<StatusCode, ApiError> {
-    Code::get(&state.db, id, user.id)
-        .await?
-        .ok_or(ApiError::NotFound)?
-        .delete(&state.db)
-        .await?;
+    scopes.require(Scope::CodesWrite)?;
+
+    let code = find_code(&state, id, &user, Access::Write).await?;
+
+    // Only the owner may delete a shared code
+    if code.shared_permission.is_some() {
+        return Err(ApiError::InsufficientSharePermission);
+    }
+
+    code.delete(&state.db).await?;
+
+    // The code is gone already, and the garbage collection removes the icon otherwise
+    if let Err(err) = state.icon_store.remove_custom(&code.id).await {
+        warn!(
+            "Unable to remove the icon of deleted code {}: {err:?}",
+            code.id
+        );
+    }
 
     Ok(StatusCode::NO_CONTENT)
 }
 
+#[derive(Deserialize, IntoParams)]
+pub struct IconParams {
+    /// Size in pixels of the square icon, rounded up to 32, 64, 128 or 256. Defaults to 128.
+    pub size: Option<u32>,
+    /// Defaults to PNG.
+    pub format: Option<OutputFormat>,
+}
+
 #[utoipa::path(
 	get,
 	path = "/v1/code/{id}/icon",
 	tag = "codes",
 	responses(
-		(status = OK, description = "Icon found"),
+		(status = OK, description = "Icon found, in the requested format. Uploaded icons are preferred over bundled icons of the issuer, which are preferred over the icon of the website"),
+		(status = NOT_MODIFIED, description = "The icon matches the ETag in If-None-Match"),
 		(status = NOT_FOUND, description = "Unable to find icon")
 	),
 	params(
-		("id", description = "Id of code to fetch icon for")
+		("id", description = "Id of code to fetch icon for"),
+		IconParams
 	)
 )]
 pub async fn get_code_icon(
     State(state): State<Arc<AppState>>,
     Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
     Path(id): Path<String>,
-) -> Result<(HeaderMap, Vec<u8>), ApiError> {
-    let code = Code::get(&state.db, id, user.id)
-        .await?
-        .ok_or(ApiError::NotFound)?;
+    Query(params): Query<IconParams>,
+    headers: HeaderMap,
+) -> Result<Response, ApiError> {
+    scopes.require(Scope::CodesRead)?;
 
-    let mut headers = HeaderMap::default();
-    headers.append(header::CONTENT_TYPE, "image/x-icon".parse().unwrap());
+    let code = find_code(&state, id, &user, Access::Read).await?;
+    let format = params.format.unwrap_or_default();
 
-    Ok((
-        headers,
+    let size = params.size.unwrap_or(normalize::DEFAULT_SIZE);
+
+    if let Some(icon) = state.icon_store.find_custom(&code.id, size, format).await {
+        // Uploaded icons may be replaced at any time, so clients always revalidate them
+        return Ok(icon_response(&headers, icon, format, "private, no-cache"));
+    }
+
+    // Well known issuers are recognized by name first, which also covers codes without a website
+    if let Some(icon) = state
+        .icon_store
+        .find_issuer(&code.display_name, size, format)
+        .await
+    {
+        return Ok(icon_response(&headers, icon, format, ICON_CACHE_CONTROL));
+    }
+
+    let website_url = code.website_url.ok_or(ApiError::NoIcon)?;
+
+    // Never contact the website on behalf of users who opted out
+    let icon = if user.icon_fetching {
+        state
+            .icon_store
+            .find_or_gather(&website_url, size, format)
+            .await
+            .map_err(|_| ApiError::NoIcon)?
+    } else {
         state
             .icon_store
-            .find_or_gather(code.website_url.ok_or(ApiError::NoIcon)?.as_str())
+            .find_cached(&website_url, size, format)
             .await
-            .map_err(|_| ApiError::NoIcon)?,
+            .ok_or(ApiError::NoIcon)?
+    };
+
+    Ok(icon_response(&headers, icon, format, ICON_CACHE_CONTROL))
+}
+
+/// Icons of websites rarely change, and are only refreshed by the store every few weeks.
+const ICON_CACHE_CONTROL: &str = "private, max-age=86400";
+
+/// Serves an icon tagged with its hash, or `304 Not Modified` when the client already has it.
+fn icon_response(
+    request_headers: &HeaderMap,
+    icon: Vec<u8>,
+    format: OutputFormat,
+    cache_control: &'static str,
+) -> Response {
+    let etag = format!(
+        "\"{}\"",
+        base16ct::lower::encode_string(&Sha256::digest(&icon))
+    );
+    let headers = [
+        (header::ETAG, etag.clone()),
+        (header::CACHE_CONTROL, cache_control.to_string()),
+    ];
+
+    let unchanged = request_headers
+        .get_all(header::IF_NONE_MATCH)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|tag| tag.trim())
+        .any(|tag| tag == "*" || tag.trim_start_matches("W/") == etag);
+    if unchanged {
+        return (StatusCode::NOT_MODIFIED, headers).into_response();
+    }
+
+    (
+        headers,
+        [(header::CONTENT_TYPE, format.content_type().to_string())],
+        icon,
+    )
+        .into_response()
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct CodeIcons {
+    /// Content type of every icon.
+    pub content_type: String,
+    /// Base64 encoded icons by id of the code. Codes without an icon ready are left out, their icons are
+    /// gathered in the background.
+    pub icons: HashMap<String, String>,
+}
+
+#[utoipa::path(
+	get,
+	path = "/v1/code/icons",
+	tag = "codes",
+	responses(
+		(status = OK, description = "Icons of all your codes which have one ready, including codes shared with you and codes in your vaults", body = CodeIcons)
+	),
+	params(IconParams)
+)]
+pub async fn list_code_icons(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Query(params): Query<IconParams>,
+) -> Result<JSON<CodeIcons>, ApiError> {
+    scopes.require(Scope::CodesRead)?;
+
+    let format = params.format.unwrap_or_default();
+    let size = params.size.unwrap_or(normalize::DEFAULT_SIZE);
+
+    let mut icons = HashMap::new();
+    let mut missing = vec![];
+    for code in Code::get_many_visible(&state.db, user.id).await? {
+        let mut icon = state.icon_store.find_custom(&code.id, size, format).await;
+        if icon.is_none() {
+            icon = state
+                .icon_store
+                .find_issuer(&code.display_name, size, format)
+                .await;
+        }
+        if let (None, Some(website_url)) = (&icon, &code.website_url) {
+            // Gathering every icon now would keep the response waiting on slow websites
+            if !state.icon_store.is_fresh(website_url) {
+                missing.push(website_url.clone());
+            }
+            icon = state
+                .icon_store
+                .find_cached(website_url, size, format)
+                .await;
+        }
+
+        if let Some(icon) = icon {
+            icons.insert(code.id, STANDARD.encode(icon));
+        }
+    }
+
+    if user.icon_fetching {
+        state.icon_warmer.warm(missing);
+    }
+
+    Ok(JSON(CodeIcons {
+        content_type: format.content_type().to_string(),
+        icons,
+    }))
+}
+
+#[utoipa::path(
+	put,
+	path = "/v1/code/{id}/icon",
+	tag = "codes",
+	params(
+		("id", description = "Id of the code to set the icon of")
+	),
+	request_body(content = Vec<u8>, description = "PNG, JPEG, GIF, WebP, BMP, ICO or SVG image of at most 1 MiB", content_type = "image/*"),
+	responses(
+		(status = OK, description = "Icon stored, and served instead of the icon of the website", body = Code),
+		(status = BAD_REQUEST, description = "Not a supported image"),
+		(status = PAYLOAD_TOO_LARGE, description = "The image is larger than 1 MiB"),
+		(status = FORBIDDEN, description = "Not allowed to edit codes in the vault")
+	),
+)]
+pub async fn set_code_icon(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Path(id): Path<String>,
+    body: Bytes,
+) -> Result<JSON<Code>, ApiError> {
+    scopes.require(Scope::CodesWrite)?;
+
+    let mut code = find_code(&state, id, &user, Access::Write).await?;
+
+    state
+        .icon_store
+        .store_custom(&code.id, body.to_vec())
+        .await
+        .map_err(|err| match err {
+            IconStoreError::IconTooLarge => ApiError::IconTooLarge,
+            IconStoreError::InvalidIcon => ApiError::InvalidIcon,
+            err => ApiError::IconStoreFail(err),
+        })?;
+
+    let icon_url = Code::custom_icon_url(&code.id);
+    Ok(JSON(
+        code.edit()
+            .pool(&state.db)
+            .icon_url(Some(icon_url))
+            .call()
+            .await?
+            .clone(),
     ))
 }
 
-// TODO: Delete icon (e.g if the user disables fetching icons using sync)
+#[utoipa::path(
+	method(delete),
+	path = "/v1/code/{id}/icon",
+	tag = "codes",
+	params(
+		("id", description = "Id of the code to remove the uploaded icon of")
+	),
+	responses(
+		(status = OK, description = "Removed, the icon of the website is used again", body = Code),
+		(status = FORBIDDEN, description = "Not allowed to edit codes in the vault")
+	),
+)]
+pub async fn delete_code_icon(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Path(id): Path<String>,
+) -> Result<JSON<Code>, ApiError> {
+    scopes.require(Scope::CodesWrite)?;
+
+    let mut code = find_code(&state, id, &user, Access::Write).await?;
+
+    state
+        .icon_store
+        .remove_custom(&code.id)
+        .await
+        .map_err(ApiError::IconStoreFail)?;
+
+    Ok(JSON(
+        code.edit()
+            .pool(&state.db)
+            .icon_url(None)
+            .call()
+            .await?
+            .clone(),
+    ))
+}
@@ -0,0 +1,232 @@
+use super::{ApiError, JSON};
+use crate::{
+    auth::{self, AuthTime, Scope, Scopes},
+    models::{device::DeviceAuthorization, user::User},
+    utils, AppState,
+};
+use axum::{extract::State, http::StatusCode, Extension};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+/// How long the user has to approve the device.
+const DEVICE_CODE_LIFETIME_MINUTES: i64 = 10;
+/// Minimum amount of seconds the device must wait between polls.
+const POLLING_INTERVAL_SECONDS: i64 = 5;
+/// Consonants only, so that codes are easy to type and never spell words.
+const USER_CODE_ALPHABET: &[u8] = b"BCDFGHJKLMNPQRSTVWXZ";
+const USER_CODE_LENGTH: usize = 8;
+
+fn generate_user_code() -> String {
+    let mut rng = rand::thread_rng();
+    (0..USER_CODE_LENGTH)
+        .map(|_| USER_CODE_ALPHABET[rng.gen_range(0..USER_CODE_ALPHABET.len())] as char)
+        .collect()
+}
+
+/// Users may type the code in lowercase, and with or without the separator.
+fn normalize_user_code(user_code: &str) -> String {
+    user_code
+        .chars()
+        .filter(char::is_ascii_alphanumeric)
+        .map(|c| c.to_ascii_uppercase())
+        .collect()
+}
+
+fn format_user_code(user_code: &str) -> String {
+    let (first, second) = user_code.split_at(user_code.len() / 2);
+    format!("{first}-{second}")
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct DeviceCodeResponse {
+    /// Secret used by the device when polling for its token.
+    pub device_code: String,
+    /// Short code the user enters on the verification page.
+    pub user_code: String,
+    pub verification_uri: String,
+    /// Verification page with the user code already filled in, e.g. for a QR code.
+    pub verification_uri_complete: String,
+    /// Seconds until the device code expires.
+    pub expires_in: i64,
+    /// Minimum amount of seconds to wait between polls.
+    pub interval: i64,
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/device/code",
+	tag = "user",
+	responses(
+		(status = OK, description = "Successfully started device authorization", body = DeviceCodeResponse)
+	),
+	security(())
+)]
+pub async fn device_code(
+    State(state): State<Arc<AppState>>,
+) -> Result<JSON<DeviceCodeResponse>, ApiError> {
+    DeviceAuthorization::delete_expired(&state.db).await?;
+
+    let device_code = utils::generate_id(40);
+    let user_code = generate_user_code();
+    let now = chrono::Utc::now();
+
+    DeviceAuthorization {
+        device_code_hash: utils::hash_token(&device_code),
+        user_code: user_code.clone(),
+        user_id: None,
+        created_at: now,
+        expires_at: now + chrono::Duration::minutes(DEVICE_CODE_LIFETIME_MINUTES),
+        last_polled_at: None,
+    }
+    .insert(&state.db)
+    .await?;
+
+    let user_code = format_user_code(&user_code);
+    let verification_uri = format!("{}/", state.settings.frontfacing);
+
+    Ok(JSON(DeviceCodeResponse {
+        device_code,
+        verification_uri_complete: format!("{verification_uri}?user_code={user_code}"),
+        verification_uri,
+        user_code,
+        expires_in: DEVICE_CODE_LIFETIME_MINUTES * 60,
+        interval: POLLING_INTERVAL_SECONDS,
+    }))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct DeviceApprovePayload {
+    /// Code shown on the device. Case and separators are ignored.
+    pub user_code: String,
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/device/approve",
+	tag = "user",
+	request_body = DeviceApprovePayload,
+	responses(
+		(status = NO_CONTENT, description = "Successfully approved the device"),
+		(status = NOT_FOUND, description = "No pending device with this code"),
+		(status = UNAUTHORIZED, description = "Requires a recent login, see `StepUpRequired`")
+	),
+)]
+pub async fn device_approve(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Extension(auth_time): Extension<AuthTime>,
+    JSON(payload): JSON<DeviceApprovePayload>,
+) -> Result<StatusCode, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+    auth_time.require_fresh()?;
+
+    let mut authorization =
+        DeviceAuthorization::get_by_user_code(&state.db, normalize_user_code(&payload.user_code))
+            .await?
+            .filter(|authorization| {
+                authorization.user_id.is_none() && authorization.expires_at > chrono::Utc::now()
+            })
+            .ok_or(ApiError::NotFound)?;
+
+    authorization.approve(&state.db, user.id).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct DeviceTokenPayload {
+    pub device_code: String,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct DeviceTokenResponse {
+    /// JWT to use as bearer token.
+    pub access_token: String,
+    pub token_type: String,
+    /// Seconds until the JWT expires.
+    pub expires_in: i64,
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/device/token",
+	tag = "user",
+	request_body = DeviceTokenPayload,
+	responses(
+		(status = OK, description = "The device was approved", body = DeviceTokenResponse),
+		(status = BAD_REQUEST, description = "Authorization is still pending, polling too fast, or the device code has expired")
+	),
+	security(())
+)]
+pub async fn device_token(
+    State(state): State<Arc<AppState>>,
+    JSON(payload): JSON<DeviceTokenPayload>,
+) -> Result<JSON<DeviceTokenResponse>, ApiError> {
+    let mut authorization = DeviceAuthorization::get_by_device_code_hash(
+        &state.db,
+        utils::hash_token(&payload.device_code),
+    )
+    .await?
+    .ok_or(ApiError::DeviceCodeExpired)?;
+
+    let now = chrono::Utc::now();
+    if authorization.expires_at <= now {
+        authorization.delete(&state.db).await?;
+        return Err(ApiError::DeviceCodeExpired);
+    }
+
+    let polled_too_fast = authorization.last_polled_at.is_some_and(|last_polled_at| {
+        now - last_polled_at < chrono::Duration::seconds(POLLING_INTERVAL_SECONDS)
+    });
+    authorization.mark_polled(&state.db).await?;
+
+    if polled_too_fast {
+        return Err(ApiError::SlowDown);
+    }
+
+    let user_id = authorization
+        .user_id
+        .clone()
+        .ok_or(ApiError::AuthorizationPending)?;
+    let user = User::get_by_id(&state.db, user_id)
+        .await?
+        .ok_or(ApiError::JwtUserGone)?;
+
+    // Device codes can only be exchanged once
+    authorization.delete(&state.db).await?;
+
+    // Approving a device does not require a recent login, and a phished device code should not allow
+    // sensitive operations either
+    let (jwt, _) =
+        auth::create_jwt_with_auth_time(&user, state.settings.jwt_secret.clone(), None).await;
+    Ok(JSON(DeviceTokenResponse {
+        access_token: jwt,
+        token_type: "Bearer".to_string(),
+        expires_in: auth::JWT_LIFETIME_DAYS * 24 * 60 * 60,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use googletest::prelude::*;
+
+    #[gtest]
+    fn user_code_format() {
+        let user_code = generate_user_code();
+
+        assert_that!(user_code, matches_regex("^[BCDFGHJKLMNPQRSTVWXZ]{8}$"));
+        assert_that!(
+            format_user_code(&user_code),
+            matches_regex("^[BCDFGHJKLMNPQRSTVWXZ]{4}-[BCDFGHJKLMNPQRSTVWXZ]{4}$")
+        );
+    }
+
+    #[gtest]
+    fn user_code_normalization() {
+        assert_that!(normalize_user_code("bcdf-ghjk"), eq("BCDFGHJK"));
+        assert_that!(normalize_user_code(" BCDF GHJK "), eq("BCDFGHJK"));
+    }
+}
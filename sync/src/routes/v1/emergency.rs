@@ -0,0 +1,278 @@
+use super::{ApiError, JSON};
+use crate::{
+    auth::{AuthTime, Scope, Scopes},
+    models::{
+        codes::Code,
+        emergency::{EmergencyContact, EmergencyEvent, EmergencyEventKind, EmergencyStatus},
+        user::User,
+    },
+    AppState,
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension,
+};
+use serde::Deserialize;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+const DEFAULT_WAIT_DAYS: i64 = 7;
+const MAX_WAIT_DAYS: i64 = 90;
+
+#[utoipa::path(
+	get,
+	path = "/v1/emergency/contacts",
+	tag = "emergency",
+	responses(
+		(status = OK, description = "Successfully fetched your trusted contacts", body = Vec<EmergencyContact>)
+	),
+)]
+pub async fn list_contacts(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+) -> Result<JSON<Vec<EmergencyContact>>, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+
+    EmergencyContact::grant_due(&state.db, chrono::Utc::now()).await?;
+    Ok(JSON(
+        EmergencyContact::get_by_owner(&state.db, user.id).await?,
+    ))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ContactPayload {
+    /// Days between a request and access being granted, during which you can deny it. Defaults to 7, at most 90.
+    pub wait_days: Option<i64>,
+}
+
+#[utoipa::path(
+	method(put),
+	path = "/v1/emergency/contacts/{user_id}",
+	tag = "emergency",
+	params(
+		("user_id", description = "Id of the user to trust")
+	),
+	request_body = ContactPayload,
+	responses(
+		(status = OK, description = "Successfully designated the contact, or changed the waiting period", body = EmergencyContact),
+		(status = NOT_FOUND, description = "Unknown user"),
+		(status = UNAUTHORIZED, description = "Requires a recent login, see `StepUpRequired`")
+	),
+)]
+pub async fn set_contact(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Extension(auth_time): Extension<AuthTime>,
+    Path(user_id): Path<String>,
+    JSON(payload): JSON<ContactPayload>,
+) -> Result<JSON<EmergencyContact>, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+    auth_time.require_fresh()?;
+
+    let wait_days = payload.wait_days.unwrap_or(DEFAULT_WAIT_DAYS);
+    if user_id == user.id || !(1..=MAX_WAIT_DAYS).contains(&wait_days) {
+        return Err(ApiError::JsonDataError);
+    }
+
+    let contact = match EmergencyContact::get(&state.db, user.id.clone(), user_id.clone()).await? {
+        Some(mut contact) => {
+            contact.set_wait_days(&state.db, wait_days).await?;
+            contact
+        }
+        None => {
+            let grantee = User::get_by_id(&state.db, user_id)
+                .await?
+                .ok_or(ApiError::NotFound)?;
+
+            let contact = EmergencyContact {
+                owner_id: user.id,
+                contact_id: grantee.id,
+                wait_days,
+                status: EmergencyStatus::Idle,
+                requested_at: None,
+                grants_at: None,
+                created_at: chrono::Utc::now(),
+            };
+            contact.insert(&state.db).await?;
+            contact
+        }
+    };
+
+    EmergencyEvent::record(&state.db, &contact, EmergencyEventKind::Designated).await?;
+    Ok(JSON(contact))
+}
+
+#[utoipa::path(
+	method(delete),
+	path = "/v1/emergency/contacts/{user_id}",
+	tag = "emergency",
+	params(
+		("user_id", description = "Id of the contact to remove. Also revokes any access")
+	),
+	responses(
+		(status = NO_CONTENT, description = "Successfully removed the contact")
+	),
+)]
+pub async fn remove_contact(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Path(user_id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+
+    let contact = EmergencyContact::get(&state.db, user.id, user_id)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    EmergencyEvent::record(&state.db, &contact, EmergencyEventKind::Removed).await?;
+    contact.delete(&state.db).await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/emergency/contacts/{user_id}/deny",
+	tag = "emergency",
+	params(
+		("user_id", description = "Id of the contact")
+	),
+	responses(
+		(status = OK, description = "Successfully denied the pending request, or revoked granted access", body = EmergencyContact)
+	),
+)]
+pub async fn deny_request(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Path(user_id): Path<String>,
+) -> Result<JSON<EmergencyContact>, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+
+    let mut contact = EmergencyContact::get(&state.db, user.id, user_id)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    if contact.status != EmergencyStatus::Idle {
+        contact.reset(&state.db).await?;
+        EmergencyEvent::record(&state.db, &contact, EmergencyEventKind::Denied).await?;
+    }
+
+    Ok(JSON(contact))
+}
+
+#[utoipa::path(
+	get,
+	path = "/v1/emergency/grantors",
+	tag = "emergency",
+	responses(
+		(status = OK, description = "Successfully fetched users who trust you as their contact", body = Vec<EmergencyContact>)
+	),
+)]
+pub async fn list_grantors(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+) -> Result<JSON<Vec<EmergencyContact>>, ApiError> {
+    scopes.require(Scope::CodesRead)?;
+
+    EmergencyContact::grant_due(&state.db, chrono::Utc::now()).await?;
+    Ok(JSON(
+        EmergencyContact::get_by_contact(&state.db, user.id).await?,
+    ))
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/emergency/grantors/{owner_id}/request",
+	tag = "emergency",
+	params(
+		("owner_id", description = "Id of the user who trusts you")
+	),
+	responses(
+		(status = OK, description = "Successfully requested access. It is granted after the waiting period, unless the owner denies it", body = EmergencyContact)
+	),
+)]
+pub async fn request_access(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Path(owner_id): Path<String>,
+) -> Result<JSON<EmergencyContact>, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+
+    let mut contact = EmergencyContact::get(&state.db, owner_id, user.id)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    if contact.status == EmergencyStatus::Idle {
+        contact.request(&state.db, chrono::Utc::now()).await?;
+        EmergencyEvent::record(&state.db, &contact, EmergencyEventKind::Requested).await?;
+    }
+
+    Ok(JSON(contact))
+}
+
+#[utoipa::path(
+	get,
+	path = "/v1/emergency/grantors/{owner_id}/codes",
+	tag = "emergency",
+	params(
+		("owner_id", description = "Id of the user who trusts you")
+	),
+	responses(
+		(status = OK, description = "Successfully fetched the personal codes of the owner", body = Vec<Code>),
+		(status = FORBIDDEN, description = "Access has not been granted")
+	),
+)]
+pub async fn grantor_codes(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Path(owner_id): Path<String>,
+) -> Result<JSON<Vec<Code>>, ApiError> {
+    scopes.require(Scope::CodesRead)?;
+
+    EmergencyContact::grant_due(&state.db, chrono::Utc::now()).await?;
+    let contact = EmergencyContact::get(&state.db, owner_id.clone(), user.id)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    if contact.status != EmergencyStatus::Granted {
+        return Err(ApiError::EmergencyAccessNotGranted);
+    }
+
+    EmergencyEvent::record(&state.db, &contact, EmergencyEventKind::Accessed).await?;
+
+    // Codes shared with the owner are not theirs to pass on
+    Ok(JSON(
+        Code::get_many(&state.db, owner_id.clone())
+            .await?
+            .into_iter()
+            .filter(|code| code.owner_id.as_ref() == Some(&owner_id))
+            .collect(),
+    ))
+}
+
+#[utoipa::path(
+	get,
+	path = "/v1/emergency/events",
+	tag = "emergency",
+	responses(
+		(status = OK, description = "Successfully fetched the audit log, both as owner and as contact. Newest first", body = Vec<EmergencyEvent>)
+	),
+)]
+pub async fn list_events(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+) -> Result<JSON<Vec<EmergencyEvent>>, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+
+    EmergencyContact::grant_due(&state.db, chrono::Utc::now()).await?;
+    Ok(JSON(EmergencyEvent::get_many(&state.db, user.id).await?))
+}
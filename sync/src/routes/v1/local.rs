@@ -0,0 +1,538 @@
+use super::{ApiError, JSON};
+use crate::{
+    auth::{self, AuthTime, Scope, Scopes},
+    cli::AuthBackend,
+    local_auth,
+    models::{
+        credentials::{CeremonyState, LocalCredential, StoredPasskey, WebauthnChallenge},
+        identity::Identity,
+        user::User,
+    },
+    registration::{self, Applicant},
+    utils, AppState,
+};
+use axum::{
+    extract::State,
+    http::{HeaderMap, StatusCode},
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use std::sync::Arc;
+use utoipa::ToSchema;
+use webauthn_rs::{
+    fake::{FakePasskeyDistribution, WebauthnFakeCredentialGenerator},
+    prelude::{
+        CreationChallengeResponse, Passkey, PublicKeyCredential, RegisterPublicKeyCredential,
+        RequestChallengeResponse, Webauthn,
+    },
+};
+use webauthn_rs_proto::AllowCredentials;
+
+/// Identities of local accounts use this provider id, with the username as upstream id.
+pub const LOCAL_PROVIDER: &str = "local";
+
+/// How long a WebAuthn ceremony may take before its challenge expires.
+const CHALLENGE_LIFETIME_MINUTES: i64 = 5;
+
+fn webauthn(state: &AppState) -> Result<&Webauthn, ApiError> {
+    match state.settings.auth_backend {
+        AuthBackend::Local => state.webauthn.as_ref().ok_or(ApiError::LocalAuthDisabled),
+        AuthBackend::OpenId => Err(ApiError::LocalAuthDisabled),
+    }
+}
+
+async fn find_local_user(state: &AppState, username: String) -> Result<User, ApiError> {
+    let identity = Identity::get(&state.db, LOCAL_PROVIDER.to_string(), username)
+        .await?
+        .ok_or(ApiError::InvalidCredentials)?;
+
+    User::get_by_id(&state.db, identity.user_id)
+        .await?
+        .ok_or(ApiError::InvalidCredentials)
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct LocalRegisterPayload {
+    pub username: String,
+    pub password: String,
+    pub display_name: Option<String>,
+    /// Required when the instance only allows registering with an invite.
+    pub invite_code: Option<String>,
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/local/register",
+	tag = "user",
+	request_body = LocalRegisterPayload,
+	responses(
+		(status = OK, description = "Successfully registered. The JWT is set as a cookie"),
+		(status = CONFLICT, description = "The username is already taken"),
+		(status = FORBIDDEN, description = "New accounts are not allowed by the registration mode of the instance")
+	),
+	security(())
+)]
+pub async fn register(
+    State(state): State<Arc<AppState>>,
+    JSON(payload): JSON<LocalRegisterPayload>,
+) -> Result<(StatusCode, HeaderMap), ApiError> {
+    webauthn(&state)?;
+
+    let username = payload.username.trim().to_string();
+    if username.is_empty() {
+        return Err(ApiError::JsonDataError);
+    }
+
+    if payload.password.chars().count() < local_auth::MINIMUM_PASSWORD_LENGTH {
+        return Err(ApiError::WeakPassword);
+    }
+
+    let user = User {
+        id: utils::generate_id(16),
+        display_name: payload.display_name.unwrap_or(username.clone()),
+        username: username.clone(),
+        avatar_url: "".to_string(),
+        display_name_override: None,
+        deletion_scheduled_for: None,
+        is_admin: false,
+        disabled_at: None,
+        sessions_valid_after: None,
+        suspended: false,
+        icon_fetching: true,
+    };
+    let credential = LocalCredential {
+        user_id: user.id.clone(),
+        password_hash: local_auth::hash_password(payload.password).await,
+    };
+
+    // All or nothing, so a failure never leaves an account without its identity or password behind,
+    // nor uses up the invite
+    let mut tx = state.db.begin().await?;
+
+    // Local accounts have neither an email nor groups, so the allowlist never matches
+    let invite = registration::authorize(
+        &state,
+        &mut *tx,
+        Applicant {
+            invite_code: payload.invite_code.as_deref(),
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    user.insert(&mut *tx).await?;
+
+    // The identity is unique, which also catches concurrent registrations of the same username
+    Identity {
+        user_id: user.id.clone(),
+        provider: LOCAL_PROVIDER.to_string(),
+        upstream_userid: username,
+        linked_at: chrono::Utc::now(),
+    }
+    .insert(&mut *tx)
+    .await
+    .map_err(|err| match err.as_database_error() {
+        Some(db_err) if db_err.is_unique_violation() => ApiError::UsernameTaken,
+        _ => err.into(),
+    })?;
+
+    credential.insert(&mut *tx).await?;
+
+    if let Some(mut invite) = invite {
+        invite.set_used_by(&mut *tx, user.id.clone()).await?;
+    }
+    tx.commit().await?;
+
+    Ok(auth::login_response(&user, state.settings.jwt_secret.clone()).await)
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct LocalLoginPayload {
+    pub username: String,
+    pub password: String,
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/local/login",
+	tag = "user",
+	request_body = LocalLoginPayload,
+	responses(
+		(status = OK, description = "Successfully logged in. The JWT is set as a cookie"),
+		(status = UNAUTHORIZED, description = "Invalid username or password")
+	),
+	security(())
+)]
+pub async fn login(
+    State(state): State<Arc<AppState>>,
+    JSON(payload): JSON<LocalLoginPayload>,
+) -> Result<(StatusCode, HeaderMap), ApiError> {
+    webauthn(&state)?;
+
+    // Unknown users are verified against a dummy hash, so they take as long to reject as wrong passwords
+    let user = find_local_user(&state, payload.username.trim().to_string()).await;
+    let password_hash = match &user {
+        Ok(user) => LocalCredential::get(&state.db, user.id.clone())
+            .await?
+            .map(|credential| credential.password_hash),
+        Err(_) => None,
+    };
+
+    let valid = local_auth::verify_password(payload.password, password_hash).await;
+    let user = user?;
+    if !valid {
+        return Err(ApiError::InvalidCredentials);
+    }
+
+    Ok(auth::login_response(&user, state.settings.jwt_secret.clone()).await)
+}
+
+#[derive(Serialize, ToSchema)]
+pub struct PasskeyChallengeResponse<T> {
+    /// Id of the challenge, to be passed to the matching `finish` endpoint.
+    pub challenge_id: String,
+    /// Options to pass to `navigator.credentials`.
+    #[schema(value_type = Object)]
+    pub options: T,
+}
+
+async fn store_challenge(
+    state: &AppState,
+    user_id: Option<String>,
+    ceremony: CeremonyState,
+) -> Result<String, ApiError> {
+    let challenge = WebauthnChallenge {
+        id: utils::generate_id(32),
+        user_id,
+        state: Json(ceremony),
+        expires_at: chrono::Utc::now() + chrono::Duration::minutes(CHALLENGE_LIFETIME_MINUTES),
+    };
+
+    challenge.insert(&state.db).await?;
+    Ok(challenge.id)
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/local/passkey/register/start",
+	tag = "user",
+	responses(
+		(status = OK, description = "Successfully started passkey registration", body = PasskeyChallengeResponse<Object>),
+		(status = UNAUTHORIZED, description = "Requires a recent login, see `StepUpRequired`")
+	),
+)]
+pub async fn passkey_register_start(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Extension(auth_time): Extension<AuthTime>,
+) -> Result<JSON<PasskeyChallengeResponse<CreationChallengeResponse>>, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+    auth_time.require_fresh()?;
+
+    let webauthn = webauthn(&state)?;
+
+    let existing = StoredPasskey::get_many(&state.db, user.id.clone())
+        .await?
+        .iter()
+        .map(|stored| stored.passkey.cred_id().clone())
+        .collect();
+
+    let (options, registration) = webauthn
+        .start_passkey_registration(
+            local_auth::user_handle(&user.id),
+            &user.username,
+            &user.display_name,
+            Some(existing),
+        )
+        .map_err(ApiError::WebauthnFail)?;
+
+    let challenge_id = store_challenge(
+        &state,
+        Some(user.id),
+        CeremonyState::Registration(registration),
+    )
+    .await?;
+
+    Ok(JSON(PasskeyChallengeResponse {
+        challenge_id,
+        options,
+    }))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct PasskeyRegisterPayload {
+    pub challenge_id: String,
+    /// Name to recognize the passkey by, e.g. the device it is stored on.
+    pub name: String,
+    #[schema(value_type = Object)]
+    pub credential: RegisterPublicKeyCredential,
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/local/passkey/register/finish",
+	tag = "user",
+	request_body = PasskeyRegisterPayload,
+	responses(
+		(status = NO_CONTENT, description = "Successfully registered passkey")
+	),
+)]
+pub async fn passkey_register_finish(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    JSON(payload): JSON<PasskeyRegisterPayload>,
+) -> Result<StatusCode, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+
+    let webauthn = webauthn(&state)?;
+
+    let challenge = WebauthnChallenge::take(&state.db, payload.challenge_id)
+        .await?
+        .filter(|challenge| challenge.user_id.as_ref() == Some(&user.id))
+        .ok_or(ApiError::WebauthnChallengeExpired)?;
+
+    let CeremonyState::Registration(registration) = challenge.state.0 else {
+        return Err(ApiError::WebauthnChallengeExpired);
+    };
+
+    let passkey = webauthn
+        .finish_passkey_registration(&payload.credential, &registration)
+        .map_err(ApiError::WebauthnFail)?;
+
+    StoredPasskey {
+        id: utils::generate_id(16),
+        user_id: user.id,
+        name: payload.name,
+        passkey: Json(passkey),
+        created_at: chrono::Utc::now(),
+    }
+    .insert(&state.db)
+    .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct PasskeyLoginStartPayload {
+    pub username: String,
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/local/passkey/login/start",
+	tag = "user",
+	request_body = PasskeyLoginStartPayload,
+	responses(
+		(status = OK, description = "Successfully started passkey authentication. Unknown users, and users without passkeys, get a challenge too, which can not be finished", body = PasskeyChallengeResponse<Object>)
+	),
+	security(())
+)]
+pub async fn passkey_login_start(
+    State(state): State<Arc<AppState>>,
+    JSON(payload): JSON<PasskeyLoginStartPayload>,
+) -> Result<JSON<PasskeyChallengeResponse<RequestChallengeResponse>>, ApiError> {
+    let webauthn = webauthn(&state)?;
+
+    let username = payload.username.trim().to_string();
+    let user_id = match find_local_user(&state, username.clone()).await {
+        Ok(user) => Some(user.id),
+        Err(ApiError::InvalidCredentials) => None,
+        Err(err) => return Err(err),
+    };
+    let passkeys = match &user_id {
+        Some(user_id) => get_passkeys(&state, user_id.clone()).await?,
+        None => vec![],
+    };
+
+    if passkeys.is_empty() {
+        return start_decoy_authentication(&state, webauthn, &username).await;
+    }
+
+    challenge_passkeys(&state, webauthn, user_id, &passkeys).await
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct PasskeyLoginFinishPayload {
+    pub challenge_id: String,
+    #[schema(value_type = Object)]
+    pub credential: PublicKeyCredential,
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/local/passkey/login/finish",
+	tag = "user",
+	request_body = PasskeyLoginFinishPayload,
+	responses(
+		(status = OK, description = "Successfully logged in. The JWT is set as a cookie")
+	),
+	security(())
+)]
+pub async fn passkey_login_finish(
+    State(state): State<Arc<AppState>>,
+    JSON(payload): JSON<PasskeyLoginFinishPayload>,
+) -> Result<(StatusCode, HeaderMap), ApiError> {
+    let webauthn = webauthn(&state)?;
+
+    let challenge = WebauthnChallenge::take(&state.db, payload.challenge_id)
+        .await?
+        .ok_or(ApiError::WebauthnChallengeExpired)?;
+
+    let user_id = finish_authentication(&state, webauthn, challenge, &payload.credential).await?;
+    let user = User::get_by_id(&state.db, user_id)
+        .await?
+        .ok_or(ApiError::InvalidCredentials)?;
+
+    Ok(auth::login_response(&user, state.settings.jwt_secret.clone()).await)
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/local/passkey/step-up/start",
+	tag = "user",
+	responses(
+		(status = OK, description = "Successfully started passkey authentication", body = PasskeyChallengeResponse<Object>),
+		(status = UNAUTHORIZED, description = "The user has no passkeys")
+	),
+)]
+pub async fn passkey_step_up_start(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+) -> Result<JSON<PasskeyChallengeResponse<RequestChallengeResponse>>, ApiError> {
+    let webauthn = webauthn(&state)?;
+
+    start_authentication(&state, webauthn, user.id).await
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/local/passkey/step-up/finish",
+	tag = "user",
+	request_body = PasskeyLoginFinishPayload,
+	responses(
+		(status = OK, description = "Successfully authenticated again. The refreshed JWT is set as a cookie, and allows sensitive operations for a while")
+	),
+)]
+pub async fn passkey_step_up_finish(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    JSON(payload): JSON<PasskeyLoginFinishPayload>,
+) -> Result<(StatusCode, HeaderMap), ApiError> {
+    let webauthn = webauthn(&state)?;
+
+    // Only passkeys of the user who is already logged in count
+    let challenge = WebauthnChallenge::take(&state.db, payload.challenge_id)
+        .await?
+        .filter(|challenge| challenge.user_id.as_ref() == Some(&user.id))
+        .ok_or(ApiError::WebauthnChallengeExpired)?;
+
+    finish_authentication(&state, webauthn, challenge, &payload.credential).await?;
+
+    Ok(auth::login_response(&user, state.settings.jwt_secret.clone()).await)
+}
+
+async fn get_passkeys(state: &AppState, user_id: String) -> Result<Vec<Passkey>, ApiError> {
+    Ok(StoredPasskey::get_many(&state.db, user_id)
+        .await?
+        .into_iter()
+        .map(|stored| stored.passkey.0)
+        .collect())
+}
+
+/// Challenges the user to authenticate with one of their passkeys.
+async fn start_authentication(
+    state: &AppState,
+    webauthn: &Webauthn,
+    user_id: String,
+) -> Result<JSON<PasskeyChallengeResponse<RequestChallengeResponse>>, ApiError> {
+    let passkeys = get_passkeys(state, user_id.clone()).await?;
+
+    if passkeys.is_empty() {
+        return Err(ApiError::InvalidCredentials);
+    }
+
+    challenge_passkeys(state, webauthn, Some(user_id), &passkeys).await
+}
+
+/// Challenges an unknown user, or one without passkeys, to authenticate with made up passkeys. The challenge
+/// looks like one for a real user, with the same passkeys every time for the username, but can never be finished.
+async fn start_decoy_authentication(
+    state: &AppState,
+    webauthn: &Webauthn,
+    username: &str,
+) -> Result<JSON<PasskeyChallengeResponse<RequestChallengeResponse>>, ApiError> {
+    let generator = WebauthnFakeCredentialGenerator::<FakePasskeyDistribution>::new(
+        state.settings.jwt_secret.as_bytes(),
+    )
+    .map_err(ApiError::WebauthnFail)?;
+    let fake_ids = generator
+        .generate(username.as_bytes())
+        .map_err(ApiError::WebauthnFail)?;
+
+    // Without any passkeys to allow, finishing fails just like with a passkey of someone else
+    let mut response = challenge_passkeys(state, webauthn, None, &[]).await?;
+    response.0.options.public_key.allow_credentials = fake_ids
+        .into_iter()
+        .map(|id| AllowCredentials {
+            type_: "public-key".to_string(),
+            id: id.to_vec().into(),
+            transports: None,
+        })
+        .collect();
+
+    Ok(response)
+}
+
+async fn challenge_passkeys(
+    state: &AppState,
+    webauthn: &Webauthn,
+    user_id: Option<String>,
+    passkeys: &[Passkey],
+) -> Result<JSON<PasskeyChallengeResponse<RequestChallengeResponse>>, ApiError> {
+    let (options, authentication) = webauthn
+        .start_passkey_authentication(passkeys)
+        .map_err(ApiError::WebauthnFail)?;
+
+    let challenge_id = store_challenge(
+        state,
+        user_id,
+        CeremonyState::Authentication(authentication),
+    )
+    .await?;
+
+    Ok(JSON(PasskeyChallengeResponse {
+        challenge_id,
+        options,
+    }))
+}
+
+/// Verifies the assertion of a passkey, returning the id of the user it belongs to.
+async fn finish_authentication(
+    state: &AppState,
+    webauthn: &Webauthn,
+    challenge: WebauthnChallenge,
+    credential: &PublicKeyCredential,
+) -> Result<String, ApiError> {
+    let CeremonyState::Authentication(authentication) = challenge.state.0 else {
+        return Err(ApiError::WebauthnChallengeExpired);
+    };
+
+    let result = webauthn
+        .finish_passkey_authentication(credential, &authentication)
+        .map_err(ApiError::WebauthnFail)?;
+
+    // Decoy challenges allow no passkeys, so they never get here
+    let user_id = challenge.user_id.ok_or(ApiError::InvalidCredentials)?;
+
+    // Keep the signature counter up to date, to detect cloned authenticators
+    for mut stored in StoredPasskey::get_many(&state.db, user_id.clone()).await? {
+        if stored.passkey.update_credential(&result) == Some(true) {
+            stored.update(&state.db).await?;
+        }
+    }
+
+    Ok(user_id)
+}
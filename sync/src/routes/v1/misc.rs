@@ -1,17 +1,42 @@
-use crate::AppState;
+use crate::{
+    cli::{AuthBackend, RegistrationMode},
+    AppState,
+};
 use axum::{extract::State, http::StatusCode, response::IntoResponse, Json};
 use serde::Serialize;
-use std::sync::Arc;
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
 use utoipa::ToSchema;
 
 #[derive(Serialize, Debug, ToSchema)]
-pub struct IceblinkInstanceMetadata {
-    version: String,
+pub struct IceblinkInstanceProvider {
+    id: String,
+    name: String,
     client_id: String,
     authorize: String,
     redirect_uri: String,
 }
 
+#[derive(Serialize, Debug, ToSchema)]
+pub struct IceblinkInstanceMetadata {
+    version: String,
+    auth_backend: AuthBackend,
+    /// Clients should ask for an invite code with the invite mode.
+    registration: RegistrationMode,
+    /// Of the `default` provider, for clients from before multiple providers. Missing without one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    client_id: Option<String>,
+    /// Of the `default` provider, for clients from before multiple providers. Missing without one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    authorize: Option<String>,
+    /// Of the `default` provider, for clients from before multiple providers. Missing without one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    redirect_uri: Option<String>,
+    providers: Vec<IceblinkInstanceProvider>,
+}
+
 #[utoipa::path(
 	get,
 	path = "/v1/",
@@ -24,13 +49,28 @@ pub struct IceblinkInstanceMetadata {
 pub async fn instance_metadata(
     State(data): State<Arc<AppState>>,
 ) -> (StatusCode, Json<IceblinkInstanceMetadata>) {
+    let default = data.openid.get("default");
+
     (
         StatusCode::OK,
         Json(IceblinkInstanceMetadata {
             version: env!("CARGO_PKG_VERSION").to_string(),
-            authorize: data.openid.authorization.clone(),
-            client_id: data.openid.client_id.clone(),
-            redirect_uri: data.settings.redirect_uri.clone(),
+            auth_backend: data.settings.auth_backend.clone(),
+            registration: data.settings.registration.mode.clone(),
+            client_id: default.map(|provider| provider.client_id.clone()),
+            authorize: default.map(|provider| provider.authorization.clone()),
+            redirect_uri: default.map(|provider| provider.redirect_uri.clone()),
+            providers: data
+                .openid
+                .iter()
+                .map(|provider| IceblinkInstanceProvider {
+                    id: provider.id.clone(),
+                    name: provider.name.clone(),
+                    authorize: provider.authorization.clone(),
+                    client_id: provider.client_id.clone(),
+                    redirect_uri: provider.redirect_uri.clone(),
+                })
+                .collect(),
         }),
     )
 }
@@ -45,5 +85,22 @@ pub async fn instance_metadata(
 	security(())
 )]
 pub async fn metrics(State(data): State<Arc<AppState>>) -> impl IntoResponse {
+    let stats = data.icon_store.stats();
+    let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+
+    metrics::with_local_recorder(data.recorder.as_ref(), || {
+        metrics::counter!("icon_cache_requests_total", "result" => "hit")
+            .absolute(load(&stats.hits));
+        metrics::counter!("icon_cache_requests_total", "result" => "negative")
+            .absolute(load(&stats.negative_hits));
+        metrics::counter!("icon_cache_requests_total", "result" => "miss")
+            .absolute(load(&stats.misses));
+        metrics::counter!("icon_fetches_total", "result" => "success")
+            .absolute(load(&stats.fetch_successes));
+        metrics::counter!("icon_fetches_total", "result" => "failure")
+            .absolute(load(&stats.fetch_failures));
+        metrics::counter!("icon_fetches_coalesced_total").absolute(load(&stats.coalesced));
+    });
+
     data.metrics.render()
 }
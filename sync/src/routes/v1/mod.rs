@@ -5,9 +5,16 @@ use serde::Serialize;
 use std::fmt::Debug;
 use tracing::warn;
 
+pub mod admin;
 pub mod codes;
+pub mod device;
+pub mod emergency;
+pub mod local;
 pub mod misc;
+pub mod shares;
+pub mod tokens;
 pub mod users;
+pub mod vaults;
 
 #[derive(Serialize)]
 pub struct ApiErrorResponse {
@@ -28,12 +35,59 @@ pub enum ApiError {
     InvalidAuthentication,
     InvalidJwtSignature,
     JwtUserGone,
+    ApiTokenExpired,
+    MissingScope,
+    /// Sensitive operations require a recent login. Clients should authenticate again and retry.
+    StepUpRequired,
     /// Usually caused by giving Iceblink an invalid authentication token.
     /// Still logging a warning regardless.
     OpenIdTokenExchangeFail(reqwest::Error),
     /// This should generally not happen, since we have received an authenticated token from the IdP.
     OpenIdUserinfoFail(reqwest::Error),
+    UnknownProvider,
+    IdentityAlreadyLinked,
+    /// An account must always have at least one identity to log in with.
+    LastIdentity,
+    LocalAuthDisabled,
+    UsernameTaken,
+    WeakPassword,
+    InvalidCredentials,
+    WebauthnChallengeExpired,
+    /// Usually caused by the client sending an invalid or tampered credential.
+    WebauthnFail(webauthn_rs::prelude::WebauthnError),
+    /// The user has not approved the device yet. The device should keep polling.
+    AuthorizationPending,
+    /// The device is polling faster than the advertised interval.
+    SlowDown,
+    DeviceCodeExpired,
+    InsufficientVaultRole,
+    /// A vault must always have at least one owner.
+    LastVaultOwner,
+    AlreadyVaultMember,
+    VaultInvitationExpired,
+    InsufficientSharePermission,
+    /// Only time-based one-time passwords can be shown through a share link.
+    NotTotpCode,
+    /// The waiting period is not over, or the owner denied the request.
+    EmergencyAccessNotGranted,
+    AccountDisabled,
+    RegistrationClosed,
+    /// The email domain or groups of the user are not on the allowlist.
+    RegistrationNotAllowed,
+    InvalidInviteCode,
+    /// Suspended accounts can only read.
+    AccountSuspended,
+    CodeLimitReached,
+    CodeContentTooLong,
+    DisplayNameTooLong,
+    AdminRequired,
+    /// Administrators can not disable, suspend or delete their own account through the admin routes.
+    AdminSelfAction,
     NoIcon,
+    IconTooLarge,
+    /// Icons must be PNG, JPEG, GIF, WebP, BMP, ICO or SVG images.
+    InvalidIcon,
+    IconStoreFail(crate::icons::IconStoreError),
 }
 
 impl IntoResponse for ApiError {
@@ -55,6 +109,9 @@ impl IntoResponse for ApiError {
 			ApiError::InvalidAuthentication => (StatusCode::UNAUTHORIZED, "The supplied authentication is invalid."),
 			ApiError::InvalidJwtSignature => (StatusCode::UNAUTHORIZED, "The supplied authentication has an invalid signature. Try logging in again."),
 			ApiError::JwtUserGone => (StatusCode::UNAUTHORIZED, "Authenticated user does not exist. Has the account been deleted?"),
+			ApiError::ApiTokenExpired => (StatusCode::UNAUTHORIZED, "The supplied personal access token has expired. Create a new one."),
+			ApiError::MissingScope => (StatusCode::FORBIDDEN, "The supplied personal access token does not have the scope required for this operation."),
+			ApiError::StepUpRequired => (StatusCode::UNAUTHORIZED, "This operation requires a recent login. Please authenticate again and retry."),
 			ApiError::OpenIdTokenExchangeFail(err) => {
 				warn!("Failed to exchange from IdP: {err}");
 				(StatusCode::BAD_REQUEST, "Failed to exchange token with authentication provider. Please make sure to not edit the URL. Please try again.")
@@ -63,7 +120,45 @@ impl IntoResponse for ApiError {
 				warn!("Failed to get userinfo from IdP: {err}");
 				(StatusCode::INTERNAL_SERVER_ERROR, "Failed to aquire userinfo from authentication provider. Try again later.")
 			},
-			ApiError::NoIcon => (StatusCode::NO_CONTENT, "Unable to find an icon for this code. Double check your website URL.")
+			ApiError::UnknownProvider => (StatusCode::NOT_FOUND, "No authentication provider with this id is configured on this instance."),
+			ApiError::IdentityAlreadyLinked => (StatusCode::CONFLICT, "This identity, or another identity from the same provider, is already linked to an account."),
+			ApiError::LastIdentity => (StatusCode::CONFLICT, "Unable to unlink the last identity of an account. Link another identity first."),
+			ApiError::LocalAuthDisabled => (StatusCode::NOT_FOUND, "Local authentication is not enabled on this instance."),
+			ApiError::UsernameTaken => (StatusCode::CONFLICT, "This username is already taken."),
+			ApiError::WeakPassword => (StatusCode::BAD_REQUEST, "Passwords must be at least 8 characters long."),
+			ApiError::InvalidCredentials => (StatusCode::UNAUTHORIZED, "Invalid username or credentials."),
+			ApiError::WebauthnChallengeExpired => (StatusCode::BAD_REQUEST, "The passkey challenge is invalid or has expired. Please try again."),
+			ApiError::WebauthnFail(err) => {
+				warn!("WebAuthn ceremony failed: {err}");
+				(StatusCode::UNAUTHORIZED, "Unable to verify the passkey. Please try again.")
+			},
+			ApiError::AuthorizationPending => (StatusCode::BAD_REQUEST, "The device has not been approved yet. Keep polling."),
+			ApiError::SlowDown => (StatusCode::BAD_REQUEST, "Polling too fast. Wait at least the given interval between requests."),
+			ApiError::DeviceCodeExpired => (StatusCode::BAD_REQUEST, "The device code is invalid or has expired. Request a new one."),
+			ApiError::InsufficientVaultRole => (StatusCode::FORBIDDEN, "Your role in this vault does not allow this operation."),
+			ApiError::LastVaultOwner => (StatusCode::CONFLICT, "Unable to remove or demote the last owner of a vault. Promote another member first."),
+			ApiError::AlreadyVaultMember => (StatusCode::CONFLICT, "You are already a member of this vault."),
+			ApiError::VaultInvitationExpired => (StatusCode::BAD_REQUEST, "The invitation is invalid, has already been used or has expired. Ask for a new one."),
+			ApiError::InsufficientSharePermission => (StatusCode::FORBIDDEN, "This code was shared with you without permission for this operation."),
+			ApiError::NotTotpCode => (StatusCode::BAD_REQUEST, "This code is not a time-based one-time password, so it can not be shared with a link."),
+			ApiError::EmergencyAccessNotGranted => (StatusCode::FORBIDDEN, "Emergency access has not been granted. Request it, and wait until the waiting period is over."),
+			ApiError::AccountDisabled => (StatusCode::FORBIDDEN, "This account has been disabled by an administrator of this instance."),
+			ApiError::RegistrationClosed => (StatusCode::FORBIDDEN, "Registration is closed on this instance. Only existing users can log in."),
+			ApiError::RegistrationNotAllowed => (StatusCode::FORBIDDEN, "Your account is not allowed to register on this instance. Ask an administrator."),
+			ApiError::InvalidInviteCode => (StatusCode::FORBIDDEN, "Registration requires an invite code. The code is missing, already used or has expired."),
+			ApiError::AccountSuspended => (StatusCode::FORBIDDEN, "This account has been suspended by an administrator of this instance. Your codes can still be read and exported."),
+			ApiError::CodeLimitReached => (StatusCode::FORBIDDEN, "The maximum amount of codes on this instance has been reached. Delete a code first."),
+			ApiError::CodeContentTooLong => (StatusCode::BAD_REQUEST, "The content of the code is longer than this instance allows."),
+			ApiError::DisplayNameTooLong => (StatusCode::BAD_REQUEST, "The display name is longer than this instance allows."),
+			ApiError::AdminRequired => (StatusCode::FORBIDDEN, "This operation is only available to administrators of this instance."),
+			ApiError::AdminSelfAction => (StatusCode::CONFLICT, "Administrators can not disable, suspend or delete their own account. Ask another administrator."),
+			ApiError::NoIcon => (StatusCode::NO_CONTENT, "Unable to find an icon for this code. Double check your website URL."),
+			ApiError::IconTooLarge => (StatusCode::PAYLOAD_TOO_LARGE, "The icon is larger than 1 MiB."),
+			ApiError::InvalidIcon => (StatusCode::BAD_REQUEST, "The icon is not a supported image. Upload a PNG, JPEG, GIF, WebP, BMP, ICO or SVG."),
+			ApiError::IconStoreFail(err) => {
+				warn!("Icon store error occoured: {:?}", err);
+				(StatusCode::INTERNAL_SERVER_ERROR, "Unable to access the icon cache. Try again later.")
+			}
         };
 
         (
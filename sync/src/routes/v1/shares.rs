@@ -0,0 +1,220 @@
+use super::{ApiError, JSON};
+use crate::{
+    auth::{Scope, Scopes},
+    models::{
+        codes::Code,
+        share::{CodeShare, ShareLink, SharePermission},
+        user::User,
+    },
+    otp, utils, AppState,
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+const DEFAULT_LINK_LIFETIME_MINUTES: i64 = 60;
+const MAX_LINK_LIFETIME_MINUTES: i64 = 60 * 24 * 7;
+
+/// Only the owner of a personal code may manage who it is shared with.
+async fn find_owned_code(state: &AppState, id: String, user: &User) -> Result<Code, ApiError> {
+    let code = Code::get(&state.db, id, user.id.clone())
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    if code.shared_permission.is_some() {
+        return Err(ApiError::InsufficientSharePermission);
+    }
+
+    Ok(code)
+}
+
+#[utoipa::path(
+	get,
+	path = "/v1/code/{id}/shares",
+	tag = "codes",
+	params(
+		("id", description = "Id of the code")
+	),
+	responses(
+		(status = OK, description = "Successfully fetched who the code is shared with", body = Vec<CodeShare>)
+	),
+)]
+pub async fn list_shares(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Path(id): Path<String>,
+) -> Result<JSON<Vec<CodeShare>>, ApiError> {
+    scopes.require(Scope::CodesRead)?;
+
+    let code = find_owned_code(&state, id, &user).await?;
+
+    Ok(JSON(CodeShare::get_many(&state.db, code.id).await?))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct SharePayload {
+    pub permission: SharePermission,
+}
+
+#[utoipa::path(
+	method(put),
+	path = "/v1/code/{id}/shares/{user_id}",
+	tag = "codes",
+	params(
+		("id", description = "Id of the code to share"),
+		("user_id", description = "Id of the user to share with")
+	),
+	request_body = SharePayload,
+	responses(
+		(status = OK, description = "Successfully shared the code, or changed the permission", body = CodeShare),
+		(status = NOT_FOUND, description = "Unknown code or user")
+	),
+)]
+pub async fn share_code(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Path((id, user_id)): Path<(String, String)>,
+    JSON(payload): JSON<SharePayload>,
+) -> Result<JSON<CodeShare>, ApiError> {
+    scopes.require(Scope::CodesWrite)?;
+
+    let code = find_owned_code(&state, id, &user).await?;
+
+    if user_id == user.id {
+        return Err(ApiError::JsonDataError);
+    }
+
+    let grantee = User::get_by_id(&state.db, user_id)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    CodeShare {
+        code_id: code.id.clone(),
+        grantee_id: grantee.id.clone(),
+        permission: payload.permission,
+        created_at: chrono::Utc::now(),
+    }
+    .upsert(&state.db)
+    .await?;
+
+    Ok(JSON(
+        CodeShare::get(&state.db, code.id, grantee.id)
+            .await?
+            .ok_or(ApiError::NotFound)?,
+    ))
+}
+
+#[utoipa::path(
+	method(delete),
+	path = "/v1/code/{id}/shares/{user_id}",
+	tag = "codes",
+	params(
+		("id", description = "Id of the shared code"),
+		("user_id", description = "Id of the user to revoke. Use your own id to remove a code shared with you")
+	),
+	responses(
+		(status = NO_CONTENT, description = "Successfully revoked the share")
+	),
+)]
+pub async fn revoke_share(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Path((id, user_id)): Path<(String, String)>,
+) -> Result<StatusCode, ApiError> {
+    scopes.require(Scope::CodesWrite)?;
+
+    // Grantees may remove shares with themselves
+    if user_id != user.id {
+        find_owned_code(&state, id.clone(), &user).await?;
+    }
+
+    CodeShare::get(&state.db, id, user_id)
+        .await?
+        .ok_or(ApiError::NotFound)?
+        .delete(&state.db)
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct ShareLinkCreatePayload {
+    /// Minutes until the link expires, if it is not opened before. Defaults to an hour, at most a week.
+    pub expires_in_minutes: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct ShareLinkCreateResponse {
+    /// Link showing the current one-time password once, without an account.
+    /// Only shown once, it can not be retrieved later.
+    pub url: String,
+    #[serde(flatten)]
+    pub details: ShareLink,
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/code/{id}/share-link",
+	tag = "codes",
+	params(
+		("id", description = "Id of the code to share")
+	),
+	request_body = ShareLinkCreatePayload,
+	responses(
+		(status = OK, description = "Successfully created a single-use link", body = ShareLinkCreateResponse),
+		(status = BAD_REQUEST, description = "The code is not a time-based one-time password")
+	),
+)]
+pub async fn create_share_link(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Path(id): Path<String>,
+    JSON(payload): JSON<ShareLinkCreatePayload>,
+) -> Result<JSON<ShareLinkCreateResponse>, ApiError> {
+    scopes.require(Scope::CodesWrite)?;
+
+    let code = super::codes::find_code(&state, id, &user, super::codes::Access::Write).await?;
+
+    // Grantees may not pass the code on
+    if code.shared_permission.is_some() {
+        return Err(ApiError::InsufficientSharePermission);
+    }
+
+    let lifetime = payload
+        .expires_in_minutes
+        .unwrap_or(DEFAULT_LINK_LIFETIME_MINUTES);
+    if !(1..=MAX_LINK_LIFETIME_MINUTES).contains(&lifetime) {
+        return Err(ApiError::JsonDataError);
+    }
+
+    let now = chrono::Utc::now();
+    if otp::generate(&code.content, now).is_none() {
+        return Err(ApiError::NotTotpCode);
+    }
+
+    ShareLink::delete_expired(&state.db).await?;
+
+    let token = utils::generate_id(32);
+    let link = ShareLink {
+        token_hash: utils::hash_token(&token),
+        code_id: code.id,
+        created_by: user.id,
+        created_at: now,
+        expires_at: now + chrono::Duration::minutes(lifetime),
+    };
+    link.insert(&state.db).await?;
+
+    Ok(JSON(ShareLinkCreateResponse {
+        url: format!("{}/s/{token}", state.settings.frontfacing),
+        details: link,
+    }))
+}
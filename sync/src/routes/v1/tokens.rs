@@ -0,0 +1,132 @@
+use super::{ApiError, JSON};
+use crate::{
+    auth::{AuthTime, Scope, Scopes, API_TOKEN_PREFIX},
+    models::{token::ApiToken, user::User},
+    utils, AppState,
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use sqlx::types::Json;
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+const DEFAULT_EXPIRY_DAYS: u32 = 90;
+const MAXIMUM_EXPIRY_DAYS: u32 = 365;
+
+#[utoipa::path(
+	get,
+	path = "/v1/user/tokens",
+	tag = "user",
+	responses(
+		(status = OK, description = "Successfully fetched personal access tokens", body = Vec<ApiToken>)
+	),
+)]
+pub async fn list_tokens(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+) -> Result<JSON<Vec<ApiToken>>, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+
+    Ok(JSON(ApiToken::get_many(&state.db, user.id).await?))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct TokenCreatePayload {
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    /// Days until the token expires. Defaults to 90, at most 365.
+    pub expires_in_days: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct TokenCreateResponse {
+    /// The token itself. Only shown once, it can not be retrieved later.
+    pub token: String,
+    #[serde(flatten)]
+    pub details: ApiToken,
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/user/tokens",
+	tag = "user",
+	request_body = TokenCreatePayload,
+	responses(
+		(status = OK, description = "Successfully created personal access token", body = TokenCreateResponse),
+		(status = UNAUTHORIZED, description = "Requires a recent login, see `StepUpRequired`")
+	),
+)]
+pub async fn create_token(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Extension(auth_time): Extension<AuthTime>,
+    JSON(payload): JSON<TokenCreatePayload>,
+) -> Result<JSON<TokenCreateResponse>, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+    auth_time.require_fresh()?;
+
+    let expires_in_days = payload.expires_in_days.unwrap_or(DEFAULT_EXPIRY_DAYS);
+    if payload.name.trim().is_empty()
+        || payload.scopes.is_empty()
+        || !(1..=MAXIMUM_EXPIRY_DAYS).contains(&expires_in_days)
+    {
+        return Err(ApiError::JsonDataError);
+    }
+
+    let mut token_scopes: Vec<Scope> = vec![];
+    for scope in payload.scopes {
+        if !token_scopes.contains(&scope) {
+            token_scopes.push(scope);
+        }
+    }
+
+    let token = API_TOKEN_PREFIX.to_string() + &utils::generate_id(40);
+    let now = chrono::Utc::now();
+    let details = ApiToken {
+        id: utils::generate_id(16),
+        user_id: user.id,
+        name: payload.name.trim().to_string(),
+        token_hash: utils::hash_token(&token),
+        scopes: Json(token_scopes),
+        created_at: now,
+        expires_at: now + chrono::Duration::days(expires_in_days.into()),
+        last_used_at: None,
+    };
+
+    details.insert(&state.db).await?;
+    Ok(JSON(TokenCreateResponse { token, details }))
+}
+
+#[utoipa::path(
+	method(delete),
+	path = "/v1/user/tokens/{id}",
+	tag = "user",
+	params(
+		("id", description = "Id of the personal access token to revoke")
+	),
+	responses(
+		(status = NO_CONTENT, description = "Successfully revoked")
+	),
+)]
+pub async fn delete_token(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+
+    ApiToken::get(&state.db, id, user.id)
+        .await?
+        .ok_or(ApiError::NotFound)?
+        .delete(&state.db)
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
@@ -1,80 +1,266 @@
 use super::{ApiError, JSON};
 use crate::{
-    auth,
-    models::{self, codes::Code, user::User},
+    auth::{self, AuthTime, OpenIdUserInfo, Scope, Scopes},
+    models::{self, codes::Code, export::FinalExport, identity::Identity, user::User},
+    registration::{self, Applicant},
     utils, AppState,
 };
 use axum::{
-    extract::{Query, State},
-    http::HeaderMap,
+    extract::{Path, Query, State},
+    http::{header, HeaderMap},
     Extension,
 };
-use reqwest::{header, StatusCode};
+use axum_extra::extract::cookie::{Cookie, CookieJar};
+use chrono::{DateTime, Utc};
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
 use std::sync::Arc;
-use utoipa::IntoParams;
+use utoipa::{IntoParams, ToSchema};
+
+/// Days between requesting the deletion of an account and actually deleting it.
+const DELETION_GRACE_PERIOD_DAYS: i64 = 7;
 
 #[derive(Deserialize, IntoParams)]
 pub struct OauthQueryParams {
     code: String,
 }
 
+/// Exchanges an authorization code with the given provider, and fetches the upstream userinfo.
+async fn exchange_userinfo(
+    state: &AppState,
+    provider: &str,
+    code: String,
+) -> Result<OpenIdUserInfo, ApiError> {
+    let openid = state
+        .openid
+        .get(provider)
+        .ok_or(ApiError::UnknownProvider)?;
+
+    let access_token = openid
+        .clone()
+        .exchange(code)
+        .await
+        .map_err(ApiError::OpenIdTokenExchangeFail)?;
+
+    openid
+        .clone()
+        .userinfo(access_token)
+        .await
+        .map_err(ApiError::OpenIdUserinfoFail)
+}
+
 #[utoipa::path(
 	method(get),
-	path = "/v1/oauth",
+	path = "/v1/oauth/{provider}",
 	tag = "user",
 	responses(
-		(status = OK, description = "Success")
+		(status = OK, description = "Success"),
+		(status = NOT_FOUND, description = "No provider with the given id is configured"),
+		(status = FORBIDDEN, description = "New accounts are not allowed by the registration mode of the instance")
 	),
 	params(
+		("provider", description = "Id of the OpenId provider, as listed in the instance metadata"),
 		OauthQueryParams
 	),
 	security(())
 )]
 pub async fn oauth(
     State(state): State<Arc<AppState>>,
+    Path(provider): Path<String>,
     query: Query<OauthQueryParams>,
+    cookie_jar: CookieJar,
 ) -> Result<(StatusCode, HeaderMap), ApiError> {
     let code = query.code.to_string();
-    let mut headers = HeaderMap::default();
 
-    let access_token = state
-        .openid
-        .clone()
-        .exchange(code.clone())
-        .await
-        .map_err(ApiError::OpenIdTokenExchangeFail)?;
+    let userinfo = exchange_userinfo(&state, &provider, code).await?;
 
-    let userinfo = state
-        .openid
-        .clone()
-        .userinfo(access_token)
-        .await
-        .map_err(ApiError::OpenIdUserinfoFail)?;
+    let user_query =
+        models::user::User::get_by_upstream_id(&state.db, provider.clone(), userinfo.clone().id)
+            .await?;
 
-    let user_query = models::user::User::get_by_upstream_id(&state.db, userinfo.clone().id).await?;
+    let username = userinfo.username();
+    let display_name = userinfo.clone().display_name.unwrap_or(username.clone());
+    let avatar_url = userinfo.clone().avatar.unwrap_or_default();
 
+    let mut invite_used = false;
     let user = match user_query {
         None => {
+            // The invite is only used up if the account is created
+            let mut tx = state.db.begin().await?;
+            let invite = registration::authorize(
+                &state,
+                &mut *tx,
+                Applicant {
+                    email: userinfo.email.as_deref(),
+                    email_verified: userinfo.email_verified,
+                    groups: &userinfo.groups,
+                    invite_code: cookie_jar
+                        .get(registration::INVITE_COOKIE)
+                        .map(|cookie| cookie.value()),
+                },
+            )
+            .await?;
+
             let user = User {
-                avatar_url: userinfo.clone().avatar,
-                display_name: userinfo
-                    .clone()
-                    .display_name
-                    .unwrap_or(userinfo.clone().username),
+                avatar_url,
+                display_name,
                 id: utils::generate_id(16),
-                upstream_userid: userinfo.clone().id,
-                username: userinfo.clone().username,
+                username,
+                display_name_override: None,
+                deletion_scheduled_for: None,
+                is_admin: false,
+                disabled_at: None,
+                sessions_valid_after: None,
+                suspended: false,
+                icon_fetching: true,
             };
-            user.insert(&state.db).await?;
+            user.insert(&mut *tx).await?;
+
+            Identity {
+                user_id: user.id.clone(),
+                provider,
+                upstream_userid: userinfo.clone().id,
+                linked_at: chrono::Utc::now(),
+            }
+            .insert(&mut *tx)
+            .await?;
+
+            if let Some(mut invite) = invite {
+                invite.set_used_by(&mut *tx, user.id.clone()).await?;
+                invite_used = true;
+            }
+            tx.commit().await?;
+
             user
         }
-        Some(user) => user,
+        // Keep the profile in sync with the IdP, so that the JWT claims are up to date
+        Some(mut user) => user
+            .edit()
+            .pool(&state.db)
+            .username(username)
+            .display_name(display_name)
+            .avatar_url(avatar_url)
+            .call()
+            .await?
+            .clone(),
     };
 
-    let (_, cookie) = auth::create_jwt(&user, state.settings.jwt_secret.clone()).await;
-    headers.insert(header::SET_COOKIE, cookie.to_string().parse().unwrap());
-    Ok((StatusCode::OK, headers))
+    let (status, mut headers) =
+        auth::login_response(&user, state.settings.jwt_secret.clone()).await;
+
+    if invite_used {
+        let removal = Cookie::build((registration::INVITE_COOKIE, ""))
+            .path("/")
+            .removal()
+            .build();
+        headers.append(header::SET_COOKIE, removal.to_string().parse().unwrap());
+    }
+
+    Ok((status, headers))
+}
+
+#[derive(Serialize, Deserialize, ToSchema, Clone, Debug)]
+pub struct UserProfile {
+    pub id: String,
+    pub username: String,
+    /// Display name to show, which is the override if set.
+    pub display_name: String,
+    /// Display name set by the user, which is kept when the profile is refreshed from the IdP.
+    pub display_name_override: Option<String>,
+    pub avatar_url: String,
+    /// Set while the account is scheduled for deletion. Clients should show a notice, with the option to cancel.
+    pub deletion_scheduled_for: Option<DateTime<Utc>>,
+    /// Whether you can use the `/v1/admin` routes.
+    pub is_admin: bool,
+    /// Whether the server may contact the websites of your codes to fetch their icons. When disabled, only
+    /// uploaded and already cached icons are served.
+    pub icon_fetching: bool,
+}
+
+impl From<User> for UserProfile {
+    fn from(user: User) -> Self {
+        UserProfile {
+            display_name: user.effective_display_name(),
+            id: user.id,
+            username: user.username,
+            display_name_override: user.display_name_override,
+            avatar_url: user.avatar_url,
+            deletion_scheduled_for: user.deletion_scheduled_for,
+            is_admin: user.is_admin,
+            icon_fetching: user.icon_fetching,
+        }
+    }
+}
+
+#[utoipa::path(
+	get,
+	path = "/v1/user",
+	tag = "user",
+	responses(
+		(status = OK, description = "Successfully fetched profile", body = UserProfile)
+	),
+)]
+pub async fn get_profile(Extension(user): Extension<User>) -> JSON<UserProfile> {
+    JSON(user.into())
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct UserEditPayload {
+    /// Set to null to use the display name from the IdP again.
+    #[serde(
+        default,
+        skip_serializing_if = "Option::is_none",
+        with = "::serde_with::rust::double_option"
+    )]
+    pub display_name: Option<Option<String>>,
+    /// Disable to keep the server from contacting the websites of your codes. Already cached icons can be
+    /// deleted with `DELETE /v1/user/icons`.
+    pub icon_fetching: Option<bool>,
+}
+
+#[utoipa::path(
+	method(patch),
+	path = "/v1/user",
+	tag = "user",
+	request_body = UserEditPayload,
+	responses(
+		(status = OK, description = "Successfully edited profile", body = UserProfile)
+	),
+)]
+pub async fn edit_profile(
+    State(state): State<Arc<AppState>>,
+    Extension(mut user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    JSON(payload): JSON<UserEditPayload>,
+) -> Result<JSON<UserProfile>, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+
+    let display_name_override = payload
+        .display_name
+        .map(|name| name.map(|inner| inner.trim().to_string()));
+
+    if let Some(Some(name)) = &display_name_override {
+        if name.is_empty() {
+            return Err(ApiError::JsonDataError);
+        }
+    }
+
+    Ok(JSON(
+        user.edit()
+            .pool(&state.db)
+            .maybe_display_name_override(display_name_override)
+            .maybe_icon_fetching(payload.icon_fetching)
+            .call()
+            .await?
+            .clone()
+            .into(),
+    ))
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct AccountDeletion {
+    /// When the account and all of its data will be deleted.
+    pub deletion_scheduled_for: DateTime<Utc>,
 }
 
 #[utoipa::path(
@@ -82,17 +268,196 @@ pub async fn oauth(
 	path = "/v1/user",
 	tag = "user",
 	responses(
-		(status = NO_CONTENT, description = "Successfully deleted")
+		(status = ACCEPTED, description = "Successfully scheduled deletion. A final export of the data is available until then", body = AccountDeletion),
+		(status = UNAUTHORIZED, description = "Requires a recent login, see `StepUpRequired`")
 	),
 )]
 pub async fn delete_account(
     State(state): State<Arc<AppState>>,
-    Extension(user): Extension<User>,
+    Extension(mut user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Extension(auth_time): Extension<AuthTime>,
+) -> Result<(StatusCode, JSON<AccountDeletion>), ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+    auth_time.require_fresh()?;
+
+    let deletion_scheduled_for = match user.deletion_scheduled_for {
+        Some(scheduled) => scheduled,
+        None => {
+            let export = build_export(&state, user.clone()).await?;
+            FinalExport {
+                user_id: user.id.clone(),
+                content: serde_json::to_string(&export).expect("Unable to serialize export"),
+                created_at: export.exported_at,
+            }
+            .insert(&state.db)
+            .await?;
+
+            let scheduled = Utc::now() + chrono::Duration::days(DELETION_GRACE_PERIOD_DAYS);
+            user.edit()
+                .pool(&state.db)
+                .deletion_scheduled_for(Some(scheduled))
+                .call()
+                .await?;
+
+            scheduled
+        }
+    };
+
+    Ok((
+        StatusCode::ACCEPTED,
+        JSON(AccountDeletion {
+            deletion_scheduled_for,
+        }),
+    ))
+}
+
+#[utoipa::path(
+	method(delete),
+	path = "/v1/user/deletion",
+	tag = "user",
+	responses(
+		(status = NO_CONTENT, description = "Successfully cancelled the deletion"),
+		(status = NOT_FOUND, description = "The account is not scheduled for deletion")
+	),
+)]
+pub async fn cancel_deletion(
+    State(state): State<Arc<AppState>>,
+    Extension(mut user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
 ) -> Result<StatusCode, ApiError> {
-    user.delete(&state.db).await?;
+    scopes.require(Scope::AccountAdmin)?;
+
+    if user.deletion_scheduled_for.is_none() {
+        return Err(ApiError::NotFound);
+    }
+
+    user.edit()
+        .pool(&state.db)
+        .deletion_scheduled_for(None)
+        .call()
+        .await?;
+    FinalExport::delete(&state.db, user.id).await?;
+
     Ok(StatusCode::NO_CONTENT)
 }
 
+#[utoipa::path(
+	get,
+	path = "/v1/user/deletion/export",
+	tag = "user",
+	responses(
+		(status = OK, description = "The export taken when the deletion was scheduled", body = AccountExport),
+		(status = NOT_FOUND, description = "The account is not scheduled for deletion"),
+		(status = UNAUTHORIZED, description = "Requires a recent login, see `StepUpRequired`")
+	),
+)]
+pub async fn final_export(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Extension(auth_time): Extension<AuthTime>,
+) -> Result<JSON<AccountExport>, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+    scopes.require(Scope::CodesRead)?;
+    auth_time.require_fresh()?;
+
+    let export = FinalExport::get(&state.db, user.id)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    Ok(JSON(serde_json::from_str(&export.content).map_err(
+        |err| ApiError::DatabaseError(sqlx::Error::Decode(err.into())),
+    )?))
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct IconDeletion {
+    /// Amount of websites whose cached icon was deleted.
+    pub removed: usize,
+}
+
+#[utoipa::path(
+	method(delete),
+	path = "/v1/user/icons",
+	tag = "user",
+	responses(
+		(status = OK, description = "Deleted the cached icons of the websites of your codes, except websites other users use too", body = IconDeletion)
+	),
+)]
+pub async fn delete_icons(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+) -> Result<JSON<IconDeletion>, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+
+    let mut websites: Vec<String> = Code::get_many(&state.db, user.id.clone())
+        .await?
+        .into_iter()
+        .filter_map(|code| code.website_url)
+        .collect();
+    websites.sort();
+    websites.dedup();
+
+    let mut removed = 0;
+    for website_url in websites {
+        if Code::website_used_by_others(&state.db, website_url.clone(), user.id.clone()).await? {
+            continue;
+        }
+
+        if state.icon_store.metadata(&website_url).await.is_some() {
+            removed += 1;
+        }
+        state
+            .icon_store
+            .remove(&website_url)
+            .await
+            .map_err(ApiError::IconStoreFail)?;
+    }
+
+    Ok(JSON(IconDeletion { removed }))
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct AccountExport {
+    pub exported_at: DateTime<Utc>,
+    pub profile: UserProfile,
+    pub identities: Vec<Identity>,
+    pub codes: Vec<Code>,
+}
+
+#[utoipa::path(
+	get,
+	path = "/v1/user/export",
+	tag = "user",
+	responses(
+		(status = OK, description = "Successfully exported all data of the account", body = AccountExport),
+		(status = UNAUTHORIZED, description = "Requires a recent login, see `StepUpRequired`")
+	),
+)]
+pub async fn export_account(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Extension(auth_time): Extension<AuthTime>,
+) -> Result<JSON<AccountExport>, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+    scopes.require(Scope::CodesRead)?;
+    auth_time.require_fresh()?;
+
+    Ok(JSON(build_export(&state, user).await?))
+}
+
+async fn build_export(state: &AppState, user: User) -> Result<AccountExport, ApiError> {
+    Ok(AccountExport {
+        exported_at: Utc::now(),
+        identities: Identity::get_many(&state.db, user.id.clone()).await?,
+        codes: Code::get_many(&state.db, user.id.clone()).await?,
+        profile: user.into(),
+    })
+}
+
 #[derive(Debug, Serialize, Deserialize, utoipa::ToSchema, Clone)]
 pub struct ChecksumResponse {
     pub checksum: String,
@@ -109,10 +474,126 @@ pub struct ChecksumResponse {
 pub async fn checksum(
     State(state): State<Arc<AppState>>,
     Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
 ) -> Result<JSON<ChecksumResponse>, ApiError> {
+    scopes.require(Scope::CodesRead)?;
+
     let codes = Code::get_many(&state.db, user.clone().id).await?;
 
     Ok(JSON(ChecksumResponse {
         checksum: utils::checksum(codes, &user),
     }))
 }
+
+#[utoipa::path(
+	get,
+	path = "/v1/user/identities",
+	tag = "user",
+	responses(
+		(status = OK, description = "Successfully fetched linked identities", body = Vec<Identity>)
+	),
+)]
+pub async fn list_identities(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+) -> Result<JSON<Vec<Identity>>, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+
+    Ok(JSON(Identity::get_many(&state.db, user.id).await?))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct IdentityLinkPayload {
+    /// Authorization code received from the provider.
+    pub code: String,
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/user/identities/{provider}",
+	tag = "user",
+	params(
+		("provider", description = "Id of the OpenId provider to link")
+	),
+	request_body = IdentityLinkPayload,
+	responses(
+		(status = OK, description = "Successfully linked identity", body = Identity),
+		(status = CONFLICT, description = "The identity, or another identity from this provider, is already linked"),
+		(status = UNAUTHORIZED, description = "Requires a recent login, see `StepUpRequired`")
+	),
+)]
+pub async fn link_identity(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Extension(auth_time): Extension<AuthTime>,
+    Path(provider): Path<String>,
+    JSON(payload): JSON<IdentityLinkPayload>,
+) -> Result<JSON<Identity>, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+    // The linked identity can log in to the account
+    auth_time.require_fresh()?;
+
+    let userinfo = exchange_userinfo(&state, &provider, payload.code).await?;
+
+    let already_linked = Identity::get(&state.db, provider.clone(), userinfo.id.clone())
+        .await?
+        .is_some()
+        || Identity::get_many(&state.db, user.id.clone())
+            .await?
+            .iter()
+            .any(|identity| identity.provider == provider);
+
+    if already_linked {
+        return Err(ApiError::IdentityAlreadyLinked);
+    }
+
+    let identity = Identity {
+        user_id: user.id,
+        provider,
+        upstream_userid: userinfo.id,
+        linked_at: chrono::Utc::now(),
+    };
+
+    identity.insert(&state.db).await?;
+    Ok(JSON(identity))
+}
+
+#[utoipa::path(
+	method(delete),
+	path = "/v1/user/identities/{provider}",
+	tag = "user",
+	params(
+		("provider", description = "Id of the OpenId provider to unlink")
+	),
+	responses(
+		(status = NO_CONTENT, description = "Successfully unlinked identity"),
+		(status = CONFLICT, description = "Refusing to unlink the last identity of the account"),
+		(status = UNAUTHORIZED, description = "Requires a recent login, see `StepUpRequired`")
+	),
+)]
+pub async fn unlink_identity(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Extension(auth_time): Extension<AuthTime>,
+    Path(provider): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+    auth_time.require_fresh()?;
+
+    let identities = Identity::get_many(&state.db, user.id).await?;
+
+    let identity = identities
+        .iter()
+        .find(|identity| identity.provider == provider)
+        .ok_or(ApiError::NotFound)?;
+
+    if identities.len() <= 1 {
+        return Err(ApiError::LastIdentity);
+    }
+
+    identity.delete(&state.db).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
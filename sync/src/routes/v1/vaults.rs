@@ -0,0 +1,414 @@
+use super::{ApiError, JSON};
+use crate::{
+    auth::{Scope, Scopes},
+    models::{
+        codes::Code,
+        user::User,
+        vault::{Vault, VaultInvitation, VaultMember, VaultMembership, VaultRole},
+    },
+    utils, AppState,
+};
+use axum::{
+    extract::{Path, State},
+    http::StatusCode,
+    Extension,
+};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use utoipa::ToSchema;
+
+const INVITATION_LIFETIME_DAYS: i64 = 7;
+
+/// Ensures the user is a member of the vault with at least the given role.
+/// Vaults the user is not a member of are reported as not found.
+pub(crate) async fn require_role(
+    state: &AppState,
+    vault_id: String,
+    user: &User,
+    role: VaultRole,
+) -> Result<VaultMember, ApiError> {
+    let member = VaultMember::get(&state.db, vault_id, user.id.clone())
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    if member.role < role {
+        return Err(ApiError::InsufficientVaultRole);
+    }
+
+    Ok(member)
+}
+
+/// Refuses to remove or demote the last owner of a vault.
+async fn ensure_other_owner(state: &AppState, member: &VaultMember) -> Result<(), ApiError> {
+    if member.role != VaultRole::Owner {
+        return Ok(());
+    }
+
+    let owners = VaultMember::get_many(&state.db, member.vault_id.clone())
+        .await?
+        .iter()
+        .filter(|m| m.role == VaultRole::Owner)
+        .count();
+
+    if owners <= 1 {
+        return Err(ApiError::LastVaultOwner);
+    }
+
+    Ok(())
+}
+
+#[utoipa::path(
+	get,
+	path = "/v1/vaults",
+	tag = "vaults",
+	responses(
+		(status = OK, description = "Successfully fetched vaults you are a member of", body = Vec<VaultMembership>)
+	),
+)]
+pub async fn list_vaults(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+) -> Result<JSON<Vec<VaultMembership>>, ApiError> {
+    scopes.require(Scope::CodesRead)?;
+
+    Ok(JSON(
+        VaultMember::get_memberships(&state.db, user.id).await?,
+    ))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct VaultCreatePayload {
+    pub name: String,
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/vaults",
+	tag = "vaults",
+	request_body = VaultCreatePayload,
+	responses(
+		(status = OK, description = "Successfully created vault. You are its owner", body = VaultMembership)
+	),
+)]
+pub async fn create_vault(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    JSON(payload): JSON<VaultCreatePayload>,
+) -> Result<JSON<VaultMembership>, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+
+    let name = payload.name.trim().to_string();
+    if name.is_empty() {
+        return Err(ApiError::JsonDataError);
+    }
+
+    let vault = Vault {
+        id: utils::generate_id(16),
+        name,
+        created_at: chrono::Utc::now(),
+    };
+    vault.insert(&state.db).await?;
+
+    VaultMember {
+        vault_id: vault.id.clone(),
+        user_id: user.id,
+        role: VaultRole::Owner,
+        joined_at: vault.created_at,
+    }
+    .insert(&state.db)
+    .await?;
+
+    Ok(JSON(VaultMembership {
+        id: vault.id,
+        name: vault.name,
+        created_at: vault.created_at,
+        role: VaultRole::Owner,
+    }))
+}
+
+#[utoipa::path(
+	method(delete),
+	path = "/v1/vaults/{id}",
+	tag = "vaults",
+	params(
+		("id", description = "Id of the vault to delete, together with all of its codes")
+	),
+	responses(
+		(status = NO_CONTENT, description = "Successfully deleted vault"),
+		(status = FORBIDDEN, description = "Only owners can delete a vault")
+	),
+)]
+pub async fn delete_vault(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Path(id): Path<String>,
+) -> Result<StatusCode, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+
+    require_role(&state, id.clone(), &user, VaultRole::Owner).await?;
+
+    Vault::get(&state.db, id)
+        .await?
+        .ok_or(ApiError::NotFound)?
+        .delete(&state.db)
+        .await?;
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[utoipa::path(
+	get,
+	path = "/v1/vaults/{id}/codes",
+	tag = "vaults",
+	params(
+		("id", description = "Id of the vault")
+	),
+	responses(
+		(status = OK, description = "Successfully fetched codes of the vault", body = Vec<Code>)
+	),
+)]
+pub async fn list_vault_codes(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Path(id): Path<String>,
+) -> Result<JSON<Vec<Code>>, ApiError> {
+    scopes.require(Scope::CodesRead)?;
+
+    require_role(&state, id.clone(), &user, VaultRole::Viewer).await?;
+
+    Ok(JSON(Code::get_many_in_vault(&state.db, id).await?))
+}
+
+#[utoipa::path(
+	get,
+	path = "/v1/vaults/{id}/members",
+	tag = "vaults",
+	params(
+		("id", description = "Id of the vault")
+	),
+	responses(
+		(status = OK, description = "Successfully fetched members of the vault", body = Vec<VaultMember>)
+	),
+)]
+pub async fn list_members(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Path(id): Path<String>,
+) -> Result<JSON<Vec<VaultMember>>, ApiError> {
+    scopes.require(Scope::CodesRead)?;
+
+    require_role(&state, id.clone(), &user, VaultRole::Viewer).await?;
+
+    Ok(JSON(VaultMember::get_many(&state.db, id).await?))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct MemberEditPayload {
+    pub role: VaultRole,
+}
+
+#[utoipa::path(
+	method(patch),
+	path = "/v1/vaults/{id}/members/{user_id}",
+	tag = "vaults",
+	params(
+		("id", description = "Id of the vault"),
+		("user_id", description = "Id of the member to change the role of")
+	),
+	request_body = MemberEditPayload,
+	responses(
+		(status = OK, description = "Successfully changed role", body = VaultMember),
+		(status = FORBIDDEN, description = "Only owners can change roles"),
+		(status = CONFLICT, description = "Refusing to demote the last owner")
+	),
+)]
+pub async fn edit_member(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Path((id, user_id)): Path<(String, String)>,
+    JSON(payload): JSON<MemberEditPayload>,
+) -> Result<JSON<VaultMember>, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+
+    require_role(&state, id.clone(), &user, VaultRole::Owner).await?;
+
+    let mut member = VaultMember::get(&state.db, id, user_id)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    if payload.role != VaultRole::Owner {
+        ensure_other_owner(&state, &member).await?;
+    }
+
+    member.set_role(&state.db, payload.role).await?;
+    Ok(JSON(member))
+}
+
+#[utoipa::path(
+	method(delete),
+	path = "/v1/vaults/{id}/members/{user_id}",
+	tag = "vaults",
+	params(
+		("id", description = "Id of the vault"),
+		("user_id", description = "Id of the member to remove. Use your own id to leave the vault")
+	),
+	responses(
+		(status = NO_CONTENT, description = "Successfully removed member"),
+		(status = FORBIDDEN, description = "Only owners can remove other members"),
+		(status = CONFLICT, description = "Refusing to remove the last owner")
+	),
+)]
+pub async fn remove_member(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Path((id, user_id)): Path<(String, String)>,
+) -> Result<StatusCode, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+
+    // Everyone may leave, but only owners may remove others
+    let required_role = if user_id == user.id {
+        VaultRole::Viewer
+    } else {
+        VaultRole::Owner
+    };
+    require_role(&state, id.clone(), &user, required_role).await?;
+
+    let member = VaultMember::get(&state.db, id, user_id)
+        .await?
+        .ok_or(ApiError::NotFound)?;
+
+    ensure_other_owner(&state, &member).await?;
+
+    member.delete(&state.db).await?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct InvitationCreatePayload {
+    /// Role the invitee gets when joining.
+    pub role: VaultRole,
+}
+
+#[derive(Serialize, Deserialize, ToSchema)]
+pub struct InvitationCreateResponse {
+    /// Code to give to the invitee. Only shown once, it can not be retrieved later.
+    pub code: String,
+    #[serde(flatten)]
+    pub details: VaultInvitation,
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/vaults/{id}/invitations",
+	tag = "vaults",
+	params(
+		("id", description = "Id of the vault to invite to")
+	),
+	request_body = InvitationCreatePayload,
+	responses(
+		(status = OK, description = "Successfully created a single-use invitation", body = InvitationCreateResponse),
+		(status = FORBIDDEN, description = "Only owners can invite")
+	),
+)]
+pub async fn create_invitation(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    Path(id): Path<String>,
+    JSON(payload): JSON<InvitationCreatePayload>,
+) -> Result<JSON<InvitationCreateResponse>, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+
+    require_role(&state, id.clone(), &user, VaultRole::Owner).await?;
+
+    let code = utils::generate_id(32);
+    let now = chrono::Utc::now();
+    let invitation = VaultInvitation {
+        id: utils::generate_id(16),
+        vault_id: id,
+        code_hash: utils::hash_token(&code),
+        role: payload.role,
+        created_by: user.id,
+        created_at: now,
+        expires_at: now + chrono::Duration::days(INVITATION_LIFETIME_DAYS),
+    };
+    invitation.insert(&state.db).await?;
+
+    Ok(JSON(InvitationCreateResponse {
+        code,
+        details: invitation,
+    }))
+}
+
+#[derive(Deserialize, ToSchema)]
+pub struct VaultJoinPayload {
+    /// Invitation code received from an owner of the vault.
+    pub code: String,
+}
+
+#[utoipa::path(
+	method(post),
+	path = "/v1/vaults/join",
+	tag = "vaults",
+	request_body = VaultJoinPayload,
+	responses(
+		(status = OK, description = "Successfully joined the vault", body = VaultMembership),
+		(status = BAD_REQUEST, description = "The invitation is invalid or has expired"),
+		(status = CONFLICT, description = "Already a member of the vault")
+	),
+)]
+pub async fn join_vault(
+    State(state): State<Arc<AppState>>,
+    Extension(user): Extension<User>,
+    Extension(scopes): Extension<Scopes>,
+    JSON(payload): JSON<VaultJoinPayload>,
+) -> Result<JSON<VaultMembership>, ApiError> {
+    scopes.require(Scope::AccountAdmin)?;
+
+    let code_hash = utils::hash_token(&payload.code);
+    let invitation = VaultInvitation::get(&state.db, code_hash.clone())
+        .await?
+        .ok_or(ApiError::VaultInvitationExpired)?;
+
+    // Members following the link again must not use up an invitation meant for someone else
+    if VaultMember::get(&state.db, invitation.vault_id.clone(), user.id.clone())
+        .await?
+        .is_some()
+    {
+        return Err(ApiError::AlreadyVaultMember);
+    }
+
+    let vault = Vault::get(&state.db, invitation.vault_id)
+        .await?
+        .ok_or(ApiError::VaultInvitationExpired)?;
+
+    // The invitation is only used up once the user has joined
+    let mut tx = state.db.begin().await?;
+    let invitation = VaultInvitation::take(&mut *tx, code_hash)
+        .await?
+        .ok_or(ApiError::VaultInvitationExpired)?;
+
+    VaultMember {
+        vault_id: vault.id.clone(),
+        user_id: user.id,
+        role: invitation.role,
+        joined_at: chrono::Utc::now(),
+    }
+    .insert(&mut *tx)
+    .await?;
+    tx.commit().await?;
+
+    Ok(JSON(VaultMembership {
+        id: vault.id,
+        name: vault.name,
+        created_at: vault.created_at,
+        role: invitation.role,
+    }))
+}
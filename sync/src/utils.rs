@@ -20,6 +20,11 @@ pub fn hash_domain(domain: &str) -> String {
     base16ct::lower::encode_string(&Sha256::digest(domain))
 }
 
+/// Personal access tokens are long and random, so a fast hash is sufficient.
+pub fn hash_token(token: &str) -> String {
+    base16ct::lower::encode_string(&Sha256::digest(token))
+}
+
 pub const USER_AGENT: &str = concat!("Snowcone-Labs/Iceblink/", env!("CARGO_PKG_VERSION"));
 
 #[cfg(test)]
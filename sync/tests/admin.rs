@@ -0,0 +1,358 @@
+use axum::{
+    http::{Method, StatusCode},
+    Router,
+};
+use googletest::prelude::*;
+use serde_json::json;
+use sqlx::SqlitePool;
+
+pub mod common;
+
+/// Makes user1 an administrator, the same way as the CLI.
+async fn bootstrap_admin(db: &SqlitePool) {
+    iceblink_sync::set_admin(db, "user1", true).await.unwrap();
+}
+
+async fn get_profile_status(app: &Router, token: &str) -> StatusCode {
+    common::request(app, Method::GET, "/v1/user", token, None)
+        .await
+        .status()
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn admin_required(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+    bootstrap_admin(&db).await;
+
+    let listed = common::request(&app, Method::GET, "/v1/admin/users", &a2, None).await;
+    assert_that!(listed.status(), eq(StatusCode::FORBIDDEN));
+    assert_that!(
+        common::convert_response(listed).await["errorKind"],
+        eq(&json!("AdminRequired"))
+    );
+
+    // Personal access tokens need the dedicated scope
+    let token = common::create_api_token_content(&app, &a1, &["account:admin"]).await;
+    let listed = common::request(&app, Method::GET, "/v1/admin/users", &token, None).await;
+    assert_that!(listed.status(), eq(StatusCode::FORBIDDEN));
+    assert_that!(
+        common::convert_response(listed).await["errorKind"],
+        eq(&json!("MissingScope"))
+    );
+
+    let token = common::create_api_token_content(&app, &a1, &["instance:admin"]).await;
+    let listed = common::request(&app, Method::GET, "/v1/admin/users", &token, None).await;
+    assert_that!(listed.status(), eq(StatusCode::OK));
+
+    let profile =
+        common::convert_response(common::request(&app, Method::GET, "/v1/user", &a1, None).await)
+            .await;
+    expect_that!(profile["is_admin"], eq(&json!(true)));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn admin_list_users(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+    bootstrap_admin(&db).await;
+
+    let users = common::convert_response(
+        common::request(&app, Method::GET, "/v1/admin/users", &a1, None).await,
+    )
+    .await;
+    expect_that!(users.as_array().unwrap().len(), eq(2));
+    expect_that!(users[0]["id"], eq(&json!(common::USER1_ID)));
+    expect_that!(users[0]["is_admin"], eq(&json!(true)));
+    expect_that!(users[0]["code_count"], eq(&json!(2)));
+    expect_that!(users[1]["code_count"], eq(&json!(1)));
+
+    let users = common::convert_response(
+        common::request(&app, Method::GET, "/v1/admin/users?query=Two", &a1, None).await,
+    )
+    .await;
+    expect_that!(users.as_array().unwrap().len(), eq(1));
+    expect_that!(users[0]["username"], eq(&json!("user2")));
+
+    let users = common::convert_response(
+        common::request(
+            &app,
+            Method::GET,
+            "/v1/admin/users?limit=1&offset=1",
+            &a1,
+            None,
+        )
+        .await,
+    )
+    .await;
+    expect_that!(users[0]["id"], eq(&json!(common::USER2_ID)));
+
+    let user = common::request(
+        &app,
+        Method::GET,
+        &format!("/v1/admin/users/{}", common::USER2_ID),
+        &a1,
+        None,
+    )
+    .await;
+    assert_that!(user.status(), eq(StatusCode::OK));
+    expect_that!(
+        common::convert_response(user).await["display_name"],
+        eq(&json!("User Two"))
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn admin_disable_user(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+    bootstrap_admin(&db).await;
+
+    let disabled = common::request(
+        &app,
+        Method::POST,
+        &format!("/v1/admin/users/{}/disable", common::USER2_ID),
+        &a1,
+        None,
+    )
+    .await;
+    assert_that!(disabled.status(), eq(StatusCode::OK));
+    expect_that!(
+        common::convert_response(disabled).await["disabled_at"],
+        not(eq(&json!(null)))
+    );
+
+    let profile = common::request(&app, Method::GET, "/v1/user", &a2, None).await;
+    assert_that!(profile.status(), eq(StatusCode::FORBIDDEN));
+    assert_that!(
+        common::convert_response(profile).await["errorKind"],
+        eq(&json!("AccountDisabled"))
+    );
+
+    let enabled = common::request(
+        &app,
+        Method::POST,
+        &format!("/v1/admin/users/{}/enable", common::USER2_ID),
+        &a1,
+        None,
+    )
+    .await;
+    assert_that!(enabled.status(), eq(StatusCode::OK));
+    expect_that!(get_profile_status(&app, &a2).await, eq(StatusCode::OK));
+
+    let disabled = common::request(
+        &app,
+        Method::POST,
+        &format!("/v1/admin/users/{}/disable", common::USER1_ID),
+        &a1,
+        None,
+    )
+    .await;
+    assert_that!(disabled.status(), eq(StatusCode::CONFLICT));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn admin_logout_user(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+    bootstrap_admin(&db).await;
+
+    let old_login = common::stale_access_token(common::USER2_ID);
+    expect_that!(
+        get_profile_status(&app, &old_login).await,
+        eq(StatusCode::OK)
+    );
+
+    let logout = common::request(
+        &app,
+        Method::POST,
+        &format!("/v1/admin/users/{}/logout", common::USER2_ID),
+        &a1,
+        None,
+    )
+    .await;
+    assert_that!(logout.status(), eq(StatusCode::NO_CONTENT));
+
+    expect_that!(
+        get_profile_status(&app, &old_login).await,
+        eq(StatusCode::UNAUTHORIZED)
+    );
+
+    // Logging in again works
+    let (_, a2) = common::get_access_tokens(&db).await;
+    expect_that!(get_profile_status(&app, &a2).await, eq(StatusCode::OK));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn admin_delete_user(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+    bootstrap_admin(&db).await;
+
+    let deleted = common::request(
+        &app,
+        Method::DELETE,
+        &format!("/v1/admin/users/{}", common::USER1_ID),
+        &a1,
+        None,
+    )
+    .await;
+    assert_that!(deleted.status(), eq(StatusCode::CONFLICT));
+
+    let deleted = common::request(
+        &app,
+        Method::DELETE,
+        &format!("/v1/admin/users/{}", common::USER2_ID),
+        &a1,
+        None,
+    )
+    .await;
+    assert_that!(deleted.status(), eq(StatusCode::NO_CONTENT));
+
+    expect_that!(
+        get_profile_status(&app, &a2).await,
+        eq(StatusCode::UNAUTHORIZED)
+    );
+
+    let user = common::request(
+        &app,
+        Method::GET,
+        &format!("/v1/admin/users/{}", common::USER2_ID),
+        &a1,
+        None,
+    )
+    .await;
+    assert_that!(user.status(), eq(StatusCode::NOT_FOUND));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn admin_delete_vault_owner(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+    bootstrap_admin(&db).await;
+
+    // User2 owns a vault which user1 joined as a viewer
+    let vault = common::convert_response(
+        common::request(
+            &app,
+            Method::POST,
+            "/v1/vaults",
+            &a2,
+            Some(json!({ "name": "On-call" })),
+        )
+        .await,
+    )
+    .await;
+    let vault_id = vault["id"].as_str().unwrap();
+    let invitation = common::convert_response(
+        common::request(
+            &app,
+            Method::POST,
+            &format!("/v1/vaults/{vault_id}/invitations"),
+            &a2,
+            Some(json!({ "role": "viewer" })),
+        )
+        .await,
+    )
+    .await;
+    let joined = common::request(
+        &app,
+        Method::POST,
+        "/v1/vaults/join",
+        &a1,
+        Some(json!({ "code": invitation["code"] })),
+    )
+    .await;
+    assert_that!(joined.status(), eq(StatusCode::OK));
+
+    let deleted = common::request(
+        &app,
+        Method::DELETE,
+        &format!("/v1/admin/users/{}", common::USER2_ID),
+        &a1,
+        None,
+    )
+    .await;
+    assert_that!(deleted.status(), eq(StatusCode::NO_CONTENT));
+
+    let member = iceblink_sync::models::vault::VaultMember::get(
+        &db,
+        vault_id.into(),
+        common::USER1_ID.into(),
+    )
+    .await
+    .unwrap();
+    expect_that!(
+        member.map(|member| member.role),
+        some(eq(iceblink_sync::models::vault::VaultRole::Owner))
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn admin_bootstrap(db: SqlitePool) {
+    expect_that!(
+        iceblink_sync::set_admin(&db, "unknown", true).await,
+        err(matches_pattern!(iceblink_sync::SetAdminError::UnknownUser))
+    );
+
+    let user = iceblink_sync::set_admin(&db, common::USER2_ID, true)
+        .await
+        .unwrap();
+    expect_that!(user.is_admin, eq(true));
+
+    let user = iceblink_sync::set_admin(&db, "user2", false).await.unwrap();
+    expect_that!(user.is_admin, eq(false));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn admin_suspend_user(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+    bootstrap_admin(&db).await;
+
+    let suspended = common::request(
+        &app,
+        Method::POST,
+        &format!("/v1/admin/users/{}/suspend", common::USER2_ID),
+        &a1,
+        None,
+    )
+    .await;
+    assert_that!(suspended.status(), eq(StatusCode::OK));
+    expect_that!(
+        common::convert_response(suspended).await["suspended"],
+        eq(&json!(true))
+    );
+
+    // Reading still works, but nothing can be changed
+    let codes = common::list_codes(&app, &a2).await;
+    expect_that!(codes.status(), eq(StatusCode::OK));
+
+    let deleted = common::delete_code(&app, &a2, common::USER2_CODE1_ID).await;
+    assert_that!(deleted.status(), eq(StatusCode::FORBIDDEN));
+    assert_that!(
+        common::convert_response(deleted).await["errorKind"],
+        eq(&json!("AccountSuspended"))
+    );
+
+    let unsuspended = common::request(
+        &app,
+        Method::POST,
+        &format!("/v1/admin/users/{}/unsuspend", common::USER2_ID),
+        &a1,
+        None,
+    )
+    .await;
+    assert_that!(unsuspended.status(), eq(StatusCode::OK));
+
+    let deleted = common::delete_code(&app, &a2, common::USER2_CODE1_ID).await;
+    expect_that!(deleted.status(), eq(StatusCode::NO_CONTENT));
+}
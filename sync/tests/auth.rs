@@ -139,3 +139,124 @@ pub async fn list_codes_invalid_signature(db: SqlitePool) {
         }))
     );
 }
+
+async fn delete_account(app: &axum::Router, token: &str) -> axum::response::Response {
+    app.clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::DELETE)
+                .uri("/v1/user")
+                .header("Authorization", format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+pub async fn step_up_stale_login(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let token = common::stale_access_token(common::USER1_ID);
+
+    let response = delete_account(&app, &token).await;
+    assert_that!(response.status(), eq(StatusCode::UNAUTHORIZED));
+    assert_that!(
+        common::convert_response(response).await,
+        eq(&json!({
+            "message": "This operation requires a recent login. Please authenticate again and retry.",
+            "errorKind": "StepUpRequired"
+        }))
+    );
+
+    // Everything else keeps working
+    let codes = common::list_codes_content(&app, &token).await;
+    assert_that!(codes.len(), eq(2));
+
+    // Logging in again allows it
+    let (a1, _) = common::get_access_tokens(&db).await;
+    let response = delete_account(&app, &a1).await;
+    assert_that!(response.status(), eq(StatusCode::ACCEPTED));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+pub async fn step_up_link_identity(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let token = common::stale_access_token(common::USER1_ID);
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method(Method::POST)
+                .uri("/v1/user/identities/default")
+                .header("Authorization", format!("Bearer {token}"))
+                .header("Content-Type", "application/json")
+                .body(Body::from(json!({ "code": "mock" }).to_string()))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_that!(response.status(), eq(StatusCode::UNAUTHORIZED));
+    assert_that!(
+        common::convert_response(response).await["errorKind"],
+        eq(&json!("StepUpRequired"))
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+pub async fn step_up_token_without_auth_time(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+
+    // Issued before the `auth_time` claim existed
+    let now = chrono::Utc::now();
+    let token = jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        &json!({
+            "iat": now.timestamp(),
+            "exp": (now + chrono::Duration::days(90)).timestamp(),
+            "sub": common::USER1_ID,
+            "username": "user1",
+            "display_name": "User One",
+            "avatar_url": "",
+        }),
+        &jsonwebtoken::EncodingKey::from_secret("my jwt secret".as_ref()),
+    )
+    .unwrap();
+
+    let response = delete_account(&app, &token).await;
+    assert_that!(response.status(), eq(StatusCode::UNAUTHORIZED));
+    assert_that!(
+        common::convert_response(response).await["errorKind"],
+        eq(&json!("StepUpRequired"))
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+pub async fn step_up_api_token(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+    let token = common::create_api_token_content(&app, &a1, &["account:admin"]).await;
+
+    let response = common::create_api_token(
+        &app,
+        &token,
+        &json!({
+            "name": "nested",
+            "scopes": ["codes:read"],
+        }),
+    )
+    .await;
+    assert_that!(response.status(), eq(StatusCode::UNAUTHORIZED));
+    assert_that!(
+        common::convert_response(response).await["errorKind"],
+        eq(&json!("StepUpRequired"))
+    );
+
+    let response = delete_account(&app, &token).await;
+    assert_that!(response.status(), eq(StatusCode::UNAUTHORIZED));
+}
@@ -49,7 +49,7 @@ async fn add_code(db: SqlitePool) {
     expect_that!(added_res.display_name, eq("Permafrost"));
     expect_that!(added_res.icon_url, none());
     expect_that!(added_res.website_url, none());
-    expect_that!(added_res.owner_id, eq(common::USER1_ID));
+    expect_that!(added_res.owner_id, some(eq(common::USER1_ID)));
     expect_that!(added_res.id.len(), eq(16));
 
     // Check that it was added to the list
@@ -63,7 +63,7 @@ async fn add_code(db: SqlitePool) {
             expect_that!(code.website_url, none());
             expect_that!(code.icon_url, none());
             expect_that!(code.content, eq("garbage"));
-            expect_that!(code.owner_id, eq(common::USER1_ID));
+            expect_that!(code.owner_id, some(eq(common::USER1_ID)));
             expect_that!(code.display_name, eq("Permafrost"));
         }
     }
@@ -131,6 +131,8 @@ async fn edit_code_remove_website(db: SqlitePool) {
             "content": common::USER1_CODE2_CONTENT,
             "id": common::USER1_CODE2_ID,
             "owner_id": common::USER1_ID,
+            "vault_id": null,
+            "shared_permission": null,
             "display_name": "google.com",
             "icon_url": null,
             "website_url": null
@@ -155,7 +157,7 @@ async fn edit_code_remove_website(db: SqlitePool) {
     expect_that!(modified_code.website_url, none());
     expect_that!(modified_code.icon_url, none());
     expect_that!(modified_code.content, eq(common::USER1_CODE2_CONTENT));
-    expect_that!(modified_code.owner_id, eq(common::USER1_ID));
+    expect_that!(modified_code.owner_id, some(eq(common::USER1_ID)));
     expect_that!(modified_code.display_name, eq("google.com"));
 }
 
@@ -182,6 +184,8 @@ async fn edit_code_update_website_removes_icon(db: SqlitePool) {
             "content": common::USER2_CODE1_CONTENT,
             "id": common::USER2_CODE1_ID,
             "owner_id": common::USER2_ID,
+            "vault_id": null,
+            "shared_permission": null,
             "display_name": "Dummy INC",
             "icon_url": null,
             "website_url": "example.com"
@@ -191,12 +195,12 @@ async fn edit_code_update_website_removes_icon(db: SqlitePool) {
     // The code is editted in the listing
     let listing_request = common::list_codes_content(&app, a2.as_str()).await;
     assert_that!(listing_request.len(), eq(1));
-    let code = listing_request.get(0).unwrap();
+    let code = listing_request.first().unwrap();
     expect_that!(code.id, eq(common::USER2_CODE1_ID));
     expect_that!(code.website_url, some(eq("example.com")));
     expect_that!(code.icon_url, none());
     expect_that!(code.content, eq(common::USER2_CODE1_CONTENT));
-    expect_that!(code.owner_id, eq(common::USER2_ID));
+    expect_that!(code.owner_id, some(eq(common::USER2_ID)));
     expect_that!(code.display_name, eq("Dummy INC"));
 }
 
@@ -225,6 +229,8 @@ async fn edit_code_content_and_display_name(db: SqlitePool) {
             "content": "yippie",
             "id": common::USER1_CODE2_ID,
             "owner_id": common::USER1_ID,
+            "vault_id": null,
+            "shared_permission": null,
             "display_name": "Modrinth",
             "icon_url": null,
             "website_url": "google.com"
@@ -248,7 +254,7 @@ async fn edit_code_content_and_display_name(db: SqlitePool) {
     expect_that!(modified_code.website_url, some(eq("google.com")));
     expect_that!(modified_code.icon_url, none());
     expect_that!(modified_code.content, eq("yippie"));
-    expect_that!(modified_code.owner_id, eq(common::USER1_ID));
+    expect_that!(modified_code.owner_id, some(eq(common::USER1_ID)));
     expect_that!(modified_code.display_name, eq("Modrinth"));
 }
 
@@ -362,7 +368,7 @@ async fn delete_code(db: SqlitePool) {
     let codes_listing = common::list_codes_content(&app, a1.as_str()).await;
     assert_that!(codes_listing.len(), eq(1));
 
-    let remaining_code = codes_listing.get(0).unwrap();
+    let remaining_code = codes_listing.first().unwrap();
     assert_that!(remaining_code.id, eq(common::USER1_CODE1_ID));
     assert!(remaining_code.is_as_expected());
 }
@@ -425,7 +431,7 @@ async fn icon_found_content_type(db: SqlitePool) {
             .headers()
             .get(axum::http::header::CONTENT_TYPE)
             .unwrap(),
-        eq("image/x-icon")
+        eq("image/png")
     );
 }
 
@@ -497,5 +503,85 @@ async fn icon_returns_same(db: SqlitePool) {
 // TODO: Icon Test: it actually using the cached version - not fetching
 // TODO: Icon Test: without website url
 // TODO: Icon Test: with invalid website url
-// TODO: Icon Test: with 404 on favicon
-// TODO: Icon Test: what if website returns non-ico?
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn add_code_limit_reached(db: SqlitePool) {
+    let app = common::testing_setup_custom()
+        .pool(&db)
+        .quotas(iceblink_sync::Quotas {
+            max_codes: Some(2),
+            ..Default::default()
+        })
+        .call()
+        .await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+    let payload = json!({ "content": "abc", "display_name": "Limited" });
+
+    // User1 already has two codes
+    let added = common::add_code(&app, &a1, &payload).await;
+    assert_that!(added.status(), eq(StatusCode::FORBIDDEN));
+    assert_that!(
+        common::convert_response(added).await["errorKind"],
+        eq(&json!("CodeLimitReached"))
+    );
+
+    let added = common::add_code(&app, &a2, &payload).await;
+    assert_that!(added.status(), eq(StatusCode::OK));
+
+    let added = common::add_code(&app, &a2, &payload).await;
+    assert_that!(added.status(), eq(StatusCode::FORBIDDEN));
+
+    common::delete_code(&app, &a1, common::USER1_CODE1_ID).await;
+    let added = common::add_code(&app, &a1, &payload).await;
+    assert_that!(added.status(), eq(StatusCode::OK));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn code_size_limits(db: SqlitePool) {
+    let app = common::testing_setup_custom()
+        .pool(&db)
+        .quotas(iceblink_sync::Quotas {
+            max_content_length: 8,
+            max_display_name_length: 4,
+            ..Default::default()
+        })
+        .call()
+        .await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let added = common::add_code(
+        &app,
+        &a1,
+        &json!({ "content": "123456789", "display_name": "Name" }),
+    )
+    .await;
+    assert_that!(added.status(), eq(StatusCode::BAD_REQUEST));
+    assert_that!(
+        common::convert_response(added).await["errorKind"],
+        eq(&json!("CodeContentTooLong"))
+    );
+
+    // Limits are in characters, not bytes
+    let added = common::add_code(
+        &app,
+        &a1,
+        &json!({ "content": "12345678", "display_name": "ÆØÅÉ" }),
+    )
+    .await;
+    assert_that!(added.status(), eq(StatusCode::OK));
+
+    let edited = common::edit_code(
+        &app,
+        &a1,
+        common::USER1_CODE1_ID,
+        &json!({ "display_name": "Names" }),
+    )
+    .await;
+    assert_that!(edited.status(), eq(StatusCode::BAD_REQUEST));
+    assert_that!(
+        common::convert_response(edited).await["errorKind"],
+        eq(&json!("DisplayNameTooLong"))
+    );
+}
@@ -9,28 +9,34 @@ fn get_codes_for_user(userid: &str) -> Vec<models::codes::Code> {
         "k0d8WrkRjK6gkc3C" => vec![
             models::codes::Code {
                 id: "Ckpt4eFi1pw9fxI3".into(),
-                owner_id: "k0d8WrkRjK6gkc3C".into(),
+                owner_id: Some("k0d8WrkRjK6gkc3C".into()),
+                vault_id: None,
                 content: "GK6ZFMqk18fuWnCw".into(),
                 display_name: "Google".into(),
                 icon_url: None,
                 website_url: Some("google.com".into()),
+                shared_permission: None,
             },
             models::codes::Code {
                 id: "DxLCqi4ZlHPD8YxA".into(),
-                owner_id: "k0d8WrkRjK6gkc3C".into(),
+                owner_id: Some("k0d8WrkRjK6gkc3C".into()),
+                vault_id: None,
                 content: "XGDi8FlvZ5OGBoxG".into(),
                 display_name: "google.com".into(),
                 icon_url: None,
                 website_url: Some("google.com".into()),
+                shared_permission: None,
             },
         ],
         "3Ck0d8WrkRjK6gkc" => vec![models::codes::Code {
             id: "fUJveqJaNpPhTUkR".into(),
-            owner_id: "3Ck0d8WrkRjK6gkc".into(),
+            owner_id: Some("3Ck0d8WrkRjK6gkc".into()),
+            vault_id: None,
             content: "djnaW1Pl2WjhWrU6".into(),
             display_name: "Dummy INC".into(),
             icon_url: Some("https://dummy.com/favicon.ico".into()),
             website_url: Some("dummy.com".into()),
+            shared_permission: None,
         }],
         _ => panic!("Unexpected UserId in code_is_expected"),
     }
@@ -55,7 +61,10 @@ impl Matcher<&Vec<models::codes::Code>> for CodeFixtureMatcher {
             None => return MatcherResult::NoMatch,
         };
 
-        let expected_user_codes = get_codes_for_user(&first_code.owner_id);
+        let expected_user_codes = match &first_code.owner_id {
+            Some(owner_id) => get_codes_for_user(owner_id),
+            None => return MatcherResult::NoMatch,
+        };
 
         if input.len() != expected_user_codes.len() {
             return MatcherResult::NoMatch;
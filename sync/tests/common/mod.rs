@@ -6,18 +6,19 @@ use axum::{
     body::Body,
     http::{Method, Request},
     response::Response,
-    Router,
+    routing::{get, post},
+    Json, Router,
 };
 use iceblink_sync::{
-    auth::{self, OpenId},
+    auth::{self, OpenId, OpenIdRegistry},
+    cli::AuthBackend,
     configure_router,
     icons::IconStore,
-    models,
+    local_auth, models,
     routes::v1::users::ChecksumResponse,
-    ServerOptions,
+    Quotas, RegistrationOptions, ServerOptions, TokenRequest,
 };
 use sqlx::SqlitePool;
-use std::usize;
 use tower::ServiceExt;
 
 pub const USER1_ID: &str = "k0d8WrkRjK6gkc3C";
@@ -30,34 +31,121 @@ pub const USER1_CODE2_CONTENT: &str = "XGDi8FlvZ5OGBoxG";
 pub const USER2_CODE1_CONTENT: &str = "djnaW1Pl2WjhWrU6";
 
 pub async fn testing_setup(pool: &SqlitePool) -> Router {
+    testing_setup_custom().pool(pool).call().await
+}
+
+pub async fn testing_setup_with_backend(pool: &SqlitePool, auth_backend: AuthBackend) -> Router {
+    testing_setup_custom()
+        .pool(pool)
+        .auth_backend(auth_backend)
+        .call()
+        .await
+}
+
+#[bon::builder]
+pub async fn testing_setup_custom(
+    pool: &SqlitePool,
+    #[builder(default)] auth_backend: AuthBackend,
+    /// Defaults to a provider with id `default` that can not be reached.
+    openid: Option<OpenId>,
+    #[builder(default)] quotas: Quotas,
+    #[builder(default)] registration: RegistrationOptions,
+    /// Defaults to a fresh store in a temporary directory.
+    icon_store: Option<IconStore>,
+) -> Router {
+    let webauthn = match auth_backend {
+        AuthBackend::Local => Some(local_auth::webauthn("http://localhost:8085").unwrap()),
+        AuthBackend::OpenId => None,
+    };
+
     configure_router()
         .pool(pool)
-        .openid(OpenId {
+        .openid(OpenIdRegistry::new(vec![openid.unwrap_or(OpenId {
+            id: "default".into(),
+            name: "N/A".into(),
             authorization: "N/A".into(),
             client_id: "N/A".into(),
             client_secret: "N/A".into(),
             token: "N/A".into(),
             userinfo: "N/A".into(),
-        })
+            redirect_uri: "N/A".into(),
+            token_request: TokenRequest::Form,
+        })]))
+        .maybe_webauthn(webauthn)
         .opts(ServerOptions {
             port: 8000,
             jwt_secret: "my jwt secret".into(),
-            client_id: "N/A".into(),
-            client_secret: "N/A".into(),
-            oauth_server: "N/A".into(),
-            redirect_uri: "N/A".into(),
+            auth_backend,
+            providers: vec![],
             frontfacing: "N/A".into(),
+            quotas,
+            registration,
+            icon_cache: Default::default(),
         })
-        .icon_store(IconStore::new().init().await.unwrap().clone())
+        .icon_store(icon_store.unwrap_or_default().init().await.unwrap().clone())
         .call()
 }
 
+/// Starts a local IdP accepting any authorization code, which always responds with the given userinfo.
+pub async fn mock_idp(userinfo: serde_json::Value) -> OpenId {
+    let router = Router::new()
+        .route(
+            "/token",
+            post(|| async { Json(serde_json::json!({ "access_token": "mock" })) }),
+        )
+        .route("/userinfo", get(move || async move { Json(userinfo) }));
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+    OpenId {
+        id: "default".into(),
+        name: "Mock".into(),
+        authorization: format!("http://{address}/authorize"),
+        client_id: "N/A".into(),
+        client_secret: "N/A".into(),
+        token: format!("http://{address}/token"),
+        userinfo: format!("http://{address}/userinfo"),
+        redirect_uri: "N/A".into(),
+        token_request: TokenRequest::Form,
+    }
+}
+
+pub async fn oauth_login(app: &Router, provider: &str) -> Response {
+    app.clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::GET)
+                .uri(format!("/v1/oauth/{provider}?code=mock"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+}
+
+pub fn jwt_from_cookie(response: &Response) -> String {
+    response
+        .headers()
+        .get("Set-Cookie")
+        .unwrap()
+        .to_str()
+        .unwrap()
+        .strip_prefix("iceblink_jwt=")
+        .unwrap()
+        .split(';')
+        .next()
+        .unwrap()
+        .to_string()
+}
+
 pub async fn get_access_tokens(pool: &SqlitePool) -> (String, String) {
-    let user1 = iceblink_sync::models::user::User::get_by_id(&pool, USER1_ID.into())
+    let user1 = iceblink_sync::models::user::User::get_by_id(pool, USER1_ID.into())
         .await
         .unwrap()
         .unwrap();
-    let user2 = iceblink_sync::models::user::User::get_by_id(&pool, USER2_ID.into())
+    let user2 = iceblink_sync::models::user::User::get_by_id(pool, USER2_ID.into())
         .await
         .unwrap()
         .unwrap();
@@ -68,6 +156,26 @@ pub async fn get_access_tokens(pool: &SqlitePool) -> (String, String) {
     )
 }
 
+/// JWT of a login which happened long ago, which is not enough for sensitive operations.
+pub fn stale_access_token(user_id: &str) -> String {
+    let login = chrono::Utc::now() - chrono::Duration::days(1);
+
+    jsonwebtoken::encode(
+        &jsonwebtoken::Header::default(),
+        &auth::TokenClaims {
+            iat: login.timestamp() as usize,
+            auth_time: login.timestamp() as usize,
+            exp: (login + chrono::Duration::days(90)).timestamp() as usize,
+            sub: user_id.to_string(),
+            username: "N/A".into(),
+            display_name: "N/A".into(),
+            avatar_url: "N/A".into(),
+        },
+        &jsonwebtoken::EncodingKey::from_secret("my jwt secret".as_ref()),
+    )
+    .unwrap()
+}
+
 pub async fn convert_response_str(response: Response) -> String {
     String::from_utf8(
         axum::body::to_bytes(response.into_body(), usize::MAX)
@@ -107,6 +215,29 @@ pub async fn list_codes_content(app: &Router, token: &str) -> Vec<models::codes:
     serde_json::from_value(convert_response(list_codes(app, token).await).await).unwrap()
 }
 
+/// Sends an authenticated request, with an optional JSON body.
+pub async fn request(
+    app: &Router,
+    method: Method,
+    uri: &str,
+    token: &str,
+    payload: Option<serde_json::Value>,
+) -> Response {
+    let request = Request::builder()
+        .method(method)
+        .uri(uri)
+        .header("Authorization", format!("Bearer {token}"));
+
+    let request = match payload {
+        Some(payload) => request
+            .header("Content-Type", "application/json")
+            .body(Body::from(serde_json::to_vec(&payload).unwrap())),
+        None => request.body(Body::empty()),
+    };
+
+    app.clone().oneshot(request.unwrap()).await.unwrap()
+}
+
 pub async fn add_code(app: &Router, token: &str, payload: &serde_json::Value) -> Response {
     app.clone()
         .oneshot(
@@ -188,6 +319,23 @@ pub async fn get_icon(app: &Router, token: &str, id: &str) -> Response {
         .unwrap()
 }
 
+pub async fn set_icon_fetching(app: &Router, token: &str, enabled: bool) -> Response {
+    app.clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::PATCH)
+                .uri("/v1/user")
+                .header("Authorization", format!("Bearer {token}"))
+                .header("Content-Type", "application/json")
+                .body(Body::from(
+                    serde_json::to_vec(&serde_json::json!({ "icon_fetching": enabled })).unwrap(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+}
+
 pub trait AsExpected {
     fn is_as_expected(&self) -> bool;
 }
@@ -198,25 +346,57 @@ impl AsExpected for models::codes::Code {
             USER1_CODE1_ID => {
                 self.content == USER1_CODE1_CONTENT
                     && self.display_name == "Google"
-                    && self.icon_url == None
-                    && self.owner_id == USER1_ID
+                    && self.icon_url.is_none()
+                    && self.owner_id.as_deref() == Some(USER1_ID)
                     && self.website_url == Some("google.com".to_string())
             }
             USER1_CODE2_ID => {
                 self.content == USER1_CODE2_CONTENT
                     && self.display_name == "google.com"
-                    && self.icon_url == None
-                    && self.owner_id == USER1_ID
+                    && self.icon_url.is_none()
+                    && self.owner_id.as_deref() == Some(USER1_ID)
                     && self.website_url == Some("google.com".to_string())
             }
             USER2_CODE1_ID => {
                 self.content == USER2_CODE1_CONTENT
                     && self.display_name == "Dummy INC"
                     && self.icon_url == Some("https://dummy.com/favicon.ico".to_string())
-                    && self.owner_id == USER2_ID
+                    && self.owner_id.as_deref() == Some(USER2_ID)
                     && self.website_url == Some("dummy.com".to_string())
             }
             _ => false,
         }
     }
 }
+
+pub async fn create_api_token(app: &Router, token: &str, payload: &serde_json::Value) -> Response {
+    app.clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::POST)
+                .uri("/v1/user/tokens")
+                .header("Authorization", format!("Bearer {token}"))
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::to_vec(payload).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+}
+
+pub async fn create_api_token_content(app: &Router, token: &str, scopes: &[&str]) -> String {
+    let response = create_api_token(
+        app,
+        token,
+        &serde_json::json!({
+            "name": "CI",
+            "scopes": scopes,
+        }),
+    )
+    .await;
+
+    convert_response(response).await["token"]
+        .as_str()
+        .unwrap()
+        .to_string()
+}
@@ -0,0 +1,193 @@
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+    response::Response,
+    Router,
+};
+use googletest::prelude::*;
+use serde_json::json;
+use sqlx::SqlitePool;
+use tower::ServiceExt;
+
+pub mod common;
+
+async fn post(
+    app: &Router,
+    uri: &str,
+    token: Option<&str>,
+    payload: &serde_json::Value,
+) -> Response {
+    let mut request = Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header("Content-Type", "application/json");
+
+    if let Some(token) = token {
+        request = request.header("Authorization", format!("Bearer {token}"));
+    }
+
+    app.clone()
+        .oneshot(
+            request
+                .body(Body::from(serde_json::to_vec(payload).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+}
+
+async fn device_code(app: &Router) -> serde_json::Value {
+    let response = post(app, "/v1/device/code", None, &json!({})).await;
+    assert_that!(response.status(), eq(StatusCode::OK));
+    common::convert_response(response).await
+}
+
+async fn approve(app: &Router, token: &str, user_code: &str) -> Response {
+    post(
+        app,
+        "/v1/device/approve",
+        Some(token),
+        &json!({ "user_code": user_code }),
+    )
+    .await
+}
+
+async fn poll(app: &Router, device_code: &str) -> Response {
+    post(
+        app,
+        "/v1/device/token",
+        None,
+        &json!({ "device_code": device_code }),
+    )
+    .await
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn device_flow(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let code = device_code(&app).await;
+    expect_that!(
+        code["user_code"].as_str().unwrap(),
+        matches_regex("^[A-Z]{4}-[A-Z]{4}$")
+    );
+    expect_that!(code["interval"], eq(&json!(5)));
+    expect_that!(
+        code["verification_uri_complete"].as_str().unwrap(),
+        ends_with(format!(
+            "?user_code={}",
+            code["user_code"].as_str().unwrap()
+        ))
+    );
+
+    // Lowercase and without separator is also accepted
+    let user_code = code["user_code"].as_str().unwrap().replace('-', "");
+    let approved = approve(&app, &a1, &user_code.to_lowercase()).await;
+    assert_that!(approved.status(), eq(StatusCode::NO_CONTENT));
+
+    let response = poll(&app, code["device_code"].as_str().unwrap()).await;
+    assert_that!(response.status(), eq(StatusCode::OK));
+
+    let body = common::convert_response(response).await;
+    expect_that!(body["token_type"], eq(&json!("Bearer")));
+
+    // The JWT belongs to the approving user
+    let jwt = body["access_token"].as_str().unwrap();
+    let codes = common::list_codes_content(&app, jwt).await;
+    expect_that!(codes.len(), eq(2));
+
+    // But does not count as a recent login for sensitive operations
+    let response = common::create_api_token(
+        &app,
+        jwt,
+        &json!({ "name": "CI", "scopes": ["codes:read"] }),
+    )
+    .await;
+    assert_that!(response.status(), eq(StatusCode::UNAUTHORIZED));
+    assert_that!(
+        common::convert_response(response).await["errorKind"],
+        eq(&json!("StepUpRequired"))
+    );
+
+    // Device codes are single use
+    let response = poll(&app, code["device_code"].as_str().unwrap()).await;
+    assert_that!(response.status(), eq(StatusCode::BAD_REQUEST));
+    assert_that!(
+        common::convert_response(response).await["errorKind"],
+        eq(&json!("DeviceCodeExpired"))
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn device_flow_pending_and_slow_down(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let code = device_code(&app).await;
+    let device_code = code["device_code"].as_str().unwrap();
+
+    let response = poll(&app, device_code).await;
+    assert_that!(response.status(), eq(StatusCode::BAD_REQUEST));
+    assert_that!(
+        common::convert_response(response).await["errorKind"],
+        eq(&json!("AuthorizationPending"))
+    );
+
+    let response = poll(&app, device_code).await;
+    assert_that!(response.status(), eq(StatusCode::BAD_REQUEST));
+    assert_that!(
+        common::convert_response(response).await["errorKind"],
+        eq(&json!("SlowDown"))
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn device_flow_expired(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+    let code = device_code(&app).await;
+
+    let expired = chrono::Utc::now() - chrono::Duration::minutes(1);
+    sqlx::query!("UPDATE device_authorizations SET expires_at = ?", expired)
+        .execute(&db)
+        .await
+        .unwrap();
+
+    let approved = approve(&app, &a1, code["user_code"].as_str().unwrap()).await;
+    assert_that!(approved.status(), eq(StatusCode::NOT_FOUND));
+
+    let response = poll(&app, code["device_code"].as_str().unwrap()).await;
+    assert_that!(response.status(), eq(StatusCode::BAD_REQUEST));
+    assert_that!(
+        common::convert_response(response).await["errorKind"],
+        eq(&json!("DeviceCodeExpired"))
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn device_approve_requires_authentication(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let code = device_code(&app).await;
+
+    let response = post(
+        &app,
+        "/v1/device/approve",
+        None,
+        &json!({ "user_code": code["user_code"] }),
+    )
+    .await;
+    assert_that!(response.status(), eq(StatusCode::UNAUTHORIZED));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn device_approve_unknown_code(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let approved = approve(&app, &a1, "BCDF-GHJK").await;
+    assert_that!(approved.status(), eq(StatusCode::NOT_FOUND));
+}
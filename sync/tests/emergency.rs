@@ -0,0 +1,249 @@
+use axum::{
+    http::{Method, StatusCode},
+    response::Response,
+    Router,
+};
+use googletest::prelude::*;
+use serde_json::json;
+use sqlx::SqlitePool;
+
+pub mod common;
+
+/// User1 trusts user2, who requests access.
+async fn requested_access(app: &Router, a1: &str, a2: &str) {
+    let designated = common::request(
+        app,
+        Method::PUT,
+        &format!("/v1/emergency/contacts/{}", common::USER2_ID),
+        a1,
+        Some(json!({ "wait_days": 3 })),
+    )
+    .await;
+    assert_that!(designated.status(), eq(StatusCode::OK));
+
+    let requested = common::request(
+        app,
+        Method::POST,
+        &format!("/v1/emergency/grantors/{}/request", common::USER1_ID),
+        a2,
+        None,
+    )
+    .await;
+    assert_that!(requested.status(), eq(StatusCode::OK));
+    let requested = common::convert_response(requested).await;
+    assert_that!(requested["status"], eq(&json!("requested")));
+}
+
+async fn grantor_codes(app: &Router, token: &str) -> Response {
+    common::request(
+        app,
+        Method::GET,
+        &format!("/v1/emergency/grantors/{}/codes", common::USER1_ID),
+        token,
+        None,
+    )
+    .await
+}
+
+async fn skip_waiting_period(db: &SqlitePool) {
+    let past = chrono::Utc::now() - chrono::Duration::minutes(1);
+    sqlx::query("UPDATE emergency_contacts SET grants_at = ?")
+        .bind(past)
+        .execute(db)
+        .await
+        .unwrap();
+}
+
+async fn event_kinds(app: &Router, token: &str) -> Vec<String> {
+    common::convert_response(
+        common::request(app, Method::GET, "/v1/emergency/events", token, None).await,
+    )
+    .await
+    .as_array()
+    .unwrap()
+    .iter()
+    .map(|event| event["kind"].as_str().unwrap().to_string())
+    .collect()
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn emergency_access_granted(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+    requested_access(&app, &a1, &a2).await;
+
+    let codes = grantor_codes(&app, &a2).await;
+    assert_that!(codes.status(), eq(StatusCode::FORBIDDEN));
+    assert_that!(
+        common::convert_response(codes).await["errorKind"],
+        eq(&json!("EmergencyAccessNotGranted"))
+    );
+
+    skip_waiting_period(&db).await;
+
+    let codes = grantor_codes(&app, &a2).await;
+    assert_that!(codes.status(), eq(StatusCode::OK));
+    let codes = common::convert_response(codes).await;
+    expect_that!(codes.as_array().unwrap().len(), eq(2));
+    expect_that!(codes[0]["owner_id"], eq(&json!(common::USER1_ID)));
+
+    // Both sides see the full history, newest first
+    expect_that!(
+        event_kinds(&app, &a1).await,
+        elements_are![
+            eq("accessed"),
+            eq("granted"),
+            eq("requested"),
+            eq("designated")
+        ]
+    );
+    expect_that!(
+        event_kinds(&app, &a2).await,
+        elements_are![
+            eq("accessed"),
+            eq("granted"),
+            eq("requested"),
+            eq("designated")
+        ]
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn emergency_access_denied(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+    requested_access(&app, &a1, &a2).await;
+
+    let contacts = common::convert_response(
+        common::request(&app, Method::GET, "/v1/emergency/contacts", &a1, None).await,
+    )
+    .await;
+    expect_that!(contacts[0]["status"], eq(&json!("requested")));
+    expect_that!(contacts[0]["wait_days"], eq(&json!(3)));
+
+    let denied = common::request(
+        &app,
+        Method::POST,
+        &format!("/v1/emergency/contacts/{}/deny", common::USER2_ID),
+        &a1,
+        None,
+    )
+    .await;
+    assert_that!(denied.status(), eq(StatusCode::OK));
+    expect_that!(
+        common::convert_response(denied).await["status"],
+        eq(&json!("idle"))
+    );
+
+    skip_waiting_period(&db).await;
+
+    let codes = grantor_codes(&app, &a2).await;
+    assert_that!(codes.status(), eq(StatusCode::FORBIDDEN));
+
+    expect_that!(event_kinds(&app, &a2).await, contains(eq("denied")));
+    expect_that!(event_kinds(&app, &a2).await, not(contains(eq("granted"))));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn emergency_contact_removed(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+    requested_access(&app, &a1, &a2).await;
+    skip_waiting_period(&db).await;
+
+    let removed = common::request(
+        &app,
+        Method::DELETE,
+        &format!("/v1/emergency/contacts/{}", common::USER2_ID),
+        &a1,
+        None,
+    )
+    .await;
+    assert_that!(removed.status(), eq(StatusCode::NO_CONTENT));
+
+    let codes = grantor_codes(&app, &a2).await;
+    assert_that!(codes.status(), eq(StatusCode::NOT_FOUND));
+
+    let grantors = common::convert_response(
+        common::request(&app, Method::GET, "/v1/emergency/grantors", &a2, None).await,
+    )
+    .await;
+    expect_that!(grantors, eq(&json!([])));
+
+    // The audit log is kept
+    expect_that!(event_kinds(&app, &a1).await, contains(eq("removed")));
+
+    // Also after the contact deleted their account
+    sqlx::query("DELETE FROM users WHERE id = ?")
+        .bind(common::USER2_ID)
+        .execute(&db)
+        .await
+        .unwrap();
+    let events = common::convert_response(
+        common::request(&app, Method::GET, "/v1/emergency/events", &a1, None).await,
+    )
+    .await;
+    expect_that!(events.as_array().unwrap().len(), eq(3));
+    expect_that!(events[0]["contact_id"], eq(&json!(null)));
+    expect_that!(events[0]["owner_id"], eq(&json!(common::USER1_ID)));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn emergency_contact_invalid(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let designate = |token: String, user_id: &'static str, payload| {
+        let app = app.clone();
+        async move {
+            common::request(
+                &app,
+                Method::PUT,
+                &format!("/v1/emergency/contacts/{user_id}"),
+                &token,
+                Some(payload),
+            )
+            .await
+            .status()
+        }
+    };
+
+    expect_that!(
+        designate(a1.clone(), common::USER1_ID, json!({})).await,
+        eq(StatusCode::BAD_REQUEST)
+    );
+    expect_that!(
+        designate(a1.clone(), common::USER2_ID, json!({ "wait_days": 0 })).await,
+        eq(StatusCode::BAD_REQUEST)
+    );
+    expect_that!(
+        designate(a1.clone(), "unknown", json!({})).await,
+        eq(StatusCode::NOT_FOUND)
+    );
+
+    // Designating a contact requires a recent login
+    expect_that!(
+        designate(
+            common::stale_access_token(common::USER1_ID),
+            common::USER2_ID,
+            json!({})
+        )
+        .await,
+        eq(StatusCode::UNAUTHORIZED)
+    );
+
+    // Users who do not trust you can not be requested
+    let requested = common::request(
+        &app,
+        Method::POST,
+        &format!("/v1/emergency/grantors/{}/request", common::USER2_ID),
+        &a1,
+        None,
+    )
+    .await;
+    expect_that!(requested.status(), eq(StatusCode::NOT_FOUND));
+}
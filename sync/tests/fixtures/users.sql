@@ -1,3 +1,7 @@
 -- Inserts two dummy users
-INSERT INTO users (id, username, display_name, avatar_url, upstream_userid) VALUES ("k0d8WrkRjK6gkc3C", "user1", "User One", "https://github.com/Snowcone-Labs.png", "8h4ar");
-INSERT INTO users (id, username, display_name, avatar_url, upstream_userid) VALUES ("3Ck0d8WrkRjK6gkc", "user2", "User Two", "https://github.com/Snowcone-Labs.png", "5ja98ij");
+INSERT INTO users (id, username, display_name, avatar_url) VALUES ("k0d8WrkRjK6gkc3C", "user1", "User One", "https://github.com/Snowcone-Labs.png");
+INSERT INTO users (id, username, display_name, avatar_url) VALUES ("3Ck0d8WrkRjK6gkc", "user2", "User Two", "https://github.com/Snowcone-Labs.png");
+
+-- Each with a single upstream identity
+INSERT INTO user_identities (user_id, provider, upstream_userid) VALUES ("k0d8WrkRjK6gkc3C", "default", "8h4ar");
+INSERT INTO user_identities (user_id, provider, upstream_userid) VALUES ("3Ck0d8WrkRjK6gkc", "default", "5ja98ij");
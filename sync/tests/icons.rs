@@ -0,0 +1,1311 @@
+use axum::{
+    body::Body,
+    http::{header, Method, Request, StatusCode},
+    middleware::Next,
+    routing::get,
+    Router,
+};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use googletest::prelude::*;
+use iceblink_sync::icons::{
+    guard::Allowlist,
+    normalize::OutputFormat,
+    pack::BundledProvider,
+    provider::{DirectProvider, ServiceProvider},
+    IconStore, IconStoreError,
+};
+use image::{ImageFormat, Rgba, RgbaImage};
+use serde_json::json;
+use sqlx::SqlitePool;
+use std::{
+    io::Cursor,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+};
+use tower::ServiceExt;
+
+pub mod common;
+
+const RED: Rgba<u8> = Rgba([255, 0, 0, 255]);
+const GREEN: Rgba<u8> = Rgba([0, 255, 0, 255]);
+const BLUE: Rgba<u8> = Rgba([0, 0, 255, 255]);
+const ERROR_PAGE: &str = "<!DOCTYPE html><html><body>Please log in</body></html>";
+
+/// Serves a fake website on a random local port, returning the domain to gather icons for.
+async fn stub_site(site: Router) -> String {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let domain = listener.local_addr().unwrap().to_string();
+    tokio::spawn(async move { axum::serve(listener, site).await.unwrap() });
+
+    domain
+}
+
+/// Like `stub_site`, also counting the requests it receives.
+async fn counted_stub_site(site: Router) -> (String, Arc<AtomicUsize>) {
+    let requests = Arc::new(AtomicUsize::new(0));
+    let counter = requests.clone();
+    let site = site.layer(axum::middleware::from_fn(
+        move |request: axum::extract::Request, next: Next| {
+            counter.fetch_add(1, Ordering::SeqCst);
+            next.run(request)
+        },
+    ));
+
+    (stub_site(site).await, requests)
+}
+
+/// The stub sites run on the loopback address, which is blocked unless allowlisted.
+fn loopback() -> Allowlist {
+    Allowlist::new(["127.0.0.1"])
+}
+
+fn cache_dir() -> std::path::PathBuf {
+    std::env::temp_dir().join(format!("iceblink-{}", iceblink_sync::utils::generate_id(5)))
+}
+
+async fn icon_store() -> IconStore {
+    IconStore::new()
+        .with_http()
+        .with_allowlist(loopback())
+        .init()
+        .await
+        .unwrap()
+        .clone()
+}
+
+/// A square icon in a single color, so tests can tell which one was picked.
+fn icon(color: Rgba<u8>, size: u32, format: ImageFormat) -> Vec<u8> {
+    let mut content = Cursor::new(Vec::new());
+    RgbaImage::from_pixel(size, size, color)
+        .write_to(&mut content, format)
+        .unwrap();
+
+    content.into_inner()
+}
+
+fn color_of(content: &[u8]) -> Rgba<u8> {
+    let image = image::load_from_memory(content).unwrap().into_rgba8();
+    *image.get_pixel(image.width() / 2, image.height() / 2)
+}
+
+#[tokio::test]
+#[gtest]
+async fn icon_discovery_prefers_best_size() {
+    let domain = stub_site(
+        Router::new()
+            .route(
+                "/",
+                get(|| async {
+                    axum::response::Html(
+                        r#"<html><head>
+                        <link rel="icon" sizes="16x16" href="/16.png">
+                        <link rel="apple-touch-icon" href="/touch.png">
+                        <link rel="manifest" href="/manifest.json">
+                        </head></html>"#,
+                    )
+                }),
+            )
+            .route("/16.png", get(|| async { icon(RED, 16, ImageFormat::Png) }))
+            .route(
+                "/touch.png",
+                get(|| async { icon(RED, 180, ImageFormat::Png) }),
+            )
+            .route(
+                "/512.png",
+                get(|| async { icon(RED, 512, ImageFormat::Png) }),
+            )
+            .route(
+                "/144.png",
+                get(|| async { icon(GREEN, 144, ImageFormat::Png) }),
+            )
+            .route(
+                "/manifest.json",
+                get(|| async {
+                    r#"{"icons": [
+                        {"src": "/512.png", "sizes": "512x512"},
+                        {"src": "/144.png", "sizes": "144x144"}
+                    ]}"#
+                }),
+            ),
+    )
+    .await;
+    let store = icon_store().await;
+
+    let metadata = store.gather(&domain).await.unwrap();
+    expect_that!(metadata.source_url, ends_with("/144.png"));
+    expect_that!(metadata.source_content_type, eq("image/png"));
+    expect_that!(
+        (metadata.source_width, metadata.source_height),
+        eq((144, 144))
+    );
+
+    // Served from the cache afterwards
+    let cached = store
+        .find_or_gather(&domain, 128, OutputFormat::Png)
+        .await
+        .unwrap();
+    expect_that!(color_of(&cached), eq(GREEN));
+    expect_that!(
+        store.metadata(&domain).await.unwrap().fetched_at,
+        eq(metadata.fetched_at)
+    );
+}
+
+#[tokio::test]
+#[gtest]
+async fn icon_discovery_skips_non_images() {
+    let domain = stub_site(
+        Router::new()
+            .route(
+                "/",
+                get(|| async {
+                    axum::response::Html(
+                        r#"<html><head>
+                        <link rel="icon" sizes="192x192" href="/login-redirect.png">
+                        <link rel="icon" sizes="128x128" href="/corrupt.png">
+                        </head></html>"#,
+                    )
+                }),
+            )
+            .route(
+                "/login-redirect.png",
+                get(|| async { axum::response::Html(ERROR_PAGE) }),
+            )
+            .route(
+                "/corrupt.png",
+                get(|| async { b"\x89PNG\r\n\x1a\ntruncated".as_slice() }),
+            )
+            .route(
+                "/favicon.ico",
+                get(|| async { icon(BLUE, 32, ImageFormat::Ico) }),
+            ),
+    )
+    .await;
+
+    let metadata = icon_store().await.gather(&domain).await.unwrap();
+    expect_that!(metadata.source_url, ends_with("/favicon.ico"));
+    expect_that!(metadata.source_content_type, eq("image/x-icon"));
+}
+
+#[tokio::test]
+#[gtest]
+async fn icon_discovery_follows_redirects() {
+    let domain = stub_site(
+        Router::new()
+            .route("/", get(|| async { axum::response::Redirect::to("/app/") }))
+            .route(
+                "/app/",
+                get(|| async { axum::response::Html(r#"<link rel="icon" href="icon.svg">"#) }),
+            )
+            .route(
+                "/app/icon.svg",
+                get(|| async {
+                    r##"<svg xmlns="http://www.w3.org/2000/svg" viewBox="0 0 1 1"><rect width="1" height="1" fill="#0000ff"/></svg>"##
+                }),
+            ),
+    )
+    .await;
+
+    let metadata = icon_store().await.gather(&domain).await.unwrap();
+    expect_that!(metadata.source_url, ends_with("/app/icon.svg"));
+    expect_that!(metadata.source_content_type, eq("image/svg+xml"));
+}
+
+#[tokio::test]
+#[gtest]
+async fn icon_discovery_without_icons() {
+    let domain = stub_site(
+        Router::new()
+            .route("/", get(|| async { (StatusCode::NOT_FOUND, ERROR_PAGE) }))
+            .route("/favicon.ico", get(|| async { ERROR_PAGE })),
+    )
+    .await;
+    let store = icon_store().await;
+
+    expect_that!(
+        store.gather(&domain).await,
+        err(matches_pattern!(IconStoreError::NoIconFound))
+    );
+
+    // Nothing is cached
+    expect_that!(
+        store.find_or_gather(&domain, 64, OutputFormat::Png).await,
+        err(matches_pattern!(IconStoreError::NoIconFound))
+    );
+    expect_that!(store.metadata(&domain).await, none());
+}
+
+#[tokio::test]
+#[gtest]
+async fn icon_discovery_unreachable() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let domain = listener.local_addr().unwrap().to_string();
+    drop(listener);
+
+    expect_that!(
+        icon_store().await.gather(&domain).await,
+        err(matches_pattern!(IconStoreError::UnableToSendRequest))
+    );
+    expect_that!(
+        icon_store().await.gather("exa mple.com").await,
+        err(matches_pattern!(IconStoreError::InvalidDomain))
+    );
+}
+
+#[tokio::test]
+#[gtest]
+async fn icon_fetching_blocks_private_addresses() {
+    let (domain, requests) = counted_stub_site(Router::new().route(
+        "/favicon.ico",
+        get(|| async { icon(RED, 16, ImageFormat::Ico) }),
+    ))
+    .await;
+    let port = domain.rsplit_once(':').unwrap().1;
+    let store = IconStore::new().with_http();
+    store.init().await.unwrap();
+
+    expect_that!(
+        store.gather(&domain).await,
+        err(matches_pattern!(IconStoreError::BlockedDestination))
+    );
+    // Domains are checked once resolved
+    expect_that!(
+        store.gather(&format!("localhost:{port}")).await,
+        err(anything())
+    );
+    expect_that!(requests.load(Ordering::SeqCst), eq(0));
+}
+
+#[tokio::test]
+#[gtest]
+async fn icon_fetching_blocks_redirects() {
+    let (private, requests) = counted_stub_site(Router::new().route(
+        "/favicon.ico",
+        get(|| async { icon(RED, 16, ImageFormat::Ico) }),
+    ))
+    .await;
+    let port = private.rsplit_once(':').unwrap().1.to_string();
+
+    // The icon redirects endlessly, and the favicon to a domain which is not allowlisted unlike its address
+    let domain = stub_site(
+        Router::new()
+            .route(
+                "/",
+                get(|| async { axum::response::Html(r#"<link rel="icon" href="/loop/0">"#) }),
+            )
+            .route(
+                "/loop/{n}",
+                get(
+                    |axum::extract::Path(n): axum::extract::Path<u32>| async move {
+                        axum::response::Redirect::to(&format!("/loop/{}", n + 1))
+                    },
+                ),
+            )
+            .route(
+                "/favicon.ico",
+                get(move || async move {
+                    axum::response::Redirect::to(&format!("http://localhost:{port}/favicon.ico"))
+                }),
+            ),
+    )
+    .await;
+
+    expect_that!(
+        icon_store().await.gather(&domain).await,
+        err(matches_pattern!(IconStoreError::NoIconFound))
+    );
+    expect_that!(requests.load(Ordering::SeqCst), eq(0));
+}
+
+#[tokio::test]
+#[gtest]
+async fn icon_providers_in_order() {
+    let (service, requests) = counted_stub_site(Router::new().route(
+        "/icons/{domain}",
+        get(
+            |axum::extract::Path(domain): axum::extract::Path<String>| async move {
+                match domain.as_str() {
+                    "example.com" => Ok(icon(GREEN, 32, ImageFormat::Png)),
+                    _ => Err(StatusCode::NOT_FOUND),
+                }
+            },
+        ),
+    ))
+    .await;
+    let direct = stub_site(Router::new().route(
+        "/favicon.ico",
+        get(|| async { icon(RED, 16, ImageFormat::Ico) }),
+    ))
+    .await;
+
+    // The service is allowlisted implicitly, as it is configured by the administrator
+    let store = icon_store().await.with_providers(vec![
+        Box::new(BundledProvider::default()),
+        Box::new(ServiceProvider::new(format!("http://{service}/icons/{{domain}}")).unwrap()),
+        Box::new(DirectProvider),
+    ]);
+
+    let bundled = store.gather("login.live.com").await.unwrap();
+    expect_that!(bundled.provider, eq("bundled"));
+    expect_that!(requests.load(Ordering::SeqCst), eq(0));
+
+    let serviced = store.gather("example.com").await.unwrap();
+    expect_that!(serviced.provider, eq("service"));
+    expect_that!(
+        serviced.source_url,
+        eq(&format!("http://{service}/icons/example.com"))
+    );
+    let icon = store
+        .find_or_gather("example.com", 64, OutputFormat::Png)
+        .await
+        .unwrap();
+    expect_that!(color_of(&icon), eq(GREEN));
+
+    let direct = store.gather(&direct).await.unwrap();
+    expect_that!(direct.provider, eq("direct"));
+
+    // Air-gapped installs only use the bundled icons
+    let offline = icon_store()
+        .await
+        .with_providers(vec![Box::new(BundledProvider::default())]);
+    expect_that!(offline.gather("microsoft.com").await, ok(anything()));
+    expect_that!(
+        offline.gather("example.com").await,
+        err(matches_pattern!(IconStoreError::NoIconFound))
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn icon_size_and_format(db: SqlitePool) {
+    let domain = stub_site(Router::new().route(
+        "/favicon.ico",
+        get(|| async { icon(RED, 16, ImageFormat::Ico) }),
+    ))
+    .await;
+    let app = common::testing_setup_custom()
+        .pool(&db)
+        .icon_store(IconStore::new().with_http().with_allowlist(loopback()))
+        .call()
+        .await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let code = common::convert_response(
+        common::add_code(
+            &app,
+            &a1,
+            &json!({
+                "content": "JBSWY3DPEHPK3PXP",
+                "display_name": "Stub",
+                "website_url": domain,
+            }),
+        )
+        .await,
+    )
+    .await;
+    let id = code["id"].as_str().unwrap();
+
+    for (query, content_type, size) in [
+        ("", "image/png", 128),
+        ("?size=64&format=webp", "image/webp", 64),
+        ("?size=20&format=png", "image/png", 32),
+        ("?size=1000", "image/png", 256),
+    ] {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get(format!("/v1/code/{id}/icon{query}"))
+                    .header("Authorization", format!("Bearer {a1}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_that!(response.status(), eq(StatusCode::OK));
+        expect_that!(
+            response.headers().get(header::CONTENT_TYPE).unwrap(),
+            eq(content_type)
+        );
+
+        let content = common::convert_response_u8(response).await;
+        let image = image::load_from_memory(&content).unwrap();
+        expect_that!((image.width(), image.height()), eq((size, size)));
+        expect_that!(color_of(&content), eq(RED));
+    }
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::get(format!("/v1/code/{id}/icon?format=gif"))
+                .header("Authorization", format!("Bearer {a1}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    expect_that!(response.status(), eq(StatusCode::BAD_REQUEST));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn icon_bundled_issuer(db: SqlitePool) {
+    let domain = stub_site(Router::new().route(
+        "/favicon.ico",
+        get(|| async { icon(RED, 16, ImageFormat::Ico) }),
+    ))
+    .await;
+    let app = common::testing_setup_custom()
+        .pool(&db)
+        .icon_store(
+            IconStore::new()
+                .with_http()
+                .with_allowlist(loopback())
+                .with_providers(vec![
+                    Box::new(BundledProvider::default()),
+                    Box::new(DirectProvider),
+                ]),
+        )
+        .call()
+        .await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let code_icon = |code: serde_json::Value| {
+        let (app, a1) = (app.clone(), a1.clone());
+        async move {
+            let code = common::convert_response(common::add_code(&app, &a1, &code).await).await;
+            let response = common::request(
+                &app,
+                Method::GET,
+                &format!("/v1/code/{}/icon?size=64", code["id"].as_str().unwrap()),
+                &a1,
+                None,
+            )
+            .await;
+            assert_that!(response.status(), eq(StatusCode::OK));
+            color_of(&common::convert_response_u8(response).await)
+        }
+    };
+
+    // The issuer is recognized from the display name, even without a website
+    expect_that!(
+        code_icon(json!({ "content": "JBSWY3DPEHPK3PXP", "display_name": "Coinbase (erb3)" }))
+            .await,
+        eq(Rgba([0x00, 0x52, 0xff, 0xff]))
+    );
+
+    // Unknown issuers fall back to the website
+    expect_that!(
+        code_icon(json!({
+            "content": "JBSWY3DPEHPK3PXP",
+            "display_name": "Stub",
+            "website_url": domain,
+        }))
+        .await,
+        eq(RED)
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn icon_upload(db: SqlitePool) {
+    let domain = stub_site(Router::new().route(
+        "/favicon.ico",
+        get(|| async { icon(RED, 16, ImageFormat::Ico) }),
+    ))
+    .await;
+    let dir = cache_dir();
+    let app = common::testing_setup_custom()
+        .pool(&db)
+        .icon_store(
+            IconStore::new_with_custom_base(dir.clone())
+                .with_http()
+                .with_allowlist(loopback()),
+        )
+        .call()
+        .await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let code = common::convert_response(
+        common::add_code(
+            &app,
+            &a1,
+            &json!({
+                "content": "JBSWY3DPEHPK3PXP",
+                "display_name": "Stub",
+                "website_url": domain,
+            }),
+        )
+        .await,
+    )
+    .await;
+    let id = code["id"].as_str().unwrap().to_string();
+
+    let request = |method: Method, body: Body| {
+        app.clone().oneshot(
+            Request::builder()
+                .method(method)
+                .uri(format!("/v1/code/{id}/icon"))
+                .header("Authorization", format!("Bearer {a1}"))
+                .body(body)
+                .unwrap(),
+        )
+    };
+    let color = || async {
+        let response = request(Method::GET, Body::empty()).await.unwrap();
+        color_of(&common::convert_response_u8(response).await)
+    };
+
+    let uploaded = request(Method::PUT, Body::from(icon(GREEN, 300, ImageFormat::Png)))
+        .await
+        .unwrap();
+    assert_that!(uploaded.status(), eq(StatusCode::OK));
+    expect_that!(
+        common::convert_response(uploaded).await["icon_url"],
+        eq(&json!(format!("/v1/code/{id}/icon")))
+    );
+    expect_that!(color().await, eq(GREEN));
+
+    // Changing the website keeps the uploaded icon
+    common::edit_code(&app, &a1, &id, &json!({ "website_url": "example.com" })).await;
+    expect_that!(color().await, eq(GREEN));
+    common::edit_code(&app, &a1, &id, &json!({ "website_url": domain })).await;
+
+    let invalid = request(Method::PUT, Body::from(ERROR_PAGE)).await.unwrap();
+    expect_that!(invalid.status(), eq(StatusCode::BAD_REQUEST));
+    let mut huge = icon(GREEN, 16, ImageFormat::Png);
+    huge.resize(2 * 1024 * 1024 - 1024, 0);
+    let too_large = request(Method::PUT, Body::from(huge)).await.unwrap();
+    expect_that!(too_large.status(), eq(StatusCode::PAYLOAD_TOO_LARGE));
+    expect_that!(color().await, eq(GREEN));
+
+    // Reverts to the icon of the website
+    let removed = request(Method::DELETE, Body::empty()).await.unwrap();
+    assert_that!(removed.status(), eq(StatusCode::OK));
+    expect_that!(
+        common::convert_response(removed).await["icon_url"],
+        eq(&json!(null))
+    );
+    expect_that!(color().await, eq(RED));
+
+    // Deleting the code deletes its uploaded icon
+    request(Method::PUT, Body::from(icon(BLUE, 16, ImageFormat::Png)))
+        .await
+        .unwrap();
+    expect_that!(
+        tokio::fs::try_exists(dir.join("custom").join(&id))
+            .await
+            .unwrap(),
+        eq(true)
+    );
+    common::delete_code(&app, &a1, &id).await;
+    expect_that!(
+        tokio::fs::try_exists(dir.join("custom").join(&id))
+            .await
+            .unwrap(),
+        eq(false)
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn icon_conditional_requests(db: SqlitePool) {
+    let domain = stub_site(Router::new().route(
+        "/favicon.ico",
+        get(|| async { icon(RED, 16, ImageFormat::Ico) }),
+    ))
+    .await;
+    let app = common::testing_setup_custom()
+        .pool(&db)
+        .icon_store(icon_store().await)
+        .call()
+        .await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let code = common::convert_response(
+        common::add_code(
+            &app,
+            &a1,
+            &json!({
+                "content": "JBSWY3DPEHPK3PXP",
+                "display_name": "Stub",
+                "website_url": domain,
+            }),
+        )
+        .await,
+    )
+    .await;
+    let id = code["id"].as_str().unwrap().to_string();
+
+    let request = |method: Method, if_none_match: Option<&str>, body: Body| {
+        let mut request = Request::builder()
+            .method(method)
+            .uri(format!("/v1/code/{id}/icon"))
+            .header("Authorization", format!("Bearer {a1}"));
+        if let Some(etag) = if_none_match {
+            request = request.header(header::IF_NONE_MATCH, etag);
+        }
+        app.clone().oneshot(request.body(body).unwrap())
+    };
+
+    let website = request(Method::GET, None, Body::empty()).await.unwrap();
+    assert_that!(website.status(), eq(StatusCode::OK));
+    expect_that!(
+        website.headers()[header::CACHE_CONTROL],
+        eq("private, max-age=86400")
+    );
+    let etag = website.headers()[header::ETAG]
+        .to_str()
+        .unwrap()
+        .to_string();
+    expect_that!(etag, matches_regex(r#"^"[0-9a-f]{64}"$"#));
+
+    let unchanged = request(
+        Method::GET,
+        Some(&format!("W/\"other\", {etag}")),
+        Body::empty(),
+    )
+    .await
+    .unwrap();
+    expect_that!(unchanged.status(), eq(StatusCode::NOT_MODIFIED));
+    expect_that!(unchanged.headers()[header::ETAG], eq(etag.as_str()));
+    expect_that!(common::convert_response_u8(unchanged).await, empty());
+
+    // Uploading an icon changes the tag, and uploaded icons are always revalidated
+    request(
+        Method::PUT,
+        None,
+        Body::from(icon(GREEN, 16, ImageFormat::Png)),
+    )
+    .await
+    .unwrap();
+    let custom = request(Method::GET, Some(&etag), Body::empty())
+        .await
+        .unwrap();
+    assert_that!(custom.status(), eq(StatusCode::OK));
+    expect_that!(
+        custom.headers()[header::CACHE_CONTROL],
+        eq("private, no-cache")
+    );
+    expect_that!(custom.headers()[header::ETAG], not(eq(etag.as_str())));
+    expect_that!(
+        color_of(&common::convert_response_u8(custom).await),
+        eq(GREEN)
+    );
+}
+
+/// Waits for the icon of a domain to be gathered in the background.
+async fn warmed(store: &IconStore, domain: &str) {
+    for _ in 0..500 {
+        if store.is_fresh(domain) {
+            return;
+        }
+        tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    }
+    panic!("Icon of {domain} was not gathered");
+}
+
+async fn add_stub_code(app: &Router, token: &str, website: Option<String>) -> String {
+    let code = common::convert_response(
+        common::add_code(
+            app,
+            token,
+            &json!({
+                "content": "JBSWY3DPEHPK3PXP",
+                "display_name": "Stub",
+                "website_url": website,
+            }),
+        )
+        .await,
+    )
+    .await;
+    code["id"].as_str().unwrap().to_string()
+}
+
+#[sqlx::test(fixtures("users"))]
+#[gtest]
+async fn icon_bulk(db: SqlitePool) {
+    let site = || {
+        Router::new().route(
+            "/favicon.ico",
+            get(|| async { icon(RED, 16, ImageFormat::Ico) }),
+        )
+    };
+    let (added, requests) = counted_stub_site(site()).await;
+    let later = stub_site(site()).await;
+    let store = icon_store().await;
+    let app = common::testing_setup_custom()
+        .pool(&db)
+        .icon_store(store.clone())
+        .call()
+        .await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let add = |website: Option<String>| add_stub_code(&app, &a1, website);
+
+    // Adding a code warms its icon
+    let website = add(Some(added.clone())).await;
+    warmed(&store, &added).await;
+    expect_that!(requests.load(Ordering::SeqCst), gt(0));
+
+    let uploaded = add(None).await;
+    app.clone()
+        .oneshot(
+            Request::put(format!("/v1/code/{uploaded}/icon"))
+                .header("Authorization", format!("Bearer {a1}"))
+                .body(Body::from(icon(GREEN, 16, ImageFormat::Png)))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    common::set_icon_fetching(&app, &a1, false).await;
+    let pending = add(Some(later.clone())).await;
+    common::set_icon_fetching(&app, &a1, true).await;
+
+    // Codes in vaults of the user are included too
+    let vault = common::convert_response(
+        common::request(
+            &app,
+            Method::POST,
+            "/v1/vaults",
+            &a1,
+            Some(json!({ "name": "On-call" })),
+        )
+        .await,
+    )
+    .await;
+    let in_vault = common::convert_response(
+        common::add_code(
+            &app,
+            &a1,
+            &json!({
+                "content": "JBSWY3DPEHPK3PXP",
+                "display_name": "Stub",
+                "website_url": added,
+                "vault_id": vault["id"],
+            }),
+        )
+        .await,
+    )
+    .await["id"]
+        .as_str()
+        .unwrap()
+        .to_string();
+
+    let bulk = || async {
+        let response = app
+            .clone()
+            .oneshot(
+                Request::get("/v1/code/icons?size=32&format=webp")
+                    .header("Authorization", format!("Bearer {a1}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_that!(response.status(), eq(StatusCode::OK));
+        common::convert_response(response).await
+    };
+    let color = |icons: &serde_json::Value, id: &str| {
+        let content = STANDARD.decode(icons[id].as_str().unwrap()).unwrap();
+        color_of(&content)
+    };
+
+    // Icons which are not ready are left out, and gathered in the background
+    let icons = bulk().await;
+    expect_that!(icons["content_type"], eq(&json!("image/webp")));
+    expect_that!(icons["icons"].as_object().unwrap().len(), eq(3));
+    expect_that!(color(&icons["icons"], &website), eq(RED));
+    expect_that!(color(&icons["icons"], &in_vault), eq(RED));
+    expect_that!(color(&icons["icons"], &uploaded), eq(GREEN));
+    expect_that!(icons["icons"].get(&pending), none());
+
+    warmed(&store, &later).await;
+    let icons = bulk().await;
+    expect_that!(color(&icons["icons"], &pending), eq(RED));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn icon_fetching_opt_out(db: SqlitePool) {
+    let (domain, requests) = counted_stub_site(Router::new().route(
+        "/favicon.ico",
+        get(|| async { icon(RED, 16, ImageFormat::Ico) }),
+    ))
+    .await;
+    let store = icon_store().await;
+    let app = common::testing_setup_custom()
+        .pool(&db)
+        .icon_store(store.clone())
+        .call()
+        .await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let send = |method: Method, uri: String, body: serde_json::Value| {
+        app.clone().oneshot(
+            Request::builder()
+                .method(method)
+                .uri(uri)
+                .header("Authorization", format!("Bearer {a1}"))
+                .header("Content-Type", "application/json")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+    };
+    let opt = |enabled: bool| {
+        send(
+            Method::PATCH,
+            "/v1/user".into(),
+            json!({ "icon_fetching": enabled }),
+        )
+    };
+
+    // Adding codes does not warm their icons either
+    let profile = common::convert_response(opt(false).await.unwrap()).await;
+    expect_that!(profile["icon_fetching"], eq(&json!(false)));
+
+    let code = common::convert_response(
+        common::add_code(
+            &app,
+            &a1,
+            &json!({
+                "content": "JBSWY3DPEHPK3PXP",
+                "display_name": "Stub",
+                "website_url": domain,
+            }),
+        )
+        .await,
+    )
+    .await;
+    let icon_uri = format!("/v1/code/{}/icon", code["id"].as_str().unwrap());
+
+    let response = send(Method::GET, icon_uri.clone(), json!(null))
+        .await
+        .unwrap();
+    expect_that!(response.status(), eq(StatusCode::NO_CONTENT));
+    expect_that!(requests.load(Ordering::SeqCst), eq(0));
+
+    opt(true).await.unwrap();
+    let response = send(Method::GET, icon_uri.clone(), json!(null))
+        .await
+        .unwrap();
+    expect_that!(response.status(), eq(StatusCode::OK));
+    let fetched = requests.load(Ordering::SeqCst);
+
+    // Cached icons are still served, as they do not contact the website
+    opt(false).await.unwrap();
+    let response = send(Method::GET, icon_uri.clone(), json!(null))
+        .await
+        .unwrap();
+    expect_that!(response.status(), eq(StatusCode::OK));
+    expect_that!(requests.load(Ordering::SeqCst), eq(fetched));
+
+    let deleted = send(Method::DELETE, "/v1/user/icons".into(), json!(null))
+        .await
+        .unwrap();
+    assert_that!(deleted.status(), eq(StatusCode::OK));
+    expect_that!(
+        common::convert_response(deleted).await,
+        eq(&json!({ "removed": 1 }))
+    );
+    expect_that!(store.metadata(&domain).await, none());
+    let response = send(Method::GET, icon_uri, json!(null)).await.unwrap();
+    expect_that!(response.status(), eq(StatusCode::NO_CONTENT));
+    expect_that!(requests.load(Ordering::SeqCst), eq(fetched));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn icon_garbage_collection(db: SqlitePool) {
+    let site = || {
+        Router::new().route(
+            "/favicon.ico",
+            get(|| async { icon(RED, 16, ImageFormat::Ico) }),
+        )
+    };
+    let (used, unused) = (stub_site(site()).await, stub_site(site()).await);
+    let dir = cache_dir();
+    let store = IconStore::new_with_custom_base(dir.clone())
+        .with_http()
+        .with_allowlist(loopback())
+        .with_garbage_grace_period(chrono::Duration::zero())
+        .init()
+        .await
+        .unwrap()
+        .clone();
+    store.gather(&used).await.unwrap();
+    store.gather(&unused).await.unwrap();
+    store
+        .store_custom(common::USER1_CODE1_ID, icon(GREEN, 16, ImageFormat::Png))
+        .await
+        .unwrap();
+    store
+        .store_custom("deletedCode", icon(GREEN, 16, ImageFormat::Png))
+        .await
+        .unwrap();
+
+    sqlx::query!(
+        "UPDATE codes SET website_url = $1 WHERE id = $2",
+        used,
+        common::USER1_CODE1_ID
+    )
+    .execute(&db)
+    .await
+    .unwrap();
+
+    expect_that!(
+        iceblink_sync::jobs::collect_icon_garbage(&db, &store)
+            .await
+            .unwrap(),
+        eq(2)
+    );
+    expect_that!(store.metadata(&used).await, some(anything()));
+    expect_that!(store.metadata(&unused).await, none());
+    expect_that!(
+        store
+            .find_custom(common::USER1_CODE1_ID, 64, OutputFormat::Png)
+            .await,
+        some(anything())
+    );
+    expect_that!(
+        store
+            .find_custom("deletedCode", 64, OutputFormat::Png)
+            .await,
+        none()
+    );
+
+    // Uploads still being written, and icons of codes created after the references were queried, are kept
+    let staging = dir.join("custom").join(".newCode-abcde");
+    tokio::fs::create_dir_all(&staging).await.unwrap();
+    let store = store.with_garbage_grace_period(chrono::Duration::hours(1));
+    store.gather(&unused).await.unwrap();
+    store
+        .store_custom("newCode", icon(GREEN, 16, ImageFormat::Png))
+        .await
+        .unwrap();
+
+    expect_that!(
+        iceblink_sync::jobs::collect_icon_garbage(&db, &store)
+            .await
+            .unwrap(),
+        eq(0)
+    );
+    expect_that!(store.metadata(&unused).await, some(anything()));
+    expect_that!(
+        store.find_custom("newCode", 64, OutputFormat::Png).await,
+        some(anything())
+    );
+    expect_that!(tokio::fs::try_exists(&staging).await.unwrap(), eq(true));
+}
+
+#[tokio::test]
+#[gtest]
+async fn icon_cache_persists() {
+    let (domain, requests) = counted_stub_site(Router::new().route(
+        "/favicon.ico",
+        get(|| async { icon(RED, 16, ImageFormat::Ico) }),
+    ))
+    .await;
+    let dir = cache_dir();
+
+    let store = IconStore::new_with_custom_base(dir.clone())
+        .with_http()
+        .with_allowlist(loopback());
+    store.init().await.unwrap();
+    store
+        .find_or_gather(&domain, 64, OutputFormat::Png)
+        .await
+        .unwrap();
+    let fetched = requests.load(Ordering::SeqCst);
+
+    // A restarted server uses the cached icon
+    let restarted = IconStore::new_with_custom_base(dir.clone())
+        .with_http()
+        .with_allowlist(loopback());
+    restarted.init().await.unwrap();
+    expect_that!(restarted.metadata(&domain).await, some(anything()));
+    let icon = restarted
+        .find_or_gather(&domain, 64, OutputFormat::Webp)
+        .await
+        .unwrap();
+    expect_that!(color_of(&icon), eq(RED));
+    expect_that!(requests.load(Ordering::SeqCst), eq(fetched));
+}
+
+#[tokio::test]
+#[gtest]
+async fn icon_cache_refreshes_expired() {
+    let served = Arc::new(AtomicUsize::new(0));
+    let counter = served.clone();
+    let domain = stub_site(Router::new().route(
+        "/favicon.ico",
+        get(move || async move {
+            // The website loses its icon after the first request
+            match counter.fetch_add(1, Ordering::SeqCst) {
+                0 => Ok(icon(RED, 16, ImageFormat::Ico)),
+                _ => Err(StatusCode::NOT_FOUND),
+            }
+        }),
+    ))
+    .await;
+    let store = icon_store().await.with_ttl(chrono::Duration::zero());
+
+    store
+        .find_or_gather(&domain, 64, OutputFormat::Png)
+        .await
+        .unwrap();
+
+    // Refreshing fails, so the previous icon is kept
+    let icon = store
+        .find_or_gather(&domain, 64, OutputFormat::Png)
+        .await
+        .unwrap();
+    expect_that!(color_of(&icon), eq(RED));
+    expect_that!(served.load(Ordering::SeqCst), eq(2));
+}
+
+#[tokio::test]
+#[gtest]
+async fn icon_cache_remembers_missing() {
+    let (domain, requests) = counted_stub_site(Router::new()).await;
+    let store = icon_store().await;
+
+    expect_that!(
+        store.find_or_gather(&domain, 64, OutputFormat::Png).await,
+        err(matches_pattern!(IconStoreError::NoIconFound))
+    );
+    let asked = requests.load(Ordering::SeqCst);
+
+    expect_that!(
+        store.find_or_gather(&domain, 64, OutputFormat::Png).await,
+        err(matches_pattern!(IconStoreError::NoIconFound))
+    );
+    expect_that!(requests.load(Ordering::SeqCst), eq(asked));
+}
+
+#[tokio::test]
+#[gtest]
+async fn icon_gathering_coalesced() {
+    let served = Arc::new(AtomicUsize::new(0));
+    let counter = served.clone();
+    let domain = stub_site(Router::new().route(
+        "/favicon.ico",
+        get(move || async move {
+            counter.fetch_add(1, Ordering::SeqCst);
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            icon(RED, 16, ImageFormat::Ico)
+        }),
+    ))
+    .await;
+    let store = icon_store().await;
+
+    let (first, second, third) = tokio::join!(
+        store.find_or_gather(&domain, 64, OutputFormat::Png),
+        store.find_or_gather(&domain, 128, OutputFormat::Webp),
+        store.gather(&domain),
+    );
+
+    expect_that!(color_of(&first.unwrap()), eq(RED));
+    expect_that!(color_of(&second.unwrap()), eq(RED));
+    expect_that!(third, ok(anything()));
+    expect_that!(served.load(Ordering::SeqCst), eq(1));
+    expect_that!(store.stats().coalesced.load(Ordering::SeqCst), eq(2));
+}
+
+#[tokio::test]
+#[gtest]
+async fn icon_gathering_outlives_request() {
+    let (domain, requests) = counted_stub_site(Router::new().route(
+        "/favicon.ico",
+        get(|| async {
+            tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+            icon(RED, 16, ImageFormat::Ico)
+        }),
+    ))
+    .await;
+    let store = icon_store().await;
+
+    // Like a request cut off by the timeout layer
+    let cancelled = tokio::time::timeout(
+        std::time::Duration::from_millis(50),
+        store.find_or_gather(&domain, 64, OutputFormat::Png),
+    )
+    .await;
+    expect_that!(cancelled, err(anything()));
+
+    warmed(&store, &domain).await;
+    let asked = requests.load(Ordering::SeqCst);
+    let found = store
+        .find_or_gather(&domain, 64, OutputFormat::Png)
+        .await
+        .unwrap();
+    expect_that!(color_of(&found), eq(RED));
+    expect_that!(requests.load(Ordering::SeqCst), eq(asked));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn icon_metrics(db: SqlitePool) {
+    let domain = stub_site(Router::new().route(
+        "/favicon.ico",
+        get(|| async { icon(RED, 16, ImageFormat::Ico) }),
+    ))
+    .await;
+    let app = common::testing_setup_custom()
+        .pool(&db)
+        .icon_store(IconStore::new().with_http().with_allowlist(loopback()))
+        .call()
+        .await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    // Keeps adding the codes from warming their icons in the background
+    common::set_icon_fetching(&app, &a1, false).await;
+    let mut ids = vec![];
+    for website in [domain.as_str(), "exa mple.com"] {
+        let code = common::convert_response(
+            common::add_code(
+                &app,
+                &a1,
+                &json!({
+                    "content": "JBSWY3DPEHPK3PXP",
+                    "display_name": "Stub",
+                    "website_url": website,
+                }),
+            )
+            .await,
+        )
+        .await;
+        ids.push(code["id"].as_str().unwrap().to_string());
+    }
+    common::set_icon_fetching(&app, &a1, true).await;
+
+    for id in [&ids[0], &ids[0], &ids[1], &ids[1]] {
+        app.clone()
+            .oneshot(
+                Request::get(format!("/v1/code/{id}/icon"))
+                    .header("Authorization", format!("Bearer {a1}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+    }
+
+    let response = app
+        .clone()
+        .oneshot(Request::get("/v1/metrics").body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    let metrics = String::from_utf8(common::convert_response_u8(response).await).unwrap();
+
+    expect_that!(
+        metrics,
+        all![
+            contains_substring("icon_cache_requests_total{result=\"hit\"} 1"),
+            contains_substring("icon_cache_requests_total{result=\"negative\"} 1"),
+            contains_substring("icon_cache_requests_total{result=\"miss\"} 2"),
+            contains_substring("icon_fetches_total{result=\"success\"} 1"),
+            contains_substring("icon_fetches_total{result=\"failure\"} 1"),
+        ]
+    );
+}
+
+#[tokio::test]
+#[gtest]
+async fn icon_cache_evicts_least_recently_used() {
+    let site = || {
+        Router::new().route(
+            "/favicon.ico",
+            get(|| async { icon(RED, 16, ImageFormat::Ico) }),
+        )
+    };
+    let (first, second, third) = (
+        stub_site(site()).await,
+        stub_site(site()).await,
+        stub_site(site()).await,
+    );
+
+    // Room for two icons, but not three
+    let store = icon_store().await;
+    store.gather(&first).await.unwrap();
+    let store = icon_store().await.with_max_size(store.size() * 5 / 2);
+
+    store.gather(&first).await.unwrap();
+    store.gather(&second).await.unwrap();
+    store
+        .find_or_gather(&first, 64, OutputFormat::Png)
+        .await
+        .unwrap();
+    store.gather(&third).await.unwrap();
+
+    expect_that!(store.metadata(&first).await, some(anything()));
+    expect_that!(store.metadata(&second).await, none());
+    expect_that!(store.metadata(&third).await, some(anything()));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn icon_cache_purge(db: SqlitePool) {
+    let domain = stub_site(Router::new().route(
+        "/favicon.ico",
+        get(|| async { icon(RED, 16, ImageFormat::Ico) }),
+    ))
+    .await;
+    let dir = cache_dir();
+    let store = IconStore::new_with_custom_base(dir.clone())
+        .with_http()
+        .with_allowlist(loopback());
+    store.init().await.unwrap();
+    store.gather(&domain).await.unwrap();
+    store.gather("exa mple.com").await.unwrap_err();
+
+    // Files not created by the store are left alone
+    tokio::fs::write(dir.join("notes.txt"), b"keep me")
+        .await
+        .unwrap();
+
+    let app = common::testing_setup_custom()
+        .pool(&db)
+        .icon_store(store.clone())
+        .call()
+        .await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+    iceblink_sync::set_admin(&db, "user1", true).await.unwrap();
+
+    let purge = |token: String| {
+        app.clone().oneshot(
+            Request::builder()
+                .method(Method::DELETE)
+                .uri("/v1/admin/icons")
+                .header("Authorization", format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+    };
+
+    let denied = purge(a2).await.unwrap();
+    expect_that!(denied.status(), eq(StatusCode::FORBIDDEN));
+
+    let purged = purge(a1).await.unwrap();
+    assert_that!(purged.status(), eq(StatusCode::OK));
+    expect_that!(
+        common::convert_response(purged).await,
+        eq(&json!({ "purged": 2 }))
+    );
+
+    expect_that!(store.metadata(&domain).await, none());
+    expect_that!(store.size(), eq(0));
+
+    let mut remaining = vec![];
+    let mut files = tokio::fs::read_dir(&dir).await.unwrap();
+    while let Some(file) = files.next_entry().await.unwrap() {
+        remaining.push(file.file_name().to_string_lossy().to_string());
+    }
+    expect_that!(
+        remaining,
+        unordered_elements_are![eq("index.json"), eq("notes.txt")]
+    );
+}
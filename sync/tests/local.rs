@@ -0,0 +1,407 @@
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+    response::Response,
+    Router,
+};
+use googletest::prelude::*;
+use iceblink_sync::cli::AuthBackend;
+use serde_json::json;
+use sqlx::SqlitePool;
+use tower::ServiceExt;
+
+pub mod common;
+
+async fn post_json(app: &Router, uri: &str, payload: &serde_json::Value) -> Response {
+    app.clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::POST)
+                .uri(uri)
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::to_vec(payload).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn register_and_login(db: SqlitePool) {
+    let app = common::testing_setup_with_backend(&db, AuthBackend::Local).await;
+
+    let registered = post_json(
+        &app,
+        "/v1/local/register",
+        &json!({
+            "username": "airgapped",
+            "password": "correct horse battery staple",
+        }),
+    )
+    .await;
+    assert_that!(registered.status(), eq(StatusCode::OK));
+
+    // The JWT works like any other
+    let token = common::jwt_from_cookie(&registered);
+    let codes = common::list_codes(&app, &token).await;
+    assert_that!(codes.status(), eq(StatusCode::OK));
+
+    let logged_in = post_json(
+        &app,
+        "/v1/local/login",
+        &json!({
+            "username": "airgapped",
+            "password": "correct horse battery staple",
+        }),
+    )
+    .await;
+    assert_that!(logged_in.status(), eq(StatusCode::OK));
+    assert_that!(
+        common::list_codes(&app, &common::jwt_from_cookie(&logged_in))
+            .await
+            .status(),
+        eq(StatusCode::OK)
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn login_wrong_password(db: SqlitePool) {
+    let app = common::testing_setup_with_backend(&db, AuthBackend::Local).await;
+
+    post_json(
+        &app,
+        "/v1/local/register",
+        &json!({
+            "username": "airgapped",
+            "password": "correct horse battery staple",
+        }),
+    )
+    .await;
+
+    let response = post_json(
+        &app,
+        "/v1/local/login",
+        &json!({
+            "username": "airgapped",
+            "password": "incorrect horse battery staple",
+        }),
+    )
+    .await;
+
+    assert_that!(response.status(), eq(StatusCode::UNAUTHORIZED));
+    assert_that!(response.headers().get("Set-Cookie"), none());
+    assert_that!(
+        common::convert_response(response).await,
+        eq(&json!({
+            "message": "Invalid username or credentials.",
+            "errorKind": "InvalidCredentials"
+        }))
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn login_unknown_user(db: SqlitePool) {
+    let app = common::testing_setup_with_backend(&db, AuthBackend::Local).await;
+
+    // Upstream ids of other providers can not be used as username
+    let response = post_json(
+        &app,
+        "/v1/local/login",
+        &json!({
+            "username": "8h4ar",
+            "password": "correct horse battery staple",
+        }),
+    )
+    .await;
+
+    assert_that!(response.status(), eq(StatusCode::UNAUTHORIZED));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn register_username_taken(db: SqlitePool) {
+    let app = common::testing_setup_with_backend(&db, AuthBackend::Local).await;
+
+    let payload = json!({
+        "username": "airgapped",
+        "password": "correct horse battery staple",
+    });
+    post_json(&app, "/v1/local/register", &payload).await;
+    let response = post_json(&app, "/v1/local/register", &payload).await;
+
+    assert_that!(response.status(), eq(StatusCode::CONFLICT));
+    assert_that!(
+        common::convert_response(response).await,
+        eq(&json!({
+            "message": "This username is already taken.",
+            "errorKind": "UsernameTaken"
+        }))
+    );
+
+    // The failed registration leaves no account behind
+    let users = sqlx::query_scalar!("SELECT COUNT(*) FROM users WHERE username = 'airgapped'")
+        .fetch_one(&db)
+        .await
+        .unwrap();
+    expect_that!(users, eq(1));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn register_weak_password(db: SqlitePool) {
+    let app = common::testing_setup_with_backend(&db, AuthBackend::Local).await;
+
+    let response = post_json(
+        &app,
+        "/v1/local/register",
+        &json!({
+            "username": "airgapped",
+            "password": "short",
+        }),
+    )
+    .await;
+
+    assert_that!(response.status(), eq(StatusCode::BAD_REQUEST));
+    assert_that!(
+        common::convert_response(response).await,
+        eq(&json!({
+            "message": "Passwords must be at least 8 characters long.",
+            "errorKind": "WeakPassword"
+        }))
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn local_disabled_with_openid(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+
+    let response = post_json(
+        &app,
+        "/v1/local/register",
+        &json!({
+            "username": "airgapped",
+            "password": "correct horse battery staple",
+        }),
+    )
+    .await;
+
+    assert_that!(response.status(), eq(StatusCode::NOT_FOUND));
+    assert_that!(
+        common::convert_response(response).await,
+        eq(&json!({
+            "message": "Local authentication is not enabled on this instance.",
+            "errorKind": "LocalAuthDisabled"
+        }))
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn passkey_register_start(db: SqlitePool) {
+    let app = common::testing_setup_with_backend(&db, AuthBackend::Local).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method(Method::POST)
+                .uri("/v1/local/passkey/register/start")
+                .header("Authorization", format!("Bearer {a1}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_that!(response.status(), eq(StatusCode::OK));
+    let body = common::convert_response(response).await;
+    assert_that!(body["challenge_id"].as_str().unwrap().len(), eq(32));
+    assert_that!(
+        body["options"]["publicKey"]["rp"]["id"].as_str(),
+        some(eq("localhost"))
+    );
+    assert_that!(
+        body["options"]["publicKey"]["user"]["name"].as_str(),
+        some(eq("user1"))
+    );
+}
+
+/// Keys of the JSON object, and of every object nested in it, to compare the shape of responses.
+fn shape(value: &serde_json::Value) -> serde_json::Value {
+    match value {
+        serde_json::Value::Object(map) => map
+            .iter()
+            .map(|(key, value)| (key.clone(), shape(value)))
+            .collect(),
+        serde_json::Value::Array(values) => values.iter().map(shape).collect(),
+        _ => serde_json::Value::Null,
+    }
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn passkey_login_without_passkeys(db: SqlitePool) {
+    let app = common::testing_setup_with_backend(&db, AuthBackend::Local).await;
+
+    post_json(
+        &app,
+        "/v1/local/register",
+        &json!({
+            "username": "offline",
+            "password": "correct horse battery staple",
+        }),
+    )
+    .await;
+
+    let start = |username: &'static str| {
+        let app = app.clone();
+        async move {
+            let response = post_json(
+                &app,
+                "/v1/local/passkey/login/start",
+                &json!({ "username": username }),
+            )
+            .await;
+            assert_that!(response.status(), eq(StatusCode::OK));
+            common::convert_response(response).await
+        }
+    };
+    let finish = |challenge: serde_json::Value| {
+        let app = app.clone();
+        async move {
+            let response = post_json(
+                &app,
+                "/v1/local/passkey/login/finish",
+                &json!({
+                    "challenge_id": challenge["challenge_id"],
+                    "credential": {
+                        "id": "AAAA",
+                        "rawId": "AAAA",
+                        "response": {
+                            "authenticatorData": "AAAA",
+                            "clientDataJSON": "AAAA",
+                            "signature": "AAAA",
+                            "userHandle": null
+                        },
+                        "extensions": {},
+                        "type": "public-key"
+                    }
+                }),
+            )
+            .await;
+            (response.status(), common::convert_response(response).await)
+        }
+    };
+
+    // Known users without passkeys can not be told apart from unknown users, until finishing fails.
+    // The made up passkeys depend only on the username, and both of these get a single one.
+    let known = start("offline").await;
+    let unknown = start("doesnotexist").await;
+    expect_that!(shape(&known), eq(&shape(&unknown)));
+    expect_that!(
+        start("doesnotexist").await["options"]["publicKey"]["allowCredentials"],
+        eq(&unknown["options"]["publicKey"]["allowCredentials"])
+    );
+
+    let (known_status, known_error) = finish(known).await;
+    let (unknown_status, unknown_error) = finish(unknown).await;
+    expect_that!(known_status, eq(StatusCode::UNAUTHORIZED));
+    expect_that!(unknown_status, eq(known_status));
+    expect_that!(known_error["errorKind"], eq(&json!("WebauthnFail")));
+    expect_that!(unknown_error, eq(&known_error));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn passkey_login_unknown_challenge(db: SqlitePool) {
+    let app = common::testing_setup_with_backend(&db, AuthBackend::Local).await;
+
+    let response = post_json(
+        &app,
+        "/v1/local/passkey/login/finish",
+        &json!({
+            "challenge_id": "doesnotexist",
+            "credential": {
+                "id": "AAAA",
+                "rawId": "AAAA",
+                "response": {
+                    "authenticatorData": "AAAA",
+                    "clientDataJSON": "AAAA",
+                    "signature": "AAAA",
+                    "userHandle": null
+                },
+                "extensions": {},
+                "type": "public-key"
+            }
+        }),
+    )
+    .await;
+
+    assert_that!(response.status(), eq(StatusCode::BAD_REQUEST));
+    assert_that!(
+        common::convert_response(response).await,
+        eq(&json!({
+            "message": "The passkey challenge is invalid or has expired. Please try again.",
+            "errorKind": "WebauthnChallengeExpired"
+        }))
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn passkey_step_up(db: SqlitePool) {
+    let app = common::testing_setup_with_backend(&db, AuthBackend::Local).await;
+    let token = common::stale_access_token(common::USER1_ID);
+
+    let send = |uri: &str, payload: serde_json::Value| {
+        app.clone().oneshot(
+            Request::builder()
+                .method(Method::POST)
+                .uri(uri)
+                .header("Authorization", format!("Bearer {token}"))
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::to_vec(&payload).unwrap()))
+                .unwrap(),
+        )
+    };
+
+    // Requires a passkey to authenticate with
+    let response = send("/v1/local/passkey/step-up/start", json!(null))
+        .await
+        .unwrap();
+    assert_that!(response.status(), eq(StatusCode::UNAUTHORIZED));
+    assert_that!(
+        common::convert_response(response).await["errorKind"],
+        eq(&json!("InvalidCredentials"))
+    );
+
+    let response = send(
+        "/v1/local/passkey/step-up/finish",
+        json!({
+            "challenge_id": "doesnotexist",
+            "credential": {
+                "id": "AAAA",
+                "rawId": "AAAA",
+                "response": {
+                    "authenticatorData": "AAAA",
+                    "clientDataJSON": "AAAA",
+                    "signature": "AAAA",
+                    "userHandle": null
+                },
+                "extensions": {},
+                "type": "public-key"
+            }
+        }),
+    )
+    .await
+    .unwrap();
+    assert_that!(response.status(), eq(StatusCode::BAD_REQUEST));
+    assert_that!(
+        common::convert_response(response).await["errorKind"],
+        eq(&json!("WebauthnChallengeExpired"))
+    );
+}
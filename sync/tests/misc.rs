@@ -35,9 +35,18 @@ async fn api_metadata(db: SqlitePool) {
         converted,
         eq(&json!({
             "version": env!("CARGO_PKG_VERSION"),
+            "auth_backend": "openid",
+            "registration": "open",
             "authorize": "N/A",
             "client_id": "N/A",
             "redirect_uri": "N/A",
+            "providers": [{
+                "id": "default",
+                "name": "N/A",
+                "authorize": "N/A",
+                "client_id": "N/A",
+                "redirect_uri": "N/A",
+            }],
         }))
     );
 
@@ -299,11 +308,13 @@ fn common_code_is_expected_user1_code2() {
             crate::common::USER1_ID,
             &models::codes::Code {
                 id: "DxLCqi4ZlHPD8YxA".into(),
-                owner_id: "k0d8WrkRjK6gkc3C".into(),
+                owner_id: Some("k0d8WrkRjK6gkc3C".into()),
+                vault_id: None,
                 content: "XGDi8FlvZ5OGBoxG".into(),
                 display_name: "google.com".into(),
                 icon_url: None,
                 website_url: Some("google.com".into()),
+                shared_permission: None,
             }
         ),
         is_true()
@@ -317,11 +328,13 @@ fn common_code_is_expected_user2_code1() {
             crate::common::USER2_ID,
             &models::codes::Code {
                 id: "fUJveqJaNpPhTUkR".into(),
-                owner_id: "3Ck0d8WrkRjK6gkc".into(),
+                owner_id: Some("3Ck0d8WrkRjK6gkc".into()),
+                vault_id: None,
                 content: "djnaW1Pl2WjhWrU6".into(),
                 display_name: "Dummy INC".into(),
                 icon_url: Some("https://dummy.com/favicon.ico".into()),
                 website_url: Some("dummy.com".into()),
+                shared_permission: None,
             }
         ),
         is_true()
@@ -335,11 +348,13 @@ fn common_code_is_expected_wrong_user() {
             crate::common::USER1_ID,
             &models::codes::Code {
                 id: "fUJveqJaNpPhTUkR".into(),
-                owner_id: "3Ck0d8WrkRjK6gkc".into(),
+                owner_id: Some("3Ck0d8WrkRjK6gkc".into()),
+                vault_id: None,
                 content: "djnaW1Pl2WjhWrU6".into(),
                 display_name: "Dummy INC".into(),
                 icon_url: Some("https://dummy.com/favicon.ico".into()),
                 website_url: Some("dummy.com".into()),
+                shared_permission: None,
             }
         ),
         is_false()
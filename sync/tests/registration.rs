@@ -0,0 +1,250 @@
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+    response::Response,
+    Router,
+};
+use googletest::prelude::*;
+use iceblink_sync::{cli::RegistrationMode, models, RegistrationOptions};
+use serde_json::json;
+use sqlx::SqlitePool;
+use tower::ServiceExt;
+
+pub mod common;
+
+async fn setup(db: &SqlitePool, userinfo: serde_json::Value, mode: RegistrationMode) -> Router {
+    let idp = common::mock_idp(userinfo).await;
+
+    common::testing_setup_custom()
+        .pool(db)
+        .openid(idp)
+        .registration(RegistrationOptions {
+            mode,
+            allowed_email_domains: vec!["snowflake.blue".into()],
+            allowed_groups: vec!["iceblink".into()],
+        })
+        .call()
+        .await
+}
+
+async fn oauth_login_with_invite(app: &Router, invite: &str) -> Response {
+    app.clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::GET)
+                .uri("/v1/oauth/default?code=mock")
+                .header("Cookie", format!("iceblink_invite={invite}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+}
+
+async fn error_kind(response: Response) -> serde_json::Value {
+    common::convert_response(response).await["errorKind"].clone()
+}
+
+async fn registered(db: &SqlitePool, upstream_id: &str) -> bool {
+    models::user::User::get_by_upstream_id(db, "default".into(), upstream_id.into())
+        .await
+        .unwrap()
+        .is_some()
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn registration_closed(db: SqlitePool) {
+    let app = setup(&db, json!({ "sub": "newcomer" }), RegistrationMode::Closed).await;
+
+    let login = common::oauth_login(&app, "default").await;
+    assert_that!(login.status(), eq(StatusCode::FORBIDDEN));
+    assert_that!(error_kind(login).await, eq(&json!("RegistrationClosed")));
+    expect_that!(registered(&db, "newcomer").await, eq(false));
+
+    // Existing users can still log in
+    let app = setup(&db, json!({ "sub": "8h4ar" }), RegistrationMode::Closed).await;
+    let login = common::oauth_login(&app, "default").await;
+    expect_that!(login.status(), eq(StatusCode::OK));
+
+    let metadata = common::convert_response(
+        app.clone()
+            .oneshot(Request::get("/v1/").body(Body::empty()).unwrap())
+            .await
+            .unwrap(),
+    )
+    .await;
+    expect_that!(metadata["registration"], eq(&json!("closed")));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn registration_allowlist(db: SqlitePool) {
+    let app = setup(
+        &db,
+        json!({ "sub": "outsider", "email": "outsider@example.com" }),
+        RegistrationMode::Allowlist,
+    )
+    .await;
+    let login = common::oauth_login(&app, "default").await;
+    assert_that!(login.status(), eq(StatusCode::FORBIDDEN));
+    assert_that!(
+        error_kind(login).await,
+        eq(&json!("RegistrationNotAllowed"))
+    );
+
+    let app = setup(
+        &db,
+        json!({ "sub": "unverified", "email": "erb3@snowflake.blue", "email_verified": false }),
+        RegistrationMode::Allowlist,
+    )
+    .await;
+    let login = common::oauth_login(&app, "default").await;
+    expect_that!(login.status(), eq(StatusCode::FORBIDDEN));
+
+    // Providers which do not say whether the email is verified are not trusted either
+    let app = setup(
+        &db,
+        json!({ "sub": "unknown", "email": "erb3@snowflake.blue" }),
+        RegistrationMode::Allowlist,
+    )
+    .await;
+    let login = common::oauth_login(&app, "default").await;
+    expect_that!(login.status(), eq(StatusCode::FORBIDDEN));
+
+    let app = setup(
+        &db,
+        json!({ "sub": "employee", "email": "erb3@snowflake.blue", "email_verified": true }),
+        RegistrationMode::Allowlist,
+    )
+    .await;
+    let login = common::oauth_login(&app, "default").await;
+    expect_that!(login.status(), eq(StatusCode::OK));
+    expect_that!(registered(&db, "employee").await, eq(true));
+
+    let app = setup(
+        &db,
+        json!({ "sub": "contractor", "email": "contractor@example.com", "groups": ["iceblink"] }),
+        RegistrationMode::Allowlist,
+    )
+    .await;
+    let login = common::oauth_login(&app, "default").await;
+    expect_that!(login.status(), eq(StatusCode::OK));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn registration_invite(db: SqlitePool) {
+    let app = setup(&db, json!({ "sub": "newcomer" }), RegistrationMode::Invite).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+    iceblink_sync::set_admin(&db, "user1", true).await.unwrap();
+
+    let login = common::oauth_login(&app, "default").await;
+    assert_that!(login.status(), eq(StatusCode::FORBIDDEN));
+    assert_that!(error_kind(login).await, eq(&json!("InvalidInviteCode")));
+
+    let invite = common::convert_response(
+        app.clone()
+            .oneshot(
+                Request::post("/v1/admin/invites")
+                    .header("Authorization", format!("Bearer {a1}"))
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(r#"{"expires_in_days": 7}"#))
+                    .unwrap(),
+            )
+            .await
+            .unwrap(),
+    )
+    .await;
+    let code = invite["code"].as_str().unwrap();
+
+    let login = oauth_login_with_invite(&app, "wrong").await;
+    assert_that!(login.status(), eq(StatusCode::FORBIDDEN));
+
+    let login = oauth_login_with_invite(&app, code).await;
+    assert_that!(login.status(), eq(StatusCode::OK));
+    expect_that!(
+        login
+            .headers()
+            .get_all("Set-Cookie")
+            .iter()
+            .map(|cookie| cookie.to_str().unwrap().to_string())
+            .collect::<Vec<_>>(),
+        contains(starts_with("iceblink_invite=;"))
+    );
+
+    let invites = common::convert_response(
+        app.clone()
+            .oneshot(
+                Request::get("/v1/admin/invites")
+                    .header("Authorization", format!("Bearer {a1}"))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap(),
+    )
+    .await;
+    let user = models::user::User::get_by_upstream_id(&db, "default".into(), "newcomer".into())
+        .await
+        .unwrap()
+        .unwrap();
+    expect_that!(invites[0]["used_by"], eq(&json!(user.id)));
+
+    // Invites are single-use
+    let app = setup(&db, json!({ "sub": "second" }), RegistrationMode::Invite).await;
+    let login = oauth_login_with_invite(&app, code).await;
+    expect_that!(login.status(), eq(StatusCode::FORBIDDEN));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn registration_invite_local(db: SqlitePool) {
+    let app = common::testing_setup_custom()
+        .pool(&db)
+        .auth_backend(iceblink_sync::cli::AuthBackend::Local)
+        .registration(RegistrationOptions {
+            mode: RegistrationMode::Invite,
+            ..Default::default()
+        })
+        .call()
+        .await;
+    let (code, _) = iceblink_sync::registration::create_invite(&db, None, None)
+        .await
+        .unwrap();
+
+    let register = |username: &'static str, invite_code: Option<String>| {
+        let app = app.clone();
+        async move {
+            app.oneshot(
+                Request::post("/v1/local/register")
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(
+                        serde_json::to_vec(&json!({
+                            "username": username,
+                            "password": "correct horse battery staple",
+                            "invite_code": invite_code,
+                        }))
+                        .unwrap(),
+                    ))
+                    .unwrap(),
+            )
+            .await
+            .unwrap()
+            .status()
+        }
+    };
+
+    expect_that!(register("newcomer", None).await, eq(StatusCode::FORBIDDEN));
+    expect_that!(register("newcomer", Some(code)).await, eq(StatusCode::OK));
+
+    // A failed registration does not use up the invite
+    let (code, _) = iceblink_sync::registration::create_invite(&db, None, None)
+        .await
+        .unwrap();
+    expect_that!(
+        register("newcomer", Some(code.clone())).await,
+        eq(StatusCode::CONFLICT)
+    );
+    expect_that!(register("second", Some(code)).await, eq(StatusCode::OK));
+}
@@ -0,0 +1,339 @@
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+    response::Response,
+    Router,
+};
+use googletest::prelude::*;
+use serde_json::json;
+use sqlx::SqlitePool;
+use tower::ServiceExt;
+
+pub mod common;
+
+async fn share(
+    app: &Router,
+    token: &str,
+    code_id: &str,
+    user_id: &str,
+    permission: &str,
+) -> Response {
+    common::request(
+        app,
+        Method::PUT,
+        &format!("/v1/code/{code_id}/shares/{user_id}"),
+        token,
+        Some(json!({ "permission": permission })),
+    )
+    .await
+}
+
+async fn revoke(app: &Router, token: &str, code_id: &str, user_id: &str) -> Response {
+    common::request(
+        app,
+        Method::DELETE,
+        &format!("/v1/code/{code_id}/shares/{user_id}"),
+        token,
+        None,
+    )
+    .await
+}
+
+async fn list_codes_json(app: &Router, token: &str) -> serde_json::Value {
+    common::convert_response(common::list_codes(app, token).await).await
+}
+
+fn find<'a>(codes: &'a serde_json::Value, id: &str) -> Option<&'a serde_json::Value> {
+    codes
+        .as_array()
+        .unwrap()
+        .iter()
+        .find(|code| code["id"] == id)
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn share_read(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+
+    let shared = share(&app, &a1, common::USER1_CODE1_ID, common::USER2_ID, "read").await;
+    assert_that!(shared.status(), eq(StatusCode::OK));
+    let shared = common::convert_response(shared).await;
+    expect_that!(shared["grantee_id"], eq(&json!(common::USER2_ID)));
+    expect_that!(shared["permission"], eq(&json!("read")));
+
+    let codes = list_codes_json(&app, &a2).await;
+    let code = find(&codes, common::USER1_CODE1_ID).unwrap();
+    expect_that!(code["shared_permission"], eq(&json!("read")));
+    expect_that!(code["content"], eq(&json!(common::USER1_CODE1_CONTENT)));
+
+    // The owner sees their code as not shared with them
+    let codes = list_codes_json(&app, &a1).await;
+    let code = find(&codes, common::USER1_CODE1_ID).unwrap();
+    expect_that!(code["shared_permission"], eq(&json!(null)));
+
+    let shares = common::convert_response(
+        common::request(
+            &app,
+            Method::GET,
+            &format!("/v1/code/{}/shares", common::USER1_CODE1_ID),
+            &a1,
+            None,
+        )
+        .await,
+    )
+    .await;
+    expect_that!(shares.as_array().unwrap().len(), eq(1));
+
+    let edited = common::edit_code(
+        &app,
+        &a2,
+        common::USER1_CODE1_ID,
+        &json!({ "display_name": "Mine" }),
+    )
+    .await;
+    assert_that!(edited.status(), eq(StatusCode::FORBIDDEN));
+    assert_that!(
+        common::convert_response(edited).await["errorKind"],
+        eq(&json!("InsufficientSharePermission"))
+    );
+
+    let icon = common::get_icon(&app, &a2, common::USER1_CODE1_ID).await;
+    expect_that!(icon.status(), not(eq(StatusCode::FORBIDDEN)));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn share_write(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+
+    share(&app, &a1, common::USER1_CODE1_ID, common::USER2_ID, "write").await;
+
+    let edited = common::edit_code(
+        &app,
+        &a2,
+        common::USER1_CODE1_ID,
+        &json!({ "display_name": "Renamed" }),
+    )
+    .await;
+    assert_that!(edited.status(), eq(StatusCode::OK));
+
+    let codes = list_codes_json(&app, &a1).await;
+    let code = find(&codes, common::USER1_CODE1_ID).unwrap();
+    expect_that!(code["display_name"], eq(&json!("Renamed")));
+
+    // Grantees can neither delete nor reshare
+    let deleted = common::delete_code(&app, &a2, common::USER1_CODE1_ID).await;
+    assert_that!(deleted.status(), eq(StatusCode::FORBIDDEN));
+
+    let reshared = share(&app, &a2, common::USER1_CODE1_ID, common::USER1_ID, "write").await;
+    assert_that!(reshared.status(), eq(StatusCode::FORBIDDEN));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn share_revoke(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+
+    share(&app, &a1, common::USER1_CODE1_ID, common::USER2_ID, "read").await;
+    share(&app, &a1, common::USER1_CODE2_ID, common::USER2_ID, "read").await;
+
+    let revoked = revoke(&app, &a1, common::USER1_CODE1_ID, common::USER2_ID).await;
+    assert_that!(revoked.status(), eq(StatusCode::NO_CONTENT));
+
+    // Grantees can remove shares with themselves
+    let removed = revoke(&app, &a2, common::USER1_CODE2_ID, common::USER2_ID).await;
+    assert_that!(removed.status(), eq(StatusCode::NO_CONTENT));
+
+    let codes = list_codes_json(&app, &a2).await;
+    expect_that!(find(&codes, common::USER1_CODE1_ID), none());
+    expect_that!(find(&codes, common::USER1_CODE2_ID), none());
+
+    let revoked = revoke(&app, &a1, common::USER1_CODE1_ID, common::USER2_ID).await;
+    assert_that!(revoked.status(), eq(StatusCode::NOT_FOUND));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn share_invalid(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+
+    let shared = share(&app, &a2, common::USER1_CODE1_ID, common::USER2_ID, "read").await;
+    assert_that!(shared.status(), eq(StatusCode::NOT_FOUND));
+
+    let shared = share(&app, &a1, common::USER1_CODE1_ID, "unknown", "read").await;
+    assert_that!(shared.status(), eq(StatusCode::NOT_FOUND));
+
+    let shared = share(&app, &a1, common::USER1_CODE1_ID, common::USER1_ID, "read").await;
+    assert_that!(shared.status(), eq(StatusCode::BAD_REQUEST));
+}
+
+const TOTP_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+async fn create_link(
+    app: &Router,
+    token: &str,
+    code_id: &str,
+    payload: serde_json::Value,
+) -> Response {
+    common::request(
+        app,
+        Method::POST,
+        &format!("/v1/code/{code_id}/share-link"),
+        token,
+        Some(payload),
+    )
+    .await
+}
+
+async fn open_link(app: &Router, path: &str) -> Response {
+    app.clone()
+        .oneshot(Request::get(path).body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+async fn reveal_link(app: &Router, path: &str) -> Response {
+    app.clone()
+        .oneshot(Request::post(path).body(Body::empty()).unwrap())
+        .await
+        .unwrap()
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn share_link_single_use(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let code = common::convert_response(
+        common::add_code(
+            &app,
+            &a1,
+            &json!({ "content": TOTP_SECRET, "display_name": "Contractor <VPN>" }),
+        )
+        .await,
+    )
+    .await;
+
+    let link = create_link(&app, &a1, code["id"].as_str().unwrap(), json!({})).await;
+    assert_that!(link.status(), eq(StatusCode::OK));
+    let link = common::convert_response(link).await;
+    let url = link["url"].as_str().unwrap();
+    expect_that!(url, starts_with("N/A/s/"));
+    expect_that!(link["code_id"], eq(&code["id"]));
+
+    let path = url.trim_start_matches("N/A");
+
+    // Previews only get the confirmation page, which does not consume the link
+    for _ in 0..2 {
+        let preview = open_link(&app, path).await;
+        assert_that!(preview.status(), eq(StatusCode::OK));
+        let page = common::convert_response_str(preview).await;
+        expect_that!(page, contains_substring(r#"<form method="post">"#));
+        expect_that!(page, not(contains_substring("Contractor")));
+    }
+
+    let before = iceblink_sync::otp::generate(TOTP_SECRET, chrono::Utc::now()).unwrap();
+    let opened = reveal_link(&app, path).await;
+    let after = iceblink_sync::otp::generate(TOTP_SECRET, chrono::Utc::now()).unwrap();
+    assert_that!(opened.status(), eq(StatusCode::OK));
+    expect_that!(
+        opened.headers()["Cache-Control"].to_str().unwrap(),
+        eq("no-store")
+    );
+
+    let page = common::convert_response_str(opened).await;
+    expect_that!(page, contains_substring("Contractor &lt;VPN&gt;"));
+    expect_that!(page, not(contains_substring(TOTP_SECRET)));
+    expect_that!(
+        page,
+        any!(
+            contains_substring(format!(">{}<", before.code)),
+            contains_substring(format!(">{}<", after.code))
+        )
+    );
+
+    let reopened = reveal_link(&app, path).await;
+    assert_that!(reopened.status(), eq(StatusCode::NOT_FOUND));
+    let reopened = open_link(&app, path).await;
+    assert_that!(reopened.status(), eq(StatusCode::NOT_FOUND));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn share_link_expired(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let code = common::convert_response(
+        common::add_code(
+            &app,
+            &a1,
+            &json!({ "content": TOTP_SECRET, "display_name": "VPN" }),
+        )
+        .await,
+    )
+    .await;
+
+    let now = chrono::Utc::now();
+    iceblink_sync::models::share::ShareLink {
+        token_hash: iceblink_sync::utils::hash_token("expired"),
+        code_id: code["id"].as_str().unwrap().into(),
+        created_by: common::USER1_ID.into(),
+        created_at: now - chrono::Duration::hours(2),
+        expires_at: now - chrono::Duration::hours(1),
+    }
+    .insert(&db)
+    .await
+    .unwrap();
+
+    let opened = open_link(&app, "/s/expired").await;
+    assert_that!(opened.status(), eq(StatusCode::NOT_FOUND));
+    let opened = reveal_link(&app, "/s/expired").await;
+    assert_that!(opened.status(), eq(StatusCode::NOT_FOUND));
+
+    let opened = open_link(&app, "/s/unknown").await;
+    assert_that!(opened.status(), eq(StatusCode::NOT_FOUND));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn share_link_invalid(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+
+    // The fixture codes are not base32 secrets
+    let created = create_link(&app, &a1, common::USER1_CODE1_ID, json!({})).await;
+    assert_that!(created.status(), eq(StatusCode::BAD_REQUEST));
+    assert_that!(
+        common::convert_response(created).await["errorKind"],
+        eq(&json!("NotTotpCode"))
+    );
+
+    let created = create_link(&app, &a2, common::USER1_CODE1_ID, json!({})).await;
+    assert_that!(created.status(), eq(StatusCode::NOT_FOUND));
+
+    let code = common::convert_response(
+        common::add_code(
+            &app,
+            &a1,
+            &json!({ "content": TOTP_SECRET, "display_name": "VPN" }),
+        )
+        .await,
+    )
+    .await;
+    let code_id = code["id"].as_str().unwrap();
+
+    let created = create_link(&app, &a1, code_id, json!({ "expires_in_minutes": 0 })).await;
+    assert_that!(created.status(), eq(StatusCode::BAD_REQUEST));
+
+    // Grantees may not pass the code on
+    share(&app, &a1, code_id, common::USER2_ID, "write").await;
+    let created = create_link(&app, &a2, code_id, json!({})).await;
+    assert_that!(created.status(), eq(StatusCode::FORBIDDEN));
+}
@@ -0,0 +1,235 @@
+use axum::{
+    body::Body,
+    http::{Method, Request, StatusCode},
+};
+use googletest::prelude::*;
+use iceblink_sync::models;
+use serde_json::json;
+use sqlx::SqlitePool;
+use tower::ServiceExt;
+
+pub mod common;
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn create_token(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let response = common::create_api_token(
+        &app,
+        &a1,
+        &json!({
+            "name": "CI",
+            "scopes": ["codes:read"],
+            "expires_in_days": 30,
+        }),
+    )
+    .await;
+    assert_that!(response.status(), eq(StatusCode::OK));
+
+    let body = common::convert_response(response).await;
+    expect_that!(body["token"].as_str().unwrap(), starts_with("ibp_"));
+    expect_that!(body["name"], eq(&json!("CI")));
+    expect_that!(body["scopes"], eq(&json!(["codes:read"])));
+    expect_that!(body["last_used_at"], eq(&json!(null)));
+
+    // Only the hash is stored
+    let stored = models::token::ApiToken::get_many(&db, common::USER1_ID.into())
+        .await
+        .unwrap();
+    assert_that!(stored.len(), eq(1));
+    assert_that!(
+        stored[0].token_hash,
+        not(eq(body["token"].as_str().unwrap()))
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn list_tokens_hides_secret(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+    common::create_api_token_content(&app, &a1, &["codes:read"]).await;
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method(Method::GET)
+                .uri("/v1/user/tokens")
+                .header("Authorization", format!("Bearer {a1}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_that!(response.status(), eq(StatusCode::OK));
+
+    let body = common::convert_response(response).await;
+    let tokens = body.as_array().unwrap();
+    assert_that!(tokens.len(), eq(1));
+    expect_that!(tokens[0].get("token"), none());
+    expect_that!(tokens[0].get("token_hash"), none());
+    expect_that!(tokens[0].get("user_id"), none());
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn token_reads_codes(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+    let token = common::create_api_token_content(&app, &a1, &["codes:read"]).await;
+
+    let codes = common::list_codes_content(&app, &token).await;
+    assert_that!(codes, common::matchers::code_fixture());
+
+    let stored = models::token::ApiToken::get_many(&db, common::USER1_ID.into())
+        .await
+        .unwrap();
+    assert_that!(stored[0].last_used_at, some(anything()));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn token_missing_scope(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+    let token = common::create_api_token_content(&app, &a1, &["codes:read"]).await;
+
+    let added = common::add_code(
+        &app,
+        &token,
+        &json!({
+            "content": "garbage",
+            "display_name": "Permafrost",
+        }),
+    )
+    .await;
+
+    assert_that!(added.status(), eq(StatusCode::FORBIDDEN));
+    assert_that!(
+        common::convert_response(added).await,
+        eq(&json!({
+            "message": "The supplied personal access token does not have the scope required for this operation.",
+            "errorKind": "MissingScope"
+        }))
+    );
+
+    // Tokens can not create more powerful tokens without `account:admin`
+    let escalation = common::create_api_token(
+        &app,
+        &token,
+        &json!({
+            "name": "escalation",
+            "scopes": ["codes:write"],
+        }),
+    )
+    .await;
+    assert_that!(escalation.status(), eq(StatusCode::FORBIDDEN));
+
+    let codes = common::list_codes_content(&app, &a1).await;
+    assert_that!(codes, common::matchers::code_fixture());
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn token_expired(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+    let token = common::create_api_token_content(&app, &a1, &["codes:read"]).await;
+
+    sqlx::query("UPDATE api_tokens SET expires_at = '2020-01-01 00:00:00'")
+        .execute(&db)
+        .await
+        .unwrap();
+
+    let response = common::list_codes(&app, &token).await;
+    assert_that!(response.status(), eq(StatusCode::UNAUTHORIZED));
+    assert_that!(
+        common::convert_response(response).await,
+        eq(&json!({
+            "message": "The supplied personal access token has expired. Create a new one.",
+            "errorKind": "ApiTokenExpired"
+        }))
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn token_unknown(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+
+    let response = common::list_codes(&app, "ibp_doesnotexist").await;
+    assert_that!(response.status(), eq(StatusCode::UNAUTHORIZED));
+    assert_that!(
+        common::convert_response(response).await,
+        eq(&json!({
+            "message": "The supplied authentication is invalid.",
+            "errorKind": "InvalidAuthentication"
+        }))
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn token_invalid_expiry(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let response = common::create_api_token(
+        &app,
+        &a1,
+        &json!({
+            "name": "CI",
+            "scopes": ["codes:read"],
+            "expires_in_days": 10000,
+        }),
+    )
+    .await;
+    assert_that!(response.status(), eq(StatusCode::BAD_REQUEST));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn delete_token(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+    let token = common::create_api_token_content(&app, &a1, &["codes:read"]).await;
+    let id = models::token::ApiToken::get_many(&db, common::USER1_ID.into())
+        .await
+        .unwrap()[0]
+        .id
+        .clone();
+
+    // Other users can not revoke the token
+    let other_user = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::DELETE)
+                .uri(format!("/v1/user/tokens/{id}"))
+                .header("Authorization", format!("Bearer {a2}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_that!(other_user.status(), eq(StatusCode::NOT_FOUND));
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::DELETE)
+                .uri(format!("/v1/user/tokens/{id}"))
+                .header("Authorization", format!("Bearer {a1}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_that!(response.status(), eq(StatusCode::NO_CONTENT));
+
+    let listing = common::list_codes(&app, &token).await;
+    assert_that!(listing.status(), eq(StatusCode::UNAUTHORIZED));
+}
@@ -1,47 +1,136 @@
 use axum::{
     body::Body,
-    http::{Method, Request, StatusCode},
+    http::{header, HeaderMap, Method, Request, StatusCode},
+    routing::post,
+    Json, Router,
 };
 use googletest::prelude::*;
-use iceblink_sync::models;
+use iceblink_sync::{auth::OpenId, models, TokenRequest};
 use serde_json::json;
 use sqlx::SqlitePool;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
 use tower::ServiceExt;
 
 pub mod common;
 
-#[sqlx::test(fixtures("users", "codes"))]
-#[gtest]
-async fn delete_account(db: SqlitePool) {
-    let app = common::testing_setup(&db).await;
-    let (a1, a2) = common::get_access_tokens(&db).await;
-
-    let user1_delete = app
-        .clone()
+async fn delete_account_request(app: &axum::Router, token: &str) -> axum::response::Response {
+    app.clone()
         .oneshot(
             Request::builder()
                 .method(Method::DELETE)
                 .uri("/v1/user")
-                .header("Authorization", format!("Bearer {a1}"))
+                .header("Authorization", format!("Bearer {token}"))
                 .body(Body::empty())
                 .unwrap(),
         )
         .await
-        .unwrap();
-    assert_that!(user1_delete.status(), eq(StatusCode::NO_CONTENT));
+        .unwrap()
+}
 
-    let user1_codes_after_deleted = app
-        .clone()
+async fn cancel_deletion_request(app: &axum::Router, token: &str) -> axum::response::Response {
+    app.clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::DELETE)
+                .uri("/v1/user/deletion")
+                .header("Authorization", format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+}
+
+async fn final_export_request(app: &axum::Router, token: &str) -> axum::response::Response {
+    app.clone()
         .oneshot(
             Request::builder()
                 .method(Method::GET)
-                .uri("/v1/code")
-                .header("Authorization", format!("Bearer {a1}"))
+                .uri("/v1/user/deletion/export")
+                .header("Authorization", format!("Bearer {token}"))
                 .body(Body::empty())
                 .unwrap(),
         )
+        .await
+        .unwrap()
+}
+
+/// Moves the scheduled deletion of every account into the past.
+async fn skip_grace_period(db: &SqlitePool) {
+    let past = chrono::Utc::now() - chrono::Duration::minutes(1);
+    sqlx::query!(
+        "UPDATE users SET deletion_scheduled_for = ? WHERE deletion_scheduled_for IS NOT NULL",
+        past
+    )
+    .execute(db)
+    .await
+    .unwrap();
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn delete_account(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+
+    let user1_delete = delete_account_request(&app, &a1).await;
+    assert_that!(user1_delete.status(), eq(StatusCode::ACCEPTED));
+
+    let scheduled = common::convert_response(user1_delete).await["deletion_scheduled_for"].clone();
+    let scheduled: chrono::DateTime<chrono::Utc> = serde_json::from_value(scheduled).unwrap();
+    expect_that!(
+        scheduled,
+        gt(chrono::Utc::now() + chrono::Duration::days(6))
+    );
+
+    // Still usable during the grace period, with a notice in the profile
+    let u1 = common::list_codes_content(&app, a1.as_str()).await;
+    assert_that!(u1.len(), eq(2));
+    assert_that!(
+        get_profile(&app, &a1).await["deletion_scheduled_for"],
+        not(eq(&json!(null)))
+    );
+
+    // A final export is taken, and kept as it was even when codes change afterwards
+    common::delete_code(&app, &a1, common::USER1_CODE1_ID).await;
+    let export = final_export_request(&app, &a1).await;
+    assert_that!(export.status(), eq(StatusCode::OK));
+    let export = common::convert_response(export).await;
+    expect_that!(export["profile"]["id"], eq(&json!(common::USER1_ID)));
+    expect_that!(export["codes"].as_array().unwrap().len(), eq(2));
+
+    // Not yet due
+    let icon_base =
+        std::env::temp_dir().join(format!("iceblink-{}", iceblink_sync::utils::generate_id(5)));
+    let icon_store = iceblink_sync::icons::IconStore::new_with_custom_base(icon_base.clone());
+    assert_that!(
+        iceblink_sync::jobs::purge_deleted_accounts(&db, &icon_store)
+            .await
+            .unwrap(),
+        eq(0)
+    );
+
+    // Cached icon of a website only user1 uses
+    icon_store.init().await.unwrap();
+    let icon_path = icon_base.join(iceblink_sync::utils::hash_domain("google.com"));
+    tokio::fs::create_dir(&icon_path).await.unwrap();
+    tokio::fs::write(icon_path.join("128.png"), b"icon")
         .await
         .unwrap();
+
+    skip_grace_period(&db).await;
+    assert_that!(
+        iceblink_sync::jobs::purge_deleted_accounts(&db, &icon_store)
+            .await
+            .unwrap(),
+        eq(1)
+    );
+    assert_that!(icon_path.exists(), eq(false));
+
+    let user1_codes_after_deleted = common::list_codes(&app, &a1).await;
     assert_that!(
         user1_codes_after_deleted.status(),
         eq(StatusCode::UNAUTHORIZED)
@@ -68,6 +157,161 @@ async fn delete_account(db: SqlitePool) {
     );
 }
 
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn cancel_account_deletion(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let not_scheduled = cancel_deletion_request(&app, &a1).await;
+    assert_that!(not_scheduled.status(), eq(StatusCode::NOT_FOUND));
+
+    delete_account_request(&app, &a1).await;
+    let cancelled = cancel_deletion_request(&app, &a1).await;
+    assert_that!(cancelled.status(), eq(StatusCode::NO_CONTENT));
+    expect_that!(
+        final_export_request(&app, &a1).await.status(),
+        eq(StatusCode::NOT_FOUND)
+    );
+    assert_that!(
+        get_profile(&app, &a1).await["deletion_scheduled_for"],
+        eq(&json!(null))
+    );
+
+    skip_grace_period(&db).await;
+    let icon_store = iceblink_sync::icons::IconStore::new();
+    assert_that!(
+        iceblink_sync::jobs::purge_deleted_accounts(&db, &icon_store)
+            .await
+            .unwrap(),
+        eq(0)
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn export_account(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::GET)
+                .uri("/v1/user/export")
+                .header("Authorization", format!("Bearer {a1}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_that!(response.status(), eq(StatusCode::OK));
+
+    let export = common::convert_response(response).await;
+    expect_that!(export["profile"]["id"], eq(&json!(common::USER1_ID)));
+    expect_that!(export["identities"].as_array().unwrap().len(), eq(1));
+
+    let codes: Vec<models::codes::Code> = serde_json::from_value(export["codes"].clone()).unwrap();
+    expect_that!(codes, common::matchers::code_fixture());
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn oauth_unknown_provider(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method(Method::GET)
+                .uri("/v1/oauth/doesnotexist?code=abc")
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_that!(response.status(), eq(StatusCode::NOT_FOUND));
+    assert_that!(
+        common::convert_response(response).await,
+        eq(&json!({
+            "message": "No authentication provider with this id is configured on this instance.",
+            "errorKind": "UnknownProvider"
+        }))
+    );
+}
+
+/// Starts a token endpoint which records the content type and body of every request.
+async fn recording_token_endpoint() -> (String, Arc<Mutex<Vec<(String, String)>>>) {
+    let requests: Arc<Mutex<Vec<(String, String)>>> = Arc::default();
+    let recorded = requests.clone();
+    let router = Router::new().route(
+        "/token",
+        post(move |headers: HeaderMap, body: String| async move {
+            let content_type = headers[header::CONTENT_TYPE].to_str().unwrap().to_string();
+            recorded.lock().unwrap().push((content_type, body));
+            Json(json!({ "access_token": "mock" }))
+        }),
+    );
+
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let address = listener.local_addr().unwrap();
+    tokio::spawn(async move { axum::serve(listener, router).await.unwrap() });
+
+    (format!("http://{address}/token"), requests)
+}
+
+#[tokio::test]
+#[gtest]
+async fn oauth_token_request_encoding() {
+    let (token, requests) = recording_token_endpoint().await;
+    let openid = |token_request| OpenId {
+        id: "default".into(),
+        name: "Mock".into(),
+        authorization: "N/A".into(),
+        client_id: "iceblink".into(),
+        client_secret: "secret".into(),
+        token: token.clone(),
+        userinfo: "N/A".into(),
+        redirect_uri: "https://iceblink.example.com/v1/oauth/default".into(),
+        token_request,
+    };
+
+    let access_token = openid(TokenRequest::Form)
+        .exchange("abc".into())
+        .await
+        .unwrap();
+    expect_that!(access_token, eq("mock"));
+    openid(TokenRequest::Json)
+        .exchange("abc".into())
+        .await
+        .unwrap();
+
+    let requests = requests.lock().unwrap();
+    assert_that!(requests.len(), eq(2));
+
+    let (content_type, body) = &requests[0];
+    expect_that!(content_type, eq("application/x-www-form-urlencoded"));
+    let fields: HashMap<String, String> = url::form_urlencoded::parse(body.as_bytes())
+        .into_owned()
+        .collect();
+    expect_that!(fields["grant_type"], eq("authorization_code"));
+    expect_that!(fields["code"], eq("abc"));
+    expect_that!(fields["client_id"], eq("iceblink"));
+    expect_that!(fields["client_secret"], eq("secret"));
+    expect_that!(
+        fields["redirect_uri"],
+        eq("https://iceblink.example.com/v1/oauth/default")
+    );
+
+    let (content_type, body) = &requests[1];
+    expect_that!(content_type, eq("application/json"));
+    let body: serde_json::Value = serde_json::from_str(body).unwrap();
+    expect_that!(body["grant_type"], eq(&json!("authorization_code")));
+    expect_that!(body["code"], eq(&json!("abc")));
+}
+
 #[sqlx::test(fixtures("users", "codes"))]
 #[gtest]
 async fn checksum_two_requests_equal(db: SqlitePool) {
@@ -182,3 +426,338 @@ async fn checksum_changes_code_delete(db: SqlitePool) {
 
     assert_that!(checksum1, not(eq(&checksum2)));
 }
+
+//
+// Identities
+//
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn list_identities(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method(Method::GET)
+                .uri("/v1/user/identities")
+                .header("Authorization", format!("Bearer {a1}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_that!(response.status(), eq(StatusCode::OK));
+
+    let identities: Vec<models::identity::Identity> =
+        serde_json::from_value(common::convert_response(response).await).unwrap();
+    assert_that!(identities.len(), eq(1));
+    assert_that!(identities[0].user_id, eq(common::USER1_ID));
+    assert_that!(identities[0].provider, eq("default"));
+    assert_that!(identities[0].upstream_userid, eq("8h4ar"));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn unlink_last_identity(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method(Method::DELETE)
+                .uri("/v1/user/identities/default")
+                .header("Authorization", format!("Bearer {a1}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_that!(response.status(), eq(StatusCode::CONFLICT));
+    assert_that!(
+        common::convert_response(response).await,
+        eq(&json!({
+            "message": "Unable to unlink the last identity of an account. Link another identity first.",
+            "errorKind": "LastIdentity"
+        }))
+    );
+
+    assert_that!(
+        models::identity::Identity::get_many(&db, common::USER1_ID.into())
+            .await
+            .unwrap()
+            .len(),
+        eq(1)
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn unlink_identity(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    models::identity::Identity {
+        user_id: common::USER1_ID.into(),
+        provider: "keycloak".into(),
+        upstream_userid: "d1a7b0f2".into(),
+        linked_at: chrono::Utc::now(),
+    }
+    .insert(&db)
+    .await
+    .unwrap();
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method(Method::DELETE)
+                .uri("/v1/user/identities/default")
+                .header("Authorization", format!("Bearer {a1}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+    assert_that!(response.status(), eq(StatusCode::NO_CONTENT));
+
+    let identities = models::identity::Identity::get_many(&db, common::USER1_ID.into())
+        .await
+        .unwrap();
+    assert_that!(identities.len(), eq(1));
+    assert_that!(identities[0].provider, eq("keycloak"));
+
+    // The old identity no longer resolves to the user
+    assert_that!(
+        models::user::User::get_by_upstream_id(&db, "default".into(), "8h4ar".into())
+            .await
+            .unwrap(),
+        none()
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn unlink_identity_not_linked(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method(Method::DELETE)
+                .uri("/v1/user/identities/keycloak")
+                .header("Authorization", format!("Bearer {a1}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_that!(response.status(), eq(StatusCode::NOT_FOUND));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn link_identity_unknown_provider(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let response = app
+        .oneshot(
+            Request::builder()
+                .method(Method::POST)
+                .uri("/v1/user/identities/doesnotexist")
+                .header("Authorization", format!("Bearer {a1}"))
+                .header("Content-Type", "application/json")
+                .body(Body::from(
+                    serde_json::to_vec(&json!({ "code": "abc" })).unwrap(),
+                ))
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_that!(response.status(), eq(StatusCode::NOT_FOUND));
+    assert_that!(
+        common::convert_response(response).await,
+        eq(&json!({
+            "message": "No authentication provider with this id is configured on this instance.",
+            "errorKind": "UnknownProvider"
+        }))
+    );
+}
+
+//
+// Profile
+//
+
+async fn get_profile(app: &axum::Router, token: &str) -> serde_json::Value {
+    let response = app
+        .clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::GET)
+                .uri("/v1/user")
+                .header("Authorization", format!("Bearer {token}"))
+                .body(Body::empty())
+                .unwrap(),
+        )
+        .await
+        .unwrap();
+
+    assert_that!(response.status(), eq(StatusCode::OK));
+    common::convert_response(response).await
+}
+
+async fn edit_profile(
+    app: &axum::Router,
+    token: &str,
+    payload: &serde_json::Value,
+) -> axum::response::Response {
+    app.clone()
+        .oneshot(
+            Request::builder()
+                .method(Method::PATCH)
+                .uri("/v1/user")
+                .header("Authorization", format!("Bearer {token}"))
+                .header("Content-Type", "application/json")
+                .body(Body::from(serde_json::to_vec(payload).unwrap()))
+                .unwrap(),
+        )
+        .await
+        .unwrap()
+}
+
+fn jwt_claims(token: &str) -> iceblink_sync::auth::TokenClaims {
+    jsonwebtoken::decode::<iceblink_sync::auth::TokenClaims>(
+        token,
+        &jsonwebtoken::DecodingKey::from_secret("my jwt secret".as_ref()),
+        &jsonwebtoken::Validation::default(),
+    )
+    .unwrap()
+    .claims
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn profile(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    assert_that!(
+        get_profile(&app, &a1).await,
+        eq(&json!({
+            "id": common::USER1_ID,
+            "username": "user1",
+            "display_name": "User One",
+            "display_name_override": null,
+            "avatar_url": "https://github.com/Snowcone-Labs.png",
+            "deletion_scheduled_for": null,
+            "is_admin": false,
+            "icon_fetching": true,
+        }))
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn profile_display_name_override(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let edited = edit_profile(&app, &a1, &json!({ "display_name": "Uno" })).await;
+    assert_that!(edited.status(), eq(StatusCode::OK));
+
+    let profile = get_profile(&app, &a1).await;
+    assert_that!(profile["display_name"], eq(&json!("Uno")));
+    assert_that!(profile["display_name_override"], eq(&json!("Uno")));
+
+    // New tokens carry the override
+    let (a1, _) = common::get_access_tokens(&db).await;
+    assert_that!(jwt_claims(&a1).display_name, eq("Uno"));
+
+    // Clearing it reverts to the name from the IdP
+    edit_profile(&app, &a1, &json!({ "display_name": null })).await;
+    let profile = get_profile(&app, &a1).await;
+    assert_that!(profile["display_name"], eq(&json!("User One")));
+    assert_that!(profile["display_name_override"], eq(&json!(null)));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn profile_empty_display_name(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let edited = edit_profile(&app, &a1, &json!({ "display_name": "  " })).await;
+    assert_that!(edited.status(), eq(StatusCode::BAD_REQUEST));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn oauth_refreshes_profile(db: SqlitePool) {
+    let idp = common::mock_idp(json!({
+        "sub": "8h4ar",
+        "name": "User Renamed",
+        "preferred_username": "user1-renamed",
+        "picture": "https://example.com/avatar.png",
+    }))
+    .await;
+    let app = common::testing_setup_custom()
+        .pool(&db)
+        .openid(idp)
+        .call()
+        .await;
+    let (a1, _) = common::get_access_tokens(&db).await;
+
+    let login = common::oauth_login(&app, "default").await;
+    assert_that!(login.status(), eq(StatusCode::OK));
+
+    let claims = jwt_claims(&common::jwt_from_cookie(&login));
+    expect_that!(claims.sub, eq(common::USER1_ID));
+    expect_that!(claims.username, eq("user1-renamed"));
+    expect_that!(claims.display_name, eq("User Renamed"));
+    expect_that!(claims.avatar_url, eq("https://example.com/avatar.png"));
+
+    // A local override survives the refresh
+    edit_profile(&app, &a1, &json!({ "display_name": "Uno" })).await;
+    let login = common::oauth_login(&app, "default").await;
+    let claims = jwt_claims(&common::jwt_from_cookie(&login));
+    expect_that!(claims.display_name, eq("Uno"));
+
+    let profile = get_profile(&app, &a1).await;
+    expect_that!(profile["username"], eq(&json!("user1-renamed")));
+    expect_that!(profile["display_name"], eq(&json!("Uno")));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn oauth_creates_user(db: SqlitePool) {
+    let idp = common::mock_idp(json!({
+        "sub": "newcomer",
+        "email": "newcomer@example.com",
+    }))
+    .await;
+    let app = common::testing_setup_custom()
+        .pool(&db)
+        .openid(idp)
+        .call()
+        .await;
+
+    let login = common::oauth_login(&app, "default").await;
+    assert_that!(login.status(), eq(StatusCode::OK));
+
+    let claims = jwt_claims(&common::jwt_from_cookie(&login));
+    expect_that!(claims.username, eq("newcomer@example.com"));
+    expect_that!(claims.display_name, eq("newcomer@example.com"));
+
+    assert_that!(
+        models::user::User::get_by_upstream_id(&db, "default".into(), "newcomer".into())
+            .await
+            .unwrap(),
+        some(anything())
+    );
+}
@@ -0,0 +1,349 @@
+use axum::{
+    http::{Method, StatusCode},
+    response::Response,
+    Router,
+};
+use googletest::prelude::*;
+use iceblink_sync::models;
+use serde_json::json;
+use sqlx::SqlitePool;
+
+pub mod common;
+
+async fn create_vault(app: &Router, token: &str) -> String {
+    let response = common::request(
+        app,
+        Method::POST,
+        "/v1/vaults",
+        token,
+        Some(json!({ "name": "On-call" })),
+    )
+    .await;
+    assert_that!(response.status(), eq(StatusCode::OK));
+
+    common::convert_response(response).await["id"]
+        .as_str()
+        .unwrap()
+        .to_string()
+}
+
+async fn invite(app: &Router, token: &str, vault_id: &str, role: &str) -> Response {
+    common::request(
+        app,
+        Method::POST,
+        &format!("/v1/vaults/{vault_id}/invitations"),
+        token,
+        Some(json!({ "role": role })),
+    )
+    .await
+}
+
+async fn join(app: &Router, token: &str, code: &str) -> Response {
+    common::request(
+        app,
+        Method::POST,
+        "/v1/vaults/join",
+        token,
+        Some(json!({ "code": code })),
+    )
+    .await
+}
+
+/// Creates a vault owned by user1, which user2 joins with the given role.
+async fn shared_vault(app: &Router, a1: &str, a2: &str, role: &str) -> String {
+    let vault_id = create_vault(app, a1).await;
+
+    let invitation = common::convert_response(invite(app, a1, &vault_id, role).await).await;
+    let joined = join(app, a2, invitation["code"].as_str().unwrap()).await;
+    assert_that!(joined.status(), eq(StatusCode::OK));
+
+    vault_id
+}
+
+async fn add_vault_code(app: &Router, token: &str, vault_id: &str) -> Response {
+    common::add_code(
+        app,
+        token,
+        &json!({
+            "content": "shared",
+            "display_name": "Service account",
+            "vault_id": vault_id,
+        }),
+    )
+    .await
+}
+
+async fn list_vault_codes(app: &Router, token: &str, vault_id: &str) -> Response {
+    common::request(
+        app,
+        Method::GET,
+        &format!("/v1/vaults/{vault_id}/codes"),
+        token,
+        None,
+    )
+    .await
+}
+
+async fn set_role(
+    app: &Router,
+    token: &str,
+    vault_id: &str,
+    user_id: &str,
+    role: &str,
+) -> Response {
+    common::request(
+        app,
+        Method::PATCH,
+        &format!("/v1/vaults/{vault_id}/members/{user_id}"),
+        token,
+        Some(json!({ "role": role })),
+    )
+    .await
+}
+
+async fn remove_member(app: &Router, token: &str, vault_id: &str, user_id: &str) -> Response {
+    common::request(
+        app,
+        Method::DELETE,
+        &format!("/v1/vaults/{vault_id}/members/{user_id}"),
+        token,
+        None,
+    )
+    .await
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn create_and_list_vaults(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+    let vault_id = create_vault(&app, &a1).await;
+
+    let vaults =
+        common::convert_response(common::request(&app, Method::GET, "/v1/vaults", &a1, None).await)
+            .await;
+    expect_that!(vaults[0]["id"], eq(&json!(vault_id)));
+    expect_that!(vaults[0]["name"], eq(&json!("On-call")));
+    expect_that!(vaults[0]["role"], eq(&json!("owner")));
+
+    let vaults =
+        common::convert_response(common::request(&app, Method::GET, "/v1/vaults", &a2, None).await)
+            .await;
+    expect_that!(vaults, eq(&json!([])));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn vault_roles(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+    let vault_id = shared_vault(&app, &a1, &a2, "viewer").await;
+
+    let added = add_vault_code(&app, &a1, &vault_id).await;
+    assert_that!(added.status(), eq(StatusCode::OK));
+    let code = common::convert_response(added).await;
+    expect_that!(code["owner_id"], eq(&json!(null)));
+    expect_that!(code["vault_id"], eq(&json!(vault_id)));
+    let code_id = code["id"].as_str().unwrap();
+
+    // Vault codes are not part of the personal codes
+    let codes = common::list_codes_content(&app, &a1).await;
+    assert_that!(codes, common::matchers::code_fixture());
+
+    // Viewers can only read
+    let codes = common::convert_response(list_vault_codes(&app, &a2, &vault_id).await).await;
+    expect_that!(codes.as_array().unwrap().len(), eq(1));
+
+    let edited = common::edit_code(&app, &a2, code_id, &json!({ "display_name": "Mine" })).await;
+    assert_that!(edited.status(), eq(StatusCode::FORBIDDEN));
+    assert_that!(
+        common::convert_response(edited).await["errorKind"],
+        eq(&json!("InsufficientVaultRole"))
+    );
+
+    let added = add_vault_code(&app, &a2, &vault_id).await;
+    assert_that!(added.status(), eq(StatusCode::FORBIDDEN));
+
+    let invited = invite(&app, &a2, &vault_id, "owner").await;
+    assert_that!(invited.status(), eq(StatusCode::FORBIDDEN));
+
+    // Editors can also write
+    let promoted = set_role(&app, &a1, &vault_id, common::USER2_ID, "editor").await;
+    assert_that!(promoted.status(), eq(StatusCode::OK));
+
+    let edited = common::edit_code(&app, &a2, code_id, &json!({ "display_name": "Mine" })).await;
+    assert_that!(edited.status(), eq(StatusCode::OK));
+
+    let deleted = common::delete_code(&app, &a2, code_id).await;
+    assert_that!(deleted.status(), eq(StatusCode::NO_CONTENT));
+
+    let codes = common::convert_response(list_vault_codes(&app, &a1, &vault_id).await).await;
+    expect_that!(codes, eq(&json!([])));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn vault_non_member(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+    let vault_id = create_vault(&app, &a1).await;
+
+    let code = common::convert_response(add_vault_code(&app, &a1, &vault_id).await).await;
+    let code_id = code["id"].as_str().unwrap();
+
+    let listed = list_vault_codes(&app, &a2, &vault_id).await;
+    assert_that!(listed.status(), eq(StatusCode::NOT_FOUND));
+
+    let added = add_vault_code(&app, &a2, &vault_id).await;
+    assert_that!(added.status(), eq(StatusCode::NOT_FOUND));
+
+    let edited = common::edit_code(&app, &a2, code_id, &json!({ "display_name": "Mine" })).await;
+    assert_that!(edited.status(), eq(StatusCode::NOT_FOUND));
+
+    let deleted = common::delete_code(&app, &a2, code_id).await;
+    assert_that!(deleted.status(), eq(StatusCode::NOT_FOUND));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn vault_invitation_single_use(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+    let vault_id = create_vault(&app, &a1).await;
+
+    let invitation = common::convert_response(invite(&app, &a1, &vault_id, "editor").await).await;
+    expect_that!(invitation["role"], eq(&json!("editor")));
+    let code = invitation["code"].as_str().unwrap();
+
+    // Members following the link do not use it up
+    let member = join(&app, &a1, code).await;
+    assert_that!(member.status(), eq(StatusCode::CONFLICT));
+
+    let joined = join(&app, &a2, code).await;
+    assert_that!(joined.status(), eq(StatusCode::OK));
+    expect_that!(
+        common::convert_response(joined).await["role"],
+        eq(&json!("editor"))
+    );
+
+    let reused = join(&app, &a1, code).await;
+    assert_that!(reused.status(), eq(StatusCode::BAD_REQUEST));
+    assert_that!(
+        common::convert_response(reused).await["errorKind"],
+        eq(&json!("VaultInvitationExpired"))
+    );
+
+    // Members can not join again
+    let invitation = common::convert_response(invite(&app, &a1, &vault_id, "viewer").await).await;
+    let joined = join(&app, &a2, invitation["code"].as_str().unwrap()).await;
+    assert_that!(joined.status(), eq(StatusCode::CONFLICT));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn vault_last_owner(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+    let vault_id = shared_vault(&app, &a1, &a2, "editor").await;
+
+    let demoted = set_role(&app, &a1, &vault_id, common::USER1_ID, "editor").await;
+    assert_that!(demoted.status(), eq(StatusCode::CONFLICT));
+    assert_that!(
+        common::convert_response(demoted).await["errorKind"],
+        eq(&json!("LastVaultOwner"))
+    );
+
+    let left = remove_member(&app, &a1, &vault_id, common::USER1_ID).await;
+    assert_that!(left.status(), eq(StatusCode::CONFLICT));
+
+    // Editors can not remove others, but can leave
+    let removed = remove_member(&app, &a2, &vault_id, common::USER1_ID).await;
+    assert_that!(removed.status(), eq(StatusCode::FORBIDDEN));
+
+    let promoted = set_role(&app, &a1, &vault_id, common::USER2_ID, "owner").await;
+    assert_that!(promoted.status(), eq(StatusCode::OK));
+
+    let left = remove_member(&app, &a1, &vault_id, common::USER1_ID).await;
+    assert_that!(left.status(), eq(StatusCode::NO_CONTENT));
+
+    let listed = list_vault_codes(&app, &a1, &vault_id).await;
+    assert_that!(listed.status(), eq(StatusCode::NOT_FOUND));
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn vault_owner_deletes_account(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+    let vault_id = shared_vault(&app, &a1, &a2, "viewer").await;
+    let code = common::convert_response(add_vault_code(&app, &a1, &vault_id).await).await;
+
+    let deleted = common::request(&app, Method::DELETE, "/v1/user", &a1, None).await;
+    assert_that!(deleted.status(), eq(StatusCode::ACCEPTED));
+
+    let past = chrono::Utc::now() - chrono::Duration::minutes(1);
+    sqlx::query!(
+        "UPDATE users SET deletion_scheduled_for = ? WHERE deletion_scheduled_for IS NOT NULL",
+        past
+    )
+    .execute(&db)
+    .await
+    .unwrap();
+    iceblink_sync::jobs::purge_deleted_accounts(&db, &iceblink_sync::icons::IconStore::new())
+        .await
+        .unwrap();
+
+    // The remaining member takes over, instead of the vault being left without an owner
+    let member = models::vault::VaultMember::get(&db, vault_id.clone(), common::USER2_ID.into())
+        .await
+        .unwrap();
+    assert_that!(
+        member.map(|member| member.role),
+        some(eq(models::vault::VaultRole::Owner))
+    );
+
+    let listed = list_vault_codes(&app, &a2, &vault_id).await;
+    assert_that!(listed.status(), eq(StatusCode::OK));
+    expect_that!(
+        common::convert_response(listed).await[0]["id"],
+        eq(&code["id"])
+    );
+}
+
+#[sqlx::test(fixtures("users", "codes"))]
+#[gtest]
+async fn delete_vault(db: SqlitePool) {
+    let app = common::testing_setup(&db).await;
+    let (a1, a2) = common::get_access_tokens(&db).await;
+    let vault_id = shared_vault(&app, &a1, &a2, "editor").await;
+
+    let code = common::convert_response(add_vault_code(&app, &a1, &vault_id).await).await;
+
+    let deleted = common::request(
+        &app,
+        Method::DELETE,
+        &format!("/v1/vaults/{vault_id}"),
+        &a2,
+        None,
+    )
+    .await;
+    assert_that!(deleted.status(), eq(StatusCode::FORBIDDEN));
+
+    let deleted = common::request(
+        &app,
+        Method::DELETE,
+        &format!("/v1/vaults/{vault_id}"),
+        &a1,
+        None,
+    )
+    .await;
+    assert_that!(deleted.status(), eq(StatusCode::NO_CONTENT));
+
+    // Codes of the vault are deleted with it
+    assert_that!(
+        models::codes::Code::get_by_id(&db, code["id"].as_str().unwrap().into())
+            .await
+            .unwrap(),
+        none()
+    );
+}